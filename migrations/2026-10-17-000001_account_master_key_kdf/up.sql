@@ -0,0 +1,3 @@
+-- Argon2 settings used to derive the master encryption key, stored as a PHC
+-- string without salt or hash. Empty for keys derived with the legacy settings.
+ALTER TABLE account ADD COLUMN master_key_kdf VARCHAR (256) NOT NULL DEFAULT '';
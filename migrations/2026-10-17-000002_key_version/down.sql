@@ -0,0 +1,2 @@
+ALTER TABLE read_grant_key DROP COLUMN key_version;
+ALTER TABLE write_grant_scope DROP COLUMN key_version;
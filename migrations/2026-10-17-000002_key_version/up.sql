@@ -0,0 +1,4 @@
+-- Version of the derivation used for the key protecting each record, see
+-- src/encryption/key_derivation.rs. Existing records use version 0.
+ALTER TABLE write_grant_scope ADD COLUMN key_version SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE read_grant_key ADD COLUMN key_version SMALLINT NOT NULL DEFAULT 0;
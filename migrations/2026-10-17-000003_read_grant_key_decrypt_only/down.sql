@@ -0,0 +1 @@
+ALTER TABLE read_grant_key DROP COLUMN decrypt_only;
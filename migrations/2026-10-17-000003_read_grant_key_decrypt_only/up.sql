@@ -0,0 +1,2 @@
+-- Keys replaced by a rotation are kept to decrypt historical messages only.
+ALTER TABLE read_grant_key ADD COLUMN decrypt_only BOOL NOT NULL DEFAULT FALSE;
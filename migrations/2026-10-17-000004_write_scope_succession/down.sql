@@ -0,0 +1 @@
+DROP TABLE write_scope_succession;
@@ -0,0 +1,12 @@
+-- A write scope moving from one signing key to the next. The statement is
+-- signed by the previous key, the successor key and the account.
+CREATE TABLE write_scope_succession(
+    id                    SERIAL                                PRIMARY KEY NOT NULL,
+    write_grant_scope_id  INT REFERENCES write_grant_scope(id)  NOT NULL,
+    previous_public_key   BYTEA                                 NOT NULL,
+    public_key            BYTEA                                 NOT NULL,
+    succession_date       TIMESTAMP                             NOT NULL,
+    previous_signature    BYTEA                                 NOT NULL,
+    successor_signature   BYTEA                                 NOT NULL,
+    signature             BYTEA                                 NOT NULL
+);
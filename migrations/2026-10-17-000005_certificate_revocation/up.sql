@@ -0,0 +1,14 @@
+-- Revoked certificate public keys. Entries are numbered per account and each
+-- one is chained to the hash of the entry before it and signed by the
+-- account, so a list fetched from any serial can be verified incrementally.
+CREATE TABLE certificate_revocation(
+    id               SERIAL                      PRIMARY KEY NOT NULL,
+    account_id       INT REFERENCES account(id)  NOT NULL,
+    serial           INT                         NOT NULL,
+    public_key       BYTEA                       NOT NULL,
+    reason           VARCHAR(64)                 NOT NULL,
+    revocation_date  TIMESTAMP                   NOT NULL,
+    previous_hash    BYTEA                       NOT NULL,
+    signature        BYTEA                       NOT NULL,
+    UNIQUE (account_id, serial)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE read_grant_key DROP COLUMN key_algorithm;
+ALTER TABLE write_grant_scope DROP COLUMN key_algorithm;
+ALTER TABLE account DROP COLUMN key_algorithm;
@@ -0,0 +1,5 @@
+-- Algorithm of each stored key, see encryption::algorithm for the ids.
+-- Existing keys are Ed25519 signing keys and X25519 exchange keys.
+ALTER TABLE account ADD COLUMN key_algorithm SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE write_grant_scope ADD COLUMN key_algorithm SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE read_grant_key ADD COLUMN key_algorithm SMALLINT NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+ALTER TABLE read_authorization DROP COLUMN encapsulated_key;
+ALTER TABLE client DROP COLUMN kem_public_key;
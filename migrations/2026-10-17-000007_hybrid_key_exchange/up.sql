@@ -0,0 +1,4 @@
+-- ML-KEM-768 encapsulation key of clients that can be authorized for hybrid
+-- read grant keys, and the ML-KEM ciphertext of hybrid read authorizations.
+ALTER TABLE client ADD COLUMN kem_public_key BYTEA;
+ALTER TABLE read_authorization ADD COLUMN encapsulated_key BYTEA;
@@ -0,0 +1,12 @@
+-- Additional copies of an account's master key, each wrapped under its own
+-- unlocker: a password, a recovery code, a key file or a device secret.
+CREATE TABLE account_key_slot(
+    id                    SERIAL                      PRIMARY KEY NOT NULL,
+    account_id            INT REFERENCES account(id)  NOT NULL,
+    label                 VARCHAR(64)                 NOT NULL,
+    slot_type             VARCHAR(32)                 NOT NULL,
+    salt                  BYTEA                       NOT NULL,
+    kdf                   VARCHAR                     NOT NULL,
+    encrypted_master_key  BYTEA                       NOT NULL,
+    UNIQUE (account_id, label)
+);
@@ -0,0 +1,14 @@
+-- Signed statements that an account moved to another identity server. They
+-- are kept by public key rather than account id so the server an account left
+-- can keep pointing to its new home after the account is deleted.
+CREATE TABLE account_move(
+    id                      SERIAL          PRIMARY KEY NOT NULL,
+    account_public_key      BYTEA           NOT NULL,
+    account_key_algorithm   SMALLINT        NOT NULL,
+    source_server_key       BYTEA           NOT NULL,
+    destination_server_key  BYTEA           NOT NULL,
+    destination_url         VARCHAR(256)    NOT NULL,
+    move_date               TIMESTAMP       NOT NULL,
+    signature               BYTEA           NOT NULL,
+    UNIQUE (account_public_key, move_date)
+);
@@ -0,0 +1,13 @@
+-- Access and refresh tokens issued to clients by the OAuth2 token endpoint.
+-- Only hashes of the tokens are kept. The scope ids are the read and write
+-- grant scopes the client was authorized for when the token was issued.
+CREATE TABLE oauth_token(
+    id                       SERIAL                              PRIMARY KEY NOT NULL,
+    client_id                BYTEA REFERENCES client(client_id)  NOT NULL,
+    access_token_hash        BYTEA                               NOT NULL UNIQUE,
+    refresh_token_hash       BYTEA                               NOT NULL UNIQUE,
+    read_grant_scope_ids     INT[]                               NOT NULL,
+    write_grant_scope_ids    INT[]                               NOT NULL,
+    expiration_date          TIMESTAMP                           NOT NULL,
+    refresh_expiration_date  TIMESTAMP                           NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Codes handed to a client application by the consent page, exchanged once
+-- at the token endpoint for the secret of the client created on approval.
+-- Only a hash of the code is kept, the secret is encrypted under a key
+-- derived from the code. code_challenge is the PKCE S256 challenge.
+CREATE TABLE authorization_code(
+    id                  SERIAL                              PRIMARY KEY NOT NULL,
+    code_hash           BYTEA                               NOT NULL UNIQUE,
+    client_id           BYTEA REFERENCES client(client_id)  NOT NULL,
+    encrypted_secret    BYTEA                               NOT NULL,
+    code_challenge      TEXT                                NOT NULL,
+    redirect_uri        TEXT                                NOT NULL,
+    scope               TEXT                                NOT NULL,
+    expiration_date     TIMESTAMP                           NOT NULL
+);
@@ -0,0 +1,3 @@
+-- The OpenID Connect id token signed on approval when the request asked for
+-- the openid scope, handed out with the client credentials.
+ALTER TABLE authorization_code ADD COLUMN id_token TEXT;
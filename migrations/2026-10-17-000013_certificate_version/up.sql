@@ -0,0 +1,5 @@
+-- Certificate version each scope key was certified under, see
+-- model::certificate. Existing keys were certified under version 1, keys
+-- with algorithms version 1 did not have are checked against version 2.
+ALTER TABLE write_grant_scope ADD COLUMN certificate_version SMALLINT NOT NULL DEFAULT 1;
+ALTER TABLE read_grant_key ADD COLUMN certificate_version SMALLINT NOT NULL DEFAULT 1;
@@ -0,0 +1,13 @@
+ALTER TABLE certificate_revocation ADD COLUMN account_id INT REFERENCES account(id);
+
+UPDATE certificate_revocation
+SET account_id = account.id
+FROM account
+WHERE account.public_key = certificate_revocation.account_public_key;
+
+DELETE FROM certificate_revocation WHERE account_id IS NULL;
+
+ALTER TABLE certificate_revocation ALTER COLUMN account_id SET NOT NULL;
+ALTER TABLE certificate_revocation DROP COLUMN account_public_key;
+ALTER TABLE certificate_revocation DROP COLUMN account_key_algorithm;
+ALTER TABLE certificate_revocation ADD UNIQUE (account_id, serial);
@@ -0,0 +1,16 @@
+-- Revocation lists are kept by account public key rather than account id, as
+-- account_move is, so an application server can still fetch the list of an
+-- account after it is deleted.
+ALTER TABLE certificate_revocation ADD COLUMN account_public_key BYTEA;
+ALTER TABLE certificate_revocation ADD COLUMN account_key_algorithm SMALLINT;
+
+UPDATE certificate_revocation
+SET account_public_key = account.public_key,
+    account_key_algorithm = account.key_algorithm
+FROM account
+WHERE account.id = certificate_revocation.account_id;
+
+ALTER TABLE certificate_revocation ALTER COLUMN account_public_key SET NOT NULL;
+ALTER TABLE certificate_revocation ALTER COLUMN account_key_algorithm SET NOT NULL;
+ALTER TABLE certificate_revocation DROP COLUMN account_id;
+ALTER TABLE certificate_revocation ADD UNIQUE (account_public_key, serial);
@@ -1,8 +1,20 @@
 use clap::{App, Arg, ArgMatches, SubCommand};
 use super::{get_input, get_new_password, get_password};
+use crate::encryption::secret::{SecretBytes, SecretString};
+use crate::encryption::shamir::Share;
 use crate::database::establish_connection;
 use crate::database::MyConnection;
-use crate::model::account::Account;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::fingerprint;
+use crate::encryption::key_format::KeyFormat;
+use crate::encryption::signing_key::SigningKey;
+use crate::model::account::{Account, NewAccount};
+use crate::model::application::Application;
+use crate::model::write_scope::WriteScope;
+use crate::model::account_key_slot::{AccountKeySlot, KeySlotType};
+use crate::model::recovery_kit::RecoveryKit;
+use crate::model::certificate_revocation::{CertificateRevocation, RevocationReason};
+use base64::decode;
 use anyhow::{bail, Context, Result};
 
 pub fn init() -> App<'static, 'static> {
@@ -42,6 +54,24 @@ pub fn init() -> App<'static, 'static> {
                         .help("Required to release an encrypted export of the account's keys.")
                         .value_name("EXPORT_KEY")
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("algorithm")
+                        .short("g")
+                        .long("algorithm")
+                        .help("The account signing key algorithm.")
+                        .value_name("ALGORITHM")
+                        .possible_values(&["ed25519", "p256"])
+                        .default_value("ed25519")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("identity")
+                        .short("i")
+                        .long("identity")
+                        .help("An existing Ed25519 private key to use as the account key, as PEM, OpenSSH or JWK.")
+                        .value_name("KEY_FILE")
+                        .takes_value(true),
                 ),
         )
         .subcommand(SubCommand::with_name("list").about("Show all accounts"))
@@ -71,6 +101,460 @@ pub fn init() -> App<'static, 'static> {
                         .help("The replacement password.")
                         .value_name("NEWPASSWORD")
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("slot")
+                        .short("l")
+                        .long("slot")
+                        .help("Unlock with this key slot instead of the current password.")
+                        .value_name("LABEL")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("secret")
+                        .short("s")
+                        .long("secret")
+                        .help("The key slot's password, recovery code or device secret.")
+                        .value_name("SECRET")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("keyfile")
+                        .short("k")
+                        .long("keyfile")
+                        .help("The key file for a key_file slot.")
+                        .value_name("FILE")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("upgrade")
+                .about("Re-encrypt keys stored in an outdated format")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name to upgrade.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("revoke")
+                .about("Add a certificate to the account's revocation list")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account that issued the certificate.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .short("k")
+                        .long("key")
+                        .help("Base64 public key of the certificate to revoke.")
+                        .value_name("PUBLIC_KEY")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("reason")
+                        .short("r")
+                        .long("reason")
+                        .help("Why the certificate is revoked.")
+                        .value_name("REASON")
+                        .possible_values(&["unspecified", "key_compromise", "superseded", "cessation_of_operation"])
+                        .default_value("unspecified")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("crl")
+                .about("Print the account's signed certificate revocation list as JSON")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .short("s")
+                        .long("since")
+                        .help("Only list entries after this serial.")
+                        .value_name("SERIAL")
+                        .default_value("0")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("addslot")
+                .about("Add a key slot that can unlock the account")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("label")
+                        .short("l")
+                        .long("label")
+                        .help("Name of the key slot.")
+                        .value_name("LABEL")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("type")
+                        .short("t")
+                        .long("type")
+                        .help("What unlocks the slot. Recovery codes and device secrets are generated and printed once.")
+                        .value_name("TYPE")
+                        .possible_values(&["password", "recovery_code", "key_file", "device"])
+                        .default_value("password")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("secret")
+                        .short("s")
+                        .long("secret")
+                        .help("The password for a password slot.")
+                        .value_name("SECRET")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("keyfile")
+                        .short("k")
+                        .long("keyfile")
+                        .help("The key file for a key_file slot.")
+                        .value_name("FILE")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("listslots")
+                .about("Show the account's key slots")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rmslot")
+                .about("Remove a key slot")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("label")
+                        .short("l")
+                        .long("label")
+                        .help("Name of the key slot.")
+                        .value_name("LABEL")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("shares")
+                .about("Split a recovery key into shares, any threshold of which can reset the password")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("threshold")
+                        .short("m")
+                        .long("threshold")
+                        .help("How many shares are needed to recover the account.")
+                        .value_name("M")
+                        .default_value("3")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("count")
+                        .short("n")
+                        .long("count")
+                        .help("How many shares to give out.")
+                        .value_name("N")
+                        .default_value("5")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("recover")
+                .about("Recover an account with its recovery shares and set a new password")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name to recover.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("share")
+                        .short("s")
+                        .long("share")
+                        .help("A recovery share, use once for each share.")
+                        .multiple(true)
+                        .value_name("SHARE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("newpassword")
+                        .short("r")
+                        .long("newpassword")
+                        .help("The replacement password.")
+                        .value_name("NEWPASSWORD")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("recovery-kit")
+                .about("Print a recovery kit with the signing key mnemonic, export key and fingerprint")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("exportkey")
+                        .short("x")
+                        .long("exportkey")
+                        .help("The account's export key.")
+                        .value_name("EXPORT_KEY")
+                        .takes_value(true),
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("chngexportkey")
+                .about("Replace the account's export key with a new random one")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's current password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("exportkey")
+                        .short("x")
+                        .long("exportkey")
+                        .help("The account's current export key.")
+                        .value_name("EXPORT_KEY")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export-key")
+                .about("Print the account or a write scope key as PEM, OpenSSH or JWK")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account's password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .short("t")
+                        .long("format")
+                        .help("The key format.")
+                        .value_name("FORMAT")
+                        .possible_values(&["pem", "openssh", "jwk"])
+                        .default_value("pem")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("private")
+                        .long("private")
+                        .help("Print the private key, requires the export key."),
+                )
+                .arg(
+                    Arg::with_name("exportkey")
+                        .short("x")
+                        .long("exportkey")
+                        .help("The account's export key.")
+                        .value_name("EXPORT_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("code")
+                        .short("c")
+                        .long("code")
+                        .help("The application of the write scope.")
+                        .value_name("CODE")
+                        .requires("write")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("write")
+                        .short("w")
+                        .long("write")
+                        .help("Export this write scope key instead of the account key.")
+                        .value_name("WRITE_SCOPE")
+                        .requires("code")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restore an account from its recovery kit")
+                .arg(
+                    Arg::with_name("username")
+                        .short("a")
+                        .long("username")
+                        .help("The account name for the restored account.")
+                        .value_name("USERNAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("email")
+                        .short("e")
+                        .long("email")
+                        .help("Email address associated with the account.")
+                        .value_name("EMAIL")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The new password used to encrypt this account's keys.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("exportkey")
+                        .short("x")
+                        .long("exportkey")
+                        .help("The export key from the recovery kit.")
+                        .value_name("EXPORT_KEY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mnemonic")
+                        .short("m")
+                        .long("mnemonic")
+                        .help("The 24 word mnemonic from the recovery kit.")
+                        .value_name("MNEMONIC")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("fingerprint")
+                        .short("f")
+                        .long("fingerprint")
+                        .help("The fingerprint from the recovery kit, checked against the restored key.")
+                        .value_name("FINGERPRINT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("algorithm")
+                        .short("g")
+                        .long("algorithm")
+                        .help("The signing key algorithm from the recovery kit.")
+                        .value_name("ALGORITHM")
+                        .possible_values(&["ed25519", "p256"])
+                        .default_value("ed25519")
+                        .takes_value(true),
                 ),
         )
         .subcommand(
@@ -104,27 +588,98 @@ pub fn init() -> App<'static, 'static> {
 pub fn run(matches: &ArgMatches) -> Result<()> {
     let connection = establish_connection()?;
 
-    match matches.subcommand() {
-        ("add", Some(m))     => add(m, &connection),
-        ("chngpwd", Some(m)) => change_password(m, &connection),
-        ("delete", Some(m))  => delete(m, &connection),
-        ("list", _)          => list(&connection),
-        (c, _)               => bail!("Subcommand {} not recognized.", c),
-    }
-}
+    match matches.subcommand() {
+        ("add", Some(m))     => add(m, &connection),
+        ("chngpwd", Some(m)) => change_password(m, &connection),
+        ("delete", Some(m))  => delete(m, &connection),
+        ("upgrade", Some(m)) => upgrade(m, &connection),
+        ("revoke", Some(m))  => revoke(m, &connection),
+        ("crl", Some(m))     => crl(m, &connection),
+        ("addslot", Some(m))   => add_slot(m, &connection),
+        ("listslots", Some(m)) => list_slots(m, &connection),
+        ("rmslot", Some(m))    => remove_slot(m, &connection),
+        ("shares", Some(m))    => shares(m, &connection),
+        ("recover", Some(m))   => recover(m, &connection),
+        ("recovery-kit", Some(m)) => recovery_kit(m, &connection),
+        ("chngexportkey", Some(m)) => change_export_key(m, &connection),
+        ("export-key", Some(m)) => export_key(m, &connection),
+        ("restore", Some(m))   => restore(m, &connection),
+        ("list", _)          => list(&connection),
+        (c, _)               => bail!("Subcommand {} not recognized.", c),
+    }
+}
+
+fn list(connection: &MyConnection) -> Result<()> {
+        let records = Account::load_all(&connection)
+            .context("Error loading account accounts.")?;
+
+        for i in 0..records.len() {
+            println!("{}", records[i].name);
+        }
+
+        Ok(())
+}
+
+fn delete(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    if !(matches.is_present("force")
+         || get_input(&format!(
+                 "Are you sure you want to delete {}? [y/n]: ",
+                 &username
+                 )) == "y")
+    {
+        bail!("Password reset cancelled.");
+    }
+
+    let name = username.clone();
+
+    Account::load_unlocked(username, password, &connection)
+        .context("No such username and password.")?
+        .delete(&connection)
+        .context(format!("Could not delete {}.", &name))?;
+
+    println!("Account {} deleted", &name);
+
+    Ok(())
+}
+
+fn upgrade(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let name = username.clone();
+
+    // Loading the account re-encrypts its own keys.
+    let account = Account::load_unlocked(username, password, &connection)
+        .context("Username and password not recognized.")?;
 
-fn list(connection: &MyConnection) -> Result<()> {
-        let records = Account::load_all(&connection)
-            .context("Error loading account accounts.")?;
+    let count = account.upgrade_encryption(&connection)
+        .context(format!("Could not upgrade {}.", &name))?;
 
-        for i in 0..records.len() {
-            println!("{}", records[i].name);
-        }
+    println!("Account {} upgraded, {} records re-encrypted.", &name, count);
 
-        Ok(())
+    Ok(())
 }
 
-fn delete(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+fn revoke(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
 
     let username = match matches.value_of("username") {
         Some(u) => u.to_owned(),
@@ -132,27 +687,50 @@ fn delete(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_password("Password: "),
     };
 
-    if !(matches.is_present("force")
-         || get_input(&format!(
-                 "Are you sure you want to delete {}? [y/n]: ",
-                 &username
-                 )) == "y")
-    {
-        bail!("Password reset cancelled.");
-    }
+    let encoded_key = matches.value_of("key").unwrap_or_default();
+    let public_key = decode(encoded_key).context("Public key is not valid base64.")?;
 
-    let name = username.clone();
+    let reason: RevocationReason = matches
+        .value_of("reason")
+        .unwrap_or_default()
+        .parse()
+        .context("Unknown revocation reason.")?;
 
-    Account::load_unlocked(username, password, &connection)
-        .context("No such username and password.")?
-        .delete(&connection)
-        .context(format!("Could not delete {}.", &name))?;
+    let account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
 
-    println!("Account {} deleted", &name);
+    let revocation = CertificateRevocation::revoke(&account, &public_key, reason, connection)
+        .context(format!("Could not revoke {}.", encoded_key))?;
+
+    println!("Certificate {} revoked, serial {}.", encoded_key, revocation.serial);
+
+    Ok(())
+}
+
+fn crl(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let since: i32 = matches
+        .value_of("since")
+        .unwrap_or_default()
+        .parse()
+        .context("Serial must be a number.")?;
+
+    let account = Account::load_locked(&username, connection)
+        .context(format!("Account {} not found.", &username))?;
+
+    let list = CertificateRevocation::load_list(&account.public_key, since, connection)
+        .context("Could not load revocation list.")?;
+
+    println!("{}", serde_json::to_string_pretty(&list)?);
 
     Ok(())
 }
@@ -164,21 +742,31 @@ fn change_password(matches: &ArgMatches, connection: &MyConnection) -> Result<()
         None => get_input("Account name: "),
     };
 
-    let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
-        None => get_password("Current password: "),
+    let unlocked_account = match matches.value_of("slot") {
+        Some(label) => {
+            let secret = slot_secret(matches, "Key slot secret: ")?;
+
+            Account::load_unlocked_with_slot(&username, label, &secret, connection)
+                .context("Username and key slot secret not recognized.")?
+        }
+        None => {
+            let password = match matches.value_of("password") {
+                Some(p) => SecretString::new(p.to_owned()),
+                None => get_password("Current password: "),
+            };
+
+            Account::load_unlocked(username.clone(), password, connection)
+                .context("Username and password not recognized.")?
+        }
     };
 
     let new_password = match matches.value_of("newpassword") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_new_password("New account password: ", "Reenter new password: "),
     };
 
     let name = username.clone();
 
-    let unlocked_account = Account::load_unlocked(username, password, &connection)
-        .context("Username and password not recognized.")?;
-
     unlocked_account.change_password(&new_password, &connection)
         .context("Could not change password.")?;
 
@@ -200,16 +788,31 @@ fn add(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_new_password("New account password: ", "Reenter password: "),
     };
 
     let export_key = match matches.value_of("exportkey") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_new_password("New account export key: ", "Reenter export key: "),
     };
 
-    let account = Account::new(&username, &email, &password, &export_key, false);
+    let account = match matches.value_of("identity") {
+        Some(path) => {
+            let key_file = SecretString::new(
+                std::fs::read_to_string(path).context(format!("Could not read key file {}.", path))?,
+            );
+
+            NewAccount::from_key_file(&username, &email, &password, &export_key, &key_file, false)
+                .context("Could not read an Ed25519 private key from the key file.")?
+        }
+        None => {
+            let algorithm: KeyAlgorithm = matches.value_of("algorithm").unwrap_or("ed25519").parse()?;
+            let signing_key = SigningKey::generate(algorithm)?;
+
+            NewAccount::with_key(&username, &email, &password, &export_key, signing_key, false)
+        }
+    };
 
     account.save(&connection)?;
 
@@ -217,3 +820,358 @@ fn add(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     
     Ok(())
 }
+
+fn add_slot(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let label = matches.value_of("label").unwrap_or_default();
+
+    let slot_type: KeySlotType = matches
+        .value_of("type")
+        .unwrap_or_default()
+        .parse()
+        .context("Unknown key slot type.")?;
+
+    let account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
+
+    let (secret, generated) = match slot_type {
+        KeySlotType::Password => match matches.value_of("secret") {
+            Some(s) => (SecretBytes::new(s.as_bytes().to_vec()), None),
+            None => {
+                let secret = get_new_password("Key slot password: ", "Reenter key slot password: ");
+                (SecretBytes::new(secret.as_bytes().to_vec()), None)
+            }
+        },
+        KeySlotType::KeyFile => match matches.value_of("keyfile") {
+            Some(_) => (slot_secret(matches, "")?, None),
+            None => bail!("A key_file slot needs --keyfile."),
+        },
+        KeySlotType::RecoveryCode | KeySlotType::Device => {
+            let secret = AccountKeySlot::generate_secret(slot_type);
+            (SecretBytes::new(secret.as_bytes().to_vec()), Some(secret))
+        }
+        KeySlotType::Shamir => bail!("Recovery shares are set up with account shares."),
+    };
+
+    AccountKeySlot::create(&account, label, slot_type, &secret)?
+        .save(connection)
+        .context(format!("Could not add key slot {}.", label))?;
+
+    println!("Key slot {} added to account {}.", label, &account.name);
+
+    if let Some(generated) = generated {
+        println!("Store this {} somewhere safe, it is not shown again:", slot_type.as_str());
+        println!("{}", &*generated);
+    }
+
+    Ok(())
+}
+
+fn list_slots(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
+
+    for slot in AccountKeySlot::load_all_for_account(account.id, connection)? {
+        println!("{}\t{}", slot.label, slot.slot_type);
+    }
+
+    Ok(())
+}
+
+fn remove_slot(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let label = matches.value_of("label").unwrap_or_default();
+
+    let account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
+
+    AccountKeySlot::delete(account.id, label, connection)
+        .context(format!("Could not remove key slot {}.", label))?;
+
+    println!("Key slot {} removed from account {}.", label, &account.name);
+
+    Ok(())
+}
+
+fn shares(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let threshold: u8 = matches
+        .value_of("threshold")
+        .unwrap_or_default()
+        .parse()
+        .context("Threshold must be a number from 2 to 255.")?;
+
+    let count: u8 = matches
+        .value_of("count")
+        .unwrap_or_default()
+        .parse()
+        .context("Count must be a number from 2 to 255.")?;
+
+    let account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
+
+    let shares = AccountKeySlot::split_recovery(&account, threshold, count, connection)
+        .context("Could not create recovery shares.")?;
+
+    println!("Any {} of these {} shares recover account {}. Give them to different people, they are not shown again:", threshold, count, &account.name);
+
+    for share in shares {
+        println!("{}", share);
+    }
+
+    Ok(())
+}
+
+fn recover(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let texts: Vec<SecretString> = match matches.values_of("share") {
+        Some(values) => values.map(|share| SecretString::new(share.to_owned())).collect(),
+        None => {
+            let mut texts = Vec::new();
+
+            loop {
+                let share = get_password("Recovery share (empty when done): ");
+
+                if share.trim().is_empty() {
+                    break;
+                }
+
+                texts.push(share);
+            }
+
+            texts
+        }
+    };
+
+    let mut shares = Vec::new();
+
+    for (number, text) in texts.iter().enumerate() {
+        shares.push(text.parse::<Share>().context(format!("Share {} is not valid.", number + 1))?);
+    }
+
+    let new_password = match matches.value_of("newpassword") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_new_password("New account password: ", "Reenter new password: "),
+    };
+
+    Account::recover(&username, &shares, &new_password, connection)
+        .context("Could not recover the account with these shares.")?;
+
+    println!("Account {} recovered, password changed.", &username);
+    println!("These shares no longer work, create new ones with account shares.");
+
+    Ok(())
+}
+
+fn recovery_kit(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
+
+    let export_key = match matches.value_of("exportkey") {
+        Some(x) => SecretString::new(x.to_owned()),
+        None => get_password("Export key: "),
+    };
+
+    let kit = account.recovery_kit(export_key)
+        .context("Export key not recognized.")?;
+
+    print!("{}", &*kit.to_text());
+
+    Ok(())
+}
+
+fn change_export_key(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let export_key = match matches.value_of("exportkey") {
+        Some(x) => SecretString::new(x.to_owned()),
+        None => get_password("Current export key: "),
+    };
+
+    let mut account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
+
+    let new_export_key = account.replace_export_key(&export_key, connection)
+        .context("Export key not recognized.")?;
+
+    println!("New export key: {}", &*new_export_key);
+    println!("Earlier recovery kits hold the old export key, print a new one.");
+
+    Ok(())
+}
+
+fn export_key(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let format: KeyFormat = matches.value_of("format").unwrap_or("pem").parse()?;
+
+    let account = Account::load_unlocked(username, password, connection)
+        .context("Username and password not recognized.")?;
+
+    let export_key = if matches.is_present("private") {
+        Some(match matches.value_of("exportkey") {
+            Some(x) => SecretString::new(x.to_owned()),
+            None => get_password("Export key: "),
+        })
+    } else {
+        None
+    };
+
+    let text = match (matches.value_of("code"), matches.value_of("write")) {
+        (Some(code), Some(write)) => {
+            let application = Application::load_by_code(code, &account, connection)
+                .context(format!("Application {} not found.", code))?;
+            let scope = WriteScope::load_unlocked(&[write.to_owned()], &account, &application, connection)?
+                .pop()
+                .context(format!("Write Scope {} not found.", write))?;
+
+            match export_key {
+                Some(x) => scope.export_private_key(&account, &x, format),
+                None => scope.export_public_key(format).map(SecretString::new),
+            }
+        }
+        _ => match export_key {
+            Some(x) => account.export_private_key(&x, format),
+            None => account.export_public_key(format).map(SecretString::new),
+        },
+    }
+    .context("Could not export the key.")?;
+
+    print!("{}", &*text);
+
+    Ok(())
+}
+
+fn restore(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+
+    let username = match matches.value_of("username") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let email = match matches.value_of("email") {
+        Some(u) => u.to_owned(),
+        None => get_input("Email Address for the account: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_new_password("New account password: ", "Reenter password: "),
+    };
+
+    let export_key = match matches.value_of("exportkey") {
+        Some(x) => SecretString::new(x.to_owned()),
+        None => get_password("Export key: "),
+    };
+
+    let mnemonic = match matches.value_of("mnemonic") {
+        Some(m) => SecretString::new(m.to_owned()),
+        None => get_password("Mnemonic: "),
+    };
+
+    let algorithm: KeyAlgorithm = matches.value_of("algorithm").unwrap_or("ed25519").parse()?;
+
+    let account = RecoveryKit::restore(
+        &username,
+        &email,
+        &password,
+        &export_key,
+        algorithm,
+        &mnemonic,
+        matches.value_of("fingerprint"),
+    )
+    .context("Could not restore the signing key from the mnemonic.")?;
+
+    account.save(connection)
+        .context(format!("Could not save account {}.", &username))?;
+
+    println!("Account \"{}\" restored with fingerprint {}.", username, fingerprint(&account.public_key));
+
+    Ok(())
+}
+
+// The secret that unlocks a key slot, read from the key file if one is given.
+fn slot_secret(matches: &ArgMatches, message: &str) -> Result<SecretBytes> {
+    Ok(match (matches.value_of("keyfile"), matches.value_of("secret")) {
+        (Some(path), _) => SecretBytes::new(
+            std::fs::read(path).context(format!("Could not read key file {}.", path))?,
+        ),
+        (None, Some(s)) => SecretBytes::new(s.as_bytes().to_vec()),
+        (None, None) => SecretBytes::new(get_password(message).as_bytes().to_vec()),
+    })
+}
@@ -0,0 +1,113 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use super::{get_new_password, get_password};
+use crate::database::establish_connection;
+use crate::database::MyConnection;
+use crate::encryption::secret::SecretString;
+use crate::model::backup::Backup;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{stdin, stdout, BufReader, BufWriter, Write};
+
+pub fn init() -> App<'static, 'static> {
+    SubCommand::with_name("admin")
+        .about("Server administration")
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about("Back up every table to an encrypted archive")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("The file to write, standard output if not given.")
+                        .value_name("FILENAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("passphrase")
+                        .short("P")
+                        .long("passphrase")
+                        .help("Used to encrypt the archive.")
+                        .value_name("PASSPHRASE")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("restore")
+                .about("Restore an archive into an empty database")
+                .arg(
+                    Arg::with_name("file")
+                        .short("f")
+                        .long("file")
+                        .help("The archive to restore, standard input if not given.")
+                        .value_name("FILENAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("passphrase")
+                        .short("P")
+                        .long("passphrase")
+                        .help("The passphrase the archive was encrypted with.")
+                        .value_name("PASSPHRASE")
+                        .takes_value(true),
+                ),
+        )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<()> {
+    let connection = establish_connection()?;
+
+    match matches.subcommand() {
+        ("backup", Some(m))  => backup(m, &connection),
+        ("restore", Some(m)) => restore(m, &connection),
+        (c, _)               => bail!("Subcommand {} not recognized.", c),
+    }
+}
+
+fn backup(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+    let passphrase = match matches.value_of("passphrase") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_new_password(
+            "Passphrase with which to encrypt the backup: ",
+            "Reenter encryption passphrase: ",
+        ),
+    };
+
+    // The summary goes to standard error when the archive goes to standard
+    // output.
+    match matches.value_of("output") {
+        Some(f) => {
+            let mut output = BufWriter::new(File::create(f).context(format!("Could not create {}.", f))?);
+            let summary = Backup::write(&mut output, &passphrase, connection).context("Backup failed.")?;
+            print!("{}", summary);
+            println!("Backed up {} records to {}.", summary.total(), f);
+        }
+        None => {
+            let summary = Backup::write(&mut stdout().lock(), &passphrase, connection).context("Backup failed.")?;
+            eprint!("{}", summary);
+        }
+    }
+
+    stdout().flush()?;
+    Ok(())
+}
+
+fn restore(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+    let passphrase = match matches.value_of("passphrase") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Backup passphrase: "),
+    };
+
+    let summary = match matches.value_of("file") {
+        Some(f) => {
+            let mut input = BufReader::new(File::open(f).context(format!("Could not read {}.", f))?);
+            Backup::restore(&mut input, &passphrase, connection)
+        }
+        None => Backup::restore(&mut stdin().lock(), &passphrase, connection),
+    }
+    .context("Restore failed.")?;
+
+    print!("{}", summary);
+    println!("Restored {} records.", summary.total());
+
+    Ok(())
+}
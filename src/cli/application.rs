@@ -1,7 +1,11 @@
 use clap::{App, Arg, ArgMatches, SubCommand};
 use super::{get_input, get_password};
+use crate::encryption::secret::SecretString;
 use crate::database::establish_connection;
 use crate::database::MyConnection;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::exchange_key::ExchangeKey;
+use crate::encryption::signing_key::SigningKey;
 use crate::model::account::Account;
 use crate::model::application::Application;
 use crate::model::write_scope::WriteScope;
@@ -133,6 +137,24 @@ pub fn init() -> App<'static, 'static> {
                         .multiple(true)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("write_algorithm")
+                        .long("write_algorithm")
+                        .help("The signing key algorithm for new write scopes.")
+                        .value_name("ALGORITHM")
+                        .possible_values(&["ed25519", "p256"])
+                        .default_value("ed25519")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("read_algorithm")
+                        .long("read_algorithm")
+                        .help("The exchange key algorithm for new read scopes.")
+                        .value_name("ALGORITHM")
+                        .possible_values(&["x25519", "p256", "x25519_mlkem768"])
+                        .default_value("x25519")
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("delete")
                         .short("d")
@@ -146,6 +168,50 @@ pub fn init() -> App<'static, 'static> {
                         .help("Delete without confirmation"),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("rotate")
+                .about("Rotate scope keys, re-authorizing existing clients.")
+                .arg(
+                    Arg::with_name("account_name")
+                        .short("a")
+                        .long("account_name")
+                        .help("The account name for which to rotate keys.")
+                        .value_name("ACCOUNT")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("password")
+                        .short("p")
+                        .long("password")
+                        .help("The account password.")
+                        .value_name("PASSWORD")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("code")
+                        .short("c")
+                        .long("code")
+                        .help("The application code.")
+                        .value_name("CODE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("write")
+                        .short("w")
+                        .long("write")
+                        .help("Write scope code, can be used multiple times.")
+                        .multiple(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("read")
+                        .short("r")
+                        .long("read")
+                        .help("Read scope code, can be used multiple times.")
+                        .multiple(true)
+                        .takes_value(true),
+                ),
+        )
 }
 
 pub fn run(matches: &ArgMatches) -> Result<()> {
@@ -155,6 +221,7 @@ pub fn run(matches: &ArgMatches) -> Result<()> {
         ("add", Some(m))     => add(m, &connection),
         ("scope", Some(m))   => scope(m, &connection),
         ("delete", Some(m))  => delete(m, &connection),
+        ("rotate", Some(m))  => rotate(m, &connection),
         ("list", _)          => list(&connection),
         (c, _)               => bail!("Subcommand {} not recognized.", c),
     }
@@ -182,7 +249,7 @@ fn scope(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_password("Password: "),
     };
 
@@ -193,6 +260,8 @@ fn scope(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
 
     let write_scope_codes = matches.values_of_lossy("write");
     let read_scope_codes = matches.values_of_lossy("read");
+    let write_algorithm: KeyAlgorithm = matches.value_of("write_algorithm").unwrap_or("ed25519").parse()?;
+    let read_algorithm: KeyAlgorithm = matches.value_of("read_algorithm").unwrap_or("x25519").parse()?;
 
     let account = Account::load_unlocked(account_name, password, &connection)
         .expect("Could not load account");
@@ -217,7 +286,7 @@ fn scope(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
                 .context("Could not load scopes.")?;
             for scope in db_scopes {
                 let scope_code = scope.code.clone();
-                scope.delete(&connection).context(format!("Could not delete scope {}", scope_code))?;
+                scope.delete(&account, connection).context(format!("Could not delete scope {}", scope_code))?;
                 println!("Write scope {} deleted successfully.", scope_code);
             }
         }
@@ -228,7 +297,7 @@ fn scope(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
                 .context("Could not load scopes.")?;
             for scope in db_scopes {
                 let scope_code = scope.code.clone();
-                scope.delete(&connection).context(format!("Could not delete scope {}", scope_code))?;
+                scope.delete(&account, connection).context(format!("Could not delete scope {}", scope_code))?;
                 println!("Read scope {} deleted successfully.", scope_code);
             }
         }
@@ -236,7 +305,8 @@ fn scope(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
         // create named write scopes
         if let Some(scope_codes) = write_scope_codes {
             for scope_code in scope_codes {
-                let scope = WriteScope::new(&scope_code, &application, &account);
+                let signing_key = SigningKey::generate(write_algorithm)?;
+                let scope = WriteScope::with_key(&scope_code, &application, &account, signing_key);
                 match scope.save(&connection) {
                     Ok(s) => {
                         println!(
@@ -265,7 +335,8 @@ fn scope(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
                         let unlocked_scope = s.to_unlocked(&account, &connection)
                             .context("Could not unlock ReadScope.")?;
 
-                        let key = ReadGrantKey::new(&unlocked_scope, &account);
+                        let exchange_key = ExchangeKey::generate(read_algorithm)?;
+                        let key = ReadGrantKey::with_key(&unlocked_scope, &account, exchange_key);
 
                         match key.save(&connection) {
                             Ok(_) => println!(
@@ -287,6 +358,77 @@ fn scope(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     Ok(())
 }
 
+fn rotate(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
+    let account_name = match matches.value_of("account_name") {
+        Some(u) => u.to_owned(),
+        None => get_input("Account name: "),
+    };
+
+    let password = match matches.value_of("password") {
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Password: "),
+    };
+
+    let application_code = match matches.value_of("code") {
+        Some(code) => code.to_owned(),
+        None => get_input("Application code: "),
+    };
+
+    let write_scope_codes = matches.values_of_lossy("write");
+    let read_scope_codes = matches.values_of_lossy("read");
+
+    if write_scope_codes.is_none() && read_scope_codes.is_none() {
+        bail!("No scopes to rotate.");
+    }
+
+    let account = Account::load_unlocked(account_name, password, connection)
+        .context("Account and password not recognized.")?;
+
+    let application = Application::load_by_code(&application_code, &account, connection)
+        .context(format!("Could not load application {}.", &application_code))?;
+
+    let write_scopes = WriteScope::load_unlocked(
+        &write_scope_codes.unwrap_or_default(),
+        &account,
+        &application,
+        connection,
+        )
+        .context("Could not load scopes.")?;
+
+    for scope in write_scopes {
+        let (_, clients) = scope
+            .rotate_key(&account, connection)
+            .context(format!("Could not rotate key for write scope {}", scope.code))?;
+
+        println!(
+            "Write Scope {} key rotated, {} clients re-authorized.",
+            scope.code, clients
+            );
+    }
+
+    let read_scopes = ReadScope::load_codes(
+        read_scope_codes.unwrap_or_default(),
+        &account,
+        &application,
+        connection,
+        )
+        .context("Could not load scopes.")?;
+
+    for scope in read_scopes {
+        let (_, clients) = scope
+            .to_unlocked(&account, connection)
+            .and_then(|s| s.rotate_key(&account, connection))
+            .context(format!("Could not rotate key for read scope {}", scope.code))?;
+
+        println!(
+            "Read Scope {} key rotated, {} clients re-authorized.",
+            scope.code, clients
+            );
+    }
+
+    Ok(())
+}
+
 fn delete(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     let account_name = match matches.value_of("account_name") {
         Some(u) => u.to_owned(),
@@ -294,7 +436,7 @@ fn delete(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_password("Account password: "),
     };
 
@@ -317,7 +459,7 @@ fn delete(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
 
     Application::load_by_code(&application_code, &account, &connection)
         .context(format!("Could not locate record {}.", &application_code))?
-        .delete(&connection)
+        .delete(&account, connection)
         .context(format!("Could not delete {}.", &application_code))?;
 
     println!("Application {} deleted successfully.", &application_code);
@@ -332,7 +474,7 @@ fn add(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_password("Account password: "),
     };
 
@@ -1,5 +1,6 @@
 use base64::{decode, encode};
 use clap::{App, Arg, ArgMatches, SubCommand};
+use crate::encryption::secret::SecretString;
 use crate::cli::{get_input, get_password};
 use crate::database::establish_connection;
 use crate::database::MyConnection;
@@ -145,7 +146,7 @@ fn revoke(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_password("Account password: "),
     };
 
@@ -214,7 +215,7 @@ fn add(matches: &ArgMatches, connection: &MyConnection) -> Result<()> {
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_password("Account password: "),
     };
 
@@ -1,91 +1,107 @@
-use database::establish_connection;
 use clap::{App, Arg, ArgMatches, SubCommand};
-use cli::{get_input, get_password, get_new_password};
-use model::account::NewAccount;
-use serde_json;
+use super::{get_input, get_new_password, get_password};
+use crate::database::establish_connection;
+use crate::encryption::secret::SecretString;
+use crate::model::account::{Account, PortableFormat};
+use anyhow::{Context, Result};
 use std::fs;
+use std::io::{stdout, Write};
 
 pub fn init() -> App<'static, 'static> {
     SubCommand::with_name("export")
-     .about("Exports key files.")
-     .arg(Arg::with_name("output")
-          .short("o")
-          .long("output")
-          .help("Sets the output file to create")
-          .value_name("FILENAME")
-          .takes_value(true)
-          )
-      .arg(Arg::with_name("username")
-           .short("u")
-           .long("username")
-           .help("The account name to export.")
-           .value_name("USERNAME")
-           .takes_value(true)
-      )
-      .arg(Arg::with_name("password")
-           .short("p")
-           .long("password")
-           .help("The account's password.")
-           .value_name("PASSWORD")
-           .takes_value(true)
-          )
-      .arg(Arg::with_name("passphrase")
-           .short("P")
-           .long("passphrase")
-           .help("Used to encrypt the keyfile.")
-           .value_name("PASSPHRASE")
-           .takes_value(true)
-          )
+        .about("Export an account with its applications, scopes and clients")
+        .arg(
+            Arg::with_name("username")
+                .short("a")
+                .long("username")
+                .help("The account name to export.")
+                .value_name("USERNAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("password")
+                .short("p")
+                .long("password")
+                .help("The account's password.")
+                .value_name("PASSWORD")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exportkey")
+                .short("x")
+                .long("exportkey")
+                .help("Required to release an encrypted export of the account's keys.")
+                .value_name("EXPORT_KEY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("passphrase")
+                .short("P")
+                .long("passphrase")
+                .help("Used to encrypt the keys in the export.")
+                .value_name("PASSPHRASE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("The file to write, standard output if not given.")
+                .value_name("FILENAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("t")
+                .long("format")
+                .help("The export format.")
+                .value_name("FORMAT")
+                .possible_values(&["json", "cbor"])
+                .default_value("json")
+                .takes_value(true),
+        )
 }
 
-pub fn run(matches: &ArgMatches) {
+pub fn run(matches: &ArgMatches) -> Result<()> {
+    let connection = establish_connection()?;
 
     let username = match matches.value_of("username") {
         Some(u) => u.to_owned(),
-        None => get_input("Username: "),
+        None => get_input("Account name: "),
     };
 
     let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
-        None => get_password(
-            &format!("Enter password for {}: ", &username)
-            ),
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password(&format!("Enter password for {}: ", &username)),
     };
 
     let export_key = match matches.value_of("exportkey") {
-        Some(p) => p.to_owned(),
-        None => get_password(
-            &format!("Enter export key for {}: ", &username)
-            ),
+        Some(x) => SecretString::new(x.to_owned()),
+        None => get_password(&format!("Enter export key for {}: ", &username)),
     };
 
     let passphrase = match matches.value_of("passphrase") {
-        Some(p) => p.to_owned(),
+        Some(p) => SecretString::new(p.to_owned()),
         None => get_new_password(
-            "Passphrase with which to encrypt keyfile: ",
-            "Reenter encryption passphrase: "
-            ),
+            "Passphrase with which to encrypt the export: ",
+            "Reenter encryption passphrase: ",
+        ),
     };
 
-    let connection = establish_connection().unwrap();
+    let format: PortableFormat = matches.value_of("format").unwrap_or("json").parse()?;
 
-    let account = UnlockedAccount::load(&username, &password, &connection);
+    let account = Account::load_unlocked(username, password, &connection)
+        .context("Username and password not recognized.")?;
 
-    let account = match account {
-        Ok(u) => u,
-        Err(_) => panic!("Account does not exist"),
-    };
+    let output = account
+        .to_portable(&export_key, &passphrase, &connection)
+        .context("Export key not recognized.")?
+        .serialize(format)?;
 
-    match account.encode_key(&export_key, &passphrase){
-        Ok(k) => {
-            let output = serde_json::to_string(&k).expect("Key encoded improperly.");
-            match matches.value_of("output") {
-                Some(f) => {
-                    fs::write(&f, output).expect("Could not write data to file.");
-                },
-                None => println!("{}", &output), 
-            }
-        },
-        Err(_) => eprintln!("Release key invalid."),
-    };
+    match matches.value_of("output") {
+        Some(f) => fs::write(f, &output).context(format!("Could not write {}.", f))?,
+        None => stdout().write_all(&output)?,
+    }
+
+    Ok(())
 }
@@ -1,101 +1,163 @@
-use database::establish_connection;
 use clap::{App, Arg, ArgMatches, SubCommand};
-use cli::{get_input, get_password, get_new_password};
-use model::account::NewAccount;
-use model::exported_key::ExportedKey;
-use serde_json;
+use super::{get_input, get_new_password, get_password};
+use crate::database::establish_connection;
+use crate::encryption::secret::SecretString;
+use crate::model::account::{Account, NewAccount, PortableAccount, PortableFormat};
+use crate::model::account_import::{ConflictStrategy, ImportOptions, ImportTarget};
+use anyhow::{Context, Result};
 use std::fs;
-use std::io::{self, Read};
+use std::io::{stdin, Read};
 
 pub fn init() -> App<'static, 'static> {
     SubCommand::with_name("import")
-     .about("Import key files.")
-     .arg(Arg::with_name("file")
-          .short("f")
-          .long("file")
-          .help("Sets the file to import")
-          .value_name("FILENAME")
-          .takes_value(true)
-          )
-      .arg(Arg::with_name("username")
-           .short("u")
-           .long("username")
-           .help("The account to import.")
-           .value_name("USERNAME")
-           .takes_value(true)
-      )
-      .arg(Arg::with_name("password")
-           .short("p")
-           .long("password")
-           .help("The account's new password.")
-           .value_name("PASSWORD")
-           .takes_value(true)
-          )
-      .arg(Arg::with_name("exportkey")
-           .short("x")
-           .long("exportkey")
-           .help("Required to release an encrypted export of the account's keys.")
-           .value_name("EXPORT_KEY")
-           .takes_value(true)
-      )
-      .arg(Arg::with_name("passphrase")
-           .short("P")
-           .long("passphrase")
-           .help("Used to decrypt the keyfile.")
-           .value_name("PASSPHRASE")
-           .takes_value(true)
-          )
+        .about("Import an exported account, creating it or merging into an existing account")
+        .arg(
+            Arg::with_name("username")
+                .short("a")
+                .long("username")
+                .help("The account to import into, created if it does not exist.")
+                .value_name("USERNAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("email")
+                .short("e")
+                .long("email")
+                .help("Email address for a new account.")
+                .value_name("EMAIL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("password")
+                .short("p")
+                .long("password")
+                .help("The account's password, or the password for a new account.")
+                .value_name("PASSWORD")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exportkey")
+                .short("x")
+                .long("exportkey")
+                .help("The export key for a new account.")
+                .value_name("EXPORT_KEY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("passphrase")
+                .short("P")
+                .long("passphrase")
+                .help("The passphrase the export was encrypted with.")
+                .value_name("PASSPHRASE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("file")
+                .short("f")
+                .long("file")
+                .help("The file to import, standard input if not given.")
+                .value_name("FILENAME")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("t")
+                .long("format")
+                .help("The export format.")
+                .value_name("FORMAT")
+                .possible_values(&["json", "cbor"])
+                .default_value("json")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strategy")
+                .short("s")
+                .long("strategy")
+                .help("What to do with a scope that exists with other keys.")
+                .value_name("STRATEGY")
+                .possible_values(&["skip", "overwrite", "rename"])
+                .default_value("skip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dryrun")
+                .short("n")
+                .long("dry-run")
+                .help("Report what would be imported without saving anything."),
+        )
 }
 
-pub fn run(matches: &ArgMatches) {
+pub fn run(matches: &ArgMatches) -> Result<()> {
+    let connection = establish_connection()?;
 
     let username = match matches.value_of("username") {
         Some(u) => u.to_owned(),
-        None => get_input("Username: "),
+        None => get_input("Account name: "),
     };
 
-    let password = match matches.value_of("password") {
-        Some(p) => p.to_owned(),
-        None => get_new_password(
-            &format!("Enter new password for {}: ", &username),
-            "Reenter new password: "
-            ),
+    let format: PortableFormat = matches.value_of("format").unwrap_or("json").parse()?;
+    let options = ImportOptions {
+        strategy: matches.value_of("strategy").unwrap_or("skip").parse::<ConflictStrategy>()?,
+        dry_run: matches.is_present("dryrun"),
     };
 
-    let export_key = match matches.value_of("exportkey") {
-        Some(p) => p.to_owned(),
-        None => get_new_password(
-            &format!("Enter export key for {}: ", &username),
-            "Reenter export key: "
-            ),
-    };
+    let mut input = Vec::new();
+
+    match matches.value_of("file") {
+        Some(f) => {
+            fs::File::open(f)
+                .and_then(|mut file| file.read_to_end(&mut input))
+                .context(format!("Could not read {}.", f))?;
+        }
+        None => {
+            stdin().read_to_end(&mut input).context("Could not read standard input.")?;
+        }
+    }
+
+    let bundle = PortableAccount::deserialize(format, &input).context("Could not parse the export.")?;
 
     let passphrase = match matches.value_of("passphrase") {
-        Some(p) => p.to_owned(),
-        None => get_password( "Passphrase with which to decrypt keyfile: "),
+        Some(p) => SecretString::new(p.to_owned()),
+        None => get_password("Passphrase with which to decrypt the export: "),
     };
 
-    let input: ExportedKey = match matches.value_of("file") {
-        Some(f) => {
-            let mut file = fs::File::open(f).expect("Unable to open the file");
-            let mut buffer = String::new();
-            file.read_to_string(&mut buffer).expect("Could not read file.");
-            serde_json::from_str(&buffer).expect("Could not parse json.")
-        },
-        None => {
-            let mut buffer = String::new();
-            io::stdin().read_to_string(&mut buffer).expect("Could not read stdin.");
-            serde_json::from_str(&buffer).expect("could not parse json.")
-        },
+    let target = if Account::load_locked(&username, &connection).is_ok() {
+        let password = match matches.value_of("password") {
+            Some(p) => SecretString::new(p.to_owned()),
+            None => get_password(&format!("Enter password for {}: ", &username)),
+        };
+
+        ImportTarget::Existing(
+            Account::load_unlocked(username, password, &connection)
+                .context("Username and password not recognized.")?,
+        )
+    } else {
+        let email = match matches.value_of("email") {
+            Some(e) => e.to_owned(),
+            None => get_input("Email Address for the account: "),
+        };
+
+        let password = match matches.value_of("password") {
+            Some(p) => SecretString::new(p.to_owned()),
+            None => get_new_password("New account password: ", "Reenter password: "),
+        };
+
+        let export_key = match matches.value_of("exportkey") {
+            Some(x) => SecretString::new(x.to_owned()),
+            None => get_new_password("New account export key: ", "Reenter export key: "),
+        };
+
+        let account = NewAccount::from_portable(&username, &email, &password, &export_key, &passphrase, &bundle)
+            .context("Passphrase does not open the export.")?;
+
+        ImportTarget::New { account, password }
     };
 
-    let mut account = NewAccount::new(&username, &password, &export_key, false);
-        
-    let connection = establish_connection().unwrap();
+    let report = bundle
+        .import(target, &passphrase, options, &connection)
+        .context("Could not import the export.")?;
 
-    match account.save(&connection) {
-        Ok(_) => println!("NewAccount \"{}\" created successfully.", username),
-        Err(e) => eprintln!("Could not save new account. Error \"{}\"", e),
-    }
-}
+    print!("{}", report);
 
+    Ok(())
+}
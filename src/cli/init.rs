@@ -3,6 +3,7 @@ use clap::{App, SubCommand};
 use crate::cli::{get_input, get_new_password};
 use crate::database::establish_connection;
 use crate::encryption::random_int_256;
+use crate::encryption::secret::SecretString;
 use crate::model::account::Account;
 use std::fs::OpenOptions;
 use std::io::{BufRead, BufReader, Write};
@@ -28,19 +29,33 @@ pub fn run() -> Result<()> {
     set_env_variable("DATABASE_URL", database_url.as_str());
     // TODO set same value in Rocket.toml
 
+    let server_url = get_input("Public url of this server: ");
+    set_env_variable("SERVER_URL", server_url.as_str());
+
+    // The server identity is kept when init runs again, other servers and
+    // migrated accounts know the server by it.
+    if dotenv::var("SERVER_KEY").is_err() {
+        set_env_variable("SERVER_KEY", &encode(random_int_256()));
+    }
+
     let admin_user_name = get_input("Administrator User Name: ");
     let email = get_input("Administrator Email: ");
     let password = get_new_password(
         "Administrator User Password: ",
         "Reenter Admin User Password: ",
     );
-    let export_key = encode(&random_int_256());
+    let export_key = SecretString::new(encode(random_int_256()));
 
     let account = Account::new(&admin_user_name, &email, &password, &export_key, true);
 
     let connection = establish_connection()?;
 
-    account.save(&connection).context("Could not save new account")?;
+    let kit = account.save(&connection).context("Could not save new account")?
+        .to_unlocked(&password)?
+        .recovery_kit(export_key)?;
+
+    println!("Print or write down this recovery kit and keep it somewhere safe, it is not shown again.");
+    print!("{}", &*kit.to_text());
 
     Ok(())
 }
@@ -55,16 +70,22 @@ fn set_env_variable(variable: &str, value: &str) {
             Ok(f) => {
                 let file = BufReader::new(&f);
                 let mut rewrite = String::new();
+                let mut found = false;
 
                 for l in file.lines() {
                     let line = l.unwrap();
-                    if line.starts_with(variable) {
+                    if line.starts_with(&format!("{}=", variable)) {
                         rewrite.push_str(&format!("{}={}\n", variable, value));
+                        found = true;
                     } else {
                         rewrite.push_str(&format!("{}\n", line));
                     }
                 }
 
+                if !found {
+                    rewrite.push_str(&format!("{}={}\n", variable, value));
+                }
+
                 rewrite
             }
             _ => format!("{}={}\n", variable, value),
@@ -1,12 +1,14 @@
 pub mod account;
+pub mod admin;
 pub mod application;
 pub mod client;
 pub mod init;
-//pub mod export;
-//pub mod import;
+pub mod export;
+pub mod import;
 //pub mod scope;
 //pub mod sign;
 
+use crate::encryption::secret::SecretString;
 use std::io::{stdin, stdout, Write};
 
 pub fn get_input(message: &str) -> String {
@@ -30,13 +32,13 @@ pub fn get_input(message: &str) -> String {
     input_string
 }
 
-pub fn get_new_password(message: &str, reenter_message: &str) -> String {
-    let mut pass: String;
-    let mut pass2: String;
+pub fn get_new_password(message: &str, reenter_message: &str) -> SecretString {
+    let mut pass: SecretString;
+    let mut pass2: SecretString;
 
     loop {
-        pass = rpassword::prompt_password_stdout(message).unwrap();
-        pass2 = rpassword::prompt_password_stdout(reenter_message).unwrap();
+        pass = SecretString::new(rpassword::prompt_password_stdout(message).unwrap());
+        pass2 = SecretString::new(rpassword::prompt_password_stdout(reenter_message).unwrap());
 
         if pass == pass2 {
             break;
@@ -48,6 +50,6 @@ pub fn get_new_password(message: &str, reenter_message: &str) -> String {
     pass
 }
 
-pub fn get_password(message: &str) -> String {
-    rpassword::prompt_password_stdout(message).unwrap()
+pub fn get_password(message: &str) -> SecretString {
+    SecretString::new(rpassword::prompt_password_stdout(message).unwrap())
 }
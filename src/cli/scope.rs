@@ -76,7 +76,7 @@ pub fn run(matches: &ArgMatches) {
         };
 
         let password = match matches.value_of("password") {
-            Some(p) => p.to_owned(),
+            Some(p) => SecretString::new(p.to_owned()),
             None => get_password("User password: "),
         };
 
@@ -27,3 +27,23 @@ pub fn establish_connection() -> Result<MyConnection, ConnectionError> {
 pub fn can_connect_to_url(database_url: &str) -> bool {
     MyConnection::establish(&database_url).is_ok()
 }
+
+// A second database next to DATABASE_URL for tests that need one of their
+// own, created and migrated on first use.
+#[cfg(test)]
+pub fn test_database(name: &str) -> String {
+    let database_url = dotenv::var("DATABASE_URL").unwrap();
+    let (server, _) = database_url.rsplit_once('/').unwrap();
+    let test_url = format!("{}/{}", server, name);
+
+    if !can_connect_to_url(&test_url) {
+        diesel::sql_query(format!("CREATE DATABASE {}", name))
+            .execute(&establish_connection().unwrap())
+            .unwrap();
+    }
+
+    let connection = MyConnection::establish(&test_url).unwrap();
+    diesel_migrations::run_pending_migrations(&connection).unwrap();
+
+    test_url
+}
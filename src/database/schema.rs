@@ -10,6 +10,33 @@ table! {
         master_key_salt -> Bytea,
         encrypted_master_key -> Bytea,
         is_admin -> Bool,
+        master_key_kdf -> Varchar,
+        key_algorithm -> Int2,
+    }
+}
+
+table! {
+    account_key_slot (id) {
+        id -> Int4,
+        account_id -> Int4,
+        label -> Varchar,
+        slot_type -> Varchar,
+        salt -> Bytea,
+        kdf -> Varchar,
+        encrypted_master_key -> Bytea,
+    }
+}
+
+table! {
+    account_move (id) {
+        id -> Int4,
+        account_public_key -> Bytea,
+        account_key_algorithm -> Int2,
+        source_server_key -> Bytea,
+        destination_server_key -> Bytea,
+        destination_url -> Varchar,
+        move_date -> Timestamp,
+        signature -> Bytea,
     }
 }
 
@@ -24,11 +51,53 @@ table! {
     }
 }
 
+table! {
+    authorization_code (id) {
+        id -> Int4,
+        code_hash -> Bytea,
+        client_id -> Bytea,
+        encrypted_secret -> Bytea,
+        code_challenge -> Text,
+        redirect_uri -> Text,
+        scope -> Text,
+        expiration_date -> Timestamp,
+        id_token -> Nullable<Text>,
+    }
+}
+
+table! {
+    certificate_revocation (id) {
+        id -> Int4,
+        serial -> Int4,
+        public_key -> Bytea,
+        reason -> Varchar,
+        revocation_date -> Timestamp,
+        previous_hash -> Bytea,
+        signature -> Bytea,
+        account_public_key -> Bytea,
+        account_key_algorithm -> Int2,
+    }
+}
+
 table! {
     client (client_id) {
         client_id -> Bytea,
         application_id -> Int4,
         signature -> Bytea,
+        kem_public_key -> Nullable<Bytea>,
+    }
+}
+
+table! {
+    oauth_token (id) {
+        id -> Int4,
+        client_id -> Bytea,
+        access_token_hash -> Bytea,
+        refresh_token_hash -> Bytea,
+        read_grant_scope_ids -> Array<Int4>,
+        write_grant_scope_ids -> Array<Int4>,
+        expiration_date -> Timestamp,
+        refresh_expiration_date -> Timestamp,
     }
 }
 
@@ -39,6 +108,7 @@ table! {
         encrypted_access_key -> Bytea,
         public_key -> Bytea,
         signature -> Bytea,
+        encapsulated_key -> Nullable<Bytea>,
     }
 }
 
@@ -51,6 +121,10 @@ table! {
         private_key_salt -> Bytea,
         expiration_date -> Timestamp,
         signature -> Bytea,
+        key_version -> Int2,
+        decrypt_only -> Bool,
+        key_algorithm -> Int2,
+        certificate_version -> Int2,
     }
 }
 
@@ -87,11 +161,30 @@ table! {
         private_key_salt -> Bytea,
         expiration_date -> Timestamp,
         signature -> Bytea,
+        key_version -> Int2,
+        key_algorithm -> Int2,
+        certificate_version -> Int2,
+    }
+}
+
+table! {
+    write_scope_succession (id) {
+        id -> Int4,
+        write_grant_scope_id -> Int4,
+        previous_public_key -> Bytea,
+        public_key -> Bytea,
+        succession_date -> Timestamp,
+        previous_signature -> Bytea,
+        successor_signature -> Bytea,
+        signature -> Bytea,
     }
 }
 
+joinable!(account_key_slot -> account (account_id));
 joinable!(application -> account (account_id));
+joinable!(authorization_code -> client (client_id));
 joinable!(client -> application (application_id));
+joinable!(oauth_token -> client (client_id));
 joinable!(read_authorization -> client (client_id));
 joinable!(read_authorization -> read_grant_key (read_grant_key_id));
 joinable!(read_grant_key -> read_grant_scope (read_grant_scope_id));
@@ -99,14 +192,21 @@ joinable!(read_grant_scope -> application (application_id));
 joinable!(write_authorization -> client (client_id));
 joinable!(write_authorization -> write_grant_scope (write_grant_scope_id));
 joinable!(write_grant_scope -> application (application_id));
+joinable!(write_scope_succession -> write_grant_scope (write_grant_scope_id));
 
 allow_tables_to_appear_in_same_query!(
     account,
+    account_key_slot,
+    account_move,
     application,
+    authorization_code,
+    certificate_revocation,
     client,
+    oauth_token,
     read_authorization,
     read_grant_key,
     read_grant_scope,
     write_authorization,
     write_grant_scope,
+    write_scope_succession,
 );
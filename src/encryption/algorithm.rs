@@ -0,0 +1,108 @@
+use crate::error::{CommonError, CommonResult};
+use diesel::deserialize::{self, FromSql};
+use diesel::pg::Pg;
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::SmallInt;
+use std::io::Write;
+use std::str::FromStr;
+
+// Identifies the algorithm of a stored key. The id is what is stored in the
+// database and certificates, so existing ids must never be renumbered.
+//
+// P-256 keys are used for ECDSA signatures and ECDH exchange, and are stored
+// as compressed SEC1 points. X25519MlKem768 is a hybrid exchange key, an
+// X25519 public key followed by an ML-KEM-768 encapsulation key.
+#[derive(Clone, Copy, Debug, PartialEq, FromSqlRow, AsExpression)]
+#[sql_type = "SmallInt"]
+pub enum KeyAlgorithm {
+    Ed25519,
+    X25519,
+    P256,
+    X25519MlKem768,
+}
+
+impl KeyAlgorithm {
+    pub fn id(&self) -> i16 {
+        match self {
+            KeyAlgorithm::Ed25519 => 0,
+            KeyAlgorithm::X25519 => 1,
+            KeyAlgorithm::P256 => 2,
+            KeyAlgorithm::X25519MlKem768 => 3,
+        }
+    }
+
+    pub fn from_id(id: i16) -> CommonResult<KeyAlgorithm> {
+        match id {
+            0 => Ok(KeyAlgorithm::Ed25519),
+            1 => Ok(KeyAlgorithm::X25519),
+            2 => Ok(KeyAlgorithm::P256),
+            3 => Ok(KeyAlgorithm::X25519MlKem768),
+            _ => Err(CommonError::NotFound(Some(format!("Unknown key algorithm {}.", id)))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::X25519 => "x25519",
+            KeyAlgorithm::P256 => "p256",
+            KeyAlgorithm::X25519MlKem768 => "x25519_mlkem768",
+        }
+    }
+
+    pub fn can_sign(&self) -> bool {
+        *self == KeyAlgorithm::Ed25519 || *self == KeyAlgorithm::P256
+    }
+
+    pub fn can_exchange(&self) -> bool {
+        *self != KeyAlgorithm::Ed25519
+    }
+}
+
+impl ToSql<SmallInt, Pg> for KeyAlgorithm {
+    fn to_sql<W: Write>(&self, out: &mut Output<W, Pg>) -> serialize::Result {
+        ToSql::<SmallInt, Pg>::to_sql(&self.id(), out)
+    }
+}
+
+impl FromSql<SmallInt, Pg> for KeyAlgorithm {
+    fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<KeyAlgorithm> {
+        let id = <i16 as FromSql<SmallInt, Pg>>::from_sql(bytes)?;
+
+        KeyAlgorithm::from_id(id).map_err(|err| err.to_string().into())
+    }
+}
+
+impl FromStr for KeyAlgorithm {
+    type Err = CommonError;
+
+    fn from_str(name: &str) -> CommonResult<KeyAlgorithm> {
+        match name {
+            "ed25519" => Ok(KeyAlgorithm::Ed25519),
+            "x25519" => Ok(KeyAlgorithm::X25519),
+            "p256" => Ok(KeyAlgorithm::P256),
+            "x25519_mlkem768" => Ok(KeyAlgorithm::X25519MlKem768),
+            _ => Err(CommonError::NotFound(Some(format!("Unknown key algorithm {}.", name)))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algorithm_ids_round_trip() {
+        for algorithm in &[
+            KeyAlgorithm::Ed25519,
+            KeyAlgorithm::X25519,
+            KeyAlgorithm::P256,
+            KeyAlgorithm::X25519MlKem768,
+        ] {
+            assert_eq!(KeyAlgorithm::from_id(algorithm.id()).unwrap(), *algorithm);
+            assert_eq!(algorithm.name().parse::<KeyAlgorithm>().unwrap(), *algorithm);
+        }
+
+        assert!(KeyAlgorithm::from_id(4).is_err());
+    }
+}
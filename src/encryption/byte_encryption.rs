@@ -1,28 +1,176 @@
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use crate::encryption::rand::{self, Rng};
+use crate::encryption::secret::{SecretKey, Zeroizing};
 use crate::encryption::secure_hash;
 use crate::error::{CommonError, CommonResult};
-// Xor encrypt 32byte data by 32B key and append
-// 32B data hash for verification.
-pub fn encrypt_32(input: &[u8; 32], key: &[u8; 32]) -> [u8; 64] {
-    let check = secure_hash(&[input]);
-    let mut encrypted: [u8; 64] = [0u8; 64];
 
-    // xor and add verification hash
-    for i in 0..32 {
-        encrypted[i] = input[i] ^ key[i];
-        encrypted[i + 32] = check[i];
+// Envelope layout: version (1B) || nonce (24B) || ciphertext (32B) || tag (16B)
+pub const ENVELOPE_VERSION: u8 = 1;
+pub const NONCE_LENGTH: usize = 24;
+pub const TAG_LENGTH: usize = 16;
+pub const ENVELOPE_32_LENGTH: usize = 1 + NONCE_LENGTH + 32 + TAG_LENGTH;
+
+// Variable length data is encrypted as a stream of chunks.
+pub const STREAM_VERSION: u8 = 1;
+pub const STREAM_PREFIX_LENGTH: usize = 19;
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+// Ciphertexts written before the envelope existed are a 32B xor followed by a
+// 32B hash of the plaintext.
+pub const LEGACY_32_LENGTH: usize = 64;
+
+// Encrypt 32byte data by 32B key with XChaCha20-Poly1305. The associated data
+// is authenticated but not stored, it must be supplied again to decrypt.
+pub fn encrypt_32(input: &[u8; 32], key: &[u8; 32], associated_data: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rand::thread_rng().fill(&mut nonce);
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let aad = envelope_aad(ENVELOPE_VERSION, associated_data);
+
+    let sealed = cipher
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg: input, aad: &aad })
+        .expect("32 byte payload is within XChaCha20-Poly1305 limits.");
+
+    let mut envelope = Vec::with_capacity(ENVELOPE_32_LENGTH);
+    envelope.push(ENVELOPE_VERSION);
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&sealed);
+
+    envelope
+}
+
+// Accepts both current envelopes and legacy 64 byte ciphertexts. Legacy
+// ciphertexts carry no associated data so it is ignored for them.
+pub fn decrypt_32(encrypted: &[u8], key: &[u8; 32], associated_data: &[u8]) -> CommonResult<SecretKey> {
+    if is_legacy(encrypted) {
+        return decrypt_legacy_32(encrypted, key);
+    }
+
+    if encrypted.len() != ENVELOPE_32_LENGTH || encrypted[0] != ENVELOPE_VERSION {
+        return Err(CommonError::FailedVerification(Some(
+            "Unrecognized encryption envelope.".to_owned(),
+        )));
     }
 
-    encrypted
+    let nonce = XNonce::from_slice(&encrypted[1..1 + NONCE_LENGTH]);
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let aad = envelope_aad(encrypted[0], associated_data);
+
+    let decrypted = Zeroizing::new(
+        cipher
+            .decrypt(nonce, Payload { msg: &encrypted[1 + NONCE_LENGTH..], aad: &aad })
+            .map_err(|_| CommonError::FailedVerification(None))?,
+    );
+
+    let mut output = SecretKey::new([0u8; 32]);
+    output.copy_from_slice(&decrypted);
+
+    Ok(output)
 }
 
-pub fn decrypt_32(encrypted: &[u8; 64], key: &[u8; 32]) -> CommonResult<[u8; 32]> {
-    let mut decrypted: [u8; 32] = [0u8; 32];
+// Encrypt arbitrary length data. The plaintext is split into CHUNK_SIZE
+// chunks, each sealed under a nonce made of a random prefix, the chunk
+// counter and a final chunk flag so chunks cannot be reordered, dropped or
+// the stream truncated.
+//
+// Layout: version (1B) || nonce prefix (19B) || sealed chunks
+pub fn encrypt(data: &[u8], key: &[u8; 32]) -> Vec<u8> {
+    let mut prefix = [0u8; STREAM_PREFIX_LENGTH];
+    rand::thread_rng().fill(&mut prefix);
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let aad = [STREAM_VERSION];
+    let chunk_count = std::cmp::max(1, data.len().div_ceil(CHUNK_SIZE));
+
+    let mut output = Vec::with_capacity(1 + STREAM_PREFIX_LENGTH + data.len() + chunk_count * TAG_LENGTH);
+    output.push(STREAM_VERSION);
+    output.extend_from_slice(&prefix);
+
+    for index in 0..chunk_count {
+        let start = index * CHUNK_SIZE;
+        let end = std::cmp::min(start + CHUNK_SIZE, data.len());
+        let nonce = stream_nonce(&prefix, index, index + 1 == chunk_count);
+
+        let sealed = cipher
+            .encrypt(XNonce::from_slice(&nonce), Payload { msg: &data[start..end], aad: &aad })
+            .expect("Chunk is within XChaCha20-Poly1305 limits.");
+
+        output.extend_from_slice(&sealed);
+    }
+
+    output
+}
+
+pub fn decrypt(encrypted: &[u8], key: &[u8; 32]) -> CommonResult<Vec<u8>> {
+    if encrypted.len() < 1 + STREAM_PREFIX_LENGTH + TAG_LENGTH || encrypted[0] != STREAM_VERSION {
+        return Err(CommonError::FailedVerification(Some(
+            "Unrecognized encryption stream.".to_owned(),
+        )));
+    }
+
+    let prefix = &encrypted[1..1 + STREAM_PREFIX_LENGTH];
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let aad = [STREAM_VERSION];
+
+    let mut remaining = &encrypted[1 + STREAM_PREFIX_LENGTH..];
+    let mut output = Vec::with_capacity(remaining.len());
+    let mut index = 0;
+
+    loop {
+        // Every chunk but the last is full, so anything longer than a full
+        // sealed chunk means more chunks follow.
+        let last = remaining.len() <= CHUNK_SIZE + TAG_LENGTH;
+        let split = if last { remaining.len() } else { CHUNK_SIZE + TAG_LENGTH };
+        let nonce = stream_nonce(prefix, index, last);
+
+        let chunk = cipher
+            .decrypt(XNonce::from_slice(&nonce), Payload { msg: &remaining[..split], aad: &aad })
+            .map_err(|_| CommonError::FailedVerification(None))?;
+
+        output.extend_from_slice(&chunk);
+        remaining = &remaining[split..];
+        index += 1;
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+// True if the ciphertext predates the versioned envelope and should be
+// re-encrypted.
+pub fn is_legacy(encrypted: &[u8]) -> bool {
+    encrypted.len() == LEGACY_32_LENGTH
+}
+
+// The version byte is authenticated along with the caller's data so an
+// envelope cannot be replayed under a different version.
+fn envelope_aad(version: u8, associated_data: &[u8]) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(1 + associated_data.len());
+    aad.push(version);
+    aad.extend_from_slice(associated_data);
+    aad
+}
+
+// nonce prefix (19B) || chunk counter (4B big endian) || final chunk flag (1B)
+fn stream_nonce(prefix: &[u8], index: usize, last: bool) -> [u8; NONCE_LENGTH] {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    nonce[..STREAM_PREFIX_LENGTH].copy_from_slice(prefix);
+    nonce[STREAM_PREFIX_LENGTH..NONCE_LENGTH - 1].copy_from_slice(&(index as u32).to_be_bytes());
+    nonce[NONCE_LENGTH - 1] = last as u8;
+    nonce
+}
+
+fn decrypt_legacy_32(encrypted: &[u8], key: &[u8; 32]) -> CommonResult<SecretKey> {
+    let mut decrypted = SecretKey::new([0u8; 32]);
 
     for i in 0..32 {
         decrypted[i] = encrypted[i] ^ key[i];
     }
 
-    let check = secure_hash(&[&decrypted]);
+    let check = secure_hash(&[&*decrypted]);
 
     let mut error = false;
     for i in 0..32 {
@@ -46,9 +194,47 @@ mod tests {
         let data = random_int_256();
         let key = random_int_256();
 
-        let encrypted_data = encrypt_32(&data, &key);
-        let decrypted_data = decrypt_32(&encrypted_data, &key).unwrap();
+        let encrypted_data = encrypt_32(&data, &key, b"record");
+        let decrypted_data = decrypt_32(&encrypted_data, &key, b"record").unwrap();
+
+        assert_eq!(encrypted_data.len(), ENVELOPE_32_LENGTH);
+        assert_eq!(data, *decrypted_data);
+    }
+
+    #[test]
+    fn encrypt32_binds_associated_data() {
+        let data = random_int_256();
+        let key = random_int_256();
+
+        let encrypted_data = encrypt_32(&data, &key, b"record 1");
+
+        assert!(decrypt_32(&encrypted_data, &key, b"record 2").is_err());
+    }
+
+    #[test]
+    fn encrypt32_rejects_tampering() {
+        let data = random_int_256();
+        let key = random_int_256();
+
+        let mut encrypted_data = encrypt_32(&data, &key, b"record");
+        encrypted_data[ENVELOPE_32_LENGTH - 1] ^= 1;
+
+        assert!(decrypt_32(&encrypted_data, &key, b"record").is_err());
+    }
+
+    #[test]
+    fn decrypt32_legacy() {
+        let data = random_int_256();
+        let key = random_int_256();
+        let check = secure_hash(&[&data]);
+
+        let mut legacy = [0u8; 64];
+        for i in 0..32 {
+            legacy[i] = data[i] ^ key[i];
+            legacy[i + 32] = check[i];
+        }
 
-        assert_eq!(data, decrypted_data);
+        assert!(is_legacy(&legacy));
+        assert_eq!(*decrypt_32(&legacy, &key, b"ignored").unwrap(), data);
     }
 }
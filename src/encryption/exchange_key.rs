@@ -1,17 +1,42 @@
+use crate::encryption::algorithm::KeyAlgorithm;
 use crate::encryption::byte_encryption::{decrypt_32, encrypt_32};
 use crate::encryption::rand;
+use crate::encryption::random_int_256;
+use crate::encryption::secret::{SecretKey, Zeroizing};
+use zeroize::Zeroize;
 use crate::encryption::x25519_dalek::EphemeralSecret;
 use crate::encryption::x25519_dalek::PublicKey;
 use crate::encryption::x25519_dalek::StaticSecret;
 use crate::encryption::SECRET_KEY_LENGTH;
 use crate::encryption::{Digest, Sha512Trunc256};
-use crate::error::CommonResult;
+use crate::encryption::hash_by_parts;
+use crate::error::{CommonError, CommonResult};
+use hkdf::Hkdf;
+use ml_kem::kem::{Decapsulate, DecapsulationKey, EncapsulationKey};
+use ml_kem::{EncapsulateDeterministic, EncodedSizeUser, KemCore, MlKem768, MlKem768Params, B32};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
 use std::convert::TryInto;
 
+// A hybrid public key is the X25519 public key followed by the ML-KEM-768
+// encapsulation key.
+pub const HYBRID_KEM_OFFSET: usize = 32;
+
+enum ExchangeSecret {
+    X25519(StaticSecret),
+    P256(p256::SecretKey),
+    // Both keys are derived from the seed, the X25519 key is the seed itself
+    // so a hybrid key has the same X25519 public key as a plain one.
+    X25519MlKem768 {
+        seed: SecretKey,
+        x25519: StaticSecret,
+        kem: Box<DecapsulationKey<MlKem768Params>>,
+    },
+}
+
 // This acts as a constant interface while the backing library is
 // in flux. should eventually use the library type.
 pub struct ExchangeKey {
-    key: StaticSecret,
+    key: ExchangeSecret,
 }
 
 impl ExchangeKey {
@@ -19,47 +44,167 @@ impl ExchangeKey {
         let mut rng = rand::thread_rng();
 
         ExchangeKey {
-            key: StaticSecret::new(&mut rng),
+            key: ExchangeSecret::X25519(StaticSecret::new(&mut rng)),
+        }
+    }
+
+    pub fn generate(algorithm: KeyAlgorithm) -> CommonResult<ExchangeKey> {
+        loop {
+            // A random P-256 scalar is out of range with negligible probability.
+            match ExchangeKey::from_private_key(algorithm, &SecretKey::new(random_int_256())) {
+                Err(CommonError::FailedVerification(_)) => continue,
+                result => return result,
+            }
         }
     }
 
-    pub fn from_key(private: [u8; SECRET_KEY_LENGTH]) -> ExchangeKey {
+    pub fn from_key(private: &[u8; SECRET_KEY_LENGTH]) -> ExchangeKey {
         ExchangeKey {
-            key: StaticSecret::from(private),
+            key: ExchangeSecret::X25519(StaticSecret::from(*private)),
         }
     }
 
-    pub fn public_key(&self) -> [u8; 32] {
-        *PublicKey::from(&self.key).as_bytes()
+    pub fn from_private_key(algorithm: KeyAlgorithm, private: &[u8; SECRET_KEY_LENGTH]) -> CommonResult<ExchangeKey> {
+        match algorithm {
+            KeyAlgorithm::X25519 => Ok(ExchangeKey::from_key(private)),
+            KeyAlgorithm::P256 => Ok(ExchangeKey {
+                key: ExchangeSecret::P256(
+                    p256::SecretKey::from_slice(private)
+                        .map_err(|_| CommonError::FailedVerification(Some("Invalid P-256 private key.".to_owned())))?,
+                ),
+            }),
+            KeyAlgorithm::X25519MlKem768 => {
+                let mut kem_seed = Zeroizing::new([0u8; 64]);
+                Hkdf::<Sha512Trunc256>::new(None, private)
+                    .expand(b"x25519_mlkem768 ml-kem seed", &mut *kem_seed)
+                    .expect("64 bytes is a valid HKDF output length.");
+                let (d, z) = kem_seed.split_at(32);
+                let mut d: B32 = d.try_into().expect("Seed halves are 32 bytes.");
+                let mut z: B32 = z.try_into().expect("Seed halves are 32 bytes.");
+                let (kem, _) = MlKem768::generate_deterministic(&d, &z);
+                d.zeroize();
+                z.zeroize();
+
+                Ok(ExchangeKey {
+                    key: ExchangeSecret::X25519MlKem768 {
+                        seed: SecretKey::new(*private),
+                        x25519: StaticSecret::from(*private),
+                        kem: Box::new(kem),
+                    },
+                })
+            }
+            KeyAlgorithm::Ed25519 => {
+                Err(CommonError::Misconfiguration(Some("Ed25519 keys cannot exchange.".to_owned())))
+            }
+        }
+    }
+
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match self.key {
+            ExchangeSecret::X25519(_) => KeyAlgorithm::X25519,
+            ExchangeSecret::P256(_) => KeyAlgorithm::P256,
+            ExchangeSecret::X25519MlKem768 { .. } => KeyAlgorithm::X25519MlKem768,
+        }
     }
 
-    pub fn encrypted_private_key(&self, encryption_key: &[u8; 32]) -> [u8; 64] {
-        encrypt_32(&self.key.to_bytes().try_into().unwrap(), encryption_key)
+    pub fn public_key(&self) -> Vec<u8> {
+        match &self.key {
+            ExchangeSecret::X25519(key) => PublicKey::from(key).as_bytes().to_vec(),
+            ExchangeSecret::P256(key) => key.public_key().to_encoded_point(true).as_bytes().to_vec(),
+            ExchangeSecret::X25519MlKem768 { x25519, kem, .. } => {
+                let mut public_key = PublicKey::from(x25519).as_bytes().to_vec();
+                public_key.extend_from_slice(&kem.encapsulation_key().as_bytes());
+                public_key
+            }
+        }
     }
 
-    pub fn private_key(&self) -> [u8; 32] {
-        self.key.to_bytes()
+    pub fn encrypted_private_key(&self, encryption_key: &[u8; 32], associated_data: &[u8]) -> Vec<u8> {
+        encrypt_32(&self.private_key(), encryption_key, associated_data)
+    }
+
+    pub fn private_key(&self) -> SecretKey {
+        match &self.key {
+            ExchangeSecret::X25519(key) => SecretKey::new(key.to_bytes()),
+            ExchangeSecret::P256(key) => SecretKey::new(key.to_bytes().into()),
+            ExchangeSecret::X25519MlKem768 { seed, .. } => seed.clone(),
+        }
     }
 
     pub fn from_encrypted(
         encryption_key: &[u8; 32],
-        encrypted_key: &[u8; 64],
+        algorithm: KeyAlgorithm,
+        encrypted_key: &[u8],
+        associated_data: &[u8],
     ) -> CommonResult<ExchangeKey> {
-        let decrypted_key: [u8; SECRET_KEY_LENGTH] = decrypt_32(&encrypted_key, &encryption_key)?;
+        let decrypted_key = decrypt_32(encrypted_key, encryption_key, associated_data)?;
 
-        Ok(ExchangeKey::from_key(decrypted_key))
+        ExchangeKey::from_private_key(algorithm, &decrypted_key)
     }
 
-    pub fn key_gen(&self, public_key: [u8; 32]) -> [u8; 32] {
-        let pk = PublicKey::from(public_key);
-        let shared_key = self.key.diffie_hellman(&pk);
-
+    // The peer key must use the same algorithm as this key. Hybrid keys agree
+    // on their X25519 part only, use decapsulate for the hybrid secret.
+    pub fn key_gen(&self, public_key: &[u8]) -> CommonResult<SecretKey> {
         let mut hasher = Sha512Trunc256::new();
 
-        hasher.update(shared_key.as_bytes());
+        match &self.key {
+            ExchangeSecret::X25519(key) | ExchangeSecret::X25519MlKem768 { x25519: key, .. } => {
+                let public_key: [u8; 32] = public_key.try_into().map_err(|_| invalid_public_key())?;
+                let shared_key = key.diffie_hellman(&PublicKey::from(public_key));
+                hasher.update(shared_key.as_bytes());
+            }
+            ExchangeSecret::P256(key) => {
+                let public_key = p256::PublicKey::from_sec1_bytes(public_key).map_err(|_| invalid_public_key())?;
+                let shared_key = p256::ecdh::diffie_hellman(key.to_nonzero_scalar(), public_key.as_affine());
+                hasher.update(shared_key.raw_secret_bytes());
+            }
+        }
 
-        hasher.finalize().try_into().unwrap()
+        Ok(SecretKey::new(hasher.finalize().try_into().unwrap()))
     }
+
+    // Recovers the secret encapsulated by EphemeralKey::encapsulate.
+    pub fn decapsulate(&self, ephemeral_public_key: &[u8], encapsulated_key: &[u8]) -> CommonResult<SecretKey> {
+        let (x25519, kem) = match &self.key {
+            ExchangeSecret::X25519MlKem768 { x25519, kem, .. } => (x25519, kem),
+            _ => {
+                return Err(CommonError::Misconfiguration(Some(
+                    "Only hybrid keys can decapsulate.".to_owned(),
+                )))
+            }
+        };
+
+        let ephemeral_public_key: [u8; 32] = ephemeral_public_key.try_into().map_err(|_| invalid_public_key())?;
+        let ciphertext = encapsulated_key.try_into().map_err(|_| invalid_encapsulated_key())?;
+        let kem_secret = kem.decapsulate(ciphertext).map_err(|_| invalid_encapsulated_key())?;
+        let x25519_secret = x25519.diffie_hellman(&PublicKey::from(ephemeral_public_key));
+
+        Ok(hybrid_secret(
+            &kem_secret,
+            x25519_secret.as_bytes(),
+            &ephemeral_public_key,
+            PublicKey::from(x25519).as_bytes(),
+        ))
+    }
+}
+
+// Combines both shared secrets, binding the X25519 public keys as X-Wing does.
+fn hybrid_secret(kem_secret: &[u8], x25519_secret: &[u8], ephemeral_public_key: &[u8], public_key: &[u8]) -> SecretKey {
+    SecretKey::new(hash_by_parts(&[
+        b"x25519_mlkem768",
+        kem_secret,
+        x25519_secret,
+        ephemeral_public_key,
+        public_key,
+    ]))
+}
+
+fn invalid_encapsulated_key() -> CommonError {
+    CommonError::FailedVerification(Some("Invalid encapsulated key.".to_owned()))
+}
+
+fn invalid_public_key() -> CommonError {
+    CommonError::FailedVerification(Some("Invalid exchange public key.".to_owned()))
 }
 
 pub struct EphemeralKey {
@@ -79,7 +224,7 @@ impl EphemeralKey {
         *PublicKey::from(&self.key).as_bytes()
     }
 
-    pub fn key_gen(self, public_key: [u8; 32]) -> [u8; 32] {
+    pub fn key_gen(self, public_key: [u8; 32]) -> SecretKey {
         let pk = PublicKey::from(public_key);
         let shared_key = self.key.diffie_hellman(&pk);
 
@@ -87,6 +232,96 @@ impl EphemeralKey {
 
         hasher.update(shared_key.as_bytes());
 
-        hasher.finalize().try_into().unwrap()
+        SecretKey::new(hasher.finalize().try_into().unwrap())
+    }
+
+    // Encapsulates a new secret to a hybrid public key. Returns the secret and
+    // the ML-KEM ciphertext, which is sent along with the ephemeral public key.
+    pub fn encapsulate(self, public_key: &[u8]) -> CommonResult<(SecretKey, Vec<u8>)> {
+        if public_key.len() <= HYBRID_KEM_OFFSET {
+            return Err(invalid_public_key());
+        }
+
+        let (x25519_public_key, kem_public_key) = public_key.split_at(HYBRID_KEM_OFFSET);
+        let x25519_public_key: [u8; 32] = x25519_public_key.try_into().unwrap();
+        let kem_public_key = kem_public_key.try_into().map_err(|_| invalid_public_key())?;
+        let (ciphertext, kem_secret) = EncapsulationKey::<MlKem768Params>::from_bytes(kem_public_key)
+            .encapsulate_deterministic(&B32::from(random_int_256()))
+            .map_err(|_| invalid_public_key())?;
+
+        let ephemeral_public_key = self.public_key();
+        let x25519_secret = self.key.diffie_hellman(&PublicKey::from(x25519_public_key));
+
+        let secret = hybrid_secret(&kem_secret, x25519_secret.as_bytes(), &ephemeral_public_key, &x25519_public_key);
+
+        Ok((secret, ciphertext.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchange_keys_agree() {
+        for algorithm in &[KeyAlgorithm::X25519, KeyAlgorithm::P256] {
+            let ours = ExchangeKey::generate(*algorithm).unwrap();
+            let theirs = ExchangeKey::generate(*algorithm).unwrap();
+
+            assert_eq!(
+                ours.key_gen(&theirs.public_key()).unwrap(),
+                theirs.key_gen(&ours.public_key()).unwrap()
+            );
+
+            let restored = ExchangeKey::from_private_key(*algorithm, &ours.private_key()).unwrap();
+            assert_eq!(restored.public_key(), ours.public_key());
+        }
+
+        let p256 = ExchangeKey::generate(KeyAlgorithm::P256).unwrap();
+        let x25519 = ExchangeKey::new();
+
+        assert!(p256.key_gen(&x25519.public_key()).is_err());
+    }
+
+    #[test]
+    fn hybrid_encapsulation() {
+        let key = ExchangeKey::generate(KeyAlgorithm::X25519MlKem768).unwrap();
+        let public_key = key.public_key();
+
+        // The X25519 part is the same as for a plain key from the same seed.
+        let plain = ExchangeKey::from_key(&key.private_key());
+        assert_eq!(&public_key[..HYBRID_KEM_OFFSET], plain.public_key().as_slice());
+
+        let ephemeral = EphemeralKey::new();
+        let ephemeral_public_key = ephemeral.public_key();
+        let (secret, encapsulated_key) = ephemeral.encapsulate(&public_key).unwrap();
+
+        assert_eq!(key.decapsulate(&ephemeral_public_key, &encapsulated_key).unwrap(), secret);
+
+        let restored = ExchangeKey::from_private_key(KeyAlgorithm::X25519MlKem768, &key.private_key()).unwrap();
+        assert_eq!(restored.public_key(), public_key);
+        assert_eq!(restored.decapsulate(&ephemeral_public_key, &encapsulated_key).unwrap(), secret);
+
+        // Either half alone does not give the secret.
+        let other = ExchangeKey::generate(KeyAlgorithm::X25519MlKem768).unwrap();
+        assert_ne!(other.decapsulate(&ephemeral_public_key, &encapsulated_key).unwrap(), secret);
+        assert_ne!(key.decapsulate(&other.public_key()[..HYBRID_KEM_OFFSET], &encapsulated_key).unwrap(), secret);
+
+        assert!(plain.decapsulate(&ephemeral_public_key, &encapsulated_key).is_err());
+        assert!(EphemeralKey::new().encapsulate(&plain.public_key()).is_err());
+    }
+
+    #[test]
+    fn exchange_key_is_wiped() {
+        use crate::encryption::secret::dropped_bytes;
+        use std::mem::ManuallyDrop;
+
+        for algorithm in &[KeyAlgorithm::X25519, KeyAlgorithm::X25519MlKem768] {
+            let key = ExchangeKey::generate(*algorithm).unwrap();
+            let private = key.private_key();
+
+            let bytes = dropped_bytes(ManuallyDrop::new(key));
+            assert!(!bytes.windows(32).any(|window| window == &private[..]));
+        }
     }
 }
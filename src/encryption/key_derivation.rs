@@ -0,0 +1,122 @@
+use hkdf::Hkdf;
+use crate::encryption::secret::SecretKey;
+use crate::encryption::{secure_hash, Sha512Trunc256};
+use crate::error::{CommonError, CommonResult};
+
+// Keys protecting records owned by an account are derived from the account
+// master key.
+//
+//   key = HKDF-SHA512/256(ikm:  master key,
+//                         salt: record salt,
+//                         info: "idvault" || version || label || record type || record id)
+//
+// Every info part is length prefixed, so keys for different purposes, record
+// types or records can never share an info string. The derived key wraps the
+// record's private key and is the access key handed to authorized clients.
+//
+// Version 0 is the original SHA-512/256(master key || salt) derivation. It is
+// only used to read records created before versioned keys existed.
+pub const LEGACY_KEY_VERSION: i16 = 0;
+pub const KEY_VERSION: i16 = 1;
+
+const KEY_DOMAIN: &[u8] = b"idvault";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyPurpose {
+    WriteScope,
+    ReadGrant,
+}
+
+impl KeyPurpose {
+    pub fn label(&self) -> &'static str {
+        match self {
+            KeyPurpose::WriteScope => "write-scope",
+            KeyPurpose::ReadGrant => "read-grant",
+        }
+    }
+}
+
+pub fn derive_key(
+    master_key: &[u8; 32],
+    version: i16,
+    purpose: KeyPurpose,
+    record_type: &str,
+    record_id: &[&[u8]],
+    salt: &[u8],
+) -> CommonResult<SecretKey> {
+    match version {
+        LEGACY_KEY_VERSION => Ok(SecretKey::new(secure_hash(&[master_key, salt]))),
+        KEY_VERSION => {
+            let mut info = Vec::new();
+            push_part(&mut info, KEY_DOMAIN);
+            push_part(&mut info, &version.to_be_bytes());
+            push_part(&mut info, purpose.label().as_bytes());
+            push_part(&mut info, record_type.as_bytes());
+
+            for part in record_id {
+                push_part(&mut info, part);
+            }
+
+            let mut key = SecretKey::new([0u8; 32]);
+            Hkdf::<Sha512Trunc256>::new(Some(salt), master_key)
+                .expand(&info, &mut *key)
+                .expect("32 bytes is a valid HKDF output length.");
+
+            Ok(key)
+        }
+        _ => Err(CommonError::FailedVerification(Some(format!(
+            "Unknown key version {}.",
+            version
+        )))),
+    }
+}
+
+// u32 big endian length || bytes
+fn push_part(info: &mut Vec<u8>, part: &[u8]) {
+    info.extend_from_slice(&(part.len() as u32).to_be_bytes());
+    info.extend_from_slice(part);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::random_int_256;
+
+    #[test]
+    fn derived_keys_are_domain_separated() {
+        let master_key = random_int_256();
+        let salt = random_int_256();
+        let record = random_int_256();
+
+        let write = derive_key(&master_key, KEY_VERSION, KeyPurpose::WriteScope, "write_grant_scope", &[&record], &salt).unwrap();
+        let read = derive_key(&master_key, KEY_VERSION, KeyPurpose::ReadGrant, "write_grant_scope", &[&record], &salt).unwrap();
+        let other_type = derive_key(&master_key, KEY_VERSION, KeyPurpose::WriteScope, "read_grant_key", &[&record], &salt).unwrap();
+        let other_record = derive_key(&master_key, KEY_VERSION, KeyPurpose::WriteScope, "write_grant_scope", &[&salt], &salt).unwrap();
+        let legacy = derive_key(&master_key, LEGACY_KEY_VERSION, KeyPurpose::WriteScope, "write_grant_scope", &[&record], &salt).unwrap();
+
+        assert_eq!(write, derive_key(&master_key, KEY_VERSION, KeyPurpose::WriteScope, "write_grant_scope", &[&record], &salt).unwrap());
+        assert_ne!(write, read);
+        assert_ne!(write, other_type);
+        assert_ne!(write, other_record);
+        assert_ne!(write, legacy);
+        assert_eq!(*legacy, secure_hash(&[&master_key, &salt]));
+    }
+
+    #[test]
+    fn record_id_parts_are_length_prefixed() {
+        let master_key = random_int_256();
+        let salt = random_int_256();
+
+        let split_one = derive_key(&master_key, KEY_VERSION, KeyPurpose::ReadGrant, "read_grant_key", &[b"ab", b"c"], &salt).unwrap();
+        let split_two = derive_key(&master_key, KEY_VERSION, KeyPurpose::ReadGrant, "read_grant_key", &[b"a", b"bc"], &salt).unwrap();
+
+        assert_ne!(split_one, split_two);
+    }
+
+    #[test]
+    fn unknown_version_is_rejected() {
+        let master_key = random_int_256();
+
+        assert!(derive_key(&master_key, KEY_VERSION + 1, KeyPurpose::WriteScope, "write_grant_scope", &[], &[]).is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use bip39::{Language, Mnemonic};
+use crate::encryption::secret::{SecretKey, SecretString};
+use crate::error::{CommonError, CommonResult};
+
+// A 32 byte key as 24 words from the BIP39 English word list, the last word
+// carrying a checksum. Words are matched case insensitively and may be
+// separated by any whitespace.
+pub fn to_mnemonic(key: &[u8; 32]) -> SecretString {
+    let mnemonic = Mnemonic::from_entropy_in(Language::English, key)
+        .expect("32 bytes is a valid mnemonic length.");
+
+    SecretString::new(mnemonic.to_string())
+}
+
+pub fn from_mnemonic(words: &str) -> CommonResult<SecretKey> {
+    let normalized = SecretString::new(words.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase());
+
+    let mnemonic = Mnemonic::parse_in_normalized(Language::English, &normalized)
+        .map_err(|err| CommonError::FailedVerification(Some(format!("Invalid mnemonic: {}.", err))))?;
+
+    let (entropy, length) = mnemonic.to_entropy_array();
+
+    if length != 32 {
+        return Err(CommonError::FailedVerification(Some("Mnemonic must be 24 words.".to_owned())));
+    }
+
+    let mut key = SecretKey::new([0u8; 32]);
+    key.copy_from_slice(&entropy[..32]);
+
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::random_int_256;
+
+    #[test]
+    fn mnemonic_round_trip() {
+        let key = random_int_256();
+        let words = to_mnemonic(&key);
+
+        assert_eq!(words.split(' ').count(), 24);
+        assert_eq!(*from_mnemonic(&words).unwrap(), key);
+        assert_eq!(*from_mnemonic(&format!("  {}\n", words.to_uppercase())).unwrap(), key);
+
+        // Swapping two words breaks the checksum.
+        let words = to_mnemonic(&[7u8; 32]);
+        let mut swapped: Vec<&str> = words.split(' ').collect();
+        swapped.swap(0, 1);
+
+        assert!(from_mnemonic(&swapped.join(" ")).is_err());
+
+        assert!(from_mnemonic("abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about").is_err());
+    }
+}
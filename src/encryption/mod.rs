@@ -1,58 +1,23 @@
+pub mod algorithm;
 pub mod byte_encryption;
 pub mod exchange_key;
+pub mod key_derivation;
+pub mod key_format;
+pub mod mnemonic;
+pub mod password;
+pub mod secret;
+pub mod shamir;
 pub mod signing_key;
 
-use base64::{decode, encode};
 pub use rand;
 pub use x25519_dalek;
 pub use ed25519_compact;
-pub use argon2rs::Argon2;
-pub use argon2rs::Variant::Argon2i;
 pub use rand::Rng;
 pub use sha2::{Digest, Sha512Trunc256};
 pub use x25519_dalek::PublicKey as XPublicKey;
-use crate::error::CommonResult;
-use std::convert::TryInto;
 
-pub const PUBLIC_KEY_LENGTH: usize = 32;
 pub const SECRET_KEY_LENGTH: usize = 32;
 
-pub fn hash_password(password: &str) -> String {
-    let salt = random_int_256();
-    let hash = hash_salted_password(password, &salt);
-
-    let mut merged = [0u8; 64];
-
-    merged[..32].copy_from_slice(&salt);
-    merged[32..].copy_from_slice(&hash);
-
-    encode(&merged.to_vec())
-}
-
-pub fn hash_salted_password(password: &str, salt: &[u8]) -> [u8; 32] {
-    let hasher = Argon2::default(Argon2i);
-
-    let mut result = [b'0'; 32];
-
-    hasher.hash(&mut result, password.as_bytes(), salt, &[0u8; 0], &[0u8; 0]);
-
-    result
-}
-
-pub fn check_password(password: &str, hashed_password: &str) -> bool {
-    let merged: [u8; 64] = match decode(hashed_password) {
-        Ok(v) if v.len() == 64 => *as_512(v.as_slice()),
-        _ => return false,
-    };
-
-    let salt: [u8; 32] = merged[0..32].try_into().unwrap();
-    let hash: [u8; 32] = merged[32..64].try_into().unwrap();
-
-    let new_hash = hash_salted_password(password, &salt);
-
-    hash_eq(&hash, &new_hash)
-}
-
 // Constant-time equality check for 32 byte arrays
 pub fn hash_eq(a: &[u8; 32], b: &[u8; 32]) -> bool {
     let mut result = 0;
@@ -89,18 +54,14 @@ pub fn hash_by_parts(data: &[&[u8]]) -> [u8; 32] {
     hasher.finalize().into()
 }
 
-pub fn decode_64(input: &str) -> CommonResult<[u8; 64]> {
-    let vec_ouput = decode(input)?;
-    let mut output: [u8; 64] = [0u8; 64];
-    output.copy_from_slice(&vec_ouput);
-    Ok(output)
-}
-
-pub fn decode_32(input: &str) -> CommonResult<[u8; 32]> {
-    let vec_ouput = decode(input)?;
-    let mut output: [u8; 32] = [0u8; 32];
-    output.copy_from_slice(&vec_ouput);
-    Ok(output)
+// A short, readable identifier of a public key for comparing it by eye,
+// eight groups of hex from hash_by_parts("public_key_fingerprint", key).
+pub fn fingerprint(public_key: &[u8]) -> String {
+    hash_by_parts(&[b"public_key_fingerprint", public_key])[..16]
+        .chunks(2)
+        .map(|group| format!("{:02x}{:02x}", group[0], group[1]))
+        .collect::<Vec<String>>()
+        .join(":")
 }
 
 pub fn random_int_256() -> [u8; 32] {
@@ -153,6 +114,7 @@ pub fn lpad_to_256(input: &[u8]) -> [u8; 32] {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encryption::byte_encryption::{decrypt, encrypt};
 
     #[test]
     fn left_pad_32byte_array() {
@@ -178,7 +140,6 @@ mod tests {
 
     }
 
-    /*
     #[test]
     fn encrypt_decrypt() {
         // get some data to encrypt not nicely aligned.
@@ -201,5 +162,41 @@ mod tests {
 
         assert_eq!(result, 0);
     }
-    */
+
+    #[test]
+    fn encrypt_decrypt_chunked() {
+        use crate::encryption::byte_encryption::CHUNK_SIZE;
+
+        let key = random_int_256();
+
+        for length in &[0, 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 17] {
+            let mut data = vec![0u8; *length];
+            rand::thread_rng().fill(data.as_mut_slice());
+
+            let encrypted = encrypt(&data, &key);
+            let decrypted = decrypt(&encrypted, &key).unwrap();
+
+            assert_eq!(data, decrypted);
+        }
+    }
+
+    #[test]
+    fn decrypt_rejects_truncation() {
+        use crate::encryption::byte_encryption::{CHUNK_SIZE, TAG_LENGTH};
+        use crate::error::CommonError;
+
+        let key = random_int_256();
+        let data = vec![7u8; 2 * CHUNK_SIZE + 5];
+
+        let encrypted = encrypt(&data, &key);
+        let truncated = &encrypted[..encrypted.len() - (5 + TAG_LENGTH)];
+
+        match decrypt(truncated, &key) {
+            Err(CommonError::FailedVerification(_)) => (),
+            _ => panic!("Truncated stream decrypted."),
+        }
+
+        let wrong_key = random_int_256();
+        assert!(decrypt(&encrypted, &wrong_key).is_err());
+    }
 }
@@ -0,0 +1,235 @@
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::decode;
+use crate::encryption::secret::SecretKey;
+use crate::encryption::{as_512, hash_eq, random_int_256};
+use crate::error::{CommonError, CommonResult};
+use std::convert::{TryFrom, TryInto};
+
+// Costs used for new hashes unless overridden by the PASSWORD_MEMORY_COST
+// (KiB), PASSWORD_TIME_COST and PASSWORD_LANES environment variables.
+pub const DEFAULT_MEMORY_COST: u32 = 19 * 1024;
+pub const DEFAULT_TIME_COST: u32 = 2;
+pub const DEFAULT_LANES: u32 = 1;
+
+// Hashes stored before PHC strings were base64(salt || hash) made with the
+// argon2rs defaults: Argon2i version 0x10, 4MiB, 3 passes, 1 lane.
+const LEGACY_MEMORY_COST: u32 = 4096;
+const LEGACY_TIME_COST: u32 = 3;
+const LEGACY_LANES: u32 = 1;
+
+const KEY_LENGTH: usize = 32;
+
+// The currently configured costs. The server checks them when it starts,
+// anywhere else invalid costs fall back to the defaults with a warning.
+pub fn password_params() -> Params {
+    configured_params().unwrap_or_else(|_| {
+        eprintln!("Warning: password hashing costs are misconfigured, using the defaults.");
+        Params::new(DEFAULT_MEMORY_COST, DEFAULT_TIME_COST, DEFAULT_LANES, Some(KEY_LENGTH))
+            .expect("Default Argon2 parameters are valid.")
+    })
+}
+
+// The costs from the environment, Misconfiguration if they are not valid.
+pub fn configured_params() -> CommonResult<Params> {
+    Params::new(
+        configured_cost("PASSWORD_MEMORY_COST", DEFAULT_MEMORY_COST)?,
+        configured_cost("PASSWORD_TIME_COST", DEFAULT_TIME_COST)?,
+        configured_cost("PASSWORD_LANES", DEFAULT_LANES)?,
+        Some(KEY_LENGTH),
+    )
+    .map_err(|err| CommonError::Misconfiguration(Some(format!("Password hashing costs are invalid: {}.", err))))
+}
+
+// Hash a password to a self describing PHC string,
+// e.g. $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+pub fn hash_password(password: &str) -> String {
+    hash_password_with(password, password_params())
+}
+
+pub fn hash_password_with(password: &str, params: Params) -> String {
+    let salt = SaltString::encode_b64(&random_int_256()).expect("32 byte salt is valid.");
+
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Password hashing failed.")
+        .to_string()
+}
+
+// The algorithm and costs are read from the stored hash, so hashes made
+// with older settings remain valid.
+pub fn check_password(password: &str, hashed_password: &str) -> bool {
+    match PasswordHash::new(hashed_password) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => check_legacy_password(password, hashed_password),
+    }
+}
+
+// True if the hash uses an older format or is weaker than the configured
+// costs and should be replaced the next time the password is known.
+pub fn password_needs_rehash(hashed_password: &str) -> bool {
+    match parse_phc(hashed_password) {
+        Ok((algorithm, _, params)) => is_weaker(algorithm, &params),
+        Err(_) => true,
+    }
+}
+
+// Key derivation settings for keys wrapped under a password are stored as a
+// PHC string without salt or hash, e.g. $argon2id$v=19$m=19456,t=2,p=1. An
+// empty string marks keys derived with the legacy settings.
+pub fn key_derivation_params() -> String {
+    let params = password_params();
+
+    format!(
+        "${}$v={}$m={},t={},p={}",
+        Algorithm::Argon2id,
+        Version::V0x13 as u32,
+        params.m_cost(),
+        params.t_cost(),
+        params.p_cost()
+    )
+}
+
+pub fn derive_password_key(password: &str, salt: &[u8], kdf: &str) -> CommonResult<SecretKey> {
+    derive_secret_key(password.as_bytes(), salt, kdf)
+}
+
+// As derive_password_key, for secrets that aren't text such as key files.
+pub fn derive_secret_key(secret: &[u8], salt: &[u8], kdf: &str) -> CommonResult<SecretKey> {
+    let (algorithm, version, params) = if kdf.is_empty() {
+        legacy_settings()
+    } else {
+        parse_phc(kdf)?
+    };
+
+    let mut key = SecretKey::new([0u8; KEY_LENGTH]);
+
+    Argon2::new(algorithm, version, params)
+        .hash_password_into(secret, salt, &mut *key)
+        .map_err(|_| CommonError::LibraryError(Some("Argon2 key derivation failed.".to_owned())))?;
+
+    Ok(key)
+}
+
+pub fn key_derivation_needs_upgrade(kdf: &str) -> bool {
+    if kdf.is_empty() {
+        return true;
+    }
+
+    match parse_phc(kdf) {
+        Ok((algorithm, _, params)) => is_weaker(algorithm, &params),
+        Err(_) => true,
+    }
+}
+
+fn configured_cost(variable: &str, default: u32) -> CommonResult<u32> {
+    match dotenv::var(variable) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| CommonError::Misconfiguration(Some(format!("{} must be a number.", variable)))),
+        Err(_) => Ok(default),
+    }
+}
+
+fn is_weaker(algorithm: Algorithm, params: &Params) -> bool {
+    let target = password_params();
+
+    algorithm != Algorithm::Argon2id
+        || params.m_cost() < target.m_cost()
+        || params.t_cost() < target.t_cost()
+        || params.p_cost() < target.p_cost()
+}
+
+fn parse_phc(phc: &str) -> CommonResult<(Algorithm, Version, Params)> {
+    let hash = PasswordHash::new(phc).map_err(malformed)?;
+    let algorithm = Algorithm::try_from(hash.algorithm).map_err(malformed)?;
+    let version = Version::try_from(hash.version.unwrap_or(Version::V0x13 as u32)).map_err(malformed)?;
+    let params = Params::try_from(&hash).map_err(malformed)?;
+
+    Ok((algorithm, version, params))
+}
+
+fn malformed<E>(_err: E) -> CommonError {
+    CommonError::Misconfiguration(Some("Malformed Argon2 parameters.".to_owned()))
+}
+
+fn legacy_settings() -> (Algorithm, Version, Params) {
+    let params = Params::new(LEGACY_MEMORY_COST, LEGACY_TIME_COST, LEGACY_LANES, Some(KEY_LENGTH))
+        .expect("Legacy Argon2 parameters are valid.");
+
+    (Algorithm::Argon2i, Version::V0x10, params)
+}
+
+fn check_legacy_password(password: &str, hashed_password: &str) -> bool {
+    let merged: [u8; 64] = match decode(hashed_password) {
+        Ok(v) if v.len() == 64 => *as_512(v.as_slice()),
+        _ => return false,
+    };
+
+    let salt: [u8; 32] = merged[0..32].try_into().unwrap();
+    let hash: [u8; 32] = merged[32..64].try_into().unwrap();
+
+    match derive_password_key(password, &salt, "") {
+        Ok(new_hash) => hash_eq(&hash, &new_hash),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // base64(salt || hash) of "correct horse" made by argon2rs
+    const LEGACY_HASH: &str =
+        "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwfKEoobebrEkyG2e/TnwC6r9j/tTD/MapumklR1nlzeHg==";
+
+    #[test]
+    fn hash_and_check_password() {
+        let hash = hash_password("correct horse");
+
+        assert!(hash.starts_with("$argon2id$v=19$"));
+        assert!(check_password("correct horse", &hash));
+        assert!(!check_password("battery staple", &hash));
+        assert!(!password_needs_rehash(&hash));
+    }
+
+    #[test]
+    fn check_legacy_password_hash() {
+        assert!(check_password("correct horse", LEGACY_HASH));
+        assert!(!check_password("battery staple", LEGACY_HASH));
+        assert!(password_needs_rehash(LEGACY_HASH));
+    }
+
+    #[test]
+    fn weak_hash_needs_rehash() {
+        let weak = hash_password_with("correct horse", Params::new(1024, 1, 1, None).unwrap());
+
+        assert!(check_password("correct horse", &weak));
+        assert!(password_needs_rehash(&weak));
+    }
+
+    #[test]
+    fn derive_key_from_stored_params() {
+        let salt = random_int_256();
+        let kdf = key_derivation_params();
+
+        let key = derive_password_key("correct horse", &salt, &kdf).unwrap();
+
+        assert_eq!(key, derive_password_key("correct horse", &salt, &kdf).unwrap());
+        assert_ne!(key, derive_password_key("correct horse", &salt, "").unwrap());
+        assert!(!key_derivation_needs_upgrade(&kdf));
+        assert!(key_derivation_needs_upgrade(""));
+        assert!(key_derivation_needs_upgrade("$argon2id$v=19$m=1024,t=1,p=1"));
+    }
+
+    #[test]
+    fn bad_cost_is_misconfiguration() {
+        std::env::set_var("IDVAULT_TEST_COST", "lots");
+        assert!(matches!(configured_cost("IDVAULT_TEST_COST", 2), Err(CommonError::Misconfiguration(_))));
+
+        std::env::remove_var("IDVAULT_TEST_COST");
+        assert_eq!(configured_cost("IDVAULT_TEST_COST", 2).unwrap(), 2);
+    }
+}
@@ -0,0 +1,69 @@
+use std::mem::size_of;
+use zeroize::Zeroize;
+pub use zeroize::Zeroizing;
+
+// Every key, master key and client token is held as a SecretKey, which is
+// wiped when dropped. It is not Copy so the bytes can't be duplicated
+// implicitly, explicit clones are wiped separately.
+pub type SecretKey = Zeroizing<[u8; 32]>;
+
+// Passwords and export keys, wiped when dropped.
+pub type SecretString = Zeroizing<String>;
+
+// Secrets that aren't text, such as key files.
+pub type SecretBytes = Zeroizing<Vec<u8>>;
+
+// Overwrites the memory of a library key type that doesn't wipe itself.
+//
+// Safety: T must be plain bytes, valid when all zero and without pointers or
+// a Drop of its own.
+pub unsafe fn wipe<T>(value: &mut T) {
+    let bytes = std::slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>());
+    bytes.zeroize();
+}
+
+// Reads back the bytes a value occupied after it has been dropped in place.
+#[cfg(test)]
+pub fn dropped_bytes<T>(mut value: std::mem::ManuallyDrop<T>) -> Vec<u8> {
+    unsafe {
+        let location = &mut *value as *mut T;
+        std::ptr::drop_in_place(location);
+        std::slice::from_raw_parts(location as *const u8, size_of::<T>()).to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::ManuallyDrop;
+
+    #[test]
+    fn secret_key_is_wiped() {
+        let key = ManuallyDrop::new(SecretKey::new([7u8; 32]));
+
+        assert_eq!(dropped_bytes(key), vec![0u8; 32]);
+    }
+
+    #[test]
+    fn secret_string_is_wiped() {
+        // The buffer is freed on drop, so check what drop does to it while
+        // it is still allocated.
+        let mut password = SecretString::new("correct horse".to_owned());
+        let buffer = password.as_ptr();
+        let capacity = password.capacity();
+
+        password.zeroize();
+
+        assert!(password.is_empty());
+        assert_eq!(unsafe { std::slice::from_raw_parts(buffer, capacity) }, vec![0u8; capacity].as_slice());
+    }
+
+    #[test]
+    fn wipe_plain_bytes() {
+        let mut key = ([3u8; 16], [5u8; 16]);
+
+        unsafe { wipe(&mut key) };
+
+        assert_eq!(key, ([0u8; 16], [0u8; 16]));
+    }
+}
@@ -0,0 +1,235 @@
+use crate::encryption::hash_by_parts;
+use crate::encryption::Rng;
+use crate::encryption::secret::{SecretKey, Zeroizing};
+use crate::error::{CommonError, CommonResult};
+use std::fmt;
+use std::str::FromStr;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+// Shamir secret sharing of a 32 byte key over GF(2^8), byte by byte. Any
+// threshold shares rebuild the key, fewer reveal nothing about it.
+//
+// Shares are printed as threshold-index-value-check, the value in hex and the
+// check the first two bytes of
+//   hash_by_parts("shamir_share", threshold, index, value)
+// so a mistyped share is caught before it is combined.
+#[derive(Clone, Debug, PartialEq, Zeroize, ZeroizeOnDrop)]
+pub struct Share {
+    pub threshold: u8,
+    pub index: u8,
+    value: [u8; 32],
+}
+
+pub fn split(secret: &[u8; 32], threshold: u8, count: u8) -> CommonResult<Vec<Share>> {
+    if threshold < 2 || count < threshold {
+        return Err(CommonError::Misconfiguration(Some(
+            "Shares need a threshold of at least 2 and no more than the number of shares.".to_owned(),
+        )));
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut coefficients = Zeroizing::new(vec![0u8; threshold as usize]);
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|index| Share { threshold, index, value: [0u8; 32] })
+        .collect();
+
+    for (position, byte) in secret.iter().enumerate() {
+        coefficients[0] = *byte;
+        rng.fill(&mut coefficients[1..]);
+
+        for share in shares.iter_mut() {
+            share.value[position] = evaluate(&coefficients, share.index);
+        }
+    }
+
+    Ok(shares)
+}
+
+pub fn combine(shares: &[Share]) -> CommonResult<SecretKey> {
+    let threshold = match shares.first() {
+        Some(share) => share.threshold,
+        None => return Err(CommonError::TooFewResults(Some("No shares given.".to_owned()))),
+    };
+
+    let mut used: Vec<&Share> = Vec::new();
+
+    for share in shares {
+        if share.threshold != threshold {
+            return Err(CommonError::FailedVerification(Some(
+                "Shares come from different splits.".to_owned(),
+            )));
+        }
+
+        if !used.iter().any(|other| other.index == share.index) {
+            used.push(share);
+        }
+    }
+
+    if used.len() < threshold as usize {
+        return Err(CommonError::TooFewResults(Some(format!(
+            "{} of {} shares needed.",
+            used.len(),
+            threshold
+        ))));
+    }
+
+    used.truncate(threshold as usize);
+
+    // Lagrange interpolation at zero, subtraction is xor.
+    let mut secret = SecretKey::new([0u8; 32]);
+
+    for share in &used {
+        let mut basis = 1u8;
+
+        for other in &used {
+            if other.index != share.index {
+                basis = gf_mul(basis, gf_mul(other.index, gf_inverse(other.index ^ share.index)));
+            }
+        }
+
+        for (position, byte) in secret.iter_mut().enumerate() {
+            *byte ^= gf_mul(share.value[position], basis);
+        }
+    }
+
+    Ok(secret)
+}
+
+impl Share {
+    fn check(&self) -> [u8; 2] {
+        let hash = hash_by_parts(&[b"shamir_share", &[self.threshold], &[self.index], &self.value]);
+        [hash[0], hash[1]]
+    }
+}
+
+impl fmt::Display for Share {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}-{}-{}", self.threshold, self.index, hex(&self.value), hex(&self.check()))
+    }
+}
+
+impl FromStr for Share {
+    type Err = CommonError;
+
+    fn from_str(text: &str) -> CommonResult<Share> {
+        let invalid = || CommonError::FailedVerification(Some("Invalid share.".to_owned()));
+        let parts: Vec<&str> = text.trim().split('-').collect();
+
+        if parts.len() != 4 {
+            return Err(invalid());
+        }
+
+        let threshold: u8 = parts[0].parse().map_err(|_| invalid())?;
+        let index: u8 = parts[1].parse().map_err(|_| invalid())?;
+        let value = from_hex(parts[2]).ok_or_else(invalid)?;
+        let check = from_hex(parts[3]).ok_or_else(invalid)?;
+
+        if index == 0 || value.len() != 32 {
+            return Err(invalid());
+        }
+
+        let mut share = Share { threshold, index, value: [0u8; 32] };
+        share.value.copy_from_slice(&value);
+
+        if share.check()[..] != check[..] {
+            return Err(invalid());
+        }
+
+        Ok(share)
+    }
+}
+
+fn evaluate(coefficients: &[u8], x: u8) -> u8 {
+    coefficients.iter().rev().fold(0u8, |result, coefficient| gf_mul(result, x) ^ coefficient)
+}
+
+// Multiplication modulo x^8 + x^4 + x^3 + x + 1 without branching on the
+// operands.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = a >> 7;
+        a = (a << 1) ^ (0x1b & 0u8.wrapping_sub(carry));
+        b >>= 1;
+    }
+
+    product
+}
+
+// a^254, the inverse of any non zero a.
+fn gf_inverse(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut power = a;
+
+    for bit in 0..8 {
+        if (254u8 >> bit) & 1 == 1 {
+            result = gf_mul(result, power);
+        }
+
+        power = gf_mul(power, power);
+    }
+
+    result
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Zeroizing<Vec<u8>>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+
+    let mut bytes = Zeroizing::new(Vec::with_capacity(text.len() / 2));
+
+    for position in (0..text.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(&text[position..position + 2], 16).ok()?);
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::random_int_256;
+
+    #[test]
+    fn any_threshold_shares_combine() {
+        let secret = random_int_256();
+        let shares = split(&secret, 3, 5).unwrap();
+
+        for (a, b, c) in &[(0, 1, 2), (4, 2, 0), (1, 3, 4)] {
+            let chosen = [shares[*a].clone(), shares[*b].clone(), shares[*c].clone()];
+            assert_eq!(*combine(&chosen).unwrap(), secret);
+        }
+
+        assert!(combine(&shares[..2]).is_err());
+        assert!(combine(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_err());
+        assert!(split(&secret, 1, 5).is_err());
+        assert!(split(&secret, 4, 3).is_err());
+    }
+
+    #[test]
+    fn shares_round_trip_as_text() {
+        let secret = random_int_256();
+        let shares = split(&secret, 2, 3).unwrap();
+        let text = shares[2].to_string();
+
+        assert_eq!(text.parse::<Share>().unwrap(), shares[2]);
+
+        // A single mistyped character fails the check.
+        let typo = format!("{}{}", &text[..10], if &text[10..11] == "0" { "1" } else { "0" });
+        assert!(format!("{}{}", typo, &text[11..]).parse::<Share>().is_err());
+    }
+
+    #[test]
+    fn field_inverse() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inverse(a)), 1);
+        }
+    }
+}
@@ -1,86 +1,174 @@
+use crate::encryption::algorithm::KeyAlgorithm;
 use crate::encryption::byte_encryption::{decrypt_32, encrypt_32};
 use crate::encryption::ed25519_compact::{KeyPair, Noise, PublicKey, Seed, Signature};
 use crate::encryption::random_int_256;
-use crate::encryption::{PUBLIC_KEY_LENGTH, SECRET_KEY_LENGTH};
-use crate::error::CommonResult;
-use std::convert::TryInto;
-
-pub fn verify_signature(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
-    let public = match PublicKey::from_slice(public_key) {
-        Ok(pk) => pk,
-        Err(_) => return false,
-    };
-
-    let lib_signature = match Signature::from_slice(signature) {
-        Ok(s) => s,
-        Err(_) => return false,
-    };
-
-    public.verify(message, &lib_signature).is_ok()
+use crate::encryption::secret::{wipe, SecretKey};
+use crate::encryption::SECRET_KEY_LENGTH;
+use crate::error::{CommonError, CommonResult};
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::ecdsa;
+
+pub fn verify_signature(
+    algorithm: KeyAlgorithm,
+    public_key: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> bool {
+    match algorithm {
+        KeyAlgorithm::Ed25519 => {
+            let public = match PublicKey::from_slice(public_key) {
+                Ok(pk) => pk,
+                Err(_) => return false,
+            };
+
+            let lib_signature = match Signature::from_slice(signature) {
+                Ok(s) => s,
+                Err(_) => return false,
+            };
+
+            public.verify(message, &lib_signature).is_ok()
+        }
+        KeyAlgorithm::P256 => {
+            let public = match ecdsa::VerifyingKey::from_sec1_bytes(public_key) {
+                Ok(pk) => pk,
+                Err(_) => return false,
+            };
+
+            let lib_signature = match ecdsa::Signature::from_slice(signature) {
+                Ok(s) => s,
+                Err(_) => return false,
+            };
+
+            public.verify(message, &lib_signature).is_ok()
+        }
+        KeyAlgorithm::X25519 | KeyAlgorithm::X25519MlKem768 => false,
+    }
+}
+
+// P-256 keys wipe themselves, ed25519-compact key pairs are wiped here.
+#[derive(Clone)]
+enum KeyPairs {
+    Ed25519 {
+        seed: SecretKey,
+        key_pair: Ed25519KeyPair,
+    },
+    P256(ecdsa::SigningKey),
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
+struct Ed25519KeyPair(KeyPair);
+
+impl Drop for Ed25519KeyPair {
+    fn drop(&mut self) {
+        // KeyPair is a public key and a secret key, both plain byte arrays.
+        unsafe { wipe(&mut self.0) };
+    }
+}
+
+#[derive(Clone)]
 pub struct SigningKey {
-    seed: [u8; SECRET_KEY_LENGTH],
-    key_pair: KeyPair,
+    key: KeyPairs,
 }
 
 impl SigningKey {
     pub fn new() -> SigningKey {
-        let seed = random_int_256();
-        let pair = KeyPair::from_seed(Seed::new(seed));
+        SigningKey::generate(KeyAlgorithm::Ed25519).expect("Ed25519 keys can always be generated.")
+    }
 
-        SigningKey {
-            seed,
-            key_pair: pair,
+    pub fn generate(algorithm: KeyAlgorithm) -> CommonResult<SigningKey> {
+        loop {
+            // A random P-256 scalar is out of range with negligible probability.
+            match SigningKey::from_private_key(algorithm, &SecretKey::new(random_int_256())) {
+                Err(CommonError::FailedVerification(_)) => continue,
+                result => return result,
+            }
         }
     }
 
-    pub fn from_keys(
-        _public: &[u8; PUBLIC_KEY_LENGTH],
-        private: &[u8; SECRET_KEY_LENGTH],
-    ) -> SigningKey {
-        SigningKey {
-            seed: private.to_owned(),
-            key_pair: KeyPair::from_seed(Seed::new(private.to_owned())),
-        }
+    pub fn from_private_key(algorithm: KeyAlgorithm, private: &[u8; SECRET_KEY_LENGTH]) -> CommonResult<SigningKey> {
+        let key = match algorithm {
+            KeyAlgorithm::Ed25519 => KeyPairs::Ed25519 {
+                seed: SecretKey::new(*private),
+                key_pair: Ed25519KeyPair(KeyPair::from_seed(Seed::new(*private))),
+            },
+            KeyAlgorithm::P256 => KeyPairs::P256(
+                ecdsa::SigningKey::from_slice(private)
+                    .map_err(|_| CommonError::FailedVerification(Some("Invalid P-256 private key.".to_owned())))?,
+            ),
+            KeyAlgorithm::X25519 | KeyAlgorithm::X25519MlKem768 => {
+                return Err(CommonError::Misconfiguration(Some("Exchange keys cannot sign.".to_owned())))
+            }
+        };
+
+        Ok(SigningKey { key })
     }
 
-    // Fails if IV authentication fails.
+    // Fails if envelope authentication fails or the decrypted key does not
+    // match the public key.
     pub fn from_encrypted(
         encryption_key: &[u8; 32],
-        public: &[u8; PUBLIC_KEY_LENGTH],
-        encrypted_key: &[u8; 64],
+        algorithm: KeyAlgorithm,
+        public: &[u8],
+        encrypted_key: &[u8],
+        associated_data: &[u8],
     ) -> CommonResult<SigningKey> {
-        let decrypted_key = &decrypt_32(encrypted_key, encryption_key)?;
+        let decrypted_key = decrypt_32(encrypted_key, encryption_key, associated_data)?;
+        let key = SigningKey::from_private_key(algorithm, &decrypted_key)?;
+
+        if key.public_key() != public {
+            return Err(CommonError::FailedVerification(Some("Private key does not match public key.".to_owned())));
+        }
 
-        Ok(SigningKey::from_keys(public, &decrypted_key))
+        Ok(key)
+    }
+
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        match self.key {
+            KeyPairs::Ed25519 { .. } => KeyAlgorithm::Ed25519,
+            KeyPairs::P256(_) => KeyAlgorithm::P256,
+        }
     }
 
     pub fn sign(&self, data: &[u8]) -> Vec<u8> {
-        self.key_pair
-            .sk
-            .sign(data, Some(Noise::default()))
-            .as_ref()
-            .to_vec()
+        match &self.key {
+            KeyPairs::Ed25519 { key_pair, .. } => key_pair
+                .0
+                .sk
+                .sign(data, Some(Noise::default()))
+                .as_ref()
+                .to_vec(),
+            KeyPairs::P256(key) => {
+                let signature: ecdsa::Signature = key.sign(data);
+                signature.to_bytes().to_vec()
+            }
+        }
     }
 
     pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
-        let lib_signature = match Signature::from_slice(signature) {
-            Ok(s) => s,
-            Err(_) => return false,
-        };
+        verify_signature(self.algorithm(), &self.public_key(), message, signature)
+    }
 
-        self.key_pair.pk.verify(message, &lib_signature).is_ok()
+    pub fn public_key(&self) -> Vec<u8> {
+        match &self.key {
+            KeyPairs::Ed25519 { key_pair, .. } => key_pair.0.pk.to_vec(),
+            KeyPairs::P256(key) => key.verifying_key().to_encoded_point(true).as_bytes().to_vec(),
+        }
     }
 
-    pub fn public_key(&self) -> [u8; PUBLIC_KEY_LENGTH] {
-        let output: [u8; PUBLIC_KEY_LENGTH] = self.key_pair.pk.as_ref().try_into().unwrap();
-        output
+    // The Ed25519 seed or P-256 scalar, what from_private_key takes.
+    pub fn private_key(&self) -> SecretKey {
+        match &self.key {
+            KeyPairs::Ed25519 { seed, .. } => seed.clone(),
+            KeyPairs::P256(key) => {
+                let mut private = SecretKey::new([0u8; SECRET_KEY_LENGTH]);
+                private.copy_from_slice(&key.to_bytes());
+                private
+            }
+        }
     }
 
-    pub fn encrypted_private_key(&self, encryption_key: &[u8; 32]) -> [u8; 64] {
-        encrypt_32(&self.seed, &encryption_key)
+    pub fn encrypted_private_key(&self, encryption_key: &[u8; 32], associated_data: &[u8]) -> Vec<u8> {
+        encrypt_32(&self.private_key(), encryption_key, associated_data)
     }
 }
 
@@ -101,13 +189,54 @@ mod tests {
         let mut rng = rand::thread_rng();
         rng.fill(&mut key);
 
-        let encrypted_key = ed_key.encrypted_private_key(&key);
+        let encrypted_key = ed_key.encrypted_private_key(&key, &public_key);
 
-        let restored_key = SigningKey::from_encrypted(&key, &public_key, &encrypted_key).unwrap();
+        let restored_key =
+            SigningKey::from_encrypted(&key, KeyAlgorithm::Ed25519, &public_key, &encrypted_key, &public_key).unwrap();
 
         // create a new key
         let signature = ed_key.sign(b"Info to sign");
         let verified = restored_key.verify(b"Info to sign", &signature);
         assert!(verified);
     }
+
+    #[test]
+    fn sign_verify_p256() {
+        let key = SigningKey::generate(KeyAlgorithm::P256).unwrap();
+        let encryption_key = random_int_256();
+        let public_key = key.public_key();
+
+        assert_eq!(public_key.len(), 33);
+
+        let encrypted_key = key.encrypted_private_key(&encryption_key, b"context");
+        let restored_key =
+            SigningKey::from_encrypted(&encryption_key, KeyAlgorithm::P256, &public_key, &encrypted_key, b"context").unwrap();
+
+        let signature = restored_key.sign(b"Info to sign");
+
+        assert!(verify_signature(KeyAlgorithm::P256, &public_key, b"Info to sign", &signature));
+        assert!(!verify_signature(KeyAlgorithm::P256, &public_key, b"Other info", &signature));
+        assert!(!verify_signature(KeyAlgorithm::Ed25519, &public_key, b"Info to sign", &signature));
+        assert!(SigningKey::from_encrypted(&encryption_key, KeyAlgorithm::Ed25519, &public_key, &encrypted_key, b"context").is_err());
+        assert!(SigningKey::generate(KeyAlgorithm::X25519).is_err());
+    }
+
+    #[test]
+    fn signing_key_is_wiped() {
+        use crate::encryption::secret::dropped_bytes;
+        use std::mem::ManuallyDrop;
+
+        let seed = random_int_256();
+        let key = SigningKey::from_private_key(KeyAlgorithm::Ed25519, &seed).unwrap();
+        let secret = key.clone();
+        let secret = match &secret.key {
+            KeyPairs::Ed25519 { key_pair, .. } => key_pair.0.sk.to_vec(),
+            _ => unreachable!(),
+        };
+
+        let bytes = dropped_bytes(ManuallyDrop::new(key));
+
+        assert!(!bytes.windows(32).any(|window| window == seed));
+        assert!(!bytes.windows(64).any(|window| window == secret.as_slice()));
+    }
 }
@@ -50,6 +50,9 @@ impl From<diesel::result::Error> for CommonError {
             diesel::result::Error::InvalidCString(_) => {
                 CommonError::LibraryError(Some("Diesel InvalidCString".to_string()))
             }
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) => {
+                CommonError::Duplicate(Some("Record already exists.".to_string()))
+            }
             diesel::result::Error::DatabaseError(_, _) => {
                 CommonError::LibraryError(Some("Diesel DatabaseError".to_string()))
             }
@@ -87,3 +90,15 @@ impl From<std::io::Error> for CommonError {
         CommonError::LibraryError(Some("IO Resource Error.".to_owned()))
     }
 }
+
+impl From<serde_json::Error> for CommonError {
+    fn from(_err: serde_json::Error) -> CommonError {
+        CommonError::LibraryError(Some("JSON Error.".to_owned()))
+    }
+}
+
+impl From<serde_cbor::Error> for CommonError {
+    fn from(_err: serde_cbor::Error) -> CommonError {
+        CommonError::LibraryError(Some("CBOR Error.".to_owned()))
+    }
+}
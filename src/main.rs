@@ -21,10 +21,11 @@ fn main() -> Result<()> {
         .author("Daniel W. <daniel@stonecottageweb.com>")
         .about("Stores keys and authenticates messages on behalf of the user.")
         .subcommand(cli::account::init())
+        .subcommand(cli::admin::init())
         .subcommand(cli::application::init())
         .subcommand(cli::client::init())
-        //.subcommand(cli::export::init())
-        //.subcommand(cli::import::init())
+        .subcommand(cli::export::init())
+        .subcommand(cli::import::init())
         .subcommand(cli::init::init())
         //.subcommand(cli::scope::init())
         //.subcommand(cli::sign::init())
@@ -36,8 +37,11 @@ fn main() -> Result<()> {
     let status: Result<()> = match matches.subcommand() {
         ("init", _)              => cli::init::run(),
         ("account", Some(m))     => cli::account::run(m),
+        ("admin", Some(m))       => cli::admin::run(m),
         ("application", Some(m)) => cli::application::run(m),
         ("client", Some(m))      => cli::client::run(m),
+        ("export", Some(m))      => cli::export::run(m),
+        ("import", Some(m))      => cli::import::run(m),
         ("run", _)               => web::run(),
         (c, _)                   => bail!("Subcommand {} not recognized.", c),
     };
@@ -1,29 +1,68 @@
 use base64::{decode, encode};
-use clear_on_drop::clear::Clear;
 use crate::database::schema::account;
 use crate::database::MyConnection;
 use diesel::prelude::*;
 use diesel::update;
-use crate::encryption::byte_encryption::{decrypt_32, encrypt_32};
+use crate::encryption::byte_encryption::{decrypt_32, encrypt_32, is_legacy};
+use crate::encryption::algorithm::KeyAlgorithm;
 use crate::encryption::signing_key::verify_signature;
 use crate::encryption::signing_key::SigningKey;
-use crate::encryption::{
-    check_password, hash_password, hash_salted_password, as_256, random_int_256, secure_hash,
+use crate::encryption::password::{
+    check_password, derive_password_key, hash_password, key_derivation_needs_upgrade,
+    key_derivation_params, password_needs_rehash,
 };
-use crate::encryption::{decode_32, decode_64, as_512};
+use crate::encryption::key_derivation::{derive_key, KeyPurpose};
+use crate::encryption::key_format::{decode_private_key, encode_private_key, encode_public_key, KeyFormat};
+use crate::encryption::random_int_256;
+use crate::encryption::shamir::{combine, Share};
+use crate::encryption::secret::{SecretKey, SecretString};
 use crate::error::{CommonError, CommonResult};
+use crate::model::account_import::BundleKey;
+use crate::model::account_key_slot::{AccountKeySlot, RECOVERY_SHARES_LABEL};
 use crate::model::application::Application;
+use crate::model::recovery_kit::RecoveryKit;
 use crate::model::application::PortableApplication;
-use crate::model::{Signable, Signed};
+use crate::model::read_scope::ReadScope;
+use crate::model::write_scope::WriteScope;
+use crate::model::{record_context, Signable, Signed};
 use crate::model::{Certifiable, Certified};
+use std::str::FromStr;
 
+// An account with everything it owns, for moving an identity between
+// servers. The bundle key derived from the export passphrase wraps the
+// account signing key and every scope key, each bound to its record by
+// context.
+#[derive(Serialize, Deserialize)]
 pub struct PortableAccount {
     pub public_key: String,
     pub private_key_salt: String,
+    pub key_algorithm: String,
+    pub key_derivation: String,
     pub encrypted_private_key: String,
     pub applications: Vec<PortableApplication>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortableFormat {
+    Json,
+    Cbor,
+}
+
+impl FromStr for PortableFormat {
+    type Err = CommonError;
+
+    fn from_str(format: &str) -> CommonResult<PortableFormat> {
+        match format {
+            "json" => Ok(PortableFormat::Json),
+            "cbor" => Ok(PortableFormat::Cbor),
+            _ => Err(CommonError::NotFound(Some(format!(
+                "Unknown export format {}.",
+                format
+            )))),
+        }
+    }
+}
+
 pub struct Account {}
 
 #[derive(Insertable)]
@@ -38,6 +77,8 @@ pub struct NewAccount {
     pub master_key_salt: Vec<u8>,
     pub encrypted_master_key: Vec<u8>,
     pub is_admin: bool,
+    pub master_key_kdf: String,
+    pub key_algorithm: KeyAlgorithm,
 }
 
 #[derive(PartialEq, Debug, Queryable, Identifiable, AsChangeset)]
@@ -53,6 +94,8 @@ pub struct LockedAccount {
     pub master_key_salt: Vec<u8>,
     pub encrypted_master_key: Vec<u8>,
     pub is_admin: bool,
+    pub master_key_kdf: String,
+    pub key_algorithm: KeyAlgorithm,
 }
 
 //#[derive(Debug)]
@@ -67,7 +110,9 @@ pub struct UnlockedAccount {
     pub master_key_salt: Vec<u8>,
     pub encrypted_master_key: Vec<u8>,
     pub is_admin: bool,
-    master_key: [u8; 32],
+    pub master_key_kdf: String,
+    pub key_algorithm: KeyAlgorithm,
+    master_key: SecretKey,
     signing_key: SigningKey,
 }
 
@@ -84,10 +129,53 @@ impl Account {
 
     pub fn load_unlocked(
         name: String,
-        password: String,
+        password: SecretString,
         connection: &MyConnection,
     ) -> CommonResult<UnlockedAccount> {
-        Account::load_locked(&name, connection)?.to_unlocked(&password)
+        let locked = Account::load_locked(&name, connection)?;
+        let unlocked = locked.to_unlocked(&password)?;
+
+        // Unlocking may have re-encrypted legacy keys, persist them if so.
+        let upgraded = LockedAccount::from(&unlocked);
+
+        if upgraded != locked {
+            upgraded.save(connection)?;
+        }
+
+        Ok(unlocked)
+    }
+
+    // Unlocks the account with one of its key slots instead of the password.
+    pub fn load_unlocked_with_slot(
+        name: &str,
+        label: &str,
+        secret: &[u8],
+        connection: &MyConnection,
+    ) -> CommonResult<UnlockedAccount> {
+        let locked = Account::load_locked(name, connection)?;
+        let slot = AccountKeySlot::load(locked.id, label, connection)?;
+
+        slot.unlock(&locked, secret)
+    }
+
+    // Rebuilds access from recovery shares. The account is only handed to
+    // change_password, recovery always ends with a new password. The shares
+    // work once, their slot is deleted along with the password change.
+    pub fn recover(
+        name: &str,
+        shares: &[Share],
+        new_password: &str,
+        connection: &MyConnection,
+    ) -> CommonResult<()> {
+        let recovery_key = combine(shares)?;
+
+        connection.transaction::<_, CommonError, _>(|| {
+            let account = Account::load_unlocked_with_slot(name, RECOVERY_SHARES_LABEL, &*recovery_key, connection)?;
+            let account_id = account.id;
+
+            account.change_password(new_password, connection)?;
+            AccountKeySlot::delete(account_id, RECOVERY_SHARES_LABEL, connection)
+        })
     }
 
     pub fn delete_id(id: &i32, connection: &MyConnection) -> CommonResult<()> {
@@ -110,13 +198,17 @@ impl NewAccount {
         is_admin: bool,
     ) -> NewAccount {
         let master_key_salt = random_int_256().to_vec();
-        let master_encryption_key = hash_salted_password(password, &master_key_salt);
-        let master_key = random_int_256();
-        let encrypted_master_key = encrypt_32(&master_key, &master_encryption_key).to_vec();
-        let encrypted_private_key = signing_key.encrypted_private_key(&master_key).to_vec();
+        let master_key_kdf = key_derivation_params();
+        let master_encryption_key = derive_password_key(password, &master_key_salt, &master_key_kdf)
+            .expect("Configured key derivation parameters are valid.");
+        let master_key = SecretKey::new(random_int_256());
+        let public_key = signing_key.public_key().to_vec();
+        let encrypted_master_key =
+            encrypt_32(&master_key, &master_encryption_key, &master_key_context(&public_key));
+        let encrypted_private_key =
+            signing_key.encrypted_private_key(&master_key, &private_key_context(&public_key));
         let password_hash = hash_password(password);
         let export_key_hash = hash_password(export_key);
-        let public_key = signing_key.public_key().to_vec();
 
         NewAccount {
             name: name.to_owned(),
@@ -128,9 +220,26 @@ impl NewAccount {
             master_key_salt,
             encrypted_master_key,
             is_admin,
+            master_key_kdf,
+            key_algorithm: signing_key.algorithm(),
         }
     }
 
+    // An account whose identity is an existing Ed25519 key, given as PEM,
+    // OpenSSH or JWK.
+    pub fn from_key_file(
+        name: &str,
+        email: &str,
+        password: &str,
+        export_key: &str,
+        key_file: &str,
+        is_admin: bool,
+    ) -> CommonResult<NewAccount> {
+        let signing_key = decode_private_key(key_file)?;
+
+        Ok(NewAccount::with_key(name, email, password, export_key, signing_key, is_admin))
+    }
+
     pub fn from_portable(
         name: &str,
         email: &str,
@@ -139,13 +248,15 @@ impl NewAccount {
         import_passphrase: &str,
         import: &PortableAccount,
     ) -> CommonResult<NewAccount> {
-        let encryption_key =
-            hash_salted_password(import_passphrase, &decode(&import.private_key_salt)?);
+        let encryption_key = import.bundle_key(import_passphrase)?;
+        let public_key = decode(&import.public_key)?;
 
         let signing_key = SigningKey::from_encrypted(
             &encryption_key,
-            &decode_32(&import.public_key)?,
-            &decode_64(&import.encrypted_private_key)?,
+            import.key_algorithm.parse()?,
+            &public_key,
+            &decode(&import.encrypted_private_key)?,
+            &portable_key_context(&public_key),
         )?;
 
         Ok(NewAccount::with_key(
@@ -165,19 +276,130 @@ impl NewAccount {
     }
 }
 
+impl PortableAccount {
+    // The key wrapping the keys in the bundle.
+    pub fn bundle_key(&self, passphrase: &str) -> CommonResult<SecretKey> {
+        derive_password_key(passphrase, &decode(&self.private_key_salt)?, &self.key_derivation)
+    }
+
+    // The bundle key, checked by opening the account key with it.
+    pub fn unlock(&self, passphrase: &str) -> CommonResult<BundleKey> {
+        let key = self.bundle_key(passphrase)?;
+        let public_key = decode(&self.public_key)?;
+        let algorithm = self.key_algorithm.parse()?;
+
+        SigningKey::from_encrypted(
+            &key,
+            algorithm,
+            &public_key,
+            &decode(&self.encrypted_private_key)?,
+            &portable_key_context(&public_key),
+        )
+        .map_err(|_| CommonError::CouldNotAuthenticate(Some("Passphrase does not open the bundle.".to_owned())))?;
+
+        Ok(BundleKey::new(key, algorithm, public_key))
+    }
+
+    pub fn serialize(&self, format: PortableFormat) -> CommonResult<Vec<u8>> {
+        match format {
+            PortableFormat::Json => Ok(serde_json::to_vec_pretty(self)?),
+            PortableFormat::Cbor => Ok(serde_cbor::to_vec(self)?),
+        }
+    }
+
+    pub fn deserialize(format: PortableFormat, data: &[u8]) -> CommonResult<PortableAccount> {
+        match format {
+            PortableFormat::Json => Ok(serde_json::from_slice(data)?),
+            PortableFormat::Cbor => Ok(serde_cbor::from_slice(data)?),
+        }
+    }
+}
+
 impl LockedAccount {
     pub fn to_unlocked(&self, password: &str) -> CommonResult<UnlockedAccount> {
         if !check_password(password, &self.password_hash) {
             return Err(CommonError::CouldNotAuthenticate(None));
         }
 
-        let master_encryption_key = hash_salted_password(password, &self.master_key_salt);
-        let master_key = decrypt_32(as_512(&self.encrypted_master_key), &master_encryption_key)?;
+        let master_encryption_key =
+            derive_password_key(password, &self.master_key_salt, &self.master_key_kdf)?;
+        let master_context = master_key_context(&self.public_key);
+        let private_context = private_key_context(&self.public_key);
+
+        let master_key = decrypt_32(
+            &self.encrypted_master_key,
+            &master_encryption_key,
+            &master_context,
+        )?;
+
+        let signing_key = SigningKey::from_encrypted(
+            &master_key,
+            self.key_algorithm,
+            &self.public_key,
+            &self.encrypted_private_key,
+            &private_context,
+        )?;
+
+        // Re-encrypt keys stored in the legacy format and rehash the password
+        // if it was hashed with weaker settings than currently configured.
+        // The caller is responsible for saving the upgraded record.
+        let password_hash = if password_needs_rehash(&self.password_hash) {
+            hash_password(password)
+        } else {
+            self.password_hash.clone()
+        };
+
+        let (master_key_salt, master_key_kdf, encrypted_master_key) =
+            if key_derivation_needs_upgrade(&self.master_key_kdf) {
+                let salt = random_int_256().to_vec();
+                let kdf = key_derivation_params();
+                let key = derive_password_key(password, &salt, &kdf)?;
+                let encrypted = encrypt_32(&master_key, &key, &master_context);
+                (salt, kdf, encrypted)
+            } else if is_legacy(&self.encrypted_master_key) {
+                let encrypted = encrypt_32(&master_key, &master_encryption_key, &master_context);
+                (self.master_key_salt.clone(), self.master_key_kdf.clone(), encrypted)
+            } else {
+                (
+                    self.master_key_salt.clone(),
+                    self.master_key_kdf.clone(),
+                    self.encrypted_master_key.clone(),
+                )
+            };
+
+        let encrypted_private_key = if is_legacy(&self.encrypted_private_key) {
+            signing_key.encrypted_private_key(&master_key, &private_context)
+        } else {
+            self.encrypted_private_key.clone()
+        };
+
+        Ok(UnlockedAccount {
+            id: self.id,
+            name: self.name.clone(),
+            email: self.email.clone(),
+            password_hash,
+            export_key_hash: self.export_key_hash.clone(),
+            public_key: self.public_key.clone(),
+            encrypted_private_key,
+            master_key_salt,
+            encrypted_master_key,
+            is_admin: self.is_admin,
+            master_key_kdf,
+            key_algorithm: self.key_algorithm,
+            master_key,
+            signing_key,
+        })
+    }
 
+    // Unlocks with a master key recovered from a key slot. Nothing is
+    // upgraded, that happens on the next password unlock.
+    pub fn unlock_with_master_key(&self, master_key: SecretKey) -> CommonResult<UnlockedAccount> {
         let signing_key = SigningKey::from_encrypted(
             &master_key,
-            &as_256(&self.public_key),
-            &as_512(&self.encrypted_private_key),
+            self.key_algorithm,
+            &self.public_key,
+            &self.encrypted_private_key,
+            &private_key_context(&self.public_key),
         )?;
 
         Ok(UnlockedAccount {
@@ -191,13 +413,15 @@ impl LockedAccount {
             master_key_salt: self.master_key_salt.clone(),
             encrypted_master_key: self.encrypted_master_key.clone(),
             is_admin: self.is_admin,
+            master_key_kdf: self.master_key_kdf.clone(),
+            key_algorithm: self.key_algorithm,
             master_key,
             signing_key,
         })
     }
 
     pub fn verify_record(&self, record: &impl Signed) -> bool {
-        verify_signature(&self.public_key, &record.record_hash(), &record.signature())
+        verify_signature(self.key_algorithm, &self.public_key, &record.record_hash(), &record.signature())
     }
 
     pub fn save(&self, connection: &MyConnection) -> CommonResult<()> {
@@ -210,6 +434,12 @@ impl LockedAccount {
 
 impl From<UnlockedAccount> for LockedAccount {
     fn from(unlocked: UnlockedAccount) -> LockedAccount {
+        LockedAccount::from(&unlocked)
+    }
+}
+
+impl From<&UnlockedAccount> for LockedAccount {
+    fn from(unlocked: &UnlockedAccount) -> LockedAccount {
         LockedAccount {
             id: unlocked.id,
             name: unlocked.name.clone(),
@@ -221,6 +451,8 @@ impl From<UnlockedAccount> for LockedAccount {
             master_key_salt: unlocked.master_key_salt.clone(),
             encrypted_master_key: unlocked.encrypted_master_key.clone(),
             is_admin: unlocked.is_admin,
+            master_key_kdf: unlocked.master_key_kdf.clone(),
+            key_algorithm: unlocked.key_algorithm,
         }
     }
 }
@@ -230,23 +462,32 @@ impl UnlockedAccount {
         &self,
         export_key: &str,
         passphrase: &str,
-        _connection: &MyConnection,
+        connection: &MyConnection,
     ) -> CommonResult<PortableAccount> {
         if !check_password(export_key, &self.export_key_hash) {
             return Err(CommonError::CouldNotAuthenticate(None));
         }
 
         let private_key_salt = random_int_256();
-        let encryption_key = hash_salted_password(passphrase, &private_key_salt);
-        let encrypted_private_key = self.signing_key.encrypted_private_key(&encryption_key);
+        let key_derivation = key_derivation_params();
+        let encryption_key = derive_password_key(passphrase, &private_key_salt, &key_derivation)?;
+        let encrypted_private_key = self
+            .signing_key
+            .encrypted_private_key(&encryption_key, &portable_key_context(&self.public_key));
+
+        let mut applications = Vec::new();
 
-        let applications = Vec::new();
+        for application in Application::load_all_for_account(self, connection)? {
+            applications.push(application.to_portable(self, &encryption_key, connection)?);
+        }
 
         Ok(PortableAccount {
             public_key: encode(&self.public_key),
+            key_algorithm: self.signing_key.algorithm().name().to_owned(),
             private_key_salt: encode(&private_key_salt),
-            encrypted_private_key: encode(&encrypted_private_key.to_vec()),
-            applications: applications,
+            key_derivation,
+            encrypted_private_key: encode(&encrypted_private_key),
+            applications,
         })
     }
 
@@ -268,12 +509,81 @@ impl UnlockedAccount {
         self.signing_key.sign(data)
     }
 
+    pub fn algorithm(&self) -> KeyAlgorithm {
+        self.signing_key.algorithm()
+    }
+
     pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
         self.signing_key.verify(data, signature)
     }
 
-    pub fn generate_key(&self, salt: &[u8]) -> [u8; 32] {
-        secure_hash(&[&self.master_key, salt])
+    // Derive the key protecting a record owned by this account, see
+    // encryption::key_derivation for the hierarchy.
+    pub fn derive_key(
+        &self,
+        version: i16,
+        purpose: KeyPurpose,
+        record_type: &str,
+        record_id: &[&[u8]],
+        salt: &[u8],
+    ) -> CommonResult<SecretKey> {
+        derive_key(&self.master_key, version, purpose, record_type, record_id, salt)
+    }
+
+    pub fn check_export_key(&self, export_key: &str) -> bool {
+        check_password(export_key, &self.export_key_hash)
+    }
+
+    // A new random export key replaces the current one. The export key gates
+    // the private key, so the password alone can't replace it.
+    pub fn replace_export_key(&mut self, export_key: &str, connection: &MyConnection) -> CommonResult<SecretString> {
+        if !self.check_export_key(export_key) {
+            return Err(CommonError::CouldNotAuthenticate(Some("Export key not recognized.".to_owned())));
+        }
+
+        let export_key = SecretString::new(encode(random_int_256()));
+
+        self.export_key_hash = hash_password(&export_key);
+        LockedAccount::from(&*self).save(connection)?;
+
+        Ok(export_key)
+    }
+
+    pub fn recovery_kit(&self, export_key: SecretString) -> CommonResult<RecoveryKit> {
+        if !self.check_export_key(&export_key) {
+            return Err(CommonError::CouldNotAuthenticate(Some("Export key not recognized.".to_owned())));
+        }
+
+        Ok(RecoveryKit::new(&self.name, &self.signing_key, export_key))
+    }
+
+    pub fn export_public_key(&self, format: KeyFormat) -> CommonResult<String> {
+        encode_public_key(format, self.algorithm(), &self.public_key, &self.name)
+    }
+
+    // The account signing key in a format other tools read, released only
+    // with the export key.
+    pub fn export_private_key(&self, export_key: &str, format: KeyFormat) -> CommonResult<SecretString> {
+        self.export_signing_key(export_key, &self.signing_key, format, &self.name)
+    }
+
+    pub fn export_signing_key(
+        &self,
+        export_key: &str,
+        signing_key: &SigningKey,
+        format: KeyFormat,
+        comment: &str,
+    ) -> CommonResult<SecretString> {
+        if !self.check_export_key(export_key) {
+            return Err(CommonError::CouldNotAuthenticate(Some("Export key not recognized.".to_owned())));
+        }
+
+        encode_private_key(format, signing_key, comment)
+    }
+
+    // The master key encrypted under another key, for key slots.
+    pub fn wrap_master_key(&self, wrapping_key: &[u8; 32], context: &[u8]) -> Vec<u8> {
+        encrypt_32(&self.master_key, wrapping_key, context)
     }
 
     pub fn change_password(
@@ -284,12 +594,17 @@ impl UnlockedAccount {
         // first all associated records need to be unlocked and stored.
 
         self.master_key_salt = random_int_256().to_vec();
-        let master_encryption_key = hash_salted_password(new_password, &self.master_key_salt);
-        self.encrypted_master_key = encrypt_32(&self.master_key, &master_encryption_key).to_vec();
+        self.master_key_kdf = key_derivation_params();
+        let master_encryption_key =
+            derive_password_key(new_password, &self.master_key_salt, &self.master_key_kdf)?;
+        self.encrypted_master_key = encrypt_32(
+            &self.master_key,
+            &master_encryption_key,
+            &master_key_context(&self.public_key),
+        );
         self.encrypted_private_key = self
             .signing_key
-            .encrypted_private_key(&self.master_key)
-            .to_vec();
+            .encrypted_private_key(&self.master_key, &private_key_context(&self.public_key));
         self.password_hash = hash_password(&new_password);
 
         self.save(connection)
@@ -303,21 +618,47 @@ impl UnlockedAccount {
         locked.save(connection)
     }
 
+    // Re-encrypts every legacy key owned by this account into the current
+    // envelope format. Returns the number of records rewritten.
+    pub fn upgrade_encryption(&self, connection: &MyConnection) -> CommonResult<usize> {
+        let mut upgraded = 0;
+
+        for application in Application::load_all_for_account(self, connection)? {
+            for write_scope in WriteScope::load_all_for_application(&application, connection)? {
+                upgraded += write_scope.upgrade_encryption(self, connection)?;
+            }
+
+            for read_scope in ReadScope::load_all_for_application(&application, connection)? {
+                upgraded += read_scope.upgrade_encryption(self, connection)?;
+            }
+        }
+
+        Ok(upgraded)
+    }
+
     pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
         let applications = Application::load_all_for_account(&self, connection)?;
 
         for app in applications {
-            app.delete(connection)?;
+            app.delete(&self, connection)?;
         }
 
+        AccountKeySlot::delete_for_account(self.id, connection)?;
+
         Account::delete_id(&self.id, connection)
     }
 }
 
-impl Drop for UnlockedAccount {
-    fn drop(&mut self) {
-        self.master_key.clear();
-    }
+fn master_key_context(public_key: &[u8]) -> [u8; 32] {
+    record_context("account_master_key", &[public_key])
+}
+
+fn private_key_context(public_key: &[u8]) -> [u8; 32] {
+    record_context("account_private_key", &[public_key])
+}
+
+fn portable_key_context(public_key: &[u8]) -> [u8; 32] {
+    record_context("portable_account", &[public_key])
 }
 
 #[cfg(test)]
@@ -377,7 +718,7 @@ mod tests {
         let locked = account.save(&connection).expect("Could not save");
         let unlocked = locked.to_unlocked("password").expect("Could not unlock");
 
-        let unlocked_loaded = Account::load_unlocked("Test02".to_owned(), "password".to_owned(), &connection)
+        let unlocked_loaded = Account::load_unlocked("Test02".to_owned(), SecretString::new("password".to_owned()), &connection)
             .expect("could not load from database");
 
         let message = b"Please sign and return";
@@ -436,4 +777,151 @@ mod tests {
 
         assert!(verified);
     }
+
+    #[test]
+    fn rehash_weak_password() {
+        use crate::encryption::password::hash_password_with;
+        use argon2::Params;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("Test05", "email05@example.com", "password", "passphrase", false);
+
+        let locked = account.save(&connection).expect("Could not save");
+        let mut unlocked = locked.to_unlocked("password").expect("Could not unlock");
+
+        // Store the account as if it was created with weaker settings.
+        let weak_kdf = "$argon2id$v=19$m=1024,t=1,p=1".to_owned();
+        let weak_key = derive_password_key("password", &unlocked.master_key_salt, &weak_kdf).unwrap();
+        unlocked.encrypted_master_key = encrypt_32(
+            &unlocked.master_key,
+            &weak_key,
+            &master_key_context(&unlocked.public_key),
+        );
+        unlocked.master_key_kdf = weak_kdf.clone();
+        unlocked.password_hash = hash_password_with("password", Params::new(1024, 1, 1, None).unwrap());
+        let weak_hash = unlocked.password_hash.clone();
+        unlocked.save(&connection).expect("Could not save");
+
+        Account::load_unlocked("Test05".to_owned(), SecretString::new("password".to_owned()), &connection)
+            .expect("Could not unlock");
+
+        let upgraded = Account::load_locked("Test05", &connection).expect("could not load from database");
+
+        assert_ne!(upgraded.password_hash, weak_hash);
+        assert!(!password_needs_rehash(&upgraded.password_hash));
+        assert_ne!(upgraded.master_key_kdf, weak_kdf);
+        assert!(!key_derivation_needs_upgrade(&upgraded.master_key_kdf));
+
+        let unlocked = upgraded.to_unlocked("password").expect("Could not unlock");
+
+        match unlocked.delete(&connection) {
+            Ok(_) => (),
+            Err(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn master_key_is_wiped() {
+        use crate::encryption::secret::dropped_bytes;
+        use std::mem::ManuallyDrop;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("Test06", "email06@example.com", "password", "passphrase", false);
+
+        let locked = account.save(&connection).expect("Could not save");
+        let unlocked = locked.to_unlocked("password").expect("Could not unlock");
+        let master_key = *unlocked.master_key;
+
+        Account::delete_id(&unlocked.id, &connection).expect("Could not delete");
+
+        let bytes = dropped_bytes(ManuallyDrop::new(unlocked));
+        assert!(!bytes.windows(32).any(|window| window == &master_key[..]));
+    }
+
+    #[test]
+    fn portable_account_round_trip() {
+        use crate::model::client::Client;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("Test07", "email07@example.com", "password", "passphrase", false);
+
+        let locked = account.save(&connection).expect("Could not save");
+        let unlocked = locked.to_unlocked("password").expect("Could not unlock");
+
+        let application = Application::new("portable", "Portable", "https://example.com", &unlocked)
+            .save(&connection)
+            .unwrap();
+        let (_, client) = Client::new(&unlocked, &application);
+        let client = client.save(&connection).unwrap();
+
+        let read_scope = ReadScope::new("read", &application, &unlocked)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked(&unlocked, &connection)
+            .unwrap();
+        read_scope.add_new_key(&unlocked, &connection).unwrap();
+        let read_scope = ReadScope::load_id(read_scope.id, &connection)
+            .unwrap()
+            .to_unlocked(&unlocked, &connection)
+            .unwrap();
+        read_scope.authorize(&unlocked, &client, &connection).unwrap();
+        read_scope.rotate_key(&unlocked, &connection).unwrap();
+
+        let write_scope = WriteScope::new("write", &application, &unlocked).save(&connection).unwrap();
+        write_scope
+            .unlock_by_account(&unlocked)
+            .unwrap()
+            .authorize(&unlocked, &client, &connection)
+            .unwrap();
+
+        assert!(unlocked.to_portable("wrong", "bundle", &connection).is_err());
+        let portable = unlocked.to_portable("passphrase", "bundle", &connection).unwrap();
+
+        for format in &[PortableFormat::Json, PortableFormat::Cbor] {
+            let data = portable.serialize(*format).unwrap();
+            let loaded = PortableAccount::deserialize(*format, &data).unwrap();
+
+            assert_eq!(loaded.public_key, portable.public_key);
+            assert_eq!(loaded.applications.len(), 1);
+
+            let application = &loaded.applications[0];
+            assert_eq!(application.code, "portable");
+            assert_eq!(application.clients.len(), 1);
+            assert_eq!(application.clients[0].client_id, encode(&client.client_id));
+
+            let read_keys = &application.read_grant_scopes[0].keys;
+            assert_eq!(read_keys.len(), 2);
+            assert_eq!(read_keys.iter().filter(|key| key.decrypt_only).count(), 1);
+            assert!(read_keys.iter().all(|key| key.authorizations.len() == 1));
+
+            let write_scope = &application.write_grant_scopes[0];
+            assert_eq!(write_scope.authorizations[0].client_id, encode(&client.client_id));
+
+            // Scope keys open with the bundle key, and only as the scope they
+            // were exported with.
+            let bundle_key = loaded.bundle_key("bundle").unwrap();
+            let public_key = decode(&write_scope.public_key).unwrap();
+            let encrypted = decode(&write_scope.encrypted_private_key).unwrap();
+            let algorithm = write_scope.key_algorithm.parse().unwrap();
+
+            assert!(SigningKey::from_encrypted(
+                &bundle_key, algorithm, &public_key, &encrypted,
+                &record_context("portable_write_scope", &[&public_key]),
+            )
+            .is_ok());
+            assert!(SigningKey::from_encrypted(
+                &bundle_key, algorithm, &public_key, &encrypted,
+                &record_context("portable_read_grant_key", &[&public_key]),
+            )
+            .is_err());
+            assert!(loaded.bundle_key("wrong").map(|key| *key != *bundle_key).unwrap());
+        }
+
+        assert!(PortableAccount::deserialize(PortableFormat::Cbor, b"{}").is_err());
+
+        match unlocked.delete(&connection) {
+            Ok(_) => (),
+            Err(_) => panic!(),
+        }
+    }
 }
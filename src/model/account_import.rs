@@ -0,0 +1,608 @@
+use base64::decode;
+use chrono::NaiveDateTime;
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::secret::{SecretKey, SecretString};
+use crate::encryption::signing_key::verify_signature;
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::{NewAccount, PortableAccount, UnlockedAccount};
+use crate::model::application::{Application, PortableApplication};
+use crate::model::certificate::CertData;
+use crate::model::client::Client;
+use crate::model::read_authorization::ReadGrantKey;
+use crate::model::read_scope::{PortableReadScope, ReadScope};
+use crate::model::write_scope::{LockedWriteScope, PortableWriteScope, WriteScope};
+use crate::model::{portable_authorization_hash, Scope};
+use std::fmt;
+use std::str::FromStr;
+
+// Importing a portable account into this server. Applications are merged by
+// code, clients by id and scopes by code within their application. A scope
+// already holding the bundle keys is left as it is, any other scope with the
+// same code is a conflict settled by the ConflictStrategy.
+//
+// Every record is checked against the account key that signed the bundle and
+// then signed again by the account it is imported into, so a bundle can be
+// merged into an account with a different key.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictStrategy {
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = CommonError;
+
+    fn from_str(strategy: &str) -> CommonResult<ConflictStrategy> {
+        match strategy {
+            "skip" => Ok(ConflictStrategy::Skip),
+            "overwrite" => Ok(ConflictStrategy::Overwrite),
+            "rename" => Ok(ConflictStrategy::Rename),
+            _ => Err(CommonError::NotFound(Some(format!(
+                "Unknown conflict strategy {}.",
+                strategy
+            )))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImportOptions {
+    pub strategy: ConflictStrategy,
+    pub dry_run: bool,
+}
+
+// The account a bundle is imported into, an existing account to merge into or
+// a new account created from the bundle key in the same transaction.
+pub enum ImportTarget {
+    Existing(UnlockedAccount),
+    New {
+        account: NewAccount,
+        password: SecretString,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImportAction {
+    Created,
+    Merged,
+    Skipped,
+    Overwritten,
+    Renamed(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportEntry {
+    pub record: String,
+    pub action: ImportAction,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub entries: Vec<ImportEntry>,
+}
+
+impl ImportReport {
+    fn add(&mut self, record: String, action: ImportAction) {
+        self.entries.push(ImportEntry { record, action });
+    }
+
+    pub fn count(&self, action: &ImportAction) -> usize {
+        self.entries.iter().filter(|entry| &entry.action == action).count()
+    }
+}
+
+impl fmt::Display for ImportAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportAction::Created => write!(f, "created"),
+            ImportAction::Merged => write!(f, "merged"),
+            ImportAction::Skipped => write!(f, "skipped"),
+            ImportAction::Overwritten => write!(f, "overwritten"),
+            ImportAction::Renamed(code) => write!(f, "renamed to {}", code),
+        }
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.dry_run {
+            writeln!(f, "Dry run, nothing was saved.")?;
+        }
+
+        for entry in &self.entries {
+            writeln!(f, "{}: {}", entry.record, entry.action)?;
+        }
+
+        Ok(())
+    }
+}
+
+// The key wrapping the bundle keys, and the account key that signed the
+// bundle records.
+pub struct BundleKey {
+    key: SecretKey,
+    algorithm: KeyAlgorithm,
+    public_key: Vec<u8>,
+}
+
+impl BundleKey {
+    pub fn new(key: SecretKey, algorithm: KeyAlgorithm, public_key: Vec<u8>) -> BundleKey {
+        BundleKey { key, algorithm, public_key }
+    }
+
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    // Checks a record hash was signed by the bundle account.
+    pub fn verify(&self, record: &str, hash: &[u8; 32], signature: &str) -> CommonResult<()> {
+        if verify_signature(self.algorithm, &self.public_key, hash, &decode(signature)?) {
+            Ok(())
+        } else {
+            Err(CommonError::FailedVerification(Some(format!(
+                "{} failed verification.",
+                record
+            ))))
+        }
+    }
+
+    // The certificate a scope key in the bundle was issued with.
+    pub fn cert_data(
+        &self,
+        key_algorithm: KeyAlgorithm,
+        public_key: &[u8],
+        scope: Scope,
+        expiration_date: NaiveDateTime,
+        version: u8,
+    ) -> CertData {
+        CertData {
+            version,
+            signing_algorithm: self.algorithm,
+            signing_key: self.public_key.clone(),
+            key_algorithm,
+            public_key: public_key.to_vec(),
+            scope,
+            expiration_date,
+        }
+    }
+}
+
+// Marks the rollback of a dry run.
+const DRY_RUN: &str = "Dry run.";
+
+impl PortableAccount {
+    // Imports the bundle in a single transaction. A dry run makes every change
+    // and rolls them back, so its report is exactly what an import would do.
+    pub fn import(
+        &self,
+        target: ImportTarget,
+        passphrase: &str,
+        options: ImportOptions,
+        connection: &MyConnection,
+    ) -> CommonResult<ImportReport> {
+        let bundle = self.unlock(passphrase)?;
+        let mut report = ImportReport {
+            dry_run: options.dry_run,
+            entries: Vec::new(),
+        };
+
+        let result = connection.transaction::<_, CommonError, _>(|| {
+            let account = match target {
+                ImportTarget::Existing(account) => {
+                    report.add(format!("account {}", account.name), ImportAction::Merged);
+                    account
+                }
+                ImportTarget::New { account, password } => {
+                    let account = account.save(connection)?.to_unlocked(&password)?;
+                    report.add(format!("account {}", account.name), ImportAction::Created);
+                    account
+                }
+            };
+
+            for application in &self.applications {
+                import_application(&account, application, &bundle, options.strategy, &mut report, connection)?;
+            }
+
+            if options.dry_run {
+                return Err(CommonError::RecordNotSaved(Some(DRY_RUN.to_owned())));
+            }
+
+            Ok(())
+        });
+
+        match result {
+            Ok(()) => Ok(report),
+            Err(CommonError::RecordNotSaved(Some(ref message))) if options.dry_run && message == DRY_RUN => {
+                Ok(report)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+fn import_application(
+    account: &UnlockedAccount,
+    import: &PortableApplication,
+    bundle: &BundleKey,
+    strategy: ConflictStrategy,
+    report: &mut ImportReport,
+    connection: &MyConnection,
+) -> CommonResult<()> {
+    let record = format!("application {}", import.code);
+
+    let application = match Application::load_by_code(&import.code, account, connection) {
+        Ok(application) => {
+            report.add(record, ImportAction::Merged);
+            application
+        }
+        Err(CommonError::NotFound(_)) => {
+            let application = Application::from_portable(account, import, bundle)?.save(connection)?;
+            report.add(record, ImportAction::Created);
+            application
+        }
+        Err(err) => return Err(err),
+    };
+
+    for client in &import.clients {
+        let record = format!("client {}", client.client_id);
+        let client_id = decode(&client.client_id)?;
+
+        match Client::load_id(client_id, connection) {
+            Ok(existing) if existing.application_id == application.id => {
+                report.add(record, ImportAction::Merged);
+            }
+            Ok(_) => {
+                return Err(CommonError::Duplicate(Some(format!(
+                    "Client {} belongs to another application.",
+                    client.client_id
+                ))));
+            }
+            Err(CommonError::NotFound(_)) => {
+                Client::from_portable(account, &application, &import.code, client, bundle)?.save(connection)?;
+                report.add(record, ImportAction::Created);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    for scope in &import.read_grant_scopes {
+        let read_scopes = ReadScope::load_all_for_application(&application, connection)?;
+        let record = format!("read scope {}/{}", application.code, scope.code);
+        let existing = read_scopes.iter().find(|existing| existing.code == scope.code);
+
+        let (code, action) = match existing {
+            None => (scope.code.clone(), ImportAction::Created),
+            Some(existing) if holds_read_keys(existing, scope, connection)? => {
+                report.add(record, ImportAction::Merged);
+                continue;
+            }
+            Some(existing) => match strategy {
+                ConflictStrategy::Skip => {
+                    report.add(record, ImportAction::Skipped);
+                    continue;
+                }
+                ConflictStrategy::Overwrite => {
+                    ReadScope::load_id(existing.id, connection)?.delete(account, connection)?;
+                    (scope.code.clone(), ImportAction::Overwritten)
+                }
+                ConflictStrategy::Rename => {
+                    let codes: Vec<&str> = read_scopes.iter().map(|s| s.code.as_str()).collect();
+                    let code = free_code(&scope.code, &codes);
+                    (code.clone(), ImportAction::Renamed(code))
+                }
+            },
+        };
+
+        let saved = ReadScope::from_portable(account, &application, &code, &import.code, scope, bundle)?
+            .save(connection)?
+            .to_unlocked(account, connection)?;
+
+        let bundle_scope = Scope::Read {
+            application: import.code.clone(),
+            grant: scope.code.clone(),
+        };
+
+        for key in &scope.keys {
+            let grant_key = ReadGrantKey::from_portable(&saved, account, &import.code, &scope.code, key, bundle)?
+                .save(connection)?
+                .to_unlocked(account)?;
+            let public_key = decode(&key.public_key)?;
+
+            for authorization in &key.authorizations {
+                let client = authorized_client(
+                    &application,
+                    &bundle_scope,
+                    &public_key,
+                    &authorization.client_id,
+                    &authorization.signature,
+                    bundle,
+                    connection,
+                )?;
+                grant_key.authorize(account, &client, connection)?;
+            }
+
+            if key.decrypt_only {
+                grant_key.retire(connection)?;
+            }
+        }
+
+        report.add(record, action);
+    }
+
+    for scope in &import.write_grant_scopes {
+        let write_scopes = WriteScope::load_all_for_application(&application, connection)?;
+        let record = format!("write scope {}/{}", application.code, scope.code);
+        let existing = write_scopes.iter().find(|existing| existing.code == scope.code);
+
+        let (code, action) = match existing {
+            None => (scope.code.clone(), ImportAction::Created),
+            Some(existing) if holds_write_key(existing, scope)? => {
+                report.add(record, ImportAction::Merged);
+                continue;
+            }
+            Some(existing) => match strategy {
+                ConflictStrategy::Skip => {
+                    report.add(record, ImportAction::Skipped);
+                    continue;
+                }
+                ConflictStrategy::Overwrite => {
+                    WriteScope::load_id(existing.id, connection)?.delete(account, connection)?;
+                    (scope.code.clone(), ImportAction::Overwritten)
+                }
+                ConflictStrategy::Rename => {
+                    let codes: Vec<&str> = write_scopes.iter().map(|s| s.code.as_str()).collect();
+                    let code = free_code(&scope.code, &codes);
+                    (code.clone(), ImportAction::Renamed(code))
+                }
+            },
+        };
+
+        let saved = WriteScope::from_portable(account, &application, &code, &import.code, scope, bundle)?
+            .save(connection)?
+            .unlock_by_account(account)?;
+
+        let bundle_scope = Scope::Write {
+            application: import.code.clone(),
+            grant: scope.code.clone(),
+        };
+        let public_key = decode(&scope.public_key)?;
+
+        for authorization in &scope.authorizations {
+            let client = authorized_client(
+                &application,
+                &bundle_scope,
+                &public_key,
+                &authorization.client_id,
+                &authorization.signature,
+                bundle,
+                connection,
+            )?;
+            saved.authorize(account, &client, connection)?;
+        }
+
+        report.add(record, action);
+    }
+
+    Ok(())
+}
+
+// A scope holding every key in the bundle scope is the same scope.
+fn holds_read_keys(
+    existing: &ReadScope,
+    import: &PortableReadScope,
+    connection: &MyConnection,
+) -> CommonResult<bool> {
+    let keys = ReadGrantKey::load_all_for_scope(existing, connection)?;
+
+    for key in &import.keys {
+        let public_key = decode(&key.public_key)?;
+
+        if !keys.iter().any(|existing| existing.public_key == public_key) {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+fn holds_write_key(existing: &LockedWriteScope, import: &PortableWriteScope) -> CommonResult<bool> {
+    Ok(existing.public_key == decode(&import.public_key)?)
+}
+
+// Authorizations may only name clients of the application they are imported
+// into.
+// The client of an authorization in the bundle, once the bundle account
+// signature over the scope key and client is checked.
+fn authorized_client(
+    application: &Application,
+    scope: &Scope,
+    public_key: &[u8],
+    client_id: &str,
+    signature: &str,
+    bundle: &BundleKey,
+    connection: &MyConnection,
+) -> CommonResult<Client> {
+    let client_id_bytes = decode(client_id)?;
+    bundle.verify(
+        &format!("Authorization for client {}", client_id),
+        &portable_authorization_hash(scope, public_key, &client_id_bytes),
+        signature,
+    )?;
+
+    let client = Client::load_id(client_id_bytes, connection)?;
+
+    if client.application_id != application.id {
+        return Err(CommonError::FailedVerification(Some(format!(
+            "Authorization for client {} of another application.",
+            client_id
+        ))));
+    }
+
+    Ok(client)
+}
+
+// The code followed by the first free number, code_2, code_3 and so on.
+fn free_code(code: &str, taken: &[&str]) -> String {
+    (2..)
+        .map(|n| format!("{}_{}", code, n))
+        .find(|candidate| !taken.contains(&candidate.as_str()))
+        .expect("Some code is free.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::establish_connection;
+    use base64::encode;
+    use chrono::Utc;
+    use crate::model::account::{Account, PortableFormat};
+    use crate::model::write_authorization::WriteAuthorization;
+
+    fn new_target(name: &str, bundle: &PortableAccount) -> ImportTarget {
+        ImportTarget::New {
+            account: NewAccount::from_portable(
+                name, &format!("{}@example.com", name), "password", "export", "bundle", bundle,
+            )
+            .unwrap(),
+            password: SecretString::new("password".to_owned()),
+        }
+    }
+
+    fn existing_target(name: &str, connection: &MyConnection) -> ImportTarget {
+        ImportTarget::Existing(Account::load_locked(name, connection).unwrap().to_unlocked("password").unwrap())
+    }
+
+    fn options(strategy: ConflictStrategy, dry_run: bool) -> ImportOptions {
+        ImportOptions { strategy, dry_run }
+    }
+
+    #[test]
+    fn import_and_merge() {
+        let connection = establish_connection().unwrap();
+        let source = Account::new("Import01", "import01@example.com", "password", "export", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+
+        let application = Application::new("moving", "Moving", "https://example.com", &source)
+            .save(&connection)
+            .unwrap();
+        let (secret, client) = Client::new(&source, &application);
+        let client = client.save(&connection).unwrap();
+        let other_client = Client::new(&source, &application).1.save(&connection).unwrap();
+
+        let read_scope = ReadScope::new("read", &application, &source)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked(&source, &connection)
+            .unwrap();
+        read_scope.add_new_key(&source, &connection).unwrap();
+        ReadScope::load_id(read_scope.id, &connection)
+            .unwrap()
+            .to_unlocked(&source, &connection)
+            .unwrap()
+            .authorize(&source, &client, &connection)
+            .unwrap();
+
+        let write_scope = WriteScope::new("write", &application, &source).save(&connection).unwrap();
+        write_scope
+            .unlock_by_account(&source)
+            .unwrap()
+            .authorize(&source, &client, &connection)
+            .unwrap();
+
+        let bundle = source.to_portable("export", "bundle", &connection).unwrap();
+        source.delete(&connection).unwrap();
+
+        let bundle = PortableAccount::deserialize(
+            PortableFormat::Json,
+            &bundle.serialize(PortableFormat::Json).unwrap(),
+        )
+        .unwrap();
+
+        // A tampered or locked bundle imports nothing.
+        let copy = || PortableAccount::deserialize(PortableFormat::Cbor, &bundle.serialize(PortableFormat::Cbor).unwrap()).unwrap();
+        let rejected = |tampered: PortableAccount| {
+            tampered.import(new_target("Import02", &bundle), "bundle", options(ConflictStrategy::Skip, false), &connection).is_err()
+        };
+
+        let mut tampered = copy();
+        tampered.applications[0].write_grant_scopes[0].expiration_date = Utc::now().naive_utc();
+        assert!(rejected(tampered));
+
+        let mut tampered = copy();
+        tampered.applications[0].write_grant_scopes[0].authorizations[0].client_id = encode(&other_client.client_id);
+        assert!(rejected(tampered));
+
+        let mut tampered = copy();
+        tampered.applications[0].read_grant_scopes[0].keys[0].authorizations[0].client_id = encode(&other_client.client_id);
+        assert!(rejected(tampered));
+
+        let mut tampered = copy();
+        tampered.applications[0].read_grant_scopes[0].keys[0].decrypt_only = true;
+        assert!(rejected(tampered));
+
+        assert!(bundle.import(new_target("Import02", &bundle), "wrong", options(ConflictStrategy::Skip, false), &connection).is_err());
+        assert!(Account::load_locked("Import02", &connection).is_err());
+
+        let dry_run = bundle
+            .import(new_target("Import02", &bundle), "bundle", options(ConflictStrategy::Skip, true), &connection)
+            .unwrap();
+        assert!(Account::load_locked("Import02", &connection).is_err());
+
+        let report = bundle
+            .import(new_target("Import02", &bundle), "bundle", options(ConflictStrategy::Skip, false), &connection)
+            .unwrap();
+        assert_eq!(report.entries, dry_run.entries);
+        assert_eq!(report.count(&ImportAction::Created), 6);
+
+        // The client still opens the scope key it was authorized for.
+        let imported = match existing_target("Import02", &connection) {
+            ImportTarget::Existing(account) => account,
+            _ => unreachable!(),
+        };
+        let application = Application::load_by_code("moving", &imported, &connection).unwrap();
+        let scope = WriteScope::load_all_for_application(&application, &connection).unwrap().remove(0);
+        let unlocked_scope = scope.unlock_by_account(&imported).unwrap();
+        let authorization = WriteAuthorization::load_scope_client(&unlocked_scope, &client, &connection).unwrap();
+        let by_client = scope.unlock_by_client(&client.to_unlocked(&secret), &authorization).unwrap();
+        assert_eq!(by_client.public_key, write_scope.public_key);
+
+        // Importing again changes nothing.
+        let report = bundle
+            .import(existing_target("Import02", &connection), "bundle", options(ConflictStrategy::Overwrite, false), &connection)
+            .unwrap();
+        assert_eq!(report.count(&ImportAction::Merged), report.entries.len());
+
+        // A different scope under the same code is a conflict.
+        WriteScope::load_id(scope.id, &connection).unwrap().delete(&imported, &connection).unwrap();
+        WriteScope::new("write", &application, &imported).save(&connection).unwrap();
+
+        let conflict = |strategy| {
+            bundle
+                .import(existing_target("Import02", &connection), "bundle", options(strategy, true), &connection)
+                .unwrap()
+                .entries
+                .pop()
+                .unwrap()
+                .action
+        };
+
+        assert_eq!(conflict(ConflictStrategy::Skip), ImportAction::Skipped);
+        assert_eq!(conflict(ConflictStrategy::Overwrite), ImportAction::Overwritten);
+        assert_eq!(conflict(ConflictStrategy::Rename), ImportAction::Renamed("write_2".to_owned()));
+
+        bundle
+            .import(existing_target("Import02", &connection), "bundle", options(ConflictStrategy::Rename, false), &connection)
+            .unwrap();
+        assert_eq!(WriteScope::load_all_for_application(&application, &connection).unwrap().len(), 2);
+
+        imported.delete(&connection).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+use base64::encode;
+use crate::database::schema::account_key_slot;
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::byte_encryption::decrypt_32;
+use crate::encryption::password::{derive_secret_key, key_derivation_params};
+use crate::encryption::random_int_256;
+use crate::encryption::secret::{SecretBytes, SecretKey, SecretString};
+use crate::encryption::shamir::{split, Share};
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::{LockedAccount, UnlockedAccount};
+use crate::model::record_context;
+use std::str::FromStr;
+
+// A key slot holds a copy of the account master key wrapped under a key
+// derived from its own unlocker, so an account can be unlocked without its
+// password while scope keys, which hang off the master key, stay as they are.
+// Slots are bound to the account public key and label.
+pub struct AccountKeySlot {}
+
+// The slot opened by recovery shares, see AccountKeySlot::split_recovery.
+pub const RECOVERY_SHARES_LABEL: &str = "recovery_shares";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeySlotType {
+    Password,
+    RecoveryCode,
+    KeyFile,
+    Device,
+    Shamir,
+}
+
+impl KeySlotType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeySlotType::Password => "password",
+            KeySlotType::RecoveryCode => "recovery_code",
+            KeySlotType::KeyFile => "key_file",
+            KeySlotType::Device => "device",
+            KeySlotType::Shamir => "shamir",
+        }
+    }
+
+    // Recovery codes are generated for the user and may be typed back in
+    // upper case or without the dashes.
+    fn normalize(&self, secret: &[u8]) -> SecretBytes {
+        SecretBytes::new(match self {
+            KeySlotType::RecoveryCode => secret
+                .iter()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .map(|c| c.to_ascii_lowercase())
+                .collect(),
+            _ => secret.to_vec(),
+        })
+    }
+}
+
+impl FromStr for KeySlotType {
+    type Err = CommonError;
+
+    fn from_str(slot_type: &str) -> CommonResult<KeySlotType> {
+        match slot_type {
+            "password" => Ok(KeySlotType::Password),
+            "recovery_code" => Ok(KeySlotType::RecoveryCode),
+            "key_file" => Ok(KeySlotType::KeyFile),
+            "device" => Ok(KeySlotType::Device),
+            "shamir" => Ok(KeySlotType::Shamir),
+            _ => Err(CommonError::NotFound(Some(format!(
+                "Unknown key slot type {}.",
+                slot_type
+            )))),
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "account_key_slot"]
+pub struct NewAccountKeySlot {
+    pub account_id: i32,
+    pub label: String,
+    pub slot_type: String,
+    pub salt: Vec<u8>,
+    pub kdf: String,
+    pub encrypted_master_key: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug, Queryable, Identifiable)]
+#[table_name = "account_key_slot"]
+pub struct LockedAccountKeySlot {
+    pub id: i32,
+    pub account_id: i32,
+    pub label: String,
+    pub slot_type: String,
+    pub salt: Vec<u8>,
+    pub kdf: String,
+    pub encrypted_master_key: Vec<u8>,
+}
+
+impl AccountKeySlot {
+    pub fn create(
+        account: &UnlockedAccount,
+        label: &str,
+        slot_type: KeySlotType,
+        secret: &[u8],
+    ) -> CommonResult<NewAccountKeySlot> {
+        let salt = random_int_256().to_vec();
+        let kdf = key_derivation_params();
+        let wrapping_key = derive_secret_key(&slot_type.normalize(secret), &salt, &kdf)?;
+
+        Ok(NewAccountKeySlot {
+            account_id: account.id,
+            label: label.to_owned(),
+            slot_type: slot_type.as_str().to_owned(),
+            salt,
+            kdf,
+            encrypted_master_key: account
+                .wrap_master_key(&wrapping_key, &slot_context(&account.public_key, label)),
+        })
+    }
+
+    // A random secret for slot types that are generated rather than chosen,
+    // recovery codes as eight dash separated groups of hex.
+    pub fn generate_secret(slot_type: KeySlotType) -> SecretString {
+        let random = random_int_256();
+
+        match slot_type {
+            KeySlotType::RecoveryCode => SecretString::new(
+                random[..16]
+                    .chunks(2)
+                    .map(|group| format!("{:02x}{:02x}", group[0], group[1]))
+                    .collect::<Vec<String>>()
+                    .join("-"),
+            ),
+            _ => SecretString::new(encode(random)),
+        }
+    }
+
+    // Opt in to recovery by shares. A random recovery key wraps the master key
+    // in a shamir slot and is split into count shares, any threshold of
+    // which rebuild it. Splitting again replaces the slot, so older shares
+    // stop working.
+    pub fn split_recovery(
+        account: &UnlockedAccount,
+        threshold: u8,
+        count: u8,
+        connection: &MyConnection,
+    ) -> CommonResult<Vec<Share>> {
+        let recovery_key = SecretKey::new(random_int_256());
+        let shares = split(&recovery_key, threshold, count)?;
+        let slot = AccountKeySlot::create(account, RECOVERY_SHARES_LABEL, KeySlotType::Shamir, &*recovery_key)?;
+
+        connection.transaction::<_, CommonError, _>(|| {
+            match AccountKeySlot::delete(account.id, RECOVERY_SHARES_LABEL, connection) {
+                Ok(_) | Err(CommonError::NotFound(_)) => (),
+                Err(err) => return Err(err),
+            }
+
+            slot.save(connection)
+        })?;
+
+        Ok(shares)
+    }
+
+    pub fn load(
+        account_id: i32,
+        label: &str,
+        connection: &MyConnection,
+    ) -> CommonResult<LockedAccountKeySlot> {
+        Ok(account_key_slot::table
+            .filter(account_key_slot::account_id.eq(account_id))
+            .filter(account_key_slot::label.eq(label))
+            .first(connection)?)
+    }
+
+    pub fn load_all_for_account(
+        account_id: i32,
+        connection: &MyConnection,
+    ) -> CommonResult<Vec<LockedAccountKeySlot>> {
+        Ok(account_key_slot::table
+            .filter(account_key_slot::account_id.eq(account_id))
+            .order(account_key_slot::id.asc())
+            .load(connection)?)
+    }
+
+    pub fn delete(account_id: i32, label: &str, connection: &MyConnection) -> CommonResult<()> {
+        let deleted = diesel::delete(account_key_slot::table
+                       .filter(account_key_slot::account_id.eq(account_id))
+                       .filter(account_key_slot::label.eq(label))
+                       )
+            .execute(connection)?;
+
+        match deleted {
+            0 => Err(CommonError::NotFound(Some(format!("No key slot {}.", label)))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn delete_for_account(account_id: i32, connection: &MyConnection) -> CommonResult<()> {
+        diesel::delete(account_key_slot::table
+                       .filter(account_key_slot::account_id.eq(account_id))
+                       )
+            .execute(connection)?;
+        Ok(())
+    }
+}
+
+impl NewAccountKeySlot {
+    pub fn save(&self, connection: &MyConnection) -> CommonResult<LockedAccountKeySlot> {
+        if AccountKeySlot::load(self.account_id, &self.label, connection).is_ok() {
+            return Err(CommonError::Duplicate(Some(format!(
+                "Key slot {} already exists.",
+                self.label
+            ))));
+        }
+
+        Ok(diesel::insert_into(account_key_slot::table)
+            .values(self)
+            .get_result(connection)?)
+    }
+}
+
+impl LockedAccountKeySlot {
+    pub fn slot_type(&self) -> CommonResult<KeySlotType> {
+        self.slot_type.parse()
+    }
+
+    pub fn unlock(&self, account: &LockedAccount, secret: &[u8]) -> CommonResult<UnlockedAccount> {
+        if account.id != self.account_id {
+            return Err(CommonError::CouldNotAuthenticate(None));
+        }
+
+        let wrapping_key = derive_secret_key(&self.slot_type()?.normalize(secret), &self.salt, &self.kdf)?;
+        let master_key = decrypt_32(
+            &self.encrypted_master_key,
+            &wrapping_key,
+            &slot_context(&account.public_key, &self.label),
+        )
+        .map_err(|_| CommonError::CouldNotAuthenticate(None))?;
+
+        account.unlock_with_master_key(master_key)
+    }
+}
+
+fn slot_context(public_key: &[u8], label: &str) -> [u8; 32] {
+    record_context("account_key_slot", &[public_key, label.as_bytes()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::establish_connection;
+    use crate::model::account::Account;
+
+    #[test]
+    fn unlock_with_slots() {
+        let connection = establish_connection().unwrap();
+        let account = Account::new("KeySlot01", "keyslot01@example.com", "password", "passphrase", false);
+        let locked = account.save(&connection).expect("Could not save");
+        let unlocked = locked.to_unlocked("password").expect("Could not unlock");
+
+        let code = AccountKeySlot::generate_secret(KeySlotType::RecoveryCode);
+        let recovery = AccountKeySlot::create(&unlocked, "recovery", KeySlotType::RecoveryCode, code.as_bytes())
+            .unwrap()
+            .save(&connection)
+            .unwrap();
+        let device = AccountKeySlot::create(&unlocked, "phone", KeySlotType::Device, b"device secret")
+            .unwrap()
+            .save(&connection)
+            .unwrap();
+
+        assert!(AccountKeySlot::create(&unlocked, "phone", KeySlotType::Password, b"other")
+            .unwrap()
+            .save(&connection)
+            .is_err());
+
+        let typed = code.to_uppercase().replace('-', " ");
+        let from_recovery = recovery.unlock(&locked, typed.as_bytes()).unwrap();
+        let from_device = device.unlock(&locked, b"device secret").unwrap();
+
+        let message = b"Please sign and return";
+        assert!(unlocked.verify(message, &from_recovery.sign(message)));
+        assert!(unlocked.verify(message, &from_device.sign(message)));
+
+        assert!(device.unlock(&locked, b"wrong secret").is_err());
+
+        // A slot copied to another label does not unlock.
+        let mut moved = AccountKeySlot::load(locked.id, "phone", &connection).unwrap();
+        moved.label = "recovery".to_owned();
+        assert!(moved.unlock(&locked, b"device secret").is_err());
+
+        assert_eq!(AccountKeySlot::load_all_for_account(locked.id, &connection).unwrap().len(), 2);
+
+        AccountKeySlot::delete(locked.id, "phone", &connection).unwrap();
+        assert!(AccountKeySlot::delete(locked.id, "phone", &connection).is_err());
+
+        unlocked.delete(&connection).unwrap();
+
+        assert!(AccountKeySlot::load_all_for_account(locked.id, &connection).unwrap().is_empty());
+    }
+
+    #[test]
+    fn recover_with_shares() {
+        let connection = establish_connection().unwrap();
+        let account = Account::new("KeySlot02", "keyslot02@example.com", "password", "passphrase", false);
+        let locked = account.save(&connection).expect("Could not save");
+        let unlocked = locked.to_unlocked("password").expect("Could not unlock");
+
+        let old_shares = AccountKeySlot::split_recovery(&unlocked, 2, 3, &connection).unwrap();
+        let shares = AccountKeySlot::split_recovery(&unlocked, 2, 3, &connection).unwrap();
+
+        assert!(Account::recover("KeySlot02", &shares[..1], "new_password", &connection).is_err());
+        assert!(Account::recover("KeySlot02", &old_shares[1..], "new_password", &connection).is_err());
+
+        Account::recover("KeySlot02", &[shares[2].clone(), shares[0].clone()], "new_password", &connection).unwrap();
+
+        let relocked = Account::load_locked("KeySlot02", &connection).unwrap();
+        assert!(relocked.to_unlocked("password").is_err());
+
+        // Shares recover the account once.
+        assert!(Account::recover("KeySlot02", &shares[..2], "third_password", &connection).is_err());
+        assert!(AccountKeySlot::load(relocked.id, RECOVERY_SHARES_LABEL, &connection).is_err());
+        assert!(relocked.to_unlocked("new_password").is_ok());
+
+        relocked.to_unlocked("new_password").unwrap().delete(&connection).unwrap();
+    }
+}
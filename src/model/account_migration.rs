@@ -0,0 +1,468 @@
+use base64::{decode, encode};
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use crate::database::schema::{account, account_move};
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::byte_encryption::{decrypt, encrypt};
+use crate::encryption::exchange_key::EphemeralKey;
+use crate::encryption::secret::{SecretKey, SecretString};
+use crate::encryption::signing_key::verify_signature;
+use crate::encryption::{hash_by_parts, lpad_to_256};
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::{Account, NewAccount, PortableAccount, PortableFormat, UnlockedAccount};
+use crate::model::account_import::{ConflictStrategy, ImportOptions, ImportTarget};
+use crate::model::server_identity::{ServerIdentity, ServerPublicKey};
+use crate::model::{Signable, Signed};
+
+// Moving an account between identity servers. The source server seals the
+// portable account to the exchange key of the destination server and signs
+// the bundle, the account signs it too. The keys inside the bundle are
+// encrypted under the account's export key, so the bundle alone does not
+// give the account to whoever holds it, not even the destination.
+//
+// The destination imports the bundle and the account signs a statement that
+// it moved there. Both servers keep the statement and publish it at
+// /api/account/<public_key>/moved for application servers.
+//
+// hash = hash_by_parts("account_migration", version, source server, destination
+//                      server, account public key, account key algorithm,
+//                      ephemeral key, encapsulated key, account, created date)
+pub const MIGRATION_VERSION: i16 = 1;
+
+// Bundles are accepted for a day after they are sealed.
+const BUNDLE_LIFETIME_HOURS: i64 = 24;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MigrationBundle {
+    pub version: i16,
+    pub source: ServerPublicKey,
+    pub destination: ServerPublicKey,
+    pub account_public_key: String,
+    pub account_key_algorithm: String,
+    pub ephemeral_key: String,
+    pub encapsulated_key: String,
+    pub account: String,
+    pub created_date: i64,
+    pub server_signature: String,
+    pub account_signature: String,
+}
+
+// hash = hash_by_parts("account_move", account public key, source server key,
+//                      destination server key, destination url, move date)
+pub struct AccountMove {}
+
+pub struct UnsignedAccountMove {
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+    pub source_server_key: Vec<u8>,
+    pub destination_server_key: Vec<u8>,
+    pub destination_url: String,
+    pub move_date: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Insertable)]
+#[table_name = "account_move"]
+pub struct NewAccountMove {
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+    pub source_server_key: Vec<u8>,
+    pub destination_server_key: Vec<u8>,
+    pub destination_url: String,
+    pub move_date: NaiveDateTime,
+    pub signature: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug, Queryable)]
+pub struct LockedAccountMove {
+    pub id: i32,
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+    pub source_server_key: Vec<u8>,
+    pub destination_server_key: Vec<u8>,
+    pub destination_url: String,
+    pub move_date: NaiveDateTime,
+    pub signature: Vec<u8>,
+}
+
+// The statement as published to application servers, binary fields base64
+// encoded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MoveStatement {
+    pub account_public_key: String,
+    pub account_key_algorithm: String,
+    pub source_server_key: String,
+    pub destination_server_key: String,
+    pub destination_url: String,
+    pub move_date: i64,
+    pub signature: String,
+}
+
+impl MigrationBundle {
+    pub fn seal(
+        account: &UnlockedAccount,
+        export_key: &str,
+        source: &ServerIdentity,
+        destination: &ServerPublicKey,
+        connection: &MyConnection,
+    ) -> CommonResult<MigrationBundle> {
+        if destination.exchange_algorithm != KeyAlgorithm::X25519MlKem768.name() {
+            return Err(CommonError::Misconfiguration(Some(
+                "The destination server needs a hybrid exchange key.".to_owned(),
+            )));
+        }
+
+        let destination_key = decode(&destination.exchange_key)?;
+
+        if destination_key == source.exchange_key.public_key() {
+            return Err(CommonError::Misconfiguration(Some(
+                "An account can't move to the server it is on.".to_owned(),
+            )));
+        }
+
+        let portable = account.to_portable(export_key, export_key, connection)?;
+
+        let ephemeral = EphemeralKey::new();
+        let ephemeral_key = ephemeral.public_key();
+        let (secret, encapsulated_key) = ephemeral.encapsulate(&destination_key)?;
+        let key = bundle_key(&secret, &destination_key);
+
+        let mut bundle = MigrationBundle {
+            version: MIGRATION_VERSION,
+            source: source.public_key(),
+            destination: destination.clone(),
+            account_public_key: encode(&account.public_key),
+            account_key_algorithm: account.algorithm().name().to_owned(),
+            ephemeral_key: encode(ephemeral_key),
+            encapsulated_key: encode(&encapsulated_key),
+            account: encode(encrypt(&portable.serialize(PortableFormat::Cbor)?, &key)),
+            created_date: Utc::now().timestamp(),
+            server_signature: String::new(),
+            account_signature: String::new(),
+        };
+
+        let hash = bundle.hash();
+        bundle.server_signature = encode(source.signing_key.sign(&hash));
+        bundle.account_signature = encode(account.sign(&hash));
+
+        Ok(bundle)
+    }
+
+    // Creates the account on this server from the bundle, with the export key
+    // it had on the source server. Returns the statement that it moved here,
+    // signed by the account.
+    pub fn import(
+        &self,
+        destination: &ServerIdentity,
+        name: &str,
+        email: &str,
+        password: &str,
+        export_key: &str,
+        connection: &MyConnection,
+    ) -> CommonResult<NewAccountMove> {
+        self.verify(destination)?;
+
+        let portable = self.open(destination)?;
+        let account_public_key = decode(&self.account_public_key)?;
+
+        if portable.public_key != self.account_public_key {
+            return Err(CommonError::FailedVerification(Some(
+                "The bundle holds another account.".to_owned(),
+            )));
+        }
+
+        let existing: i64 = account::table
+            .filter(account::public_key.eq(&account_public_key))
+            .count()
+            .get_result(connection)?;
+
+        if existing > 0 {
+            return Err(CommonError::Duplicate(Some("The account is already on this server.".to_owned())));
+        }
+
+        if Account::load_locked(name, connection).is_ok() {
+            return Err(CommonError::Duplicate(Some(format!("Account name {} is taken.", name))));
+        }
+
+        connection.transaction::<_, CommonError, _>(|| {
+            let new_account = NewAccount::from_portable(name, email, password, export_key, export_key, &portable)?;
+            let target = ImportTarget::New {
+                account: new_account,
+                password: SecretString::new(password.to_owned()),
+            };
+            let options = ImportOptions {
+                strategy: ConflictStrategy::Skip,
+                dry_run: false,
+            };
+
+            portable.import(target, export_key, options, connection)?;
+
+            let account = Account::load_unlocked(name.to_owned(), SecretString::new(password.to_owned()), connection)?;
+            let statement = UnsignedAccountMove {
+                account_public_key,
+                account_key_algorithm: account.algorithm(),
+                source_server_key: decode(&self.source.signing_key)?,
+                destination_server_key: destination.signing_key.public_key(),
+                destination_url: destination.url.clone(),
+                move_date: DateTime::from_timestamp(Utc::now().timestamp(), 0).map(|date| date.naive_utc())
+                    .ok_or(CommonError::LibraryError(None))?,
+            };
+            let statement = account.sign_record(&statement);
+
+            AccountMove::save(&statement, connection)?;
+
+            Ok(statement)
+        })
+    }
+
+    // Checks that the bundle was sealed for this server, is recent, and is
+    // signed by both the source server and the account.
+    pub fn verify(&self, destination: &ServerIdentity) -> CommonResult<()> {
+        if self.version != MIGRATION_VERSION {
+            return Err(CommonError::FailedVerification(Some(format!(
+                "Unknown migration bundle version {}.",
+                self.version
+            ))));
+        }
+
+        if decode(&self.destination.exchange_key)? != destination.exchange_key.public_key()
+            || decode(&self.destination.signing_key)? != destination.signing_key.public_key()
+        {
+            return Err(CommonError::FailedVerification(Some(
+                "The bundle was sealed for another server.".to_owned(),
+            )));
+        }
+
+        let age = Duration::seconds(Utc::now().timestamp() - self.created_date);
+
+        if age > Duration::hours(BUNDLE_LIFETIME_HOURS) || age < Duration::minutes(-5) {
+            return Err(CommonError::FailedVerification(Some("The bundle has expired.".to_owned())));
+        }
+
+        let hash = self.hash();
+        let signed_by_server = verify_signature(
+            self.source.key_algorithm.parse()?,
+            &decode(&self.source.signing_key)?,
+            &hash,
+            &decode(&self.server_signature)?,
+        );
+        let signed_by_account = verify_signature(
+            self.account_key_algorithm.parse()?,
+            &decode(&self.account_public_key)?,
+            &hash,
+            &decode(&self.account_signature)?,
+        );
+
+        if !signed_by_server || !signed_by_account {
+            return Err(CommonError::FailedVerification(Some(
+                "The bundle signatures failed verification.".to_owned(),
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn open(&self, destination: &ServerIdentity) -> CommonResult<PortableAccount> {
+        let secret = destination
+            .exchange_key
+            .decapsulate(&decode(&self.ephemeral_key)?, &decode(&self.encapsulated_key)?)?;
+        let key = bundle_key(&secret, &destination.exchange_key.public_key());
+        let account = decrypt(&decode(&self.account)?, &key)?;
+
+        PortableAccount::deserialize(PortableFormat::Cbor, &account)
+    }
+
+    pub fn hash(&self) -> [u8; 32] {
+        let version = lpad_to_256(&self.version.to_le_bytes());
+        let created_date = lpad_to_256(&self.created_date.to_le_bytes());
+
+        hash_by_parts(&[
+            b"account_migration",
+            &version,
+            &server_hash(&self.source),
+            &server_hash(&self.destination),
+            self.account_public_key.as_bytes(),
+            self.account_key_algorithm.as_bytes(),
+            self.ephemeral_key.as_bytes(),
+            self.encapsulated_key.as_bytes(),
+            self.account.as_bytes(),
+            &created_date,
+        ])
+    }
+}
+
+fn server_hash(server: &ServerPublicKey) -> [u8; 32] {
+    hash_by_parts(&[
+        server.url.as_bytes(),
+        server.signing_key.as_bytes(),
+        server.key_algorithm.as_bytes(),
+        server.exchange_key.as_bytes(),
+        server.exchange_algorithm.as_bytes(),
+    ])
+}
+
+// The key the account is encrypted under, bound to the destination key.
+fn bundle_key(secret: &[u8; 32], destination_key: &[u8]) -> SecretKey {
+    SecretKey::new(hash_by_parts(&[b"account_migration_key", secret, destination_key]))
+}
+
+pub fn move_hash(
+    account_public_key: &[u8],
+    source_server_key: &[u8],
+    destination_server_key: &[u8],
+    destination_url: &str,
+    move_date: &NaiveDateTime,
+) -> [u8; 32] {
+    let date = lpad_to_256(&move_date.and_utc().timestamp().to_le_bytes());
+
+    hash_by_parts(&[
+        b"account_move",
+        account_public_key,
+        source_server_key,
+        destination_server_key,
+        destination_url.as_bytes(),
+        &date,
+    ])
+}
+
+impl AccountMove {
+    // Records on the source server that an account it holds has moved. The
+    // statement is only accepted if the account signed it and it names this
+    // server as the source.
+    pub fn record_departure(
+        statement: &MoveStatement,
+        source: &ServerIdentity,
+        connection: &MyConnection,
+    ) -> CommonResult<LockedAccountMove> {
+        let record = statement.to_record()?;
+
+        if !record.verify() {
+            return Err(CommonError::FailedVerification(Some(
+                "The move statement failed verification.".to_owned(),
+            )));
+        }
+
+        if record.source_server_key != source.signing_key.public_key() {
+            return Err(CommonError::FailedVerification(Some(
+                "The account did not move from this server.".to_owned(),
+            )));
+        }
+
+        let key_algorithm: KeyAlgorithm = account::table
+            .filter(account::public_key.eq(&record.account_public_key))
+            .select(account::key_algorithm)
+            .first(connection)?;
+
+        if key_algorithm != record.account_key_algorithm {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        match AccountMove::load_latest(&record.account_public_key, connection) {
+            Ok(latest) if latest.move_date == record.move_date && latest.signature == record.signature => Ok(latest),
+            _ => AccountMove::save(&record, connection),
+        }
+    }
+
+    // The most recent move of the account.
+    pub fn load_latest(account_public_key: &[u8], connection: &MyConnection) -> CommonResult<LockedAccountMove> {
+        Ok(account_move::table
+            .filter(account_move::account_public_key.eq(account_public_key))
+            .order(account_move::move_date.desc())
+            .first(connection)?)
+    }
+
+    fn save(record: &NewAccountMove, connection: &MyConnection) -> CommonResult<LockedAccountMove> {
+        Ok(diesel::insert_into(account_move::table)
+            .values(record)
+            .get_result(connection)?)
+    }
+}
+
+impl Signable<NewAccountMove> for UnsignedAccountMove {
+    fn record_hash(&self) -> [u8; 32] {
+        move_hash(
+            &self.account_public_key,
+            &self.source_server_key,
+            &self.destination_server_key,
+            &self.destination_url,
+            &self.move_date,
+        )
+    }
+
+    fn sign(&self, signature: Vec<u8>) -> NewAccountMove {
+        NewAccountMove {
+            account_public_key: self.account_public_key.clone(),
+            account_key_algorithm: self.account_key_algorithm,
+            source_server_key: self.source_server_key.clone(),
+            destination_server_key: self.destination_server_key.clone(),
+            destination_url: self.destination_url.clone(),
+            move_date: self.move_date,
+            signature,
+        }
+    }
+}
+
+impl Signed for NewAccountMove {
+    fn record_hash(&self) -> [u8; 32] {
+        move_hash(
+            &self.account_public_key,
+            &self.source_server_key,
+            &self.destination_server_key,
+            &self.destination_url,
+            &self.move_date,
+        )
+    }
+
+    fn signature(&self) -> Vec<u8> {
+        self.signature.clone()
+    }
+}
+
+impl NewAccountMove {
+    pub fn verify(&self) -> bool {
+        verify_signature(self.account_key_algorithm, &self.account_public_key, &self.record_hash(), &self.signature)
+    }
+}
+
+impl MoveStatement {
+    pub fn to_record(&self) -> CommonResult<NewAccountMove> {
+        Ok(NewAccountMove {
+            account_public_key: decode(&self.account_public_key)?,
+            account_key_algorithm: self.account_key_algorithm.parse()?,
+            source_server_key: decode(&self.source_server_key)?,
+            destination_server_key: decode(&self.destination_server_key)?,
+            destination_url: self.destination_url.clone(),
+            move_date: DateTime::from_timestamp(self.move_date, 0).map(|date| date.naive_utc())
+                .ok_or_else(|| CommonError::FailedVerification(Some("Invalid move date.".to_owned())))?,
+            signature: decode(&self.signature)?,
+        })
+    }
+}
+
+impl From<&NewAccountMove> for MoveStatement {
+    fn from(item: &NewAccountMove) -> MoveStatement {
+        MoveStatement {
+            account_public_key: encode(&item.account_public_key),
+            account_key_algorithm: item.account_key_algorithm.name().to_owned(),
+            source_server_key: encode(&item.source_server_key),
+            destination_server_key: encode(&item.destination_server_key),
+            destination_url: item.destination_url.clone(),
+            move_date: item.move_date.and_utc().timestamp(),
+            signature: encode(&item.signature),
+        }
+    }
+}
+
+impl From<&LockedAccountMove> for MoveStatement {
+    fn from(item: &LockedAccountMove) -> MoveStatement {
+        MoveStatement {
+            account_public_key: encode(&item.account_public_key),
+            account_key_algorithm: item.account_key_algorithm.name().to_owned(),
+            source_server_key: encode(&item.source_server_key),
+            destination_server_key: encode(&item.destination_server_key),
+            destination_url: item.destination_url.clone(),
+            move_date: item.move_date.and_utc().timestamp(),
+            signature: encode(&item.signature),
+        }
+    }
+}
@@ -1,21 +1,25 @@
+use base64::encode;
 use crate::database::schema::application;
 use crate::database::MyConnection;
 use diesel::prelude::*;
 use crate::encryption::hash_by_parts;
 use crate::error::{CommonError, CommonResult};
 use crate::model::account::UnlockedAccount;
+use crate::model::account_import::BundleKey;
 use crate::model::{Signable, Signed};
-use crate::model::client::Client;
-use crate::model::write_scope::WriteScope;
-use crate::model::read_scope::ReadScope;
+use crate::model::client::{Client, PortableClient};
+use crate::model::write_scope::{PortableWriteScope, WriteScope};
+use crate::model::read_scope::{PortableReadScope, ReadScope};
 
+#[derive(Serialize, Deserialize)]
 pub struct PortableApplication {
     pub code: String,
     pub description: String,
     pub server_url: String,
-    //pub clients: Vec<PortableClient>,
-    //pub read_grant_scopes: Vec<ReadGrantScope>,
-    //pub write_grant_scopes: Vec<WriteGrantScope>,
+    pub signature: String,
+    pub clients: Vec<PortableClient>,
+    pub read_grant_scopes: Vec<PortableReadScope>,
+    pub write_grant_scopes: Vec<PortableWriteScope>,
 }
 
 pub struct UnsignedApplication {
@@ -121,25 +125,63 @@ impl Application {
     pub fn from_portable(
         account: &UnlockedAccount,
         import: &PortableApplication,
-    ) -> NewApplication {
-        Application::new(
-            &import.code,
-            &import.description,
-            &import.server_url,
-            account,
-        )
+        bundle: &BundleKey,
+    ) -> CommonResult<NewApplication> {
+        let application = UnsignedApplication {
+            account_id: account.id,
+            code: import.code.clone(),
+            description: import.description.clone(),
+            server_url: import.server_url.clone(),
+        };
+
+        bundle.verify(
+            &format!("Application {}", import.code),
+            &application.record_hash(),
+            &import.signature,
+        )?;
+
+        Ok(account.sign_record(&application))
     }
 
+    // The application with its clients and scopes, scope keys wrapped under
+    // the bundle key.
     pub fn to_portable(
         &self,
-        _export_key: &str,
-        _connection: &MyConnection,
-    ) -> PortableApplication {
-        PortableApplication {
+        account: &UnlockedAccount,
+        bundle_key: &[u8; 32],
+        connection: &MyConnection,
+    ) -> CommonResult<PortableApplication> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let mut clients = Vec::new();
+
+        for client in Client::load_all_for_application(self, connection)? {
+            clients.push(client.to_portable(account)?);
+        }
+
+        let mut read_grant_scopes = Vec::new();
+
+        for read_scope in ReadScope::load_all_for_application(self, connection)? {
+            read_grant_scopes.push(read_scope.to_portable(account, bundle_key, connection)?);
+        }
+
+        let mut write_grant_scopes = Vec::new();
+
+        for write_scope in WriteScope::load_all_for_application(self, connection)? {
+            write_grant_scopes.push(write_scope.to_portable(account, bundle_key, connection)?);
+        }
+
+        Ok(PortableApplication {
             code: self.code.clone(),
             description: self.description.clone(),
             server_url: self.server_url.clone(),
-        }
+            signature: encode(&self.signature),
+            clients,
+            read_grant_scopes,
+            write_grant_scopes,
+        })
     }
 
     pub fn load_by_code(
@@ -172,7 +214,27 @@ impl Application {
             .get_results(connection)?)
     }
 
-    pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
+    // Changes the description and server URL, signed again by the account.
+    // The code stays, clients and scopes are signed over it.
+    pub fn update(
+        self,
+        description: &str,
+        server_url: &str,
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<Application> {
+        let updated = Application::new(&self.code, description, server_url, account);
+
+        Ok(diesel::update(application::table.filter(application::id.eq(self.id)))
+            .set((
+                application::description.eq(&updated.description),
+                application::server_url.eq(&updated.server_url),
+                application::signature.eq(&updated.signature),
+            ))
+            .get_result(connection)?)
+    }
+
+    pub fn delete(self, account: &UnlockedAccount, connection: &MyConnection) -> CommonResult<()> {
         // Delete all dependent clients
         let clients = Client::load_all_for_application(&self, connection)?;
 
@@ -184,14 +246,14 @@ impl Application {
         let write_scopes = WriteScope::load_all_for_application(&self, connection)?;
 
         for write_scope in write_scopes {
-            write_scope.delete(connection)?;
+            write_scope.delete(account, connection)?;
         }
 
         // Delete all dependant read grant scopes.
         let read_scopes = ReadScope::load_all_for_application(&self, connection)?;
 
         for read_scope in read_scopes {
-            read_scope.delete(connection)?;
+            read_scope.delete(account, connection)?;
         }
 
 
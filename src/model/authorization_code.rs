@@ -0,0 +1,300 @@
+use base64::{decode_config, encode, encode_config, URL_SAFE_NO_PAD};
+use chrono::{Duration, NaiveDateTime, Utc};
+use crate::database::schema::authorization_code;
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::byte_encryption::{decrypt_32, encrypt_32};
+use crate::encryption::{hash_by_parts, random_int_256};
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::UnlockedAccount;
+use crate::model::application::Application;
+use crate::model::client::Client;
+use crate::model::oauth_token::{OAuthScope, OAuthToken, TokenResponse};
+use crate::model::openid::{IdToken, OPENID_SCOPE};
+use crate::model::read_scope::ReadScope;
+use crate::model::write_scope::WriteScope;
+use sha2::{Digest, Sha256};
+
+// Codes from the consent page of the authorization code flow. Approving
+// creates a client and its authorizations, the application then exchanges
+// the code once, with its PKCE verifier, for the client's id and secret, an
+// access token and, if it asked for the openid scope, an id token. Only a hash of the code is
+// stored and the secret is encrypted under a key derived from the code.
+pub struct AuthorizationCode {}
+
+pub const AUTHORIZATION_CODE_MINUTES: i64 = 10;
+
+#[derive(Queryable)]
+pub struct LockedAuthorizationCode {
+    pub client_id: Vec<u8>,
+    pub encrypted_secret: Vec<u8>,
+    pub code_challenge: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub expiration_date: NaiveDateTime,
+    pub id_token: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "authorization_code"]
+struct NewAuthorizationCode {
+    code_hash: Vec<u8>,
+    client_id: Vec<u8>,
+    encrypted_secret: Vec<u8>,
+    code_challenge: String,
+    redirect_uri: String,
+    scope: String,
+    expiration_date: NaiveDateTime,
+    id_token: Option<String>,
+}
+
+// What the application asked for, as approved on the consent page.
+pub struct CodeRequest<'a> {
+    pub scope: &'a str,
+    pub code_challenge: &'a str,
+    pub redirect_uri: &'a str,
+    pub nonce: Option<&'a str>,
+}
+
+// A requested scope as shown on the consent page.
+pub struct ScopeDetail {
+    pub scope: OAuthScope,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+}
+
+// The response to exchanging a code, the credentials `client add` prints
+// alongside an RFC 6749 token response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientCredentials {
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(flatten)]
+    pub token: TokenResponse,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+fn code_hash(code: &str) -> Vec<u8> {
+    hash_by_parts(&[b"authorization_code", code.as_bytes()]).to_vec()
+}
+
+fn code_key(code: &str) -> [u8; 32] {
+    hash_by_parts(&[b"authorization_code_key", code.as_bytes()])
+}
+
+fn invalid_grant(message: &str) -> CommonError {
+    CommonError::FailedVerification(Some(message.to_owned()))
+}
+
+impl AuthorizationCode {
+    // The S256 challenge for a verifier, RFC 7636 section 4.2.
+    pub fn code_challenge(code_verifier: &str) -> String {
+        encode_config(Sha256::digest(code_verifier.as_bytes()), URL_SAFE_NO_PAD)
+    }
+
+    // Redirects go back to the application's server only.
+    pub fn check_redirect(application: &Application, redirect_uri: &str) -> CommonResult<()> {
+        let server_url = application.server_url.trim_end_matches('/');
+        let on_server = match redirect_uri.strip_prefix(server_url) {
+            Some(rest) => !server_url.is_empty() && (rest.is_empty() || rest.starts_with('/') || rest.starts_with('?')),
+            None => false,
+        };
+
+        if on_server {
+            return Ok(());
+        }
+
+        Err(CommonError::Misconfiguration(Some(format!(
+            "Redirect {} is not on the application's server.",
+            redirect_uri
+        ))))
+    }
+
+    // The requested application scopes with their names, for the account to
+    // approve. The openid scope has no details.
+    pub fn scope_details(
+        account: &UnlockedAccount,
+        application: &Application,
+        scope: &str,
+        connection: &MyConnection,
+    ) -> CommonResult<Vec<ScopeDetail>> {
+        let unknown = |scope: &OAuthScope| CommonError::NotFound(Some(format!("Unknown scope {}.", scope)));
+        let (openid, scope) = IdToken::split_scope(scope);
+        let mut details = Vec::new();
+
+        for scope in OAuthScope::parse_list(&scope)? {
+            let (display_name, description) = match &scope {
+                OAuthScope::Read(code) => {
+                    let read = ReadScope::load_codes(vec![code.clone()], account, application, connection)?
+                        .pop()
+                        .ok_or_else(|| unknown(&scope))?;
+                    (read.display_name, read.description)
+                }
+                OAuthScope::Write(code) => {
+                    let write = WriteScope::load_codes(vec![code.clone()], application, connection)?
+                        .pop()
+                        .ok_or_else(|| unknown(&scope))?;
+                    (write.display_name, write.description)
+                }
+            };
+
+            details.push(ScopeDetail { scope, display_name, description });
+        }
+
+        if details.is_empty() && !openid {
+            return Err(CommonError::Misconfiguration(Some("No scope requested.".to_owned())));
+        }
+
+        Ok(details)
+    }
+
+    // Creates a client of the application authorized for the scopes and
+    // returns the code for it. The issuer is this server's URL, for the id
+    // token. Its audience is the application code, the client_id the relying
+    // party sent.
+    pub fn grant(
+        account: &UnlockedAccount,
+        application: &Application,
+        request: &CodeRequest,
+        issuer: &str,
+        connection: &MyConnection,
+    ) -> CommonResult<String> {
+        AuthorizationCode::check_redirect(application, request.redirect_uri)?;
+
+        if !matches!(decode_config(request.code_challenge, URL_SAFE_NO_PAD), Ok(challenge) if challenge.len() == 32) {
+            return Err(CommonError::Misconfiguration(Some("Invalid code challenge.".to_owned())));
+        }
+
+        let (openid, scope) = IdToken::split_scope(request.scope);
+
+        connection.transaction::<_, CommonError, _>(|| {
+            let (secret, new_client) = Client::new(account, application);
+            let client = new_client.save(connection)?;
+            let scopes = OAuthToken::authorize(account, &client, &scope, connection)?;
+
+            if scopes.is_empty() && !openid {
+                return Err(CommonError::Misconfiguration(Some("No scope requested.".to_owned())));
+            }
+
+            let mut granted: Vec<String> = scopes.iter().map(|s| s.to_string()).collect();
+            let id_token = match openid {
+                true => {
+                    granted.insert(0, OPENID_SCOPE.to_owned());
+                    Some(IdToken::sign(account, issuer, &application.code, request.nonce)?)
+                }
+                false => None,
+            };
+
+            let code = encode_config(random_int_256(), URL_SAFE_NO_PAD);
+
+            diesel::insert_into(authorization_code::table)
+                .values(&NewAuthorizationCode {
+                    code_hash: code_hash(&code),
+                    encrypted_secret: encrypt_32(&secret, &code_key(&code), &client.client_id),
+                    client_id: client.client_id,
+                    code_challenge: request.code_challenge.to_owned(),
+                    redirect_uri: request.redirect_uri.to_owned(),
+                    scope: granted.join(" "),
+                    expiration_date: (Utc::now() + Duration::minutes(AUTHORIZATION_CODE_MINUTES)).naive_utc(),
+                    id_token,
+                })
+                .execute(connection)?;
+
+            Ok(code)
+        })
+    }
+
+    // Exchanges a code for the client's credentials. A code works once, it
+    // is removed before anything is checked.
+    pub fn exchange(
+        code: &str,
+        code_verifier: &str,
+        redirect_uri: &str,
+        connection: &MyConnection,
+    ) -> CommonResult<ClientCredentials> {
+        let granted: LockedAuthorizationCode = diesel::delete(
+            authorization_code::table.filter(authorization_code::code_hash.eq(code_hash(code))),
+        )
+        .returning((
+            authorization_code::client_id,
+            authorization_code::encrypted_secret,
+            authorization_code::code_challenge,
+            authorization_code::redirect_uri,
+            authorization_code::scope,
+            authorization_code::expiration_date,
+            authorization_code::id_token,
+        ))
+        .get_result(connection)
+        .optional()?
+        .ok_or_else(|| invalid_grant("Authorization code not recognized."))?;
+
+        if granted.expiration_date < Utc::now().naive_utc() {
+            return Err(invalid_grant("Authorization code expired."));
+        }
+
+        if granted.redirect_uri != redirect_uri {
+            return Err(invalid_grant("Redirect does not match the authorization request."));
+        }
+
+        // RFC 7636 section 4.1 allows 43 to 128 characters.
+        let verified = (43..=128).contains(&code_verifier.len())
+            && AuthorizationCode::code_challenge(code_verifier) == granted.code_challenge;
+
+        if !verified {
+            return Err(invalid_grant("Code verifier does not match the challenge."));
+        }
+
+        let secret = decrypt_32(&granted.encrypted_secret, &code_key(code), &granted.client_id)?;
+        let client = Client::load_id(granted.client_id, connection)?;
+        let (_, scope) = IdToken::split_scope(&granted.scope);
+        let mut token = OAuthToken::issue_approved(&client, &OAuthScope::parse_list(&scope)?, connection)?;
+        token.scope = granted.scope;
+
+        Ok(ClientCredentials {
+            client_id: encode(&client.client_id),
+            client_secret: encode(secret),
+            token,
+            id_token: granted.id_token,
+        })
+    }
+
+    pub fn delete_for_client(client_id: &[u8], connection: &MyConnection) -> CommonResult<()> {
+        diesel::delete(authorization_code::table.filter(authorization_code::client_id.eq(client_id)))
+            .execute(connection)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::establish_connection;
+    use crate::model::account::Account;
+
+    #[test]
+    fn redirect_and_challenge() {
+        let connection = establish_connection().unwrap();
+        let account = Account::new("AuthCode01", "authcode01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        let application = Application::new("authcode", "Auth Code", "https://example.com/", &account)
+            .save(&connection)
+            .unwrap();
+
+        assert!(AuthorizationCode::check_redirect(&application, "https://example.com").is_ok());
+        assert!(AuthorizationCode::check_redirect(&application, "https://example.com/callback?x=1").is_ok());
+        assert!(AuthorizationCode::check_redirect(&application, "https://example.com.evil.org/callback").is_err());
+        assert!(AuthorizationCode::check_redirect(&application, "https://evil.org/https://example.com").is_err());
+
+        // RFC 7636 appendix B.
+        assert_eq!(
+            AuthorizationCode::code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+
+        account.delete(&connection).unwrap();
+    }
+}
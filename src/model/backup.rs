@@ -0,0 +1,1042 @@
+use base64::{decode, encode};
+use chrono::{NaiveDateTime, Utc};
+use crate::database::schema::{
+    account, account_key_slot, account_move, application, certificate_revocation, client, oauth_token,
+    read_authorization, read_grant_key, read_grant_scope, write_authorization, write_grant_scope,
+    write_scope_succession,
+};
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use diesel::{insert_into, sql_query};
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::byte_encryption::{decrypt, encrypt};
+use crate::encryption::hash_by_parts;
+use crate::encryption::password::{derive_password_key, key_derivation_params};
+use crate::encryption::random_int_256;
+use crate::encryption::secret::SecretKey;
+use crate::encryption::signing_key::verify_signature;
+use crate::error::{CommonError, CommonResult};
+use crate::model::account_migration::NewAccountMove;
+use crate::model::application::Application;
+use crate::model::certificate::CertData;
+use crate::model::certificate_revocation::{CertificateRevocation, LockedCertificateRevocation};
+use crate::model::client::Client;
+use crate::model::read_authorization::ReadAuthorization;
+use crate::model::read_scope::ReadScope;
+use crate::model::write_authorization::WriteAuthorization;
+use crate::model::write_scope_succession::LockedWriteScopeSuccession;
+use crate::model::{Scope, Signed};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{Read, Write};
+
+// A backup of the whole database, for restoring a server rather than moving
+// an account. Every table is copied as stored, ids and signatures included,
+// except authorization codes which expire within minutes anyway.
+//
+// Layout: MAGIC || header length (4B) || header || sections
+//
+// The header is plain CBOR, a section is its length (8B) followed by the
+// CBOR rows of one table encrypted under a key derived from the passphrase,
+// the header and the section's position, so sections can't be reordered or
+// moved to another archive. The archive ends with a section listing the row
+// count of every table, a truncated archive has none.
+pub struct Backup {}
+
+const MAGIC: &[u8] = b"idvault-backup\0";
+
+// Bumped when the layout of the archive changes.
+pub const BACKUP_VERSION: i16 = 1;
+
+// The latest migration, the rows in an archive only fit the schema it was
+// taken from. Update it with every migration.
+pub const SCHEMA_VERSION: &str = "2026-10-17-000014_certificate_revocation_account_key";
+
+// Limits on the lengths read from an archive before it is authenticated.
+// A section holds a whole table, so its limit is generous.
+const MAX_HEADER_LENGTH: u64 = 4 * 1024;
+const MAX_SECTION_LENGTH: u64 = 1024 * 1024 * 1024;
+
+// Rows per insert, postgres takes at most 65535 bind parameters.
+const INSERT_BATCH: usize = 1000;
+
+// The tables holding serial ids, their sequences are moved past the restored
+// rows.
+const SERIAL_TABLES: &[&str] = &[
+    "account",
+    "account_key_slot",
+    "account_move",
+    "application",
+    "certificate_revocation",
+    "oauth_token",
+    "read_grant_key",
+    "read_grant_scope",
+    "write_grant_scope",
+    "write_scope_succession",
+];
+
+#[derive(Serialize, Deserialize)]
+struct BackupHeader {
+    version: i16,
+    schema_version: String,
+    created_date: NaiveDateTime,
+    key_derivation: String,
+    salt: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct BackupSummary {
+    pub tables: Vec<(String, usize)>,
+}
+
+impl BackupSummary {
+    pub fn total(&self) -> usize {
+        self.tables.iter().map(|(_, count)| count).sum()
+    }
+}
+
+impl fmt::Display for BackupSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (table, count) in &self.tables {
+            writeln!(f, "{}: {}", table, count)?;
+        }
+
+        Ok(())
+    }
+}
+
+// One section per table, in an order that satisfies the foreign keys.
+#[derive(Serialize, Deserialize)]
+enum BackupSection {
+    Account(Vec<AccountRow>),
+    AccountKeySlot(Vec<AccountKeySlotRow>),
+    Application(Vec<ApplicationRow>),
+    Client(Vec<ClientRow>),
+    ReadGrantScope(Vec<ReadGrantScopeRow>),
+    ReadGrantKey(Vec<ReadGrantKeyRow>),
+    ReadAuthorization(Vec<ReadAuthorizationRow>),
+    WriteGrantScope(Vec<WriteGrantScopeRow>),
+    WriteAuthorization(Vec<WriteAuthorizationRow>),
+    WriteScopeSuccession(Vec<WriteScopeSuccessionRow>),
+    CertificateRevocation(Vec<CertificateRevocationRow>),
+    AccountMove(Vec<AccountMoveRow>),
+    OAuthToken(Vec<OAuthTokenRow>),
+    End(BackupSummary),
+}
+
+const SECTION_COUNT: usize = 13;
+
+impl BackupSection {
+    fn table(&self) -> &'static str {
+        use self::BackupSection::*;
+
+        match self {
+            Account(_) => "account",
+            AccountKeySlot(_) => "account_key_slot",
+            Application(_) => "application",
+            Client(_) => "client",
+            ReadGrantScope(_) => "read_grant_scope",
+            ReadGrantKey(_) => "read_grant_key",
+            ReadAuthorization(_) => "read_authorization",
+            WriteGrantScope(_) => "write_grant_scope",
+            WriteAuthorization(_) => "write_authorization",
+            WriteScopeSuccession(_) => "write_scope_succession",
+            CertificateRevocation(_) => "certificate_revocation",
+            AccountMove(_) => "account_move",
+            OAuthToken(_) => "oauth_token",
+            End(_) => "end",
+        }
+    }
+
+    fn len(&self) -> usize {
+        use self::BackupSection::*;
+
+        match self {
+            Account(rows) => rows.len(),
+            AccountKeySlot(rows) => rows.len(),
+            Application(rows) => rows.len(),
+            Client(rows) => rows.len(),
+            ReadGrantScope(rows) => rows.len(),
+            ReadGrantKey(rows) => rows.len(),
+            ReadAuthorization(rows) => rows.len(),
+            WriteGrantScope(rows) => rows.len(),
+            WriteAuthorization(rows) => rows.len(),
+            WriteScopeSuccession(rows) => rows.len(),
+            CertificateRevocation(rows) => rows.len(),
+            AccountMove(rows) => rows.len(),
+            OAuthToken(rows) => rows.len(),
+            End(_) => 0,
+        }
+    }
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "account"]
+struct AccountRow {
+    id: i32,
+    name: String,
+    email: String,
+    password_hash: String,
+    export_key_hash: String,
+    public_key: Vec<u8>,
+    encrypted_private_key: Vec<u8>,
+    master_key_salt: Vec<u8>,
+    encrypted_master_key: Vec<u8>,
+    is_admin: bool,
+    master_key_kdf: String,
+    key_algorithm: i16,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "account_key_slot"]
+struct AccountKeySlotRow {
+    id: i32,
+    account_id: i32,
+    label: String,
+    slot_type: String,
+    salt: Vec<u8>,
+    kdf: String,
+    encrypted_master_key: Vec<u8>,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "application"]
+struct ApplicationRow {
+    id: i32,
+    account_id: i32,
+    code: String,
+    description: String,
+    server_url: String,
+    signature: Vec<u8>,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "client"]
+struct ClientRow {
+    client_id: Vec<u8>,
+    application_id: i32,
+    signature: Vec<u8>,
+    kem_public_key: Option<Vec<u8>>,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "read_grant_scope"]
+struct ReadGrantScopeRow {
+    id: i32,
+    application_id: i32,
+    code: String,
+    display_name: Option<String>,
+    description: Option<String>,
+    signature: Vec<u8>,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "read_grant_key"]
+struct ReadGrantKeyRow {
+    id: i32,
+    read_grant_scope_id: i32,
+    public_key: Vec<u8>,
+    encrypted_private_key: Vec<u8>,
+    private_key_salt: Vec<u8>,
+    expiration_date: NaiveDateTime,
+    signature: Vec<u8>,
+    key_version: i16,
+    decrypt_only: bool,
+    key_algorithm: i16,
+    certificate_version: i16,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "read_authorization"]
+struct ReadAuthorizationRow {
+    client_id: Vec<u8>,
+    read_grant_key_id: i32,
+    encrypted_access_key: Vec<u8>,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+    encapsulated_key: Option<Vec<u8>>,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "write_grant_scope"]
+struct WriteGrantScopeRow {
+    id: i32,
+    application_id: i32,
+    code: String,
+    display_name: Option<String>,
+    description: Option<String>,
+    public_key: Vec<u8>,
+    encrypted_private_key: Vec<u8>,
+    private_key_salt: Vec<u8>,
+    expiration_date: NaiveDateTime,
+    signature: Vec<u8>,
+    key_version: i16,
+    key_algorithm: i16,
+    certificate_version: i16,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "write_authorization"]
+struct WriteAuthorizationRow {
+    client_id: Vec<u8>,
+    write_grant_scope_id: i32,
+    encrypted_access_key: Vec<u8>,
+    public_key: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "write_scope_succession"]
+struct WriteScopeSuccessionRow {
+    id: i32,
+    write_grant_scope_id: i32,
+    previous_public_key: Vec<u8>,
+    public_key: Vec<u8>,
+    succession_date: NaiveDateTime,
+    previous_signature: Vec<u8>,
+    successor_signature: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "certificate_revocation"]
+struct CertificateRevocationRow {
+    id: i32,
+    serial: i32,
+    public_key: Vec<u8>,
+    reason: String,
+    revocation_date: NaiveDateTime,
+    previous_hash: Vec<u8>,
+    signature: Vec<u8>,
+    account_public_key: Vec<u8>,
+    account_key_algorithm: i16,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "oauth_token"]
+struct OAuthTokenRow {
+    id: i32,
+    client_id: Vec<u8>,
+    access_token_hash: Vec<u8>,
+    refresh_token_hash: Vec<u8>,
+    read_grant_scope_ids: Vec<i32>,
+    write_grant_scope_ids: Vec<i32>,
+    expiration_date: NaiveDateTime,
+    refresh_expiration_date: NaiveDateTime,
+}
+
+#[derive(Queryable, Insertable, Serialize, Deserialize)]
+#[table_name = "account_move"]
+struct AccountMoveRow {
+    id: i32,
+    account_public_key: Vec<u8>,
+    account_key_algorithm: i16,
+    source_server_key: Vec<u8>,
+    destination_server_key: Vec<u8>,
+    destination_url: String,
+    move_date: NaiveDateTime,
+    signature: Vec<u8>,
+}
+
+impl Backup {
+    // Writes every table to the output, reading them from a single snapshot
+    // so the archive is consistent while the server keeps running.
+    pub fn write(output: &mut impl Write, passphrase: &str, connection: &MyConnection) -> CommonResult<BackupSummary> {
+        let salt = random_int_256();
+        let key_derivation = key_derivation_params();
+        let key = derive_password_key(passphrase, &salt, &key_derivation)?;
+        let header = serde_cbor::to_vec(&BackupHeader {
+            version: BACKUP_VERSION,
+            schema_version: SCHEMA_VERSION.to_owned(),
+            created_date: Utc::now().naive_utc(),
+            key_derivation,
+            salt: encode(salt),
+        })?;
+
+        output.write_all(MAGIC)?;
+        output.write_all(&(header.len() as u32).to_be_bytes())?;
+        output.write_all(&header)?;
+
+        let mut writer = SectionWriter { output, key, header_hash: header_hash(&header), index: 0, tables: Vec::new() };
+
+        connection.build_transaction().repeatable_read().read_only().run::<_, CommonError, _>(|| {
+            writer.write(BackupSection::Account(account::table.order(account::id).load(connection)?))?;
+            writer.write(BackupSection::AccountKeySlot(account_key_slot::table.order(account_key_slot::id).load(connection)?))?;
+            writer.write(BackupSection::Application(application::table.order(application::id).load(connection)?))?;
+            writer.write(BackupSection::Client(client::table.order(client::client_id).load(connection)?))?;
+            writer.write(BackupSection::ReadGrantScope(read_grant_scope::table.order(read_grant_scope::id).load(connection)?))?;
+            writer.write(BackupSection::ReadGrantKey(read_grant_key::table.order(read_grant_key::id).load(connection)?))?;
+            writer.write(BackupSection::ReadAuthorization(
+                read_authorization::table
+                    .order((read_authorization::read_grant_key_id, read_authorization::client_id))
+                    .load(connection)?,
+            ))?;
+            writer.write(BackupSection::WriteGrantScope(write_grant_scope::table.order(write_grant_scope::id).load(connection)?))?;
+            writer.write(BackupSection::WriteAuthorization(
+                write_authorization::table
+                    .order((write_authorization::write_grant_scope_id, write_authorization::client_id))
+                    .load(connection)?,
+            ))?;
+            writer.write(BackupSection::WriteScopeSuccession(
+                write_scope_succession::table.order(write_scope_succession::id).load(connection)?,
+            ))?;
+            writer.write(BackupSection::CertificateRevocation(
+                certificate_revocation::table.order(certificate_revocation::id).load(connection)?,
+            ))?;
+            writer.write(BackupSection::AccountMove(account_move::table.order(account_move::id).load(connection)?))?;
+            writer.write(BackupSection::OAuthToken(oauth_token::table.order(oauth_token::id).load(connection)?))?;
+            Ok(())
+        })?;
+
+        let summary = BackupSummary { tables: writer.tables.clone() };
+        writer.write(BackupSection::End(BackupSummary { tables: writer.tables.clone() }))?;
+        output.flush()?;
+
+        Ok(summary)
+    }
+
+    // Restores an archive into an empty database. The whole archive is read
+    // and every signature checked before anything is written, then all rows
+    // are inserted in one transaction.
+    pub fn restore(input: &mut impl Read, passphrase: &str, connection: &MyConnection) -> CommonResult<BackupSummary> {
+        let sections = Backup::read(input, passphrase)?;
+        verify_sections(&sections)?;
+
+        connection.transaction::<_, CommonError, _>(|| {
+            let existing: i64 = account::table.count().get_result(connection)?;
+            let moves: i64 = account_move::table.count().get_result(connection)?;
+            let revocations: i64 = certificate_revocation::table.count().get_result(connection)?;
+
+            if existing + moves + revocations > 0 {
+                return Err(CommonError::Duplicate(Some("Restore needs an empty database.".to_owned())));
+            }
+
+            for section in &sections {
+                insert_section(section, connection)?;
+            }
+
+            for table in SERIAL_TABLES {
+                sql_query(format!(
+                    "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM {0}",
+                    table
+                ))
+                .execute(connection)?;
+            }
+
+            Ok(())
+        })?;
+
+        Ok(BackupSummary { tables: sections.iter().map(|s| (s.table().to_owned(), s.len())).collect() })
+    }
+
+    // Decrypts the archive into its table sections, checking the header and
+    // that no section is missing.
+    fn read(input: &mut impl Read, passphrase: &str) -> CommonResult<Vec<BackupSection>> {
+        let mut magic = vec![0u8; MAGIC.len()];
+        input.read_exact(&mut magic).map_err(|_| not_a_backup())?;
+
+        if magic != MAGIC {
+            return Err(not_a_backup());
+        }
+
+        let mut length = [0u8; 4];
+        input.read_exact(&mut length).map_err(|_| not_a_backup())?;
+        let header = read_field(input, u32::from_be_bytes(length).into(), MAX_HEADER_LENGTH)?;
+
+        let parsed: BackupHeader = serde_cbor::from_slice(&header)?;
+
+        if parsed.version != BACKUP_VERSION {
+            return Err(CommonError::Misconfiguration(Some(format!(
+                "Unsupported backup version {}.",
+                parsed.version
+            ))));
+        }
+
+        if parsed.schema_version != SCHEMA_VERSION {
+            return Err(CommonError::Misconfiguration(Some(format!(
+                "The backup is of schema {}, this server is at {}.",
+                parsed.schema_version, SCHEMA_VERSION
+            ))));
+        }
+
+        let key = derive_password_key(passphrase, &decode(&parsed.salt)?, &parsed.key_derivation)?;
+        let header_hash = header_hash(&header);
+        let mut sections = Vec::with_capacity(SECTION_COUNT);
+
+        for index in 0.. {
+            let mut length = [0u8; 8];
+            input.read_exact(&mut length).map_err(|_| truncated())?;
+            let sealed = read_field(input, u64::from_be_bytes(length), MAX_SECTION_LENGTH)?;
+
+            let plaintext = decrypt(&sealed, &section_key(&key, &header_hash, index))
+                .map_err(|_| CommonError::CouldNotAuthenticate(Some("Wrong passphrase or damaged backup.".to_owned())))?;
+
+            match serde_cbor::from_slice(&plaintext)? {
+                BackupSection::End(summary) => {
+                    let found: Vec<(String, usize)> = sections
+                        .iter()
+                        .map(|s: &BackupSection| (s.table().to_owned(), s.len()))
+                        .collect();
+
+                    if sections.len() != SECTION_COUNT || found != summary.tables {
+                        return Err(truncated());
+                    }
+
+                    break;
+                }
+                section => sections.push(section),
+            }
+        }
+
+        if input.read(&mut [0u8; 1])? != 0 {
+            return Err(CommonError::FailedVerification(Some("Data after the end of the backup.".to_owned())));
+        }
+
+        Ok(sections)
+    }
+}
+
+struct SectionWriter<'a, W: Write> {
+    output: &'a mut W,
+    key: SecretKey,
+    header_hash: [u8; 32],
+    index: u64,
+    tables: Vec<(String, usize)>,
+}
+
+impl<'a, W: Write> SectionWriter<'a, W> {
+    fn write(&mut self, section: BackupSection) -> CommonResult<()> {
+        let sealed = encrypt(&serde_cbor::to_vec(&section)?, &section_key(&self.key, &self.header_hash, self.index));
+
+        self.output.write_all(&(sealed.len() as u64).to_be_bytes())?;
+        self.output.write_all(&sealed)?;
+        self.index += 1;
+
+        if let BackupSection::End(_) = section {
+            return Ok(());
+        }
+
+        self.tables.push((section.table().to_owned(), section.len()));
+        Ok(())
+    }
+}
+
+fn header_hash(header: &[u8]) -> [u8; 32] {
+    hash_by_parts(&[b"backup_header", header])
+}
+
+fn section_key(key: &[u8; 32], header_hash: &[u8; 32], index: u64) -> SecretKey {
+    SecretKey::new(hash_by_parts(&[b"backup_section", key, header_hash, &index.to_le_bytes()]))
+}
+
+// Reads a length prefixed field. The buffer grows with the data actually
+// read, so a damaged length can't allocate more than the archive holds.
+fn read_field(input: &mut impl Read, length: u64, limit: u64) -> CommonResult<Vec<u8>> {
+    if length > limit {
+        return Err(CommonError::FailedVerification(Some("The backup is damaged.".to_owned())));
+    }
+
+    let mut field = Vec::new();
+    input.by_ref().take(length).read_to_end(&mut field).map_err(|_| truncated())?;
+
+    if field.len() as u64 != length {
+        return Err(truncated());
+    }
+
+    Ok(field)
+}
+
+fn not_a_backup() -> CommonError {
+    CommonError::Misconfiguration(Some("Not an idvault backup.".to_owned()))
+}
+
+fn truncated() -> CommonError {
+    CommonError::FailedVerification(Some("The backup is incomplete.".to_owned()))
+}
+
+fn failed(table: &str, id: impl fmt::Display) -> CommonError {
+    CommonError::FailedVerification(Some(format!("{} {} failed verification.", table, id)))
+}
+
+// The key that signed the records of an account.
+struct Signer {
+    public_key: Vec<u8>,
+    key_algorithm: KeyAlgorithm,
+}
+
+impl Signer {
+    fn verify(&self, hash: &[u8; 32], signature: &[u8]) -> bool {
+        verify_signature(self.key_algorithm, &self.public_key, hash, signature)
+    }
+
+    fn cert_data(&self, key_algorithm: KeyAlgorithm, public_key: &[u8], scope: Scope, expiration_date: NaiveDateTime, version: i16) -> CertData {
+        CertData {
+            version: version as u8,
+            signing_algorithm: self.key_algorithm,
+            signing_key: self.public_key.clone(),
+            key_algorithm,
+            public_key: public_key.to_vec(),
+            scope,
+            expiration_date,
+        }
+    }
+}
+
+// Checks every signed row against the account owning it. The sections are in
+// foreign key order so each row's owner has been seen before it.
+fn verify_sections(sections: &[BackupSection]) -> CommonResult<()> {
+    let mut accounts: HashMap<i32, Signer> = HashMap::new();
+    // application id to (code, account id)
+    let mut applications: HashMap<i32, (String, i32)> = HashMap::new();
+    // scope id to (application id, code), and read grant key id to scope id
+    let mut read_scopes: HashMap<i32, (i32, String)> = HashMap::new();
+    let mut read_keys: HashMap<i32, i32> = HashMap::new();
+    // write scope id to (application id, code, key algorithm)
+    let mut write_scopes: HashMap<i32, (i32, String, KeyAlgorithm)> = HashMap::new();
+    let mut clients: HashSet<Vec<u8>> = HashSet::new();
+
+    for section in sections {
+        match section {
+            BackupSection::Account(rows) => {
+                for row in rows {
+                    let key_algorithm = KeyAlgorithm::from_id(row.key_algorithm)?;
+                    accounts.insert(row.id, Signer { public_key: row.public_key.clone(), key_algorithm });
+                }
+            }
+            BackupSection::AccountKeySlot(rows) => {
+                for row in rows {
+                    if !accounts.contains_key(&row.account_id) {
+                        return Err(failed("account_key_slot", row.id));
+                    }
+                }
+            }
+            BackupSection::Application(rows) => {
+                for row in rows {
+                    let record = Application {
+                        id: row.id,
+                        account_id: row.account_id,
+                        code: row.code.clone(),
+                        description: row.description.clone(),
+                        server_url: row.server_url.clone(),
+                        signature: row.signature.clone(),
+                    };
+
+                    match accounts.get(&row.account_id) {
+                        Some(signer) if signer.verify(&record.record_hash(), &row.signature) => {
+                            applications.insert(row.id, (row.code.clone(), row.account_id));
+                        }
+                        _ => return Err(failed("application", row.id)),
+                    }
+                }
+            }
+            BackupSection::Client(rows) => {
+                for row in rows {
+                    let (application_code, account_id) = applications.get(&row.application_id).cloned()
+                        .ok_or_else(|| failed("client", encode(&row.client_id)))?;
+                    let record = Client {
+                        client_id: row.client_id.clone(),
+                        application_id: row.application_id,
+                        application_code,
+                        signature: row.signature.clone(),
+                        kem_public_key: row.kem_public_key.clone(),
+                    };
+
+                    if !accounts[&account_id].verify(&record.record_hash(), &row.signature) {
+                        return Err(failed("client", encode(&row.client_id)));
+                    }
+
+                    clients.insert(row.client_id.clone());
+                }
+            }
+            BackupSection::ReadGrantScope(rows) => {
+                for row in rows {
+                    let (application_code, account_id) = applications.get(&row.application_id).cloned()
+                        .ok_or_else(|| failed("read_grant_scope", row.id))?;
+                    let record = ReadScope {
+                        id: row.id,
+                        application_id: row.application_id,
+                        application_code,
+                        code: row.code.clone(),
+                        display_name: row.display_name.clone(),
+                        description: row.description.clone(),
+                        signature: row.signature.clone(),
+                    };
+
+                    if !accounts[&account_id].verify(&record.record_hash(), &row.signature) {
+                        return Err(failed("read_grant_scope", row.id));
+                    }
+
+                    read_scopes.insert(row.id, (row.application_id, row.code.clone()));
+                }
+            }
+            BackupSection::ReadGrantKey(rows) => {
+                for row in rows {
+                    let (application_id, scope_code) =
+                        read_scopes.get(&row.read_grant_scope_id).ok_or_else(|| failed("read_grant_key", row.id))?;
+                    let (application_code, account_id) = &applications[application_id];
+                    let signer = &accounts[account_id];
+                    let data = signer.cert_data(
+                        KeyAlgorithm::from_id(row.key_algorithm)?,
+                        &row.public_key,
+                        Scope::Read { application: application_code.clone(), grant: scope_code.clone() },
+                        row.expiration_date,
+                        row.certificate_version,
+                    );
+
+                    if !signer.verify(&data.hash(), &row.signature) {
+                        return Err(failed("read_grant_key", row.id));
+                    }
+
+                    read_keys.insert(row.id, row.read_grant_scope_id);
+                }
+            }
+            BackupSection::ReadAuthorization(rows) => {
+                for row in rows {
+                    let id = format!("{}/{}", encode(&row.client_id), row.read_grant_key_id);
+                    let scope_id = read_keys.get(&row.read_grant_key_id).ok_or_else(|| failed("read_authorization", &id))?;
+                    let account_id = applications[&read_scopes[scope_id].0].1;
+                    let record = ReadAuthorization {
+                        client_id: row.client_id.clone(),
+                        read_grant_key_id: row.read_grant_key_id,
+                        encrypted_access_key: row.encrypted_access_key.clone(),
+                        public_key: row.public_key.clone(),
+                        signature: row.signature.clone(),
+                        encapsulated_key: row.encapsulated_key.clone(),
+                    };
+
+                    if !accounts[&account_id].verify(&record.record_hash(), &row.signature) {
+                        return Err(failed("read_authorization", &id));
+                    }
+                }
+            }
+            BackupSection::WriteGrantScope(rows) => {
+                for row in rows {
+                    let (application_code, account_id) = applications.get(&row.application_id).cloned()
+                        .ok_or_else(|| failed("write_grant_scope", row.id))?;
+                    let signer = &accounts[&account_id];
+                    let key_algorithm = KeyAlgorithm::from_id(row.key_algorithm)?;
+                    let data = signer.cert_data(
+                        key_algorithm,
+                        &row.public_key,
+                        Scope::Write { application: application_code, grant: row.code.clone() },
+                        row.expiration_date,
+                        row.certificate_version,
+                    );
+
+                    if !signer.verify(&data.hash(), &row.signature) {
+                        return Err(failed("write_grant_scope", row.id));
+                    }
+
+                    write_scopes.insert(row.id, (row.application_id, row.code.clone(), key_algorithm));
+                }
+            }
+            BackupSection::WriteAuthorization(rows) => {
+                for row in rows {
+                    let id = format!("{}/{}", encode(&row.client_id), row.write_grant_scope_id);
+                    let (application_id, _, _) =
+                        write_scopes.get(&row.write_grant_scope_id).ok_or_else(|| failed("write_authorization", &id))?;
+                    let account_id = applications[application_id].1;
+                    let record = WriteAuthorization {
+                        client_id: row.client_id.clone(),
+                        write_grant_scope_id: row.write_grant_scope_id,
+                        encrypted_access_key: row.encrypted_access_key.clone(),
+                        public_key: row.public_key.clone(),
+                        signature: row.signature.clone(),
+                    };
+
+                    if !accounts[&account_id].verify(&record.record_hash(), &row.signature) {
+                        return Err(failed("write_authorization", &id));
+                    }
+                }
+            }
+            BackupSection::WriteScopeSuccession(rows) => {
+                for row in rows {
+                    let (application_id, scope_code, key_algorithm) = write_scopes
+                        .get(&row.write_grant_scope_id)
+                        .ok_or_else(|| failed("write_scope_succession", row.id))?;
+                    let (application_code, account_id) = &applications[application_id];
+                    let signer = &accounts[account_id];
+                    let record = LockedWriteScopeSuccession {
+                        id: row.id,
+                        write_grant_scope_id: row.write_grant_scope_id,
+                        previous_public_key: row.previous_public_key.clone(),
+                        public_key: row.public_key.clone(),
+                        succession_date: row.succession_date,
+                        previous_signature: row.previous_signature.clone(),
+                        successor_signature: row.successor_signature.clone(),
+                        signature: row.signature.clone(),
+                        application_code: application_code.clone(),
+                        scope_code: scope_code.clone(),
+                        key_algorithm: *key_algorithm,
+                        account_public_key: signer.public_key.clone(),
+                        account_key_algorithm: signer.key_algorithm,
+                    };
+
+                    if !record.verify() {
+                        return Err(failed("write_scope_succession", row.id));
+                    }
+                }
+            }
+            BackupSection::CertificateRevocation(rows) => {
+                // Each account key's revocations form a chain ordered by
+                // serial. The account may have been deleted since.
+                let mut chains: HashMap<Vec<u8>, Vec<LockedCertificateRevocation>> = HashMap::new();
+
+                for row in rows {
+                    chains.entry(row.account_public_key.clone()).or_default().push(LockedCertificateRevocation {
+                        id: row.id,
+                        serial: row.serial,
+                        public_key: row.public_key.clone(),
+                        reason: row.reason.clone(),
+                        revocation_date: row.revocation_date,
+                        previous_hash: row.previous_hash.clone(),
+                        signature: row.signature.clone(),
+                        account_public_key: row.account_public_key.clone(),
+                        account_key_algorithm: KeyAlgorithm::from_id(row.account_key_algorithm)?,
+                    });
+                }
+
+                for (account_public_key, mut chain) in chains {
+                    chain.sort_by_key(|entry| entry.serial);
+
+                    if !CertificateRevocation::verify_chain(&account_public_key, &[0u8; 32], &chain) {
+                        return Err(failed("certificate_revocation chain starting at", chain[0].id));
+                    }
+                }
+            }
+            BackupSection::AccountMove(rows) => {
+                for row in rows {
+                    let record = NewAccountMove {
+                        account_public_key: row.account_public_key.clone(),
+                        account_key_algorithm: KeyAlgorithm::from_id(row.account_key_algorithm)?,
+                        source_server_key: row.source_server_key.clone(),
+                        destination_server_key: row.destination_server_key.clone(),
+                        destination_url: row.destination_url.clone(),
+                        move_date: row.move_date,
+                        signature: row.signature.clone(),
+                    };
+
+                    if !record.verify() {
+                        return Err(failed("account_move", row.id));
+                    }
+                }
+            }
+            BackupSection::OAuthToken(rows) => {
+                for row in rows {
+                    if !clients.contains(&row.client_id) {
+                        return Err(failed("oauth_token", row.id));
+                    }
+                }
+            }
+            BackupSection::End(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn insert_section(section: &BackupSection, connection: &MyConnection) -> CommonResult<()> {
+    match section {
+        BackupSection::Account(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(account::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::AccountKeySlot(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(account_key_slot::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::Application(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(application::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::Client(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(client::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::ReadGrantScope(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(read_grant_scope::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::ReadGrantKey(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(read_grant_key::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::ReadAuthorization(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(read_authorization::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::WriteGrantScope(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(write_grant_scope::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::WriteAuthorization(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(write_authorization::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::WriteScopeSuccession(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(write_scope_succession::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::CertificateRevocation(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(certificate_revocation::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::AccountMove(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(account_move::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::OAuthToken(rows) => {
+            for chunk in rows.chunks(INSERT_BATCH) {
+                insert_into(oauth_token::table).values(chunk).execute(connection)?;
+            }
+        }
+        BackupSection::End(_) => {}
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_database;
+    use crate::model::account::Account;
+    use crate::model::account_key_slot::{AccountKeySlot, KeySlotType};
+    use crate::model::certificate_revocation::RevocationReason;
+    use crate::model::oauth_token::OAuthToken;
+    use crate::model::write_scope::WriteScope;
+
+    fn empty_database(name: &str) -> MyConnection {
+        let connection = MyConnection::establish(&test_database(name)).unwrap();
+        sql_query("TRUNCATE account, account_move, certificate_revocation CASCADE").execute(&connection).unwrap();
+        connection
+    }
+
+    #[test]
+    fn backup_and_restore() {
+        let source = empty_database("idvault_backup");
+        let target = empty_database("idvault_restore");
+
+        let account = Account::new("Backup01", "backup01@example.com", "password", "export_key", false)
+            .save(&source)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        let application = Application::new("backup", "Backup", "https://example.com", &account)
+            .save(&source)
+            .unwrap();
+        let (_, client) = Client::new(&account, &application);
+        let client = client.save(&source).unwrap();
+
+        let read_scope = ReadScope::new("read", &application, &account)
+            .save(&source)
+            .unwrap()
+            .to_unlocked(&account, &source)
+            .unwrap();
+        read_scope.add_new_key(&account, &source).unwrap();
+        ReadScope::load_id(read_scope.id, &source)
+            .unwrap()
+            .to_unlocked(&account, &source)
+            .unwrap()
+            .authorize(&account, &client, &source)
+            .unwrap();
+
+        AccountKeySlot::create(&account, "device", KeySlotType::Device, b"device secret")
+            .unwrap()
+            .save(&source)
+            .unwrap();
+
+        // Rotating the write key leaves a succession, the old key is revoked.
+        let write_scope = WriteScope::new("write", &application, &account)
+            .save(&source)
+            .unwrap()
+            .unlock_by_account(&account)
+            .unwrap();
+        write_scope.authorize(&account, &client, &source).unwrap();
+        write_scope.rotate_key(&account, &source).unwrap();
+        CertificateRevocation::revoke(&account, &write_scope.public_key, RevocationReason::Superseded, &source).unwrap();
+        OAuthToken::issue(&client, None, &source).unwrap();
+
+        let mut archive = Vec::new();
+        let summary = Backup::write(&mut archive, "passphrase", &source).unwrap();
+        assert_eq!(summary.tables.len(), SECTION_COUNT);
+        assert!(summary.tables.iter().all(|(table, count)| *count > 0 || table == "account_move"));
+
+        // A wrong passphrase or a truncated archive is refused.
+        assert!(Backup::restore(&mut &archive[..], "wrong", &target).is_err());
+        assert!(Backup::restore(&mut &archive[..archive.len() - 1], "passphrase", &target).is_err());
+
+        assert_eq!(Backup::restore(&mut &archive[..], "passphrase", &target).unwrap(), summary);
+
+        let restored = Account::load_locked("Backup01", &target).unwrap().to_unlocked("password").unwrap();
+        Application::load_by_code("backup", &restored, &target).unwrap();
+
+        // New rows carry on from the restored ids.
+        Application::new("second", "Second", "https://example.com", &restored)
+            .save(&target)
+            .unwrap();
+
+        match Backup::restore(&mut &archive[..], "passphrase", &target) {
+            Err(CommonError::Duplicate(_)) => {}
+            _ => panic!("Restored into a database in use."),
+        }
+
+        // A record that no longer verifies fails the whole restore.
+        diesel::update(application::table)
+            .set(application::description.eq("Changed"))
+            .execute(&source)
+            .unwrap();
+        let mut archive = Vec::new();
+        Backup::write(&mut archive, "passphrase", &source).unwrap();
+
+        let target = empty_database("idvault_restore");
+        match Backup::restore(&mut &archive[..], "passphrase", &target) {
+            Err(CommonError::FailedVerification(_)) => {}
+            _ => panic!("Restored a tampered application."),
+        }
+        assert_eq!(account::table.count().get_result::<i64>(&target).unwrap(), 0);
+    }
+
+    #[test]
+    fn damaged_lengths() {
+        let header = serde_cbor::to_vec(&BackupHeader {
+            version: BACKUP_VERSION,
+            schema_version: SCHEMA_VERSION.to_owned(),
+            created_date: Utc::now().naive_utc(),
+            key_derivation: key_derivation_params(),
+            salt: encode(random_int_256()),
+        })
+        .unwrap();
+        let archive = |header_length: u32, sections: &[u8]| {
+            let mut archive = MAGIC.to_vec();
+            archive.extend_from_slice(&header_length.to_be_bytes());
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(sections);
+            archive
+        };
+        let damaged = |archive: Vec<u8>| match Backup::read(&mut &archive[..], "passphrase") {
+            Err(CommonError::FailedVerification(_)) => {}
+            _ => panic!("Read a damaged archive."),
+        };
+
+        damaged(archive(u32::MAX, &[]));
+        damaged(archive(header.len() as u32 + 1, &[]));
+
+        let length = header.len() as u32;
+        damaged(archive(length, &u64::MAX.to_be_bytes()));
+        damaged(archive(length, &[&1000u64.to_be_bytes()[..], &[0u8; 10]].concat()));
+        damaged(archive(length, &[0u8; 3]));
+    }
+}
@@ -1,53 +1,465 @@
 use crate::model::Scope;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime};
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::signing_key::verify_signature;
 use crate::encryption::{lpad_to_256, hash_by_parts};
+use crate::error::{CommonError, CommonResult};
+use std::convert::TryInto;
+
+// Wire layout, integers little endian:
+//
+// version (1B) || signing algorithm (1B) || signing key length (2B) || signing key
+// || key algorithm (1B) || public key length (2B) || public key
+// || expiration date (8B) || scope kind (1B) || application length (2B) || application
+// || grant length (2B) || grant || signature length (2B) || signature
+//
+// Version 1 had no algorithm ids and fixed 32 byte keys and a 64 byte
+// signature. It is still accepted as Ed25519 signed, with an Ed25519 write
+// scope key or an X25519 read grant key. Its hash leaves out the algorithm
+// ids, so it is only used for those algorithms. Stored scope keys record the
+// version they were certified under.
+pub const CERTIFICATE_VERSION: u8 = 2;
+pub const ED25519_CERTIFICATE_VERSION: u8 = 1;
+
+// Exports from before scope keys recorded their certificate version.
+pub fn legacy_certificate_version() -> u8 {
+    ED25519_CERTIFICATE_VERSION
+}
+
+const SCOPE_READ: u8 = 0;
+const SCOPE_WRITE: u8 = 1;
 
 #[derive(Clone)]
 pub struct CertData {
-    pub signing_key:     [u8; 32],
-    pub public_key:      [u8; 32],
-    pub scope:           Scope,
-    pub expiration_date: NaiveDateTime,
+    pub version:           u8,
+    pub signing_algorithm: KeyAlgorithm,
+    pub signing_key:       Vec<u8>,
+    pub key_algorithm:     KeyAlgorithm,
+    pub public_key:        Vec<u8>,
+    pub scope:             Scope,
+    pub expiration_date:   NaiveDateTime,
 }
 
 impl CertData {
     pub fn hash(&self) -> [u8; 32] {
-        let time = self.expiration_date.timestamp().to_le_bytes();
+        let time = self.expiration_date.and_utc().timestamp().to_le_bytes();
         let date = lpad_to_256(&time);
 
+        // Version 1 certificates keep the hash they were signed with.
+        if self.is_legacy() {
+            return hash_by_parts(&[
+                          &self.signing_key,
+                          &self.public_key,
+                          &self.scope.hash(),
+                          &date,
+            ]);
+        }
+
         hash_by_parts(&[
+                      &self.signing_algorithm.id().to_le_bytes(),
                       &self.signing_key,
+                      &self.key_algorithm.id().to_le_bytes(),
                       &self.public_key,
                       &self.scope.hash(),
                       &date,
         ])
     }
+
+    // A version 1 certificate, whose algorithms follow from its scope.
+    fn is_legacy(&self) -> bool {
+        let key_algorithm = match self.scope {
+            Scope::Read { .. } => KeyAlgorithm::X25519,
+            Scope::Write { .. } => KeyAlgorithm::Ed25519,
+        };
+
+        self.version == ED25519_CERTIFICATE_VERSION
+            && self.signing_algorithm == KeyAlgorithm::Ed25519
+            && self.key_algorithm == key_algorithm
+    }
 }
 
 pub struct Certificate {
     pub data: CertData,
-    pub signature: [u8; 64],
+    pub signature: Vec<u8>,
 }
 
 impl Certificate {
-    pub fn to_bytes(&self) -> [u8; 160] {
-        let mut bytes = [0u8; 160];
-        
-        for i in 0..32 {
-            bytes[i] = self.data.signing_key[i];
-            bytes[i + 32] = self.data.public_key[i];
-            bytes[i + 64] = self.data.scope.hash()[i];
-            bytes[i + 128] = self.signature[i];
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let (kind, application, grant) = match &self.data.scope {
+            Scope::Read { application, grant } => (SCOPE_READ, application, grant),
+            Scope::Write { application, grant } => (SCOPE_WRITE, application, grant),
+        };
+
+        let legacy = self.data.is_legacy();
+        let mut bytes = Vec::new();
+
+        if legacy {
+            bytes.push(ED25519_CERTIFICATE_VERSION);
+            bytes.extend_from_slice(&self.data.signing_key);
+            bytes.extend_from_slice(&self.data.public_key);
+        } else {
+            bytes.push(CERTIFICATE_VERSION);
+            bytes.push(self.data.signing_algorithm.id() as u8);
+            push_bytes(&mut bytes, &self.data.signing_key);
+            bytes.push(self.data.key_algorithm.id() as u8);
+            push_bytes(&mut bytes, &self.data.public_key);
         }
 
-        for i in 0..8 {
-            bytes[i + 96] = self.data.expiration_date.timestamp().to_le_bytes()[i];
+        bytes.extend_from_slice(&self.data.expiration_date.and_utc().timestamp().to_le_bytes());
+        bytes.push(kind);
+        push_bytes(&mut bytes, application.as_bytes());
+        push_bytes(&mut bytes, grant.as_bytes());
+
+        if legacy {
+            bytes.extend_from_slice(&self.signature);
+        } else {
+            push_bytes(&mut bytes, &self.signature);
         }
 
         bytes
     }
 
+    pub fn from_bytes(bytes: &[u8]) -> CommonResult<Certificate> {
+        let mut reader = Reader { bytes };
+        let version = reader.take(1)?[0];
+
+        let (signing_algorithm, signing_key, key_algorithm, public_key) = match version {
+            CERTIFICATE_VERSION => (
+                reader.algorithm()?,
+                reader.bytes()?,
+                reader.algorithm()?,
+                reader.bytes()?,
+            ),
+            ED25519_CERTIFICATE_VERSION => (
+                KeyAlgorithm::Ed25519,
+                reader.take(32)?.to_vec(),
+                KeyAlgorithm::Ed25519,
+                reader.take(32)?.to_vec(),
+            ),
+            _ => return Err(malformed("Unsupported certificate version.")),
+        };
+
+        let timestamp = i64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let kind = reader.take(1)?[0];
+        let application = reader.string()?;
+        let grant = reader.string()?;
+
+        let signature = match version {
+            CERTIFICATE_VERSION => reader.bytes()?,
+            _ => reader.take(64)?.to_vec(),
+        };
+
+        if !reader.bytes.is_empty() {
+            return Err(malformed("Trailing bytes after certificate."));
+        }
+
+        let (scope, key_algorithm) = match kind {
+            SCOPE_READ if version == ED25519_CERTIFICATE_VERSION => {
+                (Scope::Read { application, grant }, KeyAlgorithm::X25519)
+            }
+            SCOPE_READ => (Scope::Read { application, grant }, key_algorithm),
+            SCOPE_WRITE => (Scope::Write { application, grant }, key_algorithm),
+            _ => return Err(malformed("Unknown certificate scope.")),
+        };
+
+        let expiration_date = DateTime::from_timestamp(timestamp, 0).map(|date| date.naive_utc())
+            .ok_or_else(|| malformed("Invalid certificate expiration date."))?;
+
+        Ok(Certificate {
+            data: CertData {
+                version,
+                signing_algorithm,
+                signing_key,
+                key_algorithm,
+                public_key,
+                scope,
+                expiration_date,
+            },
+            signature,
+        })
+    }
+
+    // Checks the certificate was issued by the trusted account, is signed
+    // correctly, has not expired and names a complete scope.
+    pub fn verify(&self, trusted_account_key: &[u8], now: NaiveDateTime) -> CommonResult<()> {
+        if trusted_account_key != &self.data.signing_key[..] {
+            return Err(CommonError::FailedVerification(Some(
+                "Certificate was not issued by the trusted account.".to_owned(),
+            )));
+        }
+
+        if !verify_signature(self.data.signing_algorithm, trusted_account_key, &self.data.hash(), &self.signature) {
+            return Err(CommonError::FailedVerification(Some(
+                "Certificate signature is invalid.".to_owned(),
+            )));
+        }
+
+        if now >= self.data.expiration_date {
+            return Err(CommonError::FailedVerification(Some(
+                "Certificate has expired.".to_owned(),
+            )));
+        }
+
+        let (application, grant) = match &self.data.scope {
+            Scope::Read { application, grant } => (application, grant),
+            Scope::Write { application, grant } => (application, grant),
+        };
+
+        if application.is_empty() || grant.is_empty() {
+            return Err(CommonError::FailedVerification(Some(
+                "Certificate scope is incomplete.".to_owned(),
+            )));
+        }
+
+        Ok(())
+    }
+
+    // As verify, also requiring the certificate to be for the given scope.
+    pub fn verify_scope(
+        &self,
+        trusted_account_key: &[u8],
+        scope: &Scope,
+        now: NaiveDateTime,
+    ) -> CommonResult<()> {
+        self.verify(trusted_account_key, now)?;
+
+        if &self.data.scope != scope {
+            return Err(CommonError::FailedVerification(Some(
+                "Certificate is for a different scope.".to_owned(),
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Validates the chain from the trusted account key through this write
+    // scope certificate to a message signed by the scope key.
+    pub fn verify_message(
+        &self,
+        trusted_account_key: &[u8],
+        now: NaiveDateTime,
+        message: &[u8],
+        signature: &[u8],
+    ) -> CommonResult<()> {
+        self.verify(trusted_account_key, now)?;
+
+        if let Scope::Read { .. } = self.data.scope {
+            return Err(CommonError::FailedVerification(Some(
+                "Read scope certificates cannot sign messages.".to_owned(),
+            )));
+        }
+
+        if !verify_signature(self.data.key_algorithm, &self.data.public_key, message, signature) {
+            return Err(CommonError::FailedVerification(Some(
+                "Message signature is invalid.".to_owned(),
+            )));
+        }
+
+        Ok(())
+    }
+
     pub fn signature(&self) -> Vec<u8> {
-        self.signature.to_vec()
+        self.signature.clone()
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> CommonResult<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(malformed("Certificate is truncated."));
+        }
+
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn bytes(&mut self) -> CommonResult<Vec<u8>> {
+        let length = u16::from_le_bytes(self.take(2)?.try_into().unwrap()) as usize;
+
+        Ok(self.take(length)?.to_vec())
+    }
+
+    fn string(&mut self) -> CommonResult<String> {
+        String::from_utf8(self.bytes()?)
+            .map_err(|_| malformed("Certificate scope is not valid UTF-8."))
+    }
+
+    fn algorithm(&mut self) -> CommonResult<KeyAlgorithm> {
+        KeyAlgorithm::from_id(self.take(1)?[0] as i16)
+            .map_err(|_| malformed("Unknown certificate key algorithm."))
+    }
+}
+
+// u16 length || bytes
+fn push_bytes(bytes: &mut Vec<u8>, value: &[u8]) {
+    let length: u16 = value.len().try_into().expect("Certificate fields are shorter than 64KiB.");
+    bytes.extend_from_slice(&length.to_le_bytes());
+    bytes.extend_from_slice(value);
+}
+
+fn malformed(reason: &str) -> CommonError {
+    CommonError::FailedVerification(Some(reason.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::signing_key::SigningKey;
+    use chrono::{Duration, Utc};
+
+    fn certificate(account: &SigningKey, scope_key: &SigningKey, scope: Scope) -> Certificate {
+        let data = CertData {
+            version: CERTIFICATE_VERSION,
+            signing_algorithm: account.algorithm(),
+            signing_key: account.public_key(),
+            key_algorithm: scope_key.algorithm(),
+            public_key: scope_key.public_key(),
+            scope,
+            expiration_date: DateTime::from_timestamp((Utc::now() + Duration::days(365)).timestamp(), 0).unwrap().naive_utc(),
+        };
+        let signature = account.sign(&data.hash());
+
+        Certificate { data, signature }
+    }
+
+    fn write_scope() -> Scope {
+        Scope::Write { application: "spout".to_owned(), grant: "crap".to_owned() }
+    }
+
+    #[test]
+    fn certificate_round_trip() {
+        for algorithm in &[KeyAlgorithm::Ed25519, KeyAlgorithm::P256] {
+            let account = SigningKey::generate(*algorithm).unwrap();
+            let cert = certificate(&account, &SigningKey::generate(*algorithm).unwrap(), write_scope());
+
+            let parsed = Certificate::from_bytes(&cert.to_bytes()).unwrap();
+
+            assert_eq!(parsed.data.signing_algorithm, *algorithm);
+            assert_eq!(parsed.data.signing_key, cert.data.signing_key);
+            assert_eq!(parsed.data.key_algorithm, *algorithm);
+            assert_eq!(parsed.data.public_key, cert.data.public_key);
+            assert!(parsed.data.scope == cert.data.scope);
+            assert_eq!(parsed.data.expiration_date, cert.data.expiration_date);
+            assert_eq!(parsed.signature, cert.signature);
+            assert!(parsed.verify(&account.public_key(), Utc::now().naive_utc()).is_ok());
+        }
+    }
+
+    #[test]
+    fn parse_version_one_certificate() {
+        let account = SigningKey::new();
+        let mut cert = certificate(&account, &SigningKey::new(), write_scope());
+        cert.data.version = ED25519_CERTIFICATE_VERSION;
+        cert.signature = account.sign(&cert.data.hash());
+
+        let mut bytes = vec![ED25519_CERTIFICATE_VERSION];
+        bytes.extend_from_slice(&cert.data.signing_key);
+        bytes.extend_from_slice(&cert.data.public_key);
+        bytes.extend_from_slice(&cert.data.expiration_date.and_utc().timestamp().to_le_bytes());
+        bytes.push(SCOPE_WRITE);
+        push_bytes(&mut bytes, b"spout");
+        push_bytes(&mut bytes, b"crap");
+        bytes.extend_from_slice(&cert.signature);
+
+        let parsed = Certificate::from_bytes(&bytes).unwrap();
+
+        assert_eq!(parsed.data.signing_algorithm, KeyAlgorithm::Ed25519);
+        assert_eq!(parsed.data.key_algorithm, KeyAlgorithm::Ed25519);
+        assert!(parsed.verify(&account.public_key(), Utc::now().naive_utc()).is_ok());
+        assert_eq!(parsed.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn algorithms_are_signed() {
+        let account = SigningKey::new();
+        let now = Utc::now().naive_utc();
+        let mut cert = certificate(&account, &SigningKey::new(), write_scope());
+
+        cert.data.key_algorithm = KeyAlgorithm::X25519;
+        assert!(cert.verify(&account.public_key(), now).is_err());
+
+        // Version 1 hashes only cover the algorithms version 1 had.
+        let read_scope = Scope::Read { application: "spout".to_owned(), grant: "crap".to_owned() };
+        let mut legacy = certificate(&account, &SigningKey::new(), read_scope);
+        legacy.data.version = ED25519_CERTIFICATE_VERSION;
+        legacy.data.key_algorithm = KeyAlgorithm::X25519;
+        legacy.signature = account.sign(&legacy.data.hash());
+        assert!(legacy.verify(&account.public_key(), now).is_ok());
+
+        legacy.data.key_algorithm = KeyAlgorithm::X25519MlKem768;
+        assert!(legacy.verify(&account.public_key(), now).is_err());
+
+        legacy.data.key_algorithm = KeyAlgorithm::X25519;
+        legacy.data.version = CERTIFICATE_VERSION;
+        assert!(legacy.verify(&account.public_key(), now).is_err());
+    }
+
+    #[test]
+    fn reject_malformed_certificates() {
+        let account = SigningKey::new();
+        let bytes = certificate(&account, &SigningKey::new(), write_scope()).to_bytes();
+
+        assert!(Certificate::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Certificate::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[0] = CERTIFICATE_VERSION + 1;
+        assert!(Certificate::from_bytes(&wrong_version).is_err());
+
+        let mut wrong_algorithm = bytes.clone();
+        wrong_algorithm[1] = 9;
+        assert!(Certificate::from_bytes(&wrong_algorithm).is_err());
+
+        // version, then algorithm, length and key twice, then the date
+        let mut wrong_kind = bytes;
+        wrong_kind[2 * (1 + 2 + 32) + 1 + 8] = 7;
+        assert!(Certificate::from_bytes(&wrong_kind).is_err());
+    }
+
+    #[test]
+    fn verify_certificate() {
+        let account = SigningKey::new();
+        let now = Utc::now().naive_utc();
+        let mut cert = certificate(&account, &SigningKey::new(), write_scope());
+
+        assert!(cert.verify(&SigningKey::new().public_key(), now).is_err());
+        assert!(cert.verify(&account.public_key(), now + Duration::days(366)).is_err());
+        assert!(cert.verify_scope(&account.public_key(), &write_scope(), now).is_ok());
+
+        let read_scope = Scope::Read { application: "spout".to_owned(), grant: "crap".to_owned() };
+        assert!(cert.verify_scope(&account.public_key(), &read_scope, now).is_err());
+
+        cert.data.scope = Scope::Write { application: "spout".to_owned(), grant: "flush".to_owned() };
+        assert!(cert.verify(&account.public_key(), now).is_err());
+
+        cert.data.scope = write_scope();
+        cert.data.signing_algorithm = KeyAlgorithm::P256;
+        assert!(cert.verify(&account.public_key(), now).is_err());
+
+        let empty = certificate(&account, &SigningKey::new(), Scope::Write { application: "spout".to_owned(), grant: String::new() });
+        assert!(empty.verify(&account.public_key(), now).is_err());
+    }
+
+    #[test]
+    fn verify_message_chain() {
+        let account = SigningKey::new();
+        let scope_key = SigningKey::generate(KeyAlgorithm::P256).unwrap();
+        let now = Utc::now().naive_utc();
+        let cert = certificate(&account, &scope_key, write_scope());
+        let message = b"Please sign and return";
+
+        let signature = scope_key.sign(message);
+
+        assert!(cert.verify_message(&account.public_key(), now, message, &signature).is_ok());
+        assert!(cert.verify_message(&account.public_key(), now, b"Something else", &signature).is_err());
+        assert!(cert.verify_message(&account.public_key(), now, message, &account.sign(message)).is_err());
+
+        let read = certificate(&account, &scope_key, Scope::Read { application: "spout".to_owned(), grant: "crap".to_owned() });
+        assert!(read.verify_message(&account.public_key(), now, message, &signature).is_err());
     }
 }
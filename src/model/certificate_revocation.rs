@@ -0,0 +1,437 @@
+use base64::encode;
+use chrono::{NaiveDateTime, Utc};
+use crate::database::schema::{account, certificate_revocation};
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::signing_key::verify_signature;
+use crate::encryption::{hash_by_parts, lpad_to_256};
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::UnlockedAccount;
+use crate::model::{Signable, Signed};
+use std::str::FromStr;
+
+// An account's certificate revocation list. Entries are numbered from 1 and
+// each is signed by the account over the hash of the entry before it, so a
+// verifier that has checked the list up to some serial only needs the entries
+// after it. Lists are kept by account public key, so the list of a deleted
+// account is still published.
+//
+// hash = hash_by_parts("certificate_revocation", account public key, serial,
+//                      public key, reason, revocation date, previous hash)
+pub struct CertificateRevocation {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RevocationReason {
+    Unspecified,
+    KeyCompromise,
+    Superseded,
+    CessationOfOperation,
+}
+
+impl RevocationReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RevocationReason::Unspecified => "unspecified",
+            RevocationReason::KeyCompromise => "key_compromise",
+            RevocationReason::Superseded => "superseded",
+            RevocationReason::CessationOfOperation => "cessation_of_operation",
+        }
+    }
+}
+
+impl FromStr for RevocationReason {
+    type Err = CommonError;
+
+    fn from_str(reason: &str) -> CommonResult<RevocationReason> {
+        match reason {
+            "unspecified" => Ok(RevocationReason::Unspecified),
+            "key_compromise" => Ok(RevocationReason::KeyCompromise),
+            "superseded" => Ok(RevocationReason::Superseded),
+            "cessation_of_operation" => Ok(RevocationReason::CessationOfOperation),
+            _ => Err(CommonError::NotFound(Some(format!(
+                "Unknown revocation reason {}.",
+                reason
+            )))),
+        }
+    }
+}
+
+pub struct UnsignedCertificateRevocation {
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+    pub serial: i32,
+    pub public_key: Vec<u8>,
+    pub reason: String,
+    pub revocation_date: NaiveDateTime,
+    pub previous_hash: Vec<u8>,
+}
+
+pub struct NewCertificateRevocation {
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+    pub serial: i32,
+    pub public_key: Vec<u8>,
+    pub reason: String,
+    pub revocation_date: NaiveDateTime,
+    pub previous_hash: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Insertable)]
+#[table_name = "certificate_revocation"]
+pub struct InsertCertificateRevocation {
+    pub serial: i32,
+    pub public_key: Vec<u8>,
+    pub reason: String,
+    pub revocation_date: NaiveDateTime,
+    pub previous_hash: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+}
+
+#[derive(PartialEq, Debug, Queryable)]
+pub struct LockedCertificateRevocation {
+    pub id: i32,
+    pub serial: i32,
+    pub public_key: Vec<u8>,
+    pub reason: String,
+    pub revocation_date: NaiveDateTime,
+    pub previous_hash: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+}
+
+// The revocation list as published to application servers, binary fields
+// base64 encoded.
+#[derive(Serialize)]
+pub struct RevocationList {
+    pub account_public_key: String,
+    pub account_key_algorithm: String,
+    pub since: i32,
+    pub entries: Vec<RevocationEntry>,
+}
+
+#[derive(Serialize)]
+pub struct RevocationEntry {
+    pub serial: i32,
+    pub public_key: String,
+    pub reason: String,
+    pub revocation_date: i64,
+    pub previous_hash: String,
+    pub signature: String,
+}
+
+pub fn revocation_hash(
+    account_public_key: &[u8],
+    serial: i32,
+    public_key: &[u8],
+    reason: &str,
+    revocation_date: &NaiveDateTime,
+    previous_hash: &[u8],
+) -> [u8; 32] {
+    let serial = lpad_to_256(&serial.to_le_bytes());
+    let date = lpad_to_256(&revocation_date.and_utc().timestamp().to_le_bytes());
+
+    hash_by_parts(&[
+        b"certificate_revocation",
+        account_public_key,
+        &serial,
+        public_key,
+        reason.as_bytes(),
+        &date,
+        previous_hash,
+    ])
+}
+
+impl CertificateRevocation {
+    // Adds the public key to the account's revocation list.
+    pub fn revoke(
+        account: &UnlockedAccount,
+        public_key: &[u8],
+        reason: RevocationReason,
+        connection: &MyConnection,
+    ) -> CommonResult<LockedCertificateRevocation> {
+        connection.transaction::<_, CommonError, _>(|| {
+            let entries = CertificateRevocation::load_since(&account.public_key, 0, connection)?;
+
+            if entries.iter().any(|entry| entry.public_key == public_key) {
+                return Err(CommonError::Duplicate(Some("Certificate already revoked.".to_owned())));
+            }
+
+            let (serial, previous_hash) = match entries.last() {
+                Some(last) => (last.serial + 1, last.record_hash().to_vec()),
+                None => (1, vec![0u8; 32]),
+            };
+
+            let revocation = UnsignedCertificateRevocation {
+                account_public_key: account.public_key.clone(),
+                account_key_algorithm: account.key_algorithm,
+                serial,
+                public_key: public_key.to_vec(),
+                reason: reason.as_str().to_owned(),
+                revocation_date: Utc::now().naive_utc(),
+                previous_hash,
+            };
+
+            account.sign_record(&revocation).save(connection)
+        })
+    }
+
+    // As revoke, but a key that is already revoked is left as it is.
+    pub fn ensure_revoked(
+        account: &UnlockedAccount,
+        public_key: &[u8],
+        reason: RevocationReason,
+        connection: &MyConnection,
+    ) -> CommonResult<()> {
+        match CertificateRevocation::revoke(account, public_key, reason, connection) {
+            Ok(_) | Err(CommonError::Duplicate(_)) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Entries after the given serial, oldest first. Use 0 for the full list.
+    pub fn load_since(
+        account_public_key: &[u8],
+        since: i32,
+        connection: &MyConnection,
+    ) -> CommonResult<Vec<LockedCertificateRevocation>> {
+        Ok(certificate_revocation::table
+            .filter(certificate_revocation::account_public_key.eq(account_public_key))
+            .filter(certificate_revocation::serial.gt(since))
+            .order(certificate_revocation::serial.asc())
+            .get_results(connection)?)
+    }
+
+    // The published list. Fails if the stored list does not verify, and with
+    // NotFound for a key that is neither an account nor has a list.
+    pub fn load_list(
+        account_public_key: &[u8],
+        since: i32,
+        connection: &MyConnection,
+    ) -> CommonResult<RevocationList> {
+        let entries = CertificateRevocation::load_since(account_public_key, 0, connection)?;
+        let account_key_algorithm = match entries.first() {
+            Some(entry) => entry.account_key_algorithm,
+            None => account::table
+                .filter(account::public_key.eq(account_public_key))
+                .select(account::key_algorithm)
+                .first(connection)?,
+        };
+
+        if !CertificateRevocation::verify_chain(account_public_key, &[0u8; 32], &entries) {
+            return Err(CommonError::FailedVerification(Some(
+                "Certificate revocation list failed verification.".to_owned(),
+            )));
+        }
+
+        Ok(RevocationList {
+            account_public_key: encode(account_public_key),
+            account_key_algorithm: account_key_algorithm.name().to_owned(),
+            since,
+            entries: entries
+                .iter()
+                .filter(|entry| entry.serial > since)
+                .map(RevocationEntry::from)
+                .collect(),
+        })
+    }
+
+    // Checks that the entries are consecutive, chained to each other starting
+    // from previous_hash, and signed by the account. previous_hash is the hash
+    // of the last entry already verified, or 32 zero bytes for a full list.
+    pub fn verify_chain(
+        account_public_key: &[u8],
+        previous_hash: &[u8],
+        entries: &[LockedCertificateRevocation],
+    ) -> bool {
+        let mut previous_hash = previous_hash.to_vec();
+        let first_serial = match entries.first() {
+            Some(entry) => entry.serial,
+            None => return true,
+        };
+
+        for (serial, entry) in (first_serial..).zip(entries) {
+            if entry.serial != serial
+                || entry.previous_hash != previous_hash
+                || entry.account_public_key != account_public_key
+                || !entry.verify()
+            {
+                return false;
+            }
+
+            previous_hash = entry.record_hash().to_vec();
+        }
+
+        true
+    }
+}
+
+impl From<&LockedCertificateRevocation> for RevocationEntry {
+    fn from(item: &LockedCertificateRevocation) -> RevocationEntry {
+        RevocationEntry {
+            serial: item.serial,
+            public_key: encode(&item.public_key),
+            reason: item.reason.clone(),
+            revocation_date: item.revocation_date.and_utc().timestamp(),
+            previous_hash: encode(&item.previous_hash),
+            signature: encode(&item.signature),
+        }
+    }
+}
+
+impl Signable<NewCertificateRevocation> for UnsignedCertificateRevocation {
+    fn record_hash(&self) -> [u8; 32] {
+        revocation_hash(
+            &self.account_public_key,
+            self.serial,
+            &self.public_key,
+            &self.reason,
+            &self.revocation_date,
+            &self.previous_hash,
+        )
+    }
+
+    fn sign(&self, signature: Vec<u8>) -> NewCertificateRevocation {
+        NewCertificateRevocation {
+            account_public_key: self.account_public_key.clone(),
+            account_key_algorithm: self.account_key_algorithm,
+            serial: self.serial,
+            public_key: self.public_key.clone(),
+            reason: self.reason.clone(),
+            revocation_date: self.revocation_date,
+            previous_hash: self.previous_hash.clone(),
+            signature,
+        }
+    }
+}
+
+impl Signed for NewCertificateRevocation {
+    fn record_hash(&self) -> [u8; 32] {
+        revocation_hash(
+            &self.account_public_key,
+            self.serial,
+            &self.public_key,
+            &self.reason,
+            &self.revocation_date,
+            &self.previous_hash,
+        )
+    }
+
+    fn signature(&self) -> Vec<u8> {
+        self.signature.clone()
+    }
+}
+
+impl From<NewCertificateRevocation> for InsertCertificateRevocation {
+    fn from(item: NewCertificateRevocation) -> InsertCertificateRevocation {
+        InsertCertificateRevocation {
+            serial: item.serial,
+            public_key: item.public_key,
+            reason: item.reason,
+            revocation_date: item.revocation_date,
+            previous_hash: item.previous_hash,
+            signature: item.signature,
+            account_public_key: item.account_public_key,
+            account_key_algorithm: item.account_key_algorithm,
+        }
+    }
+}
+
+impl NewCertificateRevocation {
+    pub fn save(self, connection: &MyConnection) -> CommonResult<LockedCertificateRevocation> {
+        let account_public_key = self.account_public_key.clone();
+        let serial = self.serial;
+
+        diesel::insert_into(certificate_revocation::table)
+            .values(InsertCertificateRevocation::from(self))
+            .execute(connection)?;
+
+        CertificateRevocation::load_since(&account_public_key, serial - 1, connection)?
+            .into_iter()
+            .next()
+            .ok_or(CommonError::RecordNotSaved(None))
+    }
+}
+
+impl Signed for LockedCertificateRevocation {
+    fn record_hash(&self) -> [u8; 32] {
+        revocation_hash(
+            &self.account_public_key,
+            self.serial,
+            &self.public_key,
+            &self.reason,
+            &self.revocation_date,
+            &self.previous_hash,
+        )
+    }
+
+    fn signature(&self) -> Vec<u8> {
+        self.signature.clone()
+    }
+}
+
+impl LockedCertificateRevocation {
+    pub fn verify(&self) -> bool {
+        verify_signature(self.account_key_algorithm, &self.account_public_key, &self.record_hash(), &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::signing_key::SigningKey;
+
+    fn entry(account: &SigningKey, serial: i32, previous_hash: &[u8]) -> LockedCertificateRevocation {
+        let public_key = SigningKey::new().public_key().to_vec();
+        let revocation_date = Utc::now().naive_utc();
+        let hash = revocation_hash(&account.public_key(), serial, &public_key, "key_compromise", &revocation_date, previous_hash);
+
+        LockedCertificateRevocation {
+            id: serial,
+            serial,
+            public_key,
+            reason: "key_compromise".to_owned(),
+            revocation_date,
+            previous_hash: previous_hash.to_vec(),
+            signature: account.sign(&hash),
+            account_public_key: account.public_key().to_vec(),
+            account_key_algorithm: account.algorithm(),
+        }
+    }
+
+    #[test]
+    fn verify_revocation_chain() {
+        let account = SigningKey::new();
+        let first = entry(&account, 1, &[0u8; 32]);
+        let second = entry(&account, 2, &first.record_hash());
+        let third = entry(&account, 3, &second.record_hash());
+        let head = second.record_hash();
+        let mut entries = vec![first, second, third];
+
+        assert!(CertificateRevocation::verify_chain(&account.public_key(), &[0u8; 32], &entries));
+        assert!(CertificateRevocation::verify_chain(&account.public_key(), &head, &entries[2..]));
+        assert!(!CertificateRevocation::verify_chain(&account.public_key(), &[0u8; 32], &entries[1..]));
+        assert!(!CertificateRevocation::verify_chain(&SigningKey::new().public_key(), &[0u8; 32], &entries));
+
+        entries[1].reason = "superseded".to_owned();
+        assert!(!CertificateRevocation::verify_chain(&account.public_key(), &[0u8; 32], &entries));
+    }
+
+    #[test]
+    fn parse_revocation_reason() {
+        for reason in &[
+            RevocationReason::Unspecified,
+            RevocationReason::KeyCompromise,
+            RevocationReason::Superseded,
+            RevocationReason::CessationOfOperation,
+        ] {
+            assert_eq!(reason.as_str().parse::<RevocationReason>().unwrap(), *reason);
+        }
+
+        assert!("forgot".parse::<RevocationReason>().is_err());
+    }
+}
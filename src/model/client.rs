@@ -3,20 +3,30 @@ use crate::database::schema::application;
 use crate::database::MyConnection;
 use diesel::prelude::*;
 use crate::encryption::byte_encryption::decrypt_32;
-use crate::encryption::exchange_key::ExchangeKey;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::exchange_key::{ExchangeKey, HYBRID_KEM_OFFSET};
 use crate::encryption::hash_by_parts;
-use crate::error::CommonResult;
+use crate::encryption::secret::SecretKey;
+use crate::error::{CommonError, CommonResult};
 use crate::model::account::UnlockedAccount;
+use crate::model::account_import::BundleKey;
 use crate::model::application::Application;
+use crate::model::authorization_code::AuthorizationCode;
+use crate::model::oauth_token::OAuthToken;
 use crate::model::{Signable, Signed};
 use crate::model::read_authorization::ReadAuthorization;
 use crate::model::write_authorization::WriteAuthorization;
+use base64::{decode, encode};
 use std::convert::From;
 
+// The client id is the client's X25519 public key. kem_public_key is the
+// ML-KEM-768 half of the client's hybrid key, clients without one can't be
+// authorized for hybrid read grant keys.
 pub struct UnsignedClient {
     pub client_id: Vec<u8>,
     pub application_id: i32,
     pub application_code: String,
+    pub kem_public_key: Option<Vec<u8>>,
 }
 
 pub struct NewClient {
@@ -24,6 +34,7 @@ pub struct NewClient {
     pub application_id: i32,
     pub application_code: String,
     pub signature: Vec<u8>,
+    pub kem_public_key: Option<Vec<u8>>,
 }
 
 #[derive(Insertable)]
@@ -32,6 +43,7 @@ pub struct InsertClient {
     pub client_id: Vec<u8>,
     pub application_id: i32,
     pub signature: Vec<u8>,
+    pub kem_public_key: Option<Vec<u8>>,
 }
 
 #[derive(Queryable, Serialize)]
@@ -40,6 +52,16 @@ pub struct Client {
     pub application_id: i32,
     pub application_code: String,
     pub signature: Vec<u8>,
+    pub kem_public_key: Option<Vec<u8>>,
+}
+
+// A client in a portable account. Clients hold their own secret so only the
+// public keys travel.
+#[derive(Serialize, Deserialize)]
+pub struct PortableClient {
+    pub client_id: String,
+    pub kem_public_key: Option<String>,
+    pub signature: String,
 }
 
 pub struct UnlockedClient {
@@ -47,22 +69,32 @@ pub struct UnlockedClient {
     pub application_id: i32,
     pub application_code: String,
     pub signature: Vec<u8>,
+    pub kem_public_key: Option<Vec<u8>>,
     exchange_key: ExchangeKey,
 }
 
+// Clients created before hybrid keys hash the same as before.
+fn client_hash(application_code: &str, client_id: &[u8], kem_public_key: &Option<Vec<u8>>) -> [u8; 32] {
+    match kem_public_key {
+        Some(kem_public_key) => hash_by_parts(&[application_code.as_bytes(), client_id, kem_public_key]),
+        None => hash_by_parts(&[application_code.as_bytes(), client_id]),
+    }
+}
+
 impl From<NewClient> for InsertClient {
     fn from(item: NewClient) -> InsertClient {
         InsertClient {
             client_id: item.client_id,
             application_id: item.application_id,
             signature: item.signature,
+            kem_public_key: item.kem_public_key,
         }
     }
 }
 
 impl Signable<NewClient> for UnsignedClient {
     fn record_hash(&self) -> [u8; 32] {
-        hash_by_parts(&[self.application_code.as_bytes(), &self.client_id])
+        client_hash(&self.application_code, &self.client_id, &self.kem_public_key)
     }
 
     fn sign(&self, signature: Vec<u8>) -> NewClient {
@@ -71,13 +103,14 @@ impl Signable<NewClient> for UnsignedClient {
             application_id: self.application_id,
             application_code: self.application_code.clone(),
             signature,
+            kem_public_key: self.kem_public_key.clone(),
         }
     }
 }
 
 impl Signed for NewClient {
     fn record_hash(&self) -> [u8; 32] {
-        hash_by_parts(&[self.application_code.as_bytes(), &self.client_id])
+        client_hash(&self.application_code, &self.client_id, &self.kem_public_key)
     }
 
     fn signature(&self) -> Vec<u8> {
@@ -87,7 +120,7 @@ impl Signed for NewClient {
 
 impl Signed for Client {
     fn record_hash(&self) -> [u8; 32] {
-        hash_by_parts(&[self.application_code.as_bytes(), &self.client_id])
+        client_hash(&self.application_code, &self.client_id, &self.kem_public_key)
     }
 
     fn signature(&self) -> Vec<u8> {
@@ -97,7 +130,7 @@ impl Signed for Client {
 
 impl Signed for UnlockedClient {
     fn record_hash(&self) -> [u8; 32] {
-        hash_by_parts(&[self.application_code.as_bytes(), &self.client_id])
+        client_hash(&self.application_code, &self.client_id, &self.kem_public_key)
     }
 
     fn signature(&self) -> Vec<u8> {
@@ -106,13 +139,30 @@ impl Signed for UnlockedClient {
 }
 
 impl Client {
-    pub fn new(account: &UnlockedAccount, application: &Application) -> ([u8; 32], NewClient) {
-        let key = ExchangeKey::new();
+    // New clients get hybrid keys so they can be authorized for any read scope.
+    pub fn new(account: &UnlockedAccount, application: &Application) -> (SecretKey, NewClient) {
+        let key = ExchangeKey::generate(KeyAlgorithm::X25519MlKem768)
+            .expect("Hybrid keys can always be generated.");
+
+        Client::with_key(account, application, key)
+    }
+
+    pub fn with_key(account: &UnlockedAccount, application: &Application, key: ExchangeKey) -> (SecretKey, NewClient) {
+        let public_key = key.public_key();
+
+        let (client_id, kem_public_key) = match key.algorithm() {
+            KeyAlgorithm::X25519MlKem768 => {
+                let (client_id, kem_public_key) = public_key.split_at(HYBRID_KEM_OFFSET);
+                (client_id.to_vec(), Some(kem_public_key.to_vec()))
+            }
+            _ => (public_key, None),
+        };
 
         let client = UnsignedClient {
             application_id: application.id,
             application_code: application.code.clone(),
-            client_id: key.public_key().to_vec(),
+            client_id,
+            kem_public_key,
         };
 
         let new_client = account.sign_record(&client);
@@ -120,18 +170,93 @@ impl Client {
         (key.private_key(), new_client)
     }
 
-    pub fn to_unlocked(&self, secret_token: [u8; 32]) -> UnlockedClient {
-        let exchange_key = ExchangeKey::from_key(secret_token);
+    // The hybrid public key, the client id followed by the ML-KEM key.
+    pub fn hybrid_public_key(&self) -> CommonResult<Vec<u8>> {
+        match &self.kem_public_key {
+            Some(kem_public_key) => Ok([self.client_id.as_slice(), kem_public_key].concat()),
+            None => Err(CommonError::Misconfiguration(Some(
+                "Client has no ML-KEM key for hybrid read grant keys.".to_owned(),
+            ))),
+        }
+    }
+
+    pub fn to_unlocked(&self, secret_token: &[u8; 32]) -> UnlockedClient {
+        let exchange_key = match self.kem_public_key {
+            Some(_) => ExchangeKey::from_private_key(KeyAlgorithm::X25519MlKem768, secret_token)
+                .expect("Any 32 bytes are a valid hybrid key."),
+            None => ExchangeKey::from_key(secret_token),
+        };
 
         UnlockedClient {
             application_id: self.application_id,
             application_code: self.application_code.clone(),
             client_id: self.client_id.clone(),
             signature: self.signature.clone(),
+            kem_public_key: self.kem_public_key.clone(),
             exchange_key,
         }
     }
 
+    // The unlocked client, if the secret is the private half of its id.
+    pub fn authenticate(&self, secret_token: &[u8; 32]) -> CommonResult<UnlockedClient> {
+        let unlocked = self.to_unlocked(secret_token);
+        let public_key = match self.kem_public_key {
+            Some(_) => self.hybrid_public_key()?,
+            None => self.client_id.clone(),
+        };
+
+        if unlocked.exchange_key.public_key() != public_key {
+            return Err(CommonError::CouldNotAuthenticate(Some("Client secret not recognized.".to_owned())));
+        }
+
+        Ok(unlocked)
+    }
+
+    pub fn to_portable(&self, account: &UnlockedAccount) -> CommonResult<PortableClient> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        Ok(PortableClient {
+            client_id: encode(&self.client_id),
+            kem_public_key: self.kem_public_key.as_ref().map(encode),
+            signature: encode(&self.signature),
+        })
+    }
+
+    // The client signed for the application it is imported into, after
+    // checking it against the application it was exported from.
+    pub fn from_portable(
+        account: &UnlockedAccount,
+        application: &Application,
+        bundle_application: &str,
+        import: &PortableClient,
+        bundle: &BundleKey,
+    ) -> CommonResult<NewClient> {
+        let client_id = decode(&import.client_id)?;
+        let kem_public_key = match &import.kem_public_key {
+            Some(kem_public_key) => Some(decode(kem_public_key)?),
+            None => None,
+        };
+
+        if client_id.len() != 32 {
+            return Err(CommonError::FailedVerification(Some("Invalid client id.".to_owned())));
+        }
+
+        bundle.verify(
+            &format!("Client {}", import.client_id),
+            &client_hash(bundle_application, &client_id, &kem_public_key),
+            &import.signature,
+        )?;
+
+        Ok(account.sign_record(&UnsignedClient {
+            client_id,
+            application_id: application.id,
+            application_code: application.code.clone(),
+            kem_public_key,
+        }))
+    }
+
     pub fn load_id(
         id: Vec<u8>,
         connection: &MyConnection,
@@ -143,7 +268,8 @@ impl Client {
                     client::client_id,
                     client::application_id,
                     application::code,
-                    client::signature
+                    client::signature,
+                    client::kem_public_key,
                     ))
             .get_result(connection)?)
     }
@@ -159,7 +285,8 @@ impl Client {
                     client::client_id,
                     client::application_id,
                     application::code,
-                    client::signature
+                    client::signature,
+                    client::kem_public_key,
                     ))
             .get_results(connection)?)
     }
@@ -173,13 +300,17 @@ impl Client {
                     client::client_id,
                     client::application_id,
                     application::code,
-                    client::signature
+                    client::signature,
+                    client::kem_public_key,
                     ))
             .get_results(connection)?)
     }
 
     pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
-        // First delete all read authorizations pointing to this client.
+        AuthorizationCode::delete_for_client(&self.client_id, connection)?;
+        OAuthToken::delete_for_client(&self.client_id, connection)?;
+
+        // Delete all read authorizations pointing to this client.
         let read_auths = ReadAuthorization::load_all_for_client(&self, connection)?;
 
         for read_auth in read_auths {
@@ -217,9 +348,64 @@ impl UnlockedClient {
     pub fn unlock_key(
         &self,
         public_key: &[u8; 32],
-        encrypted_key: &[u8; 64],
-    ) -> CommonResult<[u8; 32]> {
-        let key = self.exchange_key.key_gen(*public_key);
-        Ok(decrypt_32(encrypted_key, &key)?)
+        encrypted_key: &[u8],
+        associated_data: &[u8],
+    ) -> CommonResult<SecretKey> {
+        let key = self.exchange_key.key_gen(public_key)?;
+        decrypt_32(encrypted_key, &key, associated_data)
+    }
+
+    // The grant access key held by a read authorization, hybrid if the
+    // authorization has an encapsulated key.
+    pub fn unlock_read_key(&self, authorization: &ReadAuthorization) -> CommonResult<SecretKey> {
+        let key = match &authorization.encapsulated_key {
+            Some(encapsulated_key) => self.exchange_key.decapsulate(&authorization.public_key, encapsulated_key)?,
+            None => self.exchange_key.key_gen(&authorization.public_key)?,
+        };
+
+        let context = ReadAuthorization::access_key_context(&authorization.client_id, authorization.read_grant_key_id);
+
+        decrypt_32(&authorization.encrypted_access_key, &key, &context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::random_int_256;
+    use crate::model::read_authorization::UnsignedReadAuthorization;
+
+    #[test]
+    fn unlock_read_key() {
+        for algorithm in &[KeyAlgorithm::X25519, KeyAlgorithm::X25519MlKem768] {
+            let key = ExchangeKey::generate(*algorithm).unwrap();
+            let public_key = key.public_key();
+            let hybrid = *algorithm == KeyAlgorithm::X25519MlKem768;
+
+            let client = Client {
+                client_id: public_key[..HYBRID_KEM_OFFSET].to_vec(),
+                application_id: 1,
+                application_code: "spout".to_owned(),
+                signature: Vec::new(),
+                kem_public_key: if hybrid { Some(public_key[HYBRID_KEM_OFFSET..].to_vec()) } else { None },
+            };
+
+            let access_key = random_int_256();
+            let authorization = UnsignedReadAuthorization::new(&client, 1, &access_key, hybrid)
+                .unwrap()
+                .sign(Vec::new());
+
+            assert_eq!(authorization.encapsulated_key.is_some(), hybrid);
+
+            let unlocked = client.to_unlocked(&key.private_key());
+            assert_eq!(*unlocked.unlock_read_key(&authorization).unwrap(), access_key);
+
+            // Without the ML-KEM secret the X25519 key alone can't unlock it.
+            if hybrid {
+                let plain = Client { kem_public_key: None, ..client };
+                assert!(plain.to_unlocked(&key.private_key()).unlock_read_key(&authorization).is_err());
+                assert!(UnsignedReadAuthorization::new(&plain, 1, &access_key, true).is_err());
+            }
+        }
     }
 }
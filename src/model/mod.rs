@@ -1,11 +1,22 @@
 pub mod account;
+pub mod account_import;
+pub mod account_key_slot;
+pub mod account_migration;
 pub mod application;
+pub mod authorization_code;
+pub mod backup;
 pub mod client;
+pub mod oauth_token;
+pub mod openid;
 pub mod write_scope;
+pub mod write_scope_succession;
 pub mod read_scope;
 pub mod write_authorization;
 pub mod read_authorization;
 pub mod certificate;
+pub mod certificate_revocation;
+pub mod recovery_kit;
+pub mod server_identity;
 use crate::model::certificate::CertData;
 use crate::model::certificate::Certificate;
 use crate::encryption::hash_by_parts;
@@ -42,7 +53,22 @@ impl <T: Certified> Signed for T{
     }
 }
 
-#[derive(Clone)]
+// Associated data for an encrypted key. Binds the ciphertext to the type and
+// identity of the record that stores it so it cannot be moved to another row.
+pub fn record_context(record_type: &str, record_id: &[&[u8]]) -> [u8; 32] {
+    let mut parts: Vec<&[u8]> = vec![record_type.as_bytes()];
+    parts.extend_from_slice(record_id);
+
+    hash_by_parts(&parts)
+}
+
+// What an account signs for an authorization in a portable account, naming
+// the scope, the scope key and the client it is for.
+pub fn portable_authorization_hash(scope: &Scope, public_key: &[u8], client_id: &[u8]) -> [u8; 32] {
+    hash_by_parts(&[b"portable_authorization", &scope.hash(), public_key, client_id])
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Scope {
     Read {
         application: String, 
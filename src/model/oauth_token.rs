@@ -0,0 +1,418 @@
+use base64::{encode_config, URL_SAFE_NO_PAD};
+use chrono::{Duration, NaiveDateTime, Utc};
+use crate::database::schema::{
+    oauth_token, read_authorization, read_grant_key, read_grant_scope, write_authorization, write_grant_scope,
+};
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::{hash_by_parts, random_int_256};
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::UnlockedAccount;
+use crate::model::application::Application;
+use crate::model::client::Client;
+use crate::model::read_scope::ReadScope;
+use crate::model::write_scope::WriteScope;
+use std::fmt;
+use std::str::FromStr;
+
+// Access and refresh tokens issued to a client by the token endpoint. A token
+// is bound to the client and to the scopes it held authorizations for when
+// the token was issued, a scope whose authorization has since been removed no
+// longer counts. Only hashes of the tokens are stored.
+pub struct OAuthToken {}
+
+pub const ACCESS_TOKEN_SECONDS: i64 = 3600;
+pub const REFRESH_TOKEN_DAYS: i64 = 30;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OAuthScope {
+    Read(String),
+    Write(String),
+}
+
+impl OAuthScope {
+    // A space separated scope parameter, such as "write:post read:view".
+    pub fn parse_list(scope: &str) -> CommonResult<Vec<OAuthScope>> {
+        scope.split_whitespace().map(|s| s.parse()).collect()
+    }
+
+    pub fn join(scopes: &[OAuthScope]) -> String {
+        scopes.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl FromStr for OAuthScope {
+    type Err = CommonError;
+
+    fn from_str(s: &str) -> CommonResult<OAuthScope> {
+        match s.split_once(':') {
+            Some(("read", code)) if !code.is_empty() => Ok(OAuthScope::Read(code.to_owned())),
+            Some(("write", code)) if !code.is_empty() => Ok(OAuthScope::Write(code.to_owned())),
+            _ => Err(CommonError::Misconfiguration(Some(format!("Invalid scope {}.", s)))),
+        }
+    }
+}
+
+impl fmt::Display for OAuthScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OAuthScope::Read(code) => write!(f, "read:{}", code),
+            OAuthScope::Write(code) => write!(f, "write:{}", code),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Queryable)]
+pub struct LockedOAuthToken {
+    pub id: i32,
+    pub client_id: Vec<u8>,
+    pub access_token_hash: Vec<u8>,
+    pub refresh_token_hash: Vec<u8>,
+    pub read_grant_scope_ids: Vec<i32>,
+    pub write_grant_scope_ids: Vec<i32>,
+    pub expiration_date: NaiveDateTime,
+    pub refresh_expiration_date: NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[table_name = "oauth_token"]
+struct NewOAuthToken {
+    client_id: Vec<u8>,
+    access_token_hash: Vec<u8>,
+    refresh_token_hash: Vec<u8>,
+    read_grant_scope_ids: Vec<i32>,
+    write_grant_scope_ids: Vec<i32>,
+    expiration_date: NaiveDateTime,
+    refresh_expiration_date: NaiveDateTime,
+}
+
+// The token endpoint response, RFC 6749 section 5.1.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+    pub scope: String,
+}
+
+// An access token that is current, with the client it was issued to and the
+// scopes it still covers.
+pub struct ActiveToken {
+    pub client: Client,
+    pub scopes: Vec<OAuthScope>,
+    pub expiration_date: NaiveDateTime,
+}
+
+// A scope with an authorization for the client, and the scope's id.
+pub type AuthorizedScope = (OAuthScope, i32);
+
+fn access_token_hash(token: &str) -> Vec<u8> {
+    hash_by_parts(&[b"oauth_access_token", token.as_bytes()]).to_vec()
+}
+
+fn refresh_token_hash(token: &str) -> Vec<u8> {
+    hash_by_parts(&[b"oauth_refresh_token", token.as_bytes()]).to_vec()
+}
+
+fn invalid_grant(message: &str) -> CommonError {
+    CommonError::FailedVerification(Some(message.to_owned()))
+}
+
+impl OAuthToken {
+    // Authorizes the account's client for the scopes, creating the read and
+    // write authorizations it does not have yet. Returns the scopes granted.
+    pub fn authorize(
+        account: &UnlockedAccount,
+        client: &Client,
+        scope: &str,
+        connection: &MyConnection,
+    ) -> CommonResult<Vec<OAuthScope>> {
+        let scopes = OAuthScope::parse_list(scope)?;
+
+        let application = Application::load_all_for_account(account, connection)?
+            .into_iter()
+            .find(|application| application.id == client.application_id)
+            .ok_or_else(|| CommonError::NotFound(Some("Client not found.".to_owned())))?;
+
+        if !account.verify_record(client) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        connection.transaction::<_, CommonError, _>(|| {
+            let authorized = OAuthToken::authorized_scopes(client, connection)?;
+
+            for scope in &scopes {
+                if authorized.iter().any(|(s, _)| s == scope) {
+                    continue;
+                }
+
+                match scope {
+                    OAuthScope::Read(code) => ReadScope::load_codes(vec![code.clone()], account, &application, connection)?
+                        .pop()
+                        .ok_or_else(|| CommonError::NotFound(Some(format!("Unknown scope {}.", scope))))?
+                        .to_unlocked(account, connection)?
+                        .authorize(account, client, connection)?,
+                    OAuthScope::Write(code) => WriteScope::load_unlocked(std::slice::from_ref(code), account, &application, connection)?
+                        .pop()
+                        .ok_or_else(|| CommonError::NotFound(Some(format!("Unknown scope {}.", scope))))?
+                        .authorize(account, client, connection)?,
+                }
+            }
+
+            Ok(scopes)
+        })
+    }
+
+    // Issues tokens for the requested scopes, or for every scope the client is
+    // authorized for if none are requested.
+    pub fn issue(client: &Client, scope: Option<&str>, connection: &MyConnection) -> CommonResult<TokenResponse> {
+        let authorized = OAuthToken::authorized_scopes(client, connection)?;
+
+        let granted = match scope {
+            Some(scope) => {
+                let mut granted = Vec::new();
+
+                for requested in OAuthScope::parse_list(scope)? {
+                    let found = authorized
+                        .iter()
+                        .find(|(s, _)| *s == requested)
+                        .ok_or_else(|| CommonError::NotFound(Some(format!("Client is not authorized for {}.", requested))))?;
+                    granted.push(found.clone());
+                }
+
+                granted
+            }
+            None => authorized,
+        };
+
+        if granted.is_empty() {
+            return Err(CommonError::NotFound(Some("Client is not authorized for any scope.".to_owned())));
+        }
+
+        OAuthToken::store(client, &granted, connection)
+    }
+
+    // Issues tokens for the scopes just approved for an authorization code.
+    // A sign in that asked only for openid gets tokens for no application
+    // scope.
+    pub fn issue_approved(client: &Client, scopes: &[OAuthScope], connection: &MyConnection) -> CommonResult<TokenResponse> {
+        let granted: Vec<AuthorizedScope> = OAuthToken::authorized_scopes(client, connection)?
+            .into_iter()
+            .filter(|(scope, _)| scopes.contains(scope))
+            .collect();
+
+        OAuthToken::store(client, &granted, connection)
+    }
+
+    fn store(client: &Client, granted: &[AuthorizedScope], connection: &MyConnection) -> CommonResult<TokenResponse> {
+        let (record, response) = OAuthToken::generate(client, granted);
+
+        diesel::insert_into(oauth_token::table)
+            .values(&record)
+            .execute(connection)?;
+
+        Ok(response)
+    }
+
+    // Exchanges a refresh token for a new pair, the old refresh token stops
+    // working. Scopes the client has lost its authorization for are dropped.
+    pub fn refresh(client: &Client, refresh_token: &str, connection: &MyConnection) -> CommonResult<TokenResponse> {
+        connection.transaction::<_, CommonError, _>(|| {
+            let token: LockedOAuthToken = oauth_token::table
+                .filter(oauth_token::client_id.eq(&client.client_id))
+                .filter(oauth_token::refresh_token_hash.eq(refresh_token_hash(refresh_token)))
+                .get_result(connection)
+                .optional()?
+                .ok_or_else(|| invalid_grant("Refresh token not recognized."))?;
+
+            if token.refresh_expiration_date < Utc::now().naive_utc() {
+                OAuthToken::delete(token.id, connection)?;
+                return Err(invalid_grant("Refresh token expired."));
+            }
+
+            let granted: Vec<AuthorizedScope> = OAuthToken::authorized_scopes(client, connection)?
+                .into_iter()
+                .filter(|(scope, id)| match scope {
+                    OAuthScope::Read(_) => token.read_grant_scope_ids.contains(id),
+                    OAuthScope::Write(_) => token.write_grant_scope_ids.contains(id),
+                })
+                .collect();
+
+            if granted.is_empty() {
+                OAuthToken::delete(token.id, connection)?;
+                return Err(invalid_grant("Client is no longer authorized for the token's scopes."));
+            }
+
+            let (record, response) = OAuthToken::generate(client, &granted);
+
+            diesel::update(oauth_token::table.filter(oauth_token::id.eq(token.id)))
+                .set(&record)
+                .execute(connection)?;
+
+            Ok(response)
+        })
+    }
+
+    // The client and scopes of an access token, for resource servers. A
+    // token is not active once expired, or once its client has lost the
+    // authorizations for all of its scopes. Tokens issued for openid alone
+    // cover no scope.
+    pub fn validate(access_token: &str, connection: &MyConnection) -> CommonResult<ActiveToken> {
+        let inactive = || CommonError::CouldNotAuthenticate(Some("Access token is not active.".to_owned()));
+
+        let token: LockedOAuthToken = oauth_token::table
+            .filter(oauth_token::access_token_hash.eq(access_token_hash(access_token)))
+            .get_result(connection)
+            .optional()?
+            .ok_or_else(inactive)?;
+
+        if token.expiration_date < Utc::now().naive_utc() {
+            return Err(inactive());
+        }
+
+        let client = Client::load_id(token.client_id.clone(), connection)?;
+        let scopes: Vec<OAuthScope> = OAuthToken::authorized_scopes(&client, connection)?
+            .into_iter()
+            .filter(|(scope, id)| match scope {
+                OAuthScope::Read(_) => token.read_grant_scope_ids.contains(id),
+                OAuthScope::Write(_) => token.write_grant_scope_ids.contains(id),
+            })
+            .map(|(scope, _)| scope)
+            .collect();
+
+        let issued_for_scopes = !token.read_grant_scope_ids.is_empty() || !token.write_grant_scope_ids.is_empty();
+
+        if scopes.is_empty() && issued_for_scopes {
+            return Err(inactive());
+        }
+
+        Ok(ActiveToken { client, scopes, expiration_date: token.expiration_date })
+    }
+
+    // Revokes an access or refresh token of the client along with the
+    // client's authorizations for the token's scopes. Unknown tokens are
+    // ignored, as RFC 7009 asks.
+    pub fn revoke(client: &Client, token: &str, connection: &MyConnection) -> CommonResult<()> {
+        connection.transaction::<_, CommonError, _>(|| {
+            let token: Option<LockedOAuthToken> = oauth_token::table
+                .filter(oauth_token::client_id.eq(&client.client_id))
+                .filter(
+                    oauth_token::access_token_hash
+                        .eq(access_token_hash(token))
+                        .or(oauth_token::refresh_token_hash.eq(refresh_token_hash(token))),
+                )
+                .get_result(connection)
+                .optional()?;
+
+            let token = match token {
+                Some(token) => token,
+                None => return Ok(()),
+            };
+
+            let read_keys = read_grant_key::table
+                .filter(read_grant_key::read_grant_scope_id.eq_any(&token.read_grant_scope_ids))
+                .select(read_grant_key::id);
+
+            diesel::delete(
+                read_authorization::table
+                    .filter(read_authorization::client_id.eq(&client.client_id))
+                    .filter(read_authorization::read_grant_key_id.eq_any(read_keys)),
+            )
+            .execute(connection)?;
+
+            diesel::delete(
+                write_authorization::table
+                    .filter(write_authorization::client_id.eq(&client.client_id))
+                    .filter(write_authorization::write_grant_scope_id.eq_any(&token.write_grant_scope_ids)),
+            )
+            .execute(connection)?;
+
+            OAuthToken::delete(token.id, connection)
+        })
+    }
+
+    pub fn delete_for_client(client_id: &[u8], connection: &MyConnection) -> CommonResult<()> {
+        diesel::delete(oauth_token::table.filter(oauth_token::client_id.eq(client_id)))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    fn delete(id: i32, connection: &MyConnection) -> CommonResult<()> {
+        diesel::delete(oauth_token::table.filter(oauth_token::id.eq(id)))
+            .execute(connection)?;
+        Ok(())
+    }
+
+    // The client's application scopes it holds an authorization for.
+    pub fn authorized_scopes(client: &Client, connection: &MyConnection) -> CommonResult<Vec<AuthorizedScope>> {
+        let read: Vec<(i32, String)> = read_authorization::table
+            .inner_join(read_grant_key::table.inner_join(read_grant_scope::table))
+            .filter(read_authorization::client_id.eq(&client.client_id))
+            .select((read_grant_scope::id, read_grant_scope::code))
+            .distinct()
+            .get_results(connection)?;
+
+        let write: Vec<(i32, String)> = write_authorization::table
+            .inner_join(write_grant_scope::table)
+            .filter(write_authorization::client_id.eq(&client.client_id))
+            .select((write_grant_scope::id, write_grant_scope::code))
+            .get_results(connection)?;
+
+        Ok(read
+            .into_iter()
+            .map(|(id, code)| (OAuthScope::Read(code), id))
+            .chain(write.into_iter().map(|(id, code)| (OAuthScope::Write(code), id)))
+            .collect())
+    }
+
+    fn generate(client: &Client, granted: &[AuthorizedScope]) -> (NewOAuthToken, TokenResponse) {
+        let access_token = encode_config(random_int_256(), URL_SAFE_NO_PAD);
+        let refresh_token = encode_config(random_int_256(), URL_SAFE_NO_PAD);
+        let now = Utc::now();
+        let scopes: Vec<OAuthScope> = granted.iter().map(|(scope, _)| scope.clone()).collect();
+        let ids = |read: bool| -> Vec<i32> {
+            granted
+                .iter()
+                .filter(|(scope, _)| matches!(scope, OAuthScope::Read(_)) == read)
+                .map(|(_, id)| *id)
+                .collect()
+        };
+
+        let record = NewOAuthToken {
+            client_id: client.client_id.clone(),
+            access_token_hash: access_token_hash(&access_token),
+            refresh_token_hash: refresh_token_hash(&refresh_token),
+            read_grant_scope_ids: ids(true),
+            write_grant_scope_ids: ids(false),
+            expiration_date: (now + Duration::seconds(ACCESS_TOKEN_SECONDS)).naive_utc(),
+            refresh_expiration_date: (now + Duration::days(REFRESH_TOKEN_DAYS)).naive_utc(),
+        };
+
+        let response = TokenResponse {
+            access_token,
+            token_type: "bearer".to_owned(),
+            expires_in: ACCESS_TOKEN_SECONDS,
+            refresh_token,
+            scope: OAuthScope::join(&scopes),
+        };
+
+        (record, response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_scopes() {
+        let scopes = OAuthScope::parse_list(" write:post  read:view ").unwrap();
+
+        assert_eq!(scopes, vec![OAuthScope::Write("post".to_owned()), OAuthScope::Read("view".to_owned())]);
+        assert_eq!(OAuthScope::join(&scopes), "write:post read:view");
+        assert!("post".parse::<OAuthScope>().is_err());
+        assert!("admin:post".parse::<OAuthScope>().is_err());
+        assert!("read:".parse::<OAuthScope>().is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use base64::{encode_config, URL_SAFE_NO_PAD};
+use chrono::{Duration, Utc};
+use crate::database::schema::{account, write_grant_scope};
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::key_format::public_jwk;
+use crate::encryption::{fingerprint, hash_by_parts};
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::UnlockedAccount;
+use serde_json::{json, Value};
+
+// OpenID Connect id tokens. An id token is a JWT signed with EdDSA by the
+// account's own key. Its subject is derived from the account's public key
+// and its kid is the key's fingerprint, the kid of the key in the JWKS.
+pub struct IdToken {}
+
+pub const ID_TOKEN_SECONDS: i64 = 3600;
+pub const OPENID_SCOPE: &str = "openid";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+}
+
+#[cfg(test)]
+fn invalid_token(message: &str) -> CommonError {
+    CommonError::FailedVerification(Some(message.to_owned()))
+}
+
+impl IdToken {
+    // The subject for an account, it changes when the account's key does.
+    pub fn subject(public_key: &[u8]) -> String {
+        encode_config(hash_by_parts(&[b"openid_subject", public_key]), URL_SAFE_NO_PAD)
+    }
+
+    // Whether the scope parameter asks for an id token, and the application
+    // scopes it asks for besides.
+    pub fn split_scope(scope: &str) -> (bool, String) {
+        let openid = scope.split_whitespace().any(|s| s == OPENID_SCOPE);
+        let rest = scope
+            .split_whitespace()
+            .filter(|s| *s != OPENID_SCOPE)
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        (openid, rest)
+    }
+
+    pub fn sign(
+        account: &UnlockedAccount,
+        issuer: &str,
+        audience: &str,
+        nonce: Option<&str>,
+    ) -> CommonResult<String> {
+        if account.algorithm() != KeyAlgorithm::Ed25519 {
+            return Err(CommonError::Misconfiguration(Some(
+                "Only accounts with Ed25519 keys can sign id tokens.".to_owned(),
+            )));
+        }
+
+        let now = Utc::now();
+        let header = json!({
+            "alg": "EdDSA",
+            "typ": "JWT",
+            "kid": fingerprint(&account.public_key),
+        });
+        let claims = IdTokenClaims {
+            iss: issuer.to_owned(),
+            sub: IdToken::subject(&account.public_key),
+            aud: audience.to_owned(),
+            exp: (now + Duration::seconds(ID_TOKEN_SECONDS)).timestamp(),
+            iat: now.timestamp(),
+            nonce: nonce.map(|nonce| nonce.to_owned()),
+        };
+
+        let signing_input = format!(
+            "{}.{}",
+            encode_config(header.to_string(), URL_SAFE_NO_PAD),
+            encode_config(serde_json::to_string(&claims)?, URL_SAFE_NO_PAD),
+        );
+        let signature = account.sign(signing_input.as_bytes());
+
+        Ok(format!("{}.{}", signing_input, encode_config(signature, URL_SAFE_NO_PAD)))
+    }
+
+    // The claims of a token signed by the key that has not expired, as a
+    // relying party checks them.
+    #[cfg(test)]
+    pub fn verify(token: &str, public_key: &[u8]) -> CommonResult<IdTokenClaims> {
+        let parts: Vec<&str> = token.split('.').collect();
+
+        if parts.len() != 3 {
+            return Err(invalid_token("Malformed id token."));
+        }
+
+        let decode = |part: &str| base64::decode_config(part, URL_SAFE_NO_PAD);
+        let header: Value = serde_json::from_slice(&decode(parts[0])?)?;
+        let signature = decode(parts[2])?;
+        let signing_input = &token[..parts[0].len() + 1 + parts[1].len()];
+
+        if header["alg"] != "EdDSA" || header["kid"] != fingerprint(public_key).as_str() {
+            return Err(invalid_token("Id token is not signed by this key."));
+        }
+
+        if !crate::encryption::signing_key::verify_signature(KeyAlgorithm::Ed25519, public_key, signing_input.as_bytes(), &signature) {
+            return Err(invalid_token("Id token signature is not valid."));
+        }
+
+        let claims: IdTokenClaims = serde_json::from_slice(&decode(parts[1])?)?;
+
+        if claims.exp < Utc::now().timestamp() {
+            return Err(invalid_token("Id token expired."));
+        }
+
+        Ok(claims)
+    }
+
+    // The key set published at jwks_uri, every Ed25519 account and write
+    // scope key on the server.
+    pub fn key_set(connection: &MyConnection) -> CommonResult<Value> {
+        let account_keys: Vec<(Vec<u8>, KeyAlgorithm)> = account::table
+            .select((account::public_key, account::key_algorithm))
+            .order(account::id)
+            .load(connection)?;
+
+        let scope_keys: Vec<(Vec<u8>, KeyAlgorithm)> = write_grant_scope::table
+            .select((write_grant_scope::public_key, write_grant_scope::key_algorithm))
+            .order(write_grant_scope::id)
+            .load(connection)?;
+
+        let keys: Vec<Value> = account_keys
+            .into_iter()
+            .chain(scope_keys)
+            .filter(|(_, algorithm)| *algorithm == KeyAlgorithm::Ed25519)
+            .map(|(public_key, _)| {
+                let mut jwk = public_jwk(&public_key);
+                jwk["use"] = json!("sig");
+                jwk["alg"] = json!("EdDSA");
+                jwk
+            })
+            .collect();
+
+        Ok(json!({ "keys": keys }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::establish_connection;
+    use crate::model::account::Account;
+
+    #[test]
+    fn sign_and_verify() {
+        let connection = establish_connection().unwrap();
+        let account = Account::new("IdToken01", "idtoken01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+
+        let token = IdToken::sign(&account, "https://vault.example.com", "client", Some("n-0S6_WzA2Mj")).unwrap();
+        let claims = IdToken::verify(&token, &account.public_key).unwrap();
+
+        assert_eq!(claims.iss, "https://vault.example.com");
+        assert_eq!(claims.sub, IdToken::subject(&account.public_key));
+        assert_eq!(claims.aud, "client");
+        assert_eq!(claims.nonce.as_deref(), Some("n-0S6_WzA2Mj"));
+
+        let mut tampered = token.clone();
+        tampered.insert(token.find('.').unwrap() + 2, 'x');
+        assert!(IdToken::verify(&tampered, &account.public_key).is_err());
+
+        let keys = IdToken::key_set(&connection).unwrap();
+        assert!(keys["keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|key| key["kid"] == fingerprint(&account.public_key).as_str()));
+
+        assert_eq!(IdToken::split_scope("openid write:post"), (true, "write:post".to_owned()));
+        assert_eq!(IdToken::split_scope("read:view"), (false, "read:view".to_owned()));
+
+        account.delete(&connection).unwrap();
+    }
+}
@@ -1,19 +1,25 @@
+use base64::{decode, encode};
 use chrono::NaiveDateTime;
 use chrono::{Duration, Utc};
 use crate::database::schema::read_authorization;
 use crate::database::schema::read_grant_key;
 use crate::database::MyConnection;
 use diesel::prelude::*;
-use crate::encryption::byte_encryption::encrypt_32;
+use crate::encryption::secret::SecretKey;
+use crate::encryption::byte_encryption::{decrypt_32, encrypt_32, is_legacy};
 use crate::encryption::exchange_key::{EphemeralKey, ExchangeKey};
-use crate::encryption::{hash_by_parts, as_256, as_512, random_int_256};
-use crate::error::CommonResult;
+use crate::encryption::key_derivation::{KeyPurpose, KEY_VERSION};
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::{hash_by_parts, as_256, random_int_256};
+use crate::error::{CommonError, CommonResult};
 use crate::model::account::UnlockedAccount;
-use crate::model::{Signed, Signable};
+use crate::model::account_import::BundleKey;
+use crate::model::{portable_authorization_hash, record_context, Signed, Signable};
 use crate::model::{Certified, Certifiable};
 use crate::model::client::Client;
 use crate::model::read_scope::{ReadScope, UnlockedReadScope};
-use crate::model::certificate::{Certificate, CertData};
+use crate::model::certificate::{legacy_certificate_version, Certificate, CertData, CERTIFICATE_VERSION};
+use crate::model::certificate_revocation::{CertificateRevocation, RevocationReason};
 use crate::model::Scope;
 
 #[derive(PartialEq, Debug, Queryable, Identifiable)]
@@ -26,6 +32,36 @@ pub struct ReadGrantKey {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
+    pub key_version: i16,
+    pub decrypt_only: bool,
+    pub key_algorithm: KeyAlgorithm,
+    pub certificate_version: i16,
+}
+
+// A grant key in a portable account, its private key wrapped under the
+// bundle key.
+#[derive(Serialize, Deserialize)]
+pub struct PortableReadGrantKey {
+    pub public_key: String,
+    pub key_algorithm: String,
+    pub encrypted_private_key: String,
+    pub expiration_date: NaiveDateTime,
+    pub decrypt_only: bool,
+    #[serde(default = "legacy_certificate_version")]
+    pub certificate_version: u8,
+    pub signature: String,
+    // The account's signature over portable_grant_key_hash, the certificate
+    // doesn't cover decrypt_only.
+    pub decrypt_only_signature: String,
+    pub authorizations: Vec<PortableReadAuthorization>,
+}
+
+// See PortableWriteAuthorization, hybrid keys reissue to the client's hybrid
+// key. The signature covers the grant key's public key.
+#[derive(Serialize, Deserialize)]
+pub struct PortableReadAuthorization {
+    pub client_id: String,
+    pub signature: String,
 }
 
 pub struct UncertifiedReadGrantKey {
@@ -33,10 +69,13 @@ pub struct UncertifiedReadGrantKey {
     pub public_key: Vec<u8>,
     pub encrypted_private_key: Vec<u8>,
     pub private_key_salt: Vec<u8>,
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
     pub expiration_date: NaiveDateTime,
     pub application_code: String,
     pub read_grant_code: String,
     pub signing_key: Vec<u8>,
+    pub signing_algorithm: KeyAlgorithm,
 }
 
 #[derive(Insertable)]
@@ -48,6 +87,9 @@ pub struct InsertReadGrantKey {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
+    pub certificate_version: i16,
 }
 
 pub struct NewReadGrantKey {
@@ -57,9 +99,12 @@ pub struct NewReadGrantKey {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
     pub application_code: String,
     pub read_grant_code: String,
     pub signing_key: Vec<u8>,
+    pub signing_algorithm: KeyAlgorithm,
 }
 
 pub struct UnlockedReadGrantKey {
@@ -70,15 +115,21 @@ pub struct UnlockedReadGrantKey {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
+    pub key_version: i16,
+    pub decrypt_only: bool,
+    pub key_algorithm: KeyAlgorithm,
     pub exchange_key: ExchangeKey,
 }
 
 impl Certifiable<NewReadGrantKey> for UncertifiedReadGrantKey {
     fn data(&self) -> CertData {
         CertData{
-            signing_key: *as_256(&self.signing_key),
-            public_key: *as_256(&self.public_key),
-            scope: Scope::Write{
+            version: CERTIFICATE_VERSION,
+            signing_algorithm: self.signing_algorithm,
+            signing_key: self.signing_key.clone(),
+            key_algorithm: self.key_algorithm,
+            public_key: self.public_key.clone(),
+            scope: Scope::Read{
                 application: self.application_code.clone(),
                 grant: self.read_grant_code.clone(),
             },
@@ -94,9 +145,12 @@ impl Certifiable<NewReadGrantKey> for UncertifiedReadGrantKey {
             private_key_salt: self.private_key_salt.clone(),
             expiration_date: self.expiration_date,
             signature,
+            key_version: self.key_version,
+            key_algorithm: self.key_algorithm,
             application_code: self.application_code.clone(),
             read_grant_code: self.read_grant_code.clone(),
             signing_key: authorizing_key,
+            signing_algorithm: self.signing_algorithm,
         }
     }
 }
@@ -105,15 +159,18 @@ impl Certified for NewReadGrantKey {
     fn certificate(&self) -> Certificate {
         Certificate {
             data: CertData {
-                signing_key:     *as_256(&self.signing_key),
-                public_key:      *as_256(&self.public_key),
+                version:         CERTIFICATE_VERSION,
+                signing_algorithm: self.signing_algorithm,
+                signing_key:     self.signing_key.clone(),
+                key_algorithm:   self.key_algorithm,
+                public_key:      self.public_key.clone(),
                 scope:           Scope::Read{
                     application: self.application_code.clone(),
                     grant: self.read_grant_code.clone(),
                 },
                 expiration_date: self.expiration_date,
             },
-            signature: *as_512(&self.signature),
+            signature: self.signature.clone(),
         }
     }
 }
@@ -127,23 +184,27 @@ impl NewReadGrantKey {
             private_key_salt:      self.private_key_salt.clone(),
             expiration_date:       self.expiration_date,
             signature:             self.signature.clone(),
+            key_version:           self.key_version,
+            key_algorithm:         self.key_algorithm,
+            certificate_version:   CERTIFICATE_VERSION as i16,
         }
     }
 
-    pub fn save(&self, connection: &MyConnection) -> CommonResult<()> {
-        diesel::insert_into(read_grant_key::table)
+    pub fn save(&self, connection: &MyConnection) -> CommonResult<ReadGrantKey> {
+        Ok(diesel::insert_into(read_grant_key::table)
             .values(self.to_insertable())
-            .execute(connection)?;
-
-        Ok(())
+            .get_result(connection)?)
     }
 }
 
+// Authorizations for hybrid grant keys also hold the ML-KEM ciphertext
+// encapsulated to the client's hybrid key.
 pub struct UnsignedReadAuthorization {
     pub client_id: Vec<u8>,
     pub read_grant_key_id: i32,
     pub encrypted_access_key: Vec<u8>,
     pub public_key: Vec<u8>,
+    pub encapsulated_key: Option<Vec<u8>>,
 }
 
 #[derive(PartialEq, Debug, Queryable, Insertable)]
@@ -154,16 +215,66 @@ pub struct ReadAuthorization {
     pub encrypted_access_key: Vec<u8>,
     pub public_key: Vec<u8>,
     pub signature: Vec<u8>,
+    pub encapsulated_key: Option<Vec<u8>>,
+}
+
+impl UnsignedReadAuthorization {
+    // Encrypt the grant access key to the client's public key, or to its
+    // hybrid key for hybrid grant keys.
+    pub fn new(
+        client: &Client,
+        read_grant_key_id: i32,
+        access_key: &[u8; 32],
+        hybrid: bool,
+    ) -> CommonResult<UnsignedReadAuthorization> {
+        let ephemeral = EphemeralKey::new();
+        let public_key = ephemeral.public_key().to_vec();
+
+        let (encryption_key, encapsulated_key) = if hybrid {
+            let (encryption_key, encapsulated_key) = ephemeral.encapsulate(&client.hybrid_public_key()?)?;
+            (encryption_key, Some(encapsulated_key))
+        } else {
+            (ephemeral.key_gen(*as_256(&client.client_id)), None)
+        };
+
+        let context = ReadAuthorization::access_key_context(&client.client_id, read_grant_key_id);
+
+        Ok(UnsignedReadAuthorization {
+            client_id: client.client_id.clone(),
+            read_grant_key_id,
+            encrypted_access_key: encrypt_32(access_key, &encryption_key, &context),
+            public_key,
+            encapsulated_key,
+        })
+    }
+}
+
+fn authorization_hash(
+    client_id: &[u8],
+    read_grant_key_id: i32,
+    encrypted_access_key: &[u8],
+    public_key: &[u8],
+    encapsulated_key: &Option<Vec<u8>>,
+) -> [u8; 32] {
+    let read_grant_key_id = read_grant_key_id.to_le_bytes();
+    let mut parts: Vec<&[u8]> = vec![client_id, &read_grant_key_id, encrypted_access_key, public_key];
+
+    if let Some(encapsulated_key) = encapsulated_key {
+        parts.push(encapsulated_key);
+    }
+
+    hash_by_parts(&parts)
 }
 
 impl Signable<ReadAuthorization> for UnsignedReadAuthorization {
     fn record_hash(&self) -> [u8; 32] {
-        hash_by_parts(&[
+        authorization_hash(
             &self.client_id,
-            &self.read_grant_key_id.to_le_bytes(),
+            self.read_grant_key_id,
             &self.encrypted_access_key,
             &self.public_key,
-        ])
+            &self.encapsulated_key,
+        )
     }
 
     fn sign(&self, signature: Vec<u8>) -> ReadAuthorization {
@@ -173,18 +284,20 @@ impl Signable<ReadAuthorization> for UnsignedReadAuthorization {
             encrypted_access_key: self.encrypted_access_key.clone(),
             public_key: self.public_key.clone(),
             signature,
+            encapsulated_key: self.encapsulated_key.clone(),
         }
     }
 }
 
 impl Signed for ReadAuthorization {
     fn record_hash(&self) -> [u8; 32] {
-        hash_by_parts(&[
+        authorization_hash(
             &self.client_id,
-            &self.read_grant_key_id.to_le_bytes(),
+            self.read_grant_key_id,
             &self.encrypted_access_key,
             &self.public_key,
-        ])
+            &self.encapsulated_key,
+        )
     }
 
     fn signature(&self) -> Vec<u8> {
@@ -193,6 +306,11 @@ impl Signed for ReadAuthorization {
 }
 
 impl ReadAuthorization {
+    // Associated data for the access key envelope held by an authorization.
+    pub fn access_key_context(client_id: &[u8], read_grant_key_id: i32) -> [u8; 32] {
+        record_context("read_authorization", &[client_id, &read_grant_key_id.to_le_bytes()])
+    }
+
     pub fn save(&self, connection: &MyConnection) -> CommonResult<()> {
         diesel::insert_into(read_authorization::table)
             .values(self)
@@ -200,6 +318,22 @@ impl ReadAuthorization {
         Ok(())
     }
 
+    pub fn to_portable(
+        &self,
+        account: &UnlockedAccount,
+        scope: &Scope,
+        public_key: &[u8],
+    ) -> CommonResult<PortableReadAuthorization> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        Ok(PortableReadAuthorization {
+            client_id: encode(&self.client_id),
+            signature: encode(account.sign(&portable_authorization_hash(scope, public_key, &self.client_id))),
+        })
+    }
+
     pub fn load_all_for_client(client: &Client, connection: &MyConnection) -> CommonResult<Vec<ReadAuthorization>> {
         Ok(read_authorization::table
             .filter(read_authorization::client_id.eq(&client.client_id))
@@ -213,12 +347,93 @@ impl ReadAuthorization {
             .get_result(connection)?)
     }
 
+    pub fn load_all_for_key(key: &UnlockedReadGrantKey, connection: &MyConnection) -> CommonResult<Vec<ReadAuthorization>> {
+        Ok(read_authorization::table
+            .filter(read_authorization::read_grant_key_id.eq(&key.id))
+            .get_results(connection)?)
+    }
+
     pub fn load_all_for_grant(grant: &ReadGrantKey, connection: &MyConnection) -> CommonResult<Vec<ReadAuthorization>> {
         Ok(read_authorization::table
             .filter(read_authorization::read_grant_key_id.eq(&grant.id))
             .get_results(connection)?)
     }
 
+    // See WriteAuthorization::upgrade_encryption, legacy read authorizations
+    // have the same reversed key order.
+    pub fn upgrade_encryption(
+        self,
+        access_key: &[u8; 32],
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<usize> {
+        if !is_legacy(&self.encrypted_access_key) {
+            return Ok(0);
+        }
+
+        if !account.verify_record(&self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let encryption_key = decrypt_32(&self.encrypted_access_key, access_key, &[])?;
+        let context = ReadAuthorization::access_key_context(&self.client_id, self.read_grant_key_id);
+
+        let authorization = account.sign_record(&UnsignedReadAuthorization {
+            client_id: self.client_id.clone(),
+            read_grant_key_id: self.read_grant_key_id,
+            encrypted_access_key: encrypt_32(access_key, &encryption_key, &context),
+            public_key: self.public_key.clone(),
+            encapsulated_key: self.encapsulated_key.clone(),
+        });
+
+        diesel::update(read_authorization::table
+                       .filter(read_authorization::client_id.eq(&self.client_id))
+                       .filter(read_authorization::read_grant_key_id.eq(self.read_grant_key_id))
+                       )
+            .set((
+                    read_authorization::encrypted_access_key.eq(authorization.encrypted_access_key),
+                    read_authorization::signature.eq(authorization.signature),
+                    ))
+            .execute(connection)?;
+
+        Ok(1)
+    }
+
+    // Replace the authorization with one holding a new access key for the same
+    // client, used when the grant access key changes.
+    pub fn reissue(
+        self,
+        access_key: &[u8; 32],
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<usize> {
+        if !account.verify_record(&self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let client = Client::load_id(self.client_id.clone(), connection)?;
+        let authorization = account.sign_record(&UnsignedReadAuthorization::new(
+            &client,
+            self.read_grant_key_id,
+            access_key,
+            self.encapsulated_key.is_some(),
+        )?);
+
+        diesel::update(read_authorization::table
+                       .filter(read_authorization::client_id.eq(&self.client_id))
+                       .filter(read_authorization::read_grant_key_id.eq(self.read_grant_key_id))
+                       )
+            .set((
+                    read_authorization::encrypted_access_key.eq(authorization.encrypted_access_key),
+                    read_authorization::public_key.eq(authorization.public_key),
+                    read_authorization::encapsulated_key.eq(authorization.encapsulated_key),
+                    read_authorization::signature.eq(authorization.signature),
+                    ))
+            .execute(connection)?;
+
+        Ok(1)
+    }
+
     pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
         diesel::delete(read_authorization::table
                        .filter(read_authorization::client_id.eq(self.client_id))
@@ -232,29 +447,91 @@ impl ReadAuthorization {
 
 impl ReadGrantKey {
     pub fn new(scope: &UnlockedReadScope, account: &UnlockedAccount) -> NewReadGrantKey {
-        let salt = random_int_256();
+        ReadGrantKey::with_key(scope, account, ExchangeKey::new())
+    }
 
+    pub fn with_key(scope: &UnlockedReadScope, account: &UnlockedAccount, exchange_key: ExchangeKey) -> NewReadGrantKey {
         // expire in one year as default
         let expiration_date = (Utc::now() + Duration::days(365)).naive_utc();
-        let exchange_key = ExchangeKey::new();
-        let encryption_key = account.generate_key(&salt);
-        let encrypted_private_key = exchange_key.encrypted_private_key(&encryption_key).to_vec();
-        let public_key = exchange_key.public_key().to_vec();
+
+        ReadGrantKey::with_key_expiring(scope, account, exchange_key, expiration_date)
+    }
+
+    fn with_key_expiring(
+        scope: &UnlockedReadScope,
+        account: &UnlockedAccount,
+        exchange_key: ExchangeKey,
+        expiration_date: NaiveDateTime,
+    ) -> NewReadGrantKey {
+        let salt = random_int_256();
+        let public_key = exchange_key.public_key();
+        let encryption_key = grant_access_key(account, KEY_VERSION, &public_key, &salt)
+            .expect("Current key version is supported.");
+        let encrypted_private_key = exchange_key
+            .encrypted_private_key(&encryption_key, &private_key_context(&public_key));
 
         let new_key = UncertifiedReadGrantKey {
             read_grant_scope_id: scope.id,
             public_key,
             encrypted_private_key,
             private_key_salt: salt.to_vec(),
+            key_version: KEY_VERSION,
+            key_algorithm: exchange_key.algorithm(),
             expiration_date,
             application_code: scope.application_code.clone(),
             read_grant_code: scope.code.clone(),
             signing_key: account.public_key.clone(),
+            signing_algorithm: account.key_algorithm,
         };
 
         account.certify_record(&new_key)
     }
 
+    // The bundle key certified for the scope it is imported into, after
+    // checking its certificate from the scope it was exported from.
+    pub fn from_portable(
+        scope: &UnlockedReadScope,
+        account: &UnlockedAccount,
+        bundle_application: &str,
+        bundle_scope: &str,
+        import: &PortableReadGrantKey,
+        bundle: &BundleKey,
+    ) -> CommonResult<NewReadGrantKey> {
+        let public_key = decode(&import.public_key)?;
+        let key_algorithm = import.key_algorithm.parse()?;
+        let portable_scope = Scope::Read {
+            application: bundle_application.to_owned(),
+            grant: bundle_scope.to_owned(),
+        };
+        let data = bundle.cert_data(
+            key_algorithm,
+            &public_key,
+            portable_scope.clone(),
+            import.expiration_date,
+            import.certificate_version,
+        );
+
+        bundle.verify(&format!("Read grant key of {}", bundle_scope), &data.hash(), &import.signature)?;
+        bundle.verify(
+            &format!("Decrypt only flag of read grant key of {}", bundle_scope),
+            &portable_grant_key_hash(&portable_scope, &public_key, import.decrypt_only),
+            &import.decrypt_only_signature,
+        )?;
+
+        let exchange_key = ExchangeKey::from_encrypted(
+            bundle.key(),
+            key_algorithm,
+            &decode(&import.encrypted_private_key)?,
+            &portable_key_context(&public_key),
+        )?;
+
+        if exchange_key.public_key() != public_key {
+            return Err(CommonError::FailedVerification(Some("Private key does not match public key.".to_owned())));
+        }
+
+        Ok(ReadGrantKey::with_key_expiring(scope, account, exchange_key, import.expiration_date))
+    }
+
     pub fn load_with_account(
         scope: &ReadScope,
         account: &UnlockedAccount,
@@ -280,9 +557,13 @@ impl ReadGrantKey {
     }
 
     pub fn to_unlocked(&self, account: &UnlockedAccount) -> CommonResult<UnlockedReadGrantKey> {
-        let encryption_key = account.generate_key(&self.private_key_salt);
-        let exchange_key =
-            ExchangeKey::from_encrypted(&encryption_key, as_512(&self.encrypted_private_key))?;
+        let encryption_key = grant_access_key(account, self.key_version, &self.public_key, &self.private_key_salt)?;
+        let exchange_key = ExchangeKey::from_encrypted(
+            &encryption_key,
+            self.key_algorithm,
+            &self.encrypted_private_key,
+            &private_key_context(&self.public_key),
+        )?;
 
         Ok(UnlockedReadGrantKey {
             id: self.id,
@@ -292,11 +573,112 @@ impl ReadGrantKey {
             private_key_salt: self.private_key_salt.clone(),
             expiration_date: self.expiration_date.clone(),
             signature: self.signature.clone(),
+            key_version: self.key_version,
+            decrypt_only: self.decrypt_only,
+            key_algorithm: self.key_algorithm,
             exchange_key,
         })
     }
 
-    pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
+    pub fn to_portable(
+        &self,
+        scope: &ReadScope,
+        account: &UnlockedAccount,
+        bundle_key: &[u8; 32],
+        connection: &MyConnection,
+    ) -> CommonResult<PortableReadGrantKey> {
+        let unlocked = self.to_unlocked(account)?;
+        let portable_scope = Scope::Read { application: scope.application_code.clone(), grant: scope.code.clone() };
+        let mut authorizations = Vec::new();
+
+        for authorization in ReadAuthorization::load_all_for_grant(self, connection)? {
+            authorizations.push(authorization.to_portable(account, &portable_scope, &self.public_key)?);
+        }
+
+        Ok(PortableReadGrantKey {
+            public_key: encode(&self.public_key),
+            key_algorithm: self.key_algorithm.name().to_owned(),
+            encrypted_private_key: encode(unlocked
+                .exchange_key
+                .encrypted_private_key(bundle_key, &portable_key_context(&self.public_key))),
+            expiration_date: self.expiration_date,
+            decrypt_only: self.decrypt_only,
+            certificate_version: self.certificate_version as u8,
+            signature: encode(&self.signature),
+            decrypt_only_signature: encode(account.sign(&portable_grant_key_hash(
+                &portable_scope,
+                &self.public_key,
+                self.decrypt_only,
+            ))),
+            authorizations,
+        })
+    }
+
+    // Re-encrypts the grant key and its authorizations if they are still in
+    // the legacy format, and moves the key to the current key version,
+    // reissuing every authorization. Returns the number of records rewritten.
+    pub fn upgrade_encryption(
+        &self,
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<usize> {
+        let authorizations = ReadAuthorization::load_all_for_grant(self, connection)?;
+        let mut upgraded = 0;
+
+        if self.key_version != KEY_VERSION {
+            let unlocked = self.to_unlocked(account)?;
+            let access_key = grant_access_key(account, KEY_VERSION, &self.public_key, &self.private_key_salt)?;
+            let encrypted_private_key = unlocked
+                .exchange_key
+                .encrypted_private_key(&access_key, &private_key_context(&self.public_key));
+
+            diesel::update(read_grant_key::table.filter(read_grant_key::id.eq(self.id)))
+                .set((
+                        read_grant_key::encrypted_private_key.eq(encrypted_private_key),
+                        read_grant_key::key_version.eq(KEY_VERSION),
+                        ))
+                .execute(connection)?;
+
+            upgraded += 1;
+
+            for authorization in authorizations {
+                upgraded += authorization.reissue(&access_key, account, connection)?;
+            }
+
+            return Ok(upgraded);
+        }
+
+        let access_key = grant_access_key(account, self.key_version, &self.public_key, &self.private_key_salt)?;
+
+        if is_legacy(&self.encrypted_private_key) {
+            let unlocked = self.to_unlocked(account)?;
+            let encrypted_private_key = unlocked
+                .exchange_key
+                .encrypted_private_key(&access_key, &private_key_context(&self.public_key));
+
+            diesel::update(read_grant_key::table.filter(read_grant_key::id.eq(self.id)))
+                .set(read_grant_key::encrypted_private_key.eq(encrypted_private_key))
+                .execute(connection)?;
+
+            upgraded += 1;
+        }
+
+        for authorization in authorizations {
+            upgraded += authorization.upgrade_encryption(&access_key, account, connection)?;
+        }
+
+        Ok(upgraded)
+    }
+
+    // Revokes the key certificate before deleting the key.
+    pub fn delete(self, account: &UnlockedAccount, connection: &MyConnection) -> CommonResult<()> {
+        CertificateRevocation::ensure_revoked(
+            account,
+            &self.public_key,
+            RevocationReason::CessationOfOperation,
+            connection,
+            )?;
+
         // Delete dependant authorizations
         let authorizations = ReadAuthorization::load_all_for_grant(&self, connection)?;
 
@@ -317,18 +699,9 @@ impl UnlockedReadGrantKey {
         client: &Client,
         connection: &MyConnection,
     ) -> CommonResult<()> {
-        let ephemeral = EphemeralKey::new();
-        let public_key = ephemeral.public_key().to_vec();
-        let encryption_key = ephemeral.key_gen(*as_256(&client.client_id));
-        let access_key = account.generate_key(&self.private_key_salt);
-        let encrypted_access_key = encrypt_32(&encryption_key, &access_key).to_vec();
-
-        let new_authorization = UnsignedReadAuthorization {
-            client_id: client.client_id.clone(),
-            read_grant_key_id: self.id,
-            encrypted_access_key,
-            public_key,
-        };
+        let access_key = grant_access_key(account, self.key_version, &self.public_key, &self.private_key_salt)?;
+        let hybrid = self.key_algorithm == KeyAlgorithm::X25519MlKem768;
+        let new_authorization = UnsignedReadAuthorization::new(client, self.id, &access_key, hybrid)?;
 
         account.sign_record(&new_authorization).save(connection)?;
 
@@ -338,5 +711,36 @@ impl UnlockedReadGrantKey {
     pub fn revoke(&self, client: &Client, connection: &MyConnection) -> CommonResult<()> {
         ReadAuthorization::load_by_key_client(self, client, connection)?.delete(connection)
     }
+
+    // Keep the key for decrypting historical messages only.
+    pub fn retire(&self, connection: &MyConnection) -> CommonResult<()> {
+        diesel::update(read_grant_key::table.filter(read_grant_key::id.eq(self.id)))
+            .set(read_grant_key::decrypt_only.eq(true))
+            .execute(connection)?;
+        Ok(())
+    }
+}
+
+// The key wrapping the grant exchange key, shared with authorized clients.
+fn grant_access_key(
+    account: &UnlockedAccount,
+    key_version: i16,
+    public_key: &[u8],
+    salt: &[u8],
+) -> CommonResult<SecretKey> {
+    account.derive_key(key_version, KeyPurpose::ReadGrant, "read_grant_key", &[public_key], salt)
+}
+
+fn private_key_context(public_key: &[u8]) -> [u8; 32] {
+    record_context("read_grant_key", &[public_key])
+}
+
+// What an account signs for a grant key in a portable account besides its
+// certificate, whether the key was retired to decrypt only.
+fn portable_grant_key_hash(scope: &Scope, public_key: &[u8], decrypt_only: bool) -> [u8; 32] {
+    hash_by_parts(&[b"portable_read_grant_key", &scope.hash(), public_key, &[decrypt_only as u8]])
 }
 
+fn portable_key_context(public_key: &[u8]) -> [u8; 32] {
+    record_context("portable_read_grant_key", &[public_key])
+}
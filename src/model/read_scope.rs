@@ -1,3 +1,4 @@
+use base64::encode;
 use crate::database::schema::read_grant_scope;
 use crate::database::schema::application;
 use crate::database::MyConnection;
@@ -5,10 +6,15 @@ use diesel::expression::dsl::any;
 use diesel::prelude::*;
 use crate::error::{CommonError, CommonResult};
 use crate::model::account::UnlockedAccount;
+use crate::model::account_import::BundleKey;
 use crate::model::application::Application;
 use crate::model::{Signable, Signed};
-use crate::model::read_authorization::{ReadGrantKey, UnlockedReadGrantKey};
+use crate::model::read_authorization::{
+    PortableReadGrantKey, ReadAuthorization, ReadGrantKey, UnlockedReadGrantKey,
+};
 use crate::model::client::Client;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::exchange_key::ExchangeKey;
 use crate::encryption::hash_by_parts;
 
 // Read scope logic
@@ -24,6 +30,15 @@ pub struct ReadScope {
     pub signature: Vec<u8>,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct PortableReadScope {
+    pub code: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub signature: String,
+    pub keys: Vec<PortableReadGrantKey>,
+}
+
 pub struct UnsignedReadScope {
     pub application_id: i32,
     pub application_code: String,
@@ -104,6 +119,31 @@ impl ReadScope {
         account.sign_record(&scope)
     }
 
+    // The scope under the given code, after checking it against the
+    // application it was exported from.
+    pub fn from_portable(
+        account: &UnlockedAccount,
+        application: &Application,
+        code: &str,
+        bundle_application: &str,
+        import: &PortableReadScope,
+        bundle: &BundleKey,
+    ) -> CommonResult<NewReadScope> {
+        bundle.verify(
+            &format!("Read scope {}", import.code),
+            &hash_by_parts(&[bundle_application.as_bytes(), import.code.as_bytes()]),
+            &import.signature,
+        )?;
+
+        Ok(account.sign_record(&UnsignedReadScope {
+            application_id: application.id,
+            application_code: application.code.clone(),
+            code: code.to_owned(),
+            display_name: import.display_name.clone(),
+            description: import.description.clone(),
+        }))
+    }
+
     pub fn load_codes(
         codes: Vec<String>,
         account: &UnlockedAccount,
@@ -172,13 +212,41 @@ impl ReadScope {
         })
     }
 
-    pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
+    // The scope with every grant key, current and retired.
+    pub fn to_portable(
+        &self,
+        account: &UnlockedAccount,
+        bundle_key: &[u8; 32],
+        connection: &MyConnection,
+    ) -> CommonResult<PortableReadScope> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(Some(
+                "Read scope failed verification.".to_owned(),
+            )));
+        }
+
+        let mut keys = Vec::new();
+
+        for key in ReadGrantKey::load_all_for_scope(self, connection)? {
+            keys.push(key.to_portable(self, account, bundle_key, connection)?);
+        }
+
+        Ok(PortableReadScope {
+            code: self.code.clone(),
+            display_name: self.display_name.clone(),
+            description: self.description.clone(),
+            signature: encode(&self.signature),
+            keys,
+        })
+    }
+
+    pub fn delete(self, account: &UnlockedAccount, connection: &MyConnection) -> CommonResult<()> {
         //delete dependant scope keys
         
         let keys = ReadGrantKey::load_all_for_scope(&self, connection)?;
 
         for key in keys {
-            key.delete(connection)?;
+            key.delete(account, connection)?;
         }
 
         diesel::delete(read_grant_scope::table.filter(read_grant_scope::id.eq(self.id)))
@@ -186,6 +254,26 @@ impl ReadScope {
         Ok(())
     }
 
+    // Re-encrypts every legacy grant key of this scope along with its
+    // authorizations. Returns the number of records rewritten.
+    pub fn upgrade_encryption(
+        &self,
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<usize> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let mut upgraded = 0;
+
+        for key in ReadGrantKey::load_all_for_scope(self, connection)? {
+            upgraded += key.upgrade_encryption(account, connection)?;
+        }
+
+        Ok(upgraded)
+    }
+
     pub fn load_id(
         id: i32,
         connection: &MyConnection,
@@ -233,6 +321,51 @@ impl UnlockedReadScope {
         key.save(connection)?;
         Ok(())
     }
+
+    // Replace the current keys with a new one. Every client holding a current
+    // key is authorized for the new key, and the old keys are kept to decrypt
+    // historical messages only. Returns the new key and the number of clients
+    // re-authorized.
+    pub fn rotate_key(
+        &self,
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<(ReadGrantKey, usize)> {
+        connection.transaction::<_, CommonError, _>(|| {
+            // The new key keeps the algorithm of the keys it replaces.
+            let algorithm = self
+                .read_keys
+                .iter()
+                .find(|k| !k.decrypt_only)
+                .map_or(KeyAlgorithm::X25519, |k| k.key_algorithm);
+            let new_key = ReadGrantKey::with_key(self, account, ExchangeKey::generate(algorithm)?).save(connection)?;
+            let unlocked_key = new_key.to_unlocked(account)?;
+            let mut client_ids: Vec<Vec<u8>> = Vec::new();
+
+            for key in self.read_keys.iter().filter(|k| !k.decrypt_only) {
+                for authorization in ReadAuthorization::load_all_for_key(key, connection)? {
+                    if !account.verify_record(&authorization) {
+                        return Err(CommonError::FailedVerification(Some(
+                            "Read authorization failed verification.".to_owned(),
+                        )));
+                    }
+
+                    if !client_ids.contains(&authorization.client_id) {
+                        client_ids.push(authorization.client_id);
+                    }
+                }
+
+                key.retire(connection)?;
+            }
+
+            for client_id in &client_ids {
+                let client = Client::load_id(client_id.clone(), connection)?;
+                unlocked_key.authorize(account, &client, connection)?;
+            }
+
+            Ok((new_key, client_ids.len()))
+        })
+    }
 }
 
 impl Signed for NewReadScope {
@@ -0,0 +1,104 @@
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::fingerprint;
+use crate::encryption::mnemonic::{from_mnemonic, to_mnemonic};
+use crate::encryption::secret::SecretString;
+use crate::encryption::signing_key::SigningKey;
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::NewAccount;
+
+// An offline backup of an account's identity: the signing key as a mnemonic,
+// the export key and the public key fingerprint to check a restore against.
+// Scope keys hang off the master key and are not part of the kit, a restored
+// account has the same public key but no applications.
+pub struct RecoveryKit {
+    pub account_name: String,
+    pub key_algorithm: KeyAlgorithm,
+    pub mnemonic: SecretString,
+    pub export_key: SecretString,
+    pub fingerprint: String,
+}
+
+impl RecoveryKit {
+    pub fn new(account_name: &str, signing_key: &SigningKey, export_key: SecretString) -> RecoveryKit {
+        RecoveryKit {
+            account_name: account_name.to_owned(),
+            key_algorithm: signing_key.algorithm(),
+            mnemonic: to_mnemonic(&signing_key.private_key()),
+            export_key,
+            fingerprint: fingerprint(&signing_key.public_key()),
+        }
+    }
+
+    // The kit as text to print or write down.
+    pub fn to_text(&self) -> SecretString {
+        SecretString::new(format!(
+            "Cardinal Identity Server recovery kit\n\
+             Account:     {}\n\
+             Algorithm:   {}\n\
+             Fingerprint: {}\n\
+             Export key:  {}\n\
+             Mnemonic:    {}\n",
+            self.account_name,
+            self.key_algorithm.name(),
+            self.fingerprint,
+            &*self.export_key,
+            &*self.mnemonic,
+        ))
+    }
+
+    // Rebuilds the account from its mnemonic under a new password. If a
+    // fingerprint is given the restored key must match it.
+    pub fn restore(
+        name: &str,
+        email: &str,
+        password: &str,
+        export_key: &str,
+        algorithm: KeyAlgorithm,
+        mnemonic: &str,
+        expected_fingerprint: Option<&str>,
+    ) -> CommonResult<NewAccount> {
+        let private_key = from_mnemonic(mnemonic)?;
+        let signing_key = SigningKey::from_private_key(algorithm, &private_key)?;
+
+        if let Some(expected) = expected_fingerprint {
+            if fingerprint(&signing_key.public_key()) != expected.trim().to_lowercase() {
+                return Err(CommonError::FailedVerification(Some(
+                    "Restored key does not match the fingerprint.".to_owned(),
+                )));
+            }
+        }
+
+        Ok(NewAccount::with_key(name, email, password, export_key, signing_key, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_from_kit() {
+        for algorithm in &[KeyAlgorithm::Ed25519, KeyAlgorithm::P256] {
+            let signing_key = SigningKey::generate(*algorithm).unwrap();
+            let kit = RecoveryKit::new("Kit01", &signing_key, SecretString::new("export".to_owned()));
+
+            assert!(kit.to_text().contains(&kit.fingerprint));
+
+            let restored = RecoveryKit::restore(
+                "Kit01", "kit01@example.com", "password", "export",
+                *algorithm, &kit.mnemonic, Some(&kit.fingerprint),
+            )
+            .unwrap();
+
+            assert_eq!(restored.public_key, signing_key.public_key());
+            assert_eq!(restored.key_algorithm, *algorithm);
+
+            let other = SigningKey::generate(*algorithm).unwrap();
+            assert!(RecoveryKit::restore(
+                "Kit01", "kit01@example.com", "password", "export",
+                *algorithm, &kit.mnemonic, Some(&fingerprint(&other.public_key())),
+            )
+            .is_err());
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use base64::{decode, encode};
+use dotenv::dotenv;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::exchange_key::ExchangeKey;
+use crate::encryption::hash_by_parts;
+use crate::encryption::secret::{SecretBytes, SecretKey};
+use crate::encryption::signing_key::SigningKey;
+use crate::error::{CommonError, CommonResult};
+
+// The keys identifying this server to other identity servers. Both are
+// derived from the SERVER_KEY seed, so the server keeps its identity as long
+// as the seed is kept. The exchange key is a hybrid X25519 + ML-KEM-768 key,
+// migration bundles sealed to it hold every key of an account.
+pub struct ServerIdentity {
+    pub url: String,
+    pub signing_key: SigningKey,
+    pub exchange_key: ExchangeKey,
+}
+
+// The public half, as published at /api/server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ServerPublicKey {
+    pub url: String,
+    pub signing_key: String,
+    pub key_algorithm: String,
+    pub exchange_key: String,
+    pub exchange_algorithm: String,
+}
+
+impl ServerIdentity {
+    pub fn from_seed(url: &str, seed: &[u8; 32]) -> CommonResult<ServerIdentity> {
+        let signing_seed = SecretKey::new(hash_by_parts(&[b"server_signing_key", seed]));
+        let exchange_seed = SecretKey::new(hash_by_parts(&[b"server_exchange_key", seed]));
+
+        Ok(ServerIdentity {
+            url: url.to_owned(),
+            signing_key: SigningKey::from_private_key(KeyAlgorithm::Ed25519, &signing_seed)?,
+            exchange_key: ExchangeKey::from_private_key(KeyAlgorithm::X25519MlKem768, &exchange_seed)?,
+        })
+    }
+
+    // Reads SERVER_URL and SERVER_KEY, a base64 32 byte seed written by init.
+    pub fn from_env() -> CommonResult<ServerIdentity> {
+        dotenv().ok();
+
+        let url = dotenv::var("SERVER_URL").map_err(|_| not_configured("SERVER_URL"))?;
+        let seed = dotenv::var("SERVER_KEY").map_err(|_| not_configured("SERVER_KEY"))?;
+        let seed = decode_seed(&seed)?;
+
+        ServerIdentity::from_seed(&url, &seed)
+    }
+
+    pub fn public_key(&self) -> ServerPublicKey {
+        ServerPublicKey {
+            url: self.url.clone(),
+            signing_key: encode(self.signing_key.public_key()),
+            key_algorithm: self.signing_key.algorithm().name().to_owned(),
+            exchange_key: encode(self.exchange_key.public_key()),
+            exchange_algorithm: self.exchange_key.algorithm().name().to_owned(),
+        }
+    }
+}
+
+fn decode_seed(seed: &str) -> CommonResult<SecretKey> {
+    let bytes = SecretBytes::new(decode(seed)?);
+
+    if bytes.len() != 32 {
+        return Err(CommonError::Misconfiguration(Some("SERVER_KEY must be 32 bytes.".to_owned())));
+    }
+
+    let mut output = SecretKey::new([0u8; 32]);
+    output.copy_from_slice(&bytes);
+    Ok(output)
+}
+
+fn not_configured(variable: &str) -> CommonError {
+    CommonError::Misconfiguration(Some(format!("{} is not set, run init.", variable)))
+}
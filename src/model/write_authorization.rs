@@ -1,13 +1,26 @@
+use base64::encode;
 use crate::database::schema::write_authorization;
 use crate::database::MyConnection;
 use diesel::prelude::*;
-use crate::error::CommonResult;
-use crate::model::{Signable, Signed};
+use crate::error::{CommonError, CommonResult};
+use crate::model::{portable_authorization_hash, record_context, Scope, Signable, Signed};
+use crate::model::account::UnlockedAccount;
 use crate::model::client::Client;
 use crate::model::write_scope::LockedWriteScope;
 use crate::model::write_scope::UnlockedWriteScope;
-use crate::encryption::hash_by_parts;
-
+use crate::encryption::byte_encryption::{decrypt_32, encrypt_32, is_legacy};
+use crate::encryption::exchange_key::EphemeralKey;
+use crate::encryption::{as_256, hash_by_parts};
+
+// An authorization in a portable account. The access key it held derives from
+// the account master key, so only the client travels and the authorization is
+// reissued under the new access key on import. The signature is the
+// account's over portable_authorization_hash.
+#[derive(Serialize, Deserialize)]
+pub struct PortableWriteAuthorization {
+    pub client_id: String,
+    pub signature: String,
+}
 
 #[derive(PartialEq, Debug, Queryable)]
 pub struct WriteAuthorization {
@@ -35,6 +48,23 @@ pub struct UnsignedWriteAuthorization {
     pub public_key: Vec<u8>,
 }
 
+impl UnsignedWriteAuthorization {
+    // Encrypt the scope access key to the client's public key.
+    pub fn new(client_id: &[u8], write_grant_scope_id: i32, access_key: &[u8; 32]) -> UnsignedWriteAuthorization {
+        let ephemeral = EphemeralKey::new();
+        let public_key = ephemeral.public_key().to_vec();
+        let encryption_key = ephemeral.key_gen(*as_256(client_id));
+        let context = WriteAuthorization::access_key_context(client_id, write_grant_scope_id);
+
+        UnsignedWriteAuthorization {
+            client_id: client_id.to_vec(),
+            write_grant_scope_id,
+            encrypted_access_key: encrypt_32(access_key, &encryption_key, &context),
+            public_key,
+        }
+    }
+}
+
 impl Signable<NewWriteAuthorization> for UnsignedWriteAuthorization {
     fn record_hash(&self) -> [u8; 32] {
         hash_by_parts(&[
@@ -95,6 +125,27 @@ impl NewWriteAuthorization {
 }
 
 impl WriteAuthorization {
+    // Associated data for the access key envelope held by an authorization.
+    pub fn access_key_context(client_id: &[u8], write_grant_scope_id: i32) -> [u8; 32] {
+        record_context("write_authorization", &[client_id, &write_grant_scope_id.to_le_bytes()])
+    }
+
+    pub fn to_portable(
+        &self,
+        account: &UnlockedAccount,
+        scope: &Scope,
+        public_key: &[u8],
+    ) -> CommonResult<PortableWriteAuthorization> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        Ok(PortableWriteAuthorization {
+            client_id: encode(&self.client_id),
+            signature: encode(account.sign(&portable_authorization_hash(scope, public_key, &self.client_id))),
+        })
+    }
+
     pub fn load_all_for_client(client: &Client, connection: &MyConnection) -> CommonResult<Vec<WriteAuthorization>> {
         Ok(write_authorization::table
             .filter(write_authorization::client_id.eq(&client.client_id))
@@ -114,6 +165,78 @@ impl WriteAuthorization {
             .get_result(connection)?)
     }
 
+    // Legacy authorizations stored the client exchange key encrypted under the
+    // access key, so the account can recover it and re-encrypt the access key
+    // the right way round without the client secret.
+    pub fn upgrade_encryption(
+        self,
+        access_key: &[u8; 32],
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<usize> {
+        if !is_legacy(&self.encrypted_access_key) {
+            return Ok(0);
+        }
+
+        if !account.verify_record(&self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let encryption_key = decrypt_32(&self.encrypted_access_key, access_key, &[])?;
+        let context = WriteAuthorization::access_key_context(&self.client_id, self.write_grant_scope_id);
+
+        let authorization = account.sign_record(&UnsignedWriteAuthorization {
+            client_id: self.client_id.clone(),
+            write_grant_scope_id: self.write_grant_scope_id,
+            encrypted_access_key: encrypt_32(access_key, &encryption_key, &context),
+            public_key: self.public_key.clone(),
+        });
+
+        diesel::update(write_authorization::table
+                       .filter(write_authorization::client_id.eq(&self.client_id))
+                       .filter(write_authorization::write_grant_scope_id.eq(self.write_grant_scope_id))
+                       )
+            .set((
+                    write_authorization::encrypted_access_key.eq(authorization.encrypted_access_key),
+                    write_authorization::signature.eq(authorization.signature),
+                    ))
+            .execute(connection)?;
+
+        Ok(1)
+    }
+
+    // Replace the authorization with one holding a new access key for the same
+    // client, used when the scope access key changes.
+    pub fn reissue(
+        self,
+        access_key: &[u8; 32],
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<usize> {
+        if !account.verify_record(&self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let authorization = account.sign_record(&UnsignedWriteAuthorization::new(
+            &self.client_id,
+            self.write_grant_scope_id,
+            access_key,
+        ));
+
+        diesel::update(write_authorization::table
+                       .filter(write_authorization::client_id.eq(&self.client_id))
+                       .filter(write_authorization::write_grant_scope_id.eq(self.write_grant_scope_id))
+                       )
+            .set((
+                    write_authorization::encrypted_access_key.eq(authorization.encrypted_access_key),
+                    write_authorization::public_key.eq(authorization.public_key),
+                    write_authorization::signature.eq(authorization.signature),
+                    ))
+            .execute(connection)?;
+
+        Ok(1)
+    }
+
     pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
         diesel::delete(write_authorization::table
                        .filter(write_authorization::client_id.eq(self.client_id))
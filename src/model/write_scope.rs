@@ -1,24 +1,51 @@
+use base64::{decode, encode};
 use chrono::NaiveDateTime;
 use chrono::{Duration, Utc};
-use crate::database::schema::{write_grant_scope, application, account};
+use crate::database::schema::{write_grant_scope, write_authorization, application, account};
 use crate::database::MyConnection;
 use diesel::expression::dsl::any;
 use diesel::prelude::*;
-use crate::encryption::byte_encryption::encrypt_32;
-use crate::encryption::exchange_key::EphemeralKey;
-use crate::encryption::signing_key::SigningKey;
-use crate::encryption::{random_int_256, as_256, as_512};
+use crate::encryption::secret::SecretKey;
+use crate::encryption::byte_encryption::is_legacy;
+use crate::encryption::key_derivation::{KeyPurpose, KEY_VERSION};
+use crate::encryption::key_format::{encode_public_key, KeyFormat};
+use crate::encryption::secret::SecretString;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::signing_key::{verify_signature, SigningKey};
+use crate::encryption::{random_int_256, as_256};
 use crate::error::{CommonError, CommonResult};
 use crate::model::account::UnlockedAccount;
+use crate::model::account_import::BundleKey;
 use crate::model::application::Application;
 use crate::model::client::{Client, UnlockedClient};
-use crate::model::{Certifiable, Scope};
-use crate::model::write_authorization::{UnsignedWriteAuthorization, WriteAuthorization};
-use crate::model::certificate::{CertData, Certificate};
+use crate::model::{record_context, Certifiable, Scope, Signable, Signed};
+use crate::model::write_authorization::{
+    PortableWriteAuthorization, UnsignedWriteAuthorization, WriteAuthorization,
+};
+use crate::model::write_scope_succession::{UnsignedWriteScopeSuccession, WriteScopeSuccession};
+use crate::model::certificate::{legacy_certificate_version, CertData, Certificate, CERTIFICATE_VERSION};
+use crate::model::certificate_revocation::{CertificateRevocation, RevocationReason};
 use crate::model::Certified;
 
 pub struct WriteScope {}
 
+// A write scope in a portable account, its signing key wrapped under the
+// bundle key.
+#[derive(Serialize, Deserialize)]
+pub struct PortableWriteScope {
+    pub code: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub public_key: String,
+    pub key_algorithm: String,
+    pub encrypted_private_key: String,
+    pub expiration_date: NaiveDateTime,
+    #[serde(default = "legacy_certificate_version")]
+    pub certificate_version: u8,
+    pub signature: String,
+    pub authorizations: Vec<PortableWriteAuthorization>,
+}
+
 pub struct UnlockedWriteScope {
     pub id: i32,
     pub application_id: i32,
@@ -30,6 +57,8 @@ pub struct UnlockedWriteScope {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
     pub application_code: String,
     pub signing_key: SigningKey,
 }
@@ -43,7 +72,10 @@ pub struct UncertifiedWriteScope {
     pub public_key: Vec<u8>,
     pub encrypted_private_key: Vec<u8>,
     pub private_key_salt: Vec<u8>,
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
     pub expiration_date: NaiveDateTime,
+    pub signing_algorithm: KeyAlgorithm,
     pub signing_key: Vec<u8>,
 }
 
@@ -58,7 +90,10 @@ pub struct NewWriteScope {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
-    pub signing_key: [u8; 32],
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
+    pub signing_algorithm: KeyAlgorithm,
+    pub signing_key: Vec<u8>,
 }
 
 #[derive(Insertable)]
@@ -73,6 +108,9 @@ pub struct InsertWriteScope {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
+    pub certificate_version: i16,
 }
 
 #[derive(PartialEq, Debug, Queryable, Identifiable)]
@@ -88,8 +126,12 @@ pub struct LockedWriteScope {
     pub private_key_salt: Vec<u8>,
     pub expiration_date: NaiveDateTime,
     pub signature: Vec<u8>,
+    pub key_version: i16,
+    pub key_algorithm: KeyAlgorithm,
+    pub certificate_version: i16,
     pub application_code: String,
     pub signing_key: Vec<u8>,
+    pub signing_algorithm: KeyAlgorithm,
 }
 
 impl InsertWriteScope {
@@ -104,22 +146,86 @@ impl InsertWriteScope {
             private_key_salt: source.private_key_salt.clone(),
             expiration_date: source.expiration_date,
             signature: source.signature.clone(),
+            key_version: source.key_version,
+            key_algorithm: source.key_algorithm,
+            certificate_version: CERTIFICATE_VERSION as i16,
         }
     }
 }
 
 impl WriteScope {
+    #[cfg(test)]
     pub fn new(code: &str, application: &Application, account: &UnlockedAccount) -> NewWriteScope {
-        let salt = random_int_256();
+        WriteScope::with_key(code, application, account, SigningKey::new())
+    }
 
+    pub fn with_key(
+        code: &str,
+        application: &Application,
+        account: &UnlockedAccount,
+        signing_key: SigningKey,
+    ) -> NewWriteScope {
         // expire in one year as default
         let expiration_date = (Utc::now() + Duration::days(365)).naive_utc();
-        let signing_key = SigningKey::new();
-        let encryption_key = account.generate_key(&salt);
-        let encrypted_private_key = signing_key.encrypted_private_key(&encryption_key).to_vec();
+
+        account.certify_record(&WriteScope::uncertified(code, application, account, &signing_key, expiration_date))
+    }
+
+    // The bundle scope under the given code, after checking its certificate
+    // from the application it was exported from.
+    pub fn from_portable(
+        account: &UnlockedAccount,
+        application: &Application,
+        code: &str,
+        bundle_application: &str,
+        import: &PortableWriteScope,
+        bundle: &BundleKey,
+    ) -> CommonResult<NewWriteScope> {
+        let public_key = decode(&import.public_key)?;
+        let key_algorithm = import.key_algorithm.parse()?;
+        let data = bundle.cert_data(
+            key_algorithm,
+            &public_key,
+            Scope::Write {
+                application: bundle_application.to_owned(),
+                grant: import.code.clone(),
+            },
+            import.expiration_date,
+            import.certificate_version,
+        );
+
+        bundle.verify(&format!("Write scope {}", import.code), &data.hash(), &import.signature)?;
+
+        let signing_key = SigningKey::from_encrypted(
+            bundle.key(),
+            key_algorithm,
+            &public_key,
+            &decode(&import.encrypted_private_key)?,
+            &portable_key_context(&public_key),
+        )?;
+
+        let mut scope = WriteScope::uncertified(code, application, account, &signing_key, import.expiration_date);
+        scope.display_name = import.display_name.clone();
+        scope.description = import.description.clone();
+
+        Ok(account.certify_record(&scope))
+    }
+
+    fn uncertified(
+        code: &str,
+        application: &Application,
+        account: &UnlockedAccount,
+        signing_key: &SigningKey,
+        expiration_date: NaiveDateTime,
+    ) -> UncertifiedWriteScope {
+        let salt = random_int_256();
         let public_key = signing_key.public_key().to_vec();
+        let encryption_key = scope_access_key(account, KEY_VERSION, &public_key, &salt)
+            .expect("Current key version is supported.");
+        let encrypted_private_key = signing_key
+            .encrypted_private_key(&encryption_key, &private_key_context(&public_key));
 
-        let scope = UncertifiedWriteScope {
+        UncertifiedWriteScope {
             application_id: application.id,
             application_code: application.code.clone(),
             code: code.to_owned(),
@@ -128,11 +234,12 @@ impl WriteScope {
             public_key,
             encrypted_private_key,
             private_key_salt: salt.to_vec(),
+            key_version: KEY_VERSION,
+            key_algorithm: signing_key.algorithm(),
             expiration_date,
+            signing_algorithm: account.algorithm(),
             signing_key: account.public_key.clone(),
-        };
-
-        account.certify_record(&scope)
+        }
     }
 
     pub fn load_unlocked(
@@ -156,8 +263,12 @@ impl WriteScope {
                     write_grant_scope::private_key_salt,
                     write_grant_scope::expiration_date,
                     write_grant_scope::signature,
+                    write_grant_scope::key_version,
+                    write_grant_scope::key_algorithm,
+                    write_grant_scope::certificate_version,
                     application::code,
-                    account::public_key
+                    account::public_key,
+                    account::key_algorithm,
                     ))
             .load::<LockedWriteScope>(connection)?;
 
@@ -195,8 +306,12 @@ impl WriteScope {
                     write_grant_scope::private_key_salt,
                     write_grant_scope::expiration_date,
                     write_grant_scope::signature,
+                    write_grant_scope::key_version,
+                    write_grant_scope::key_algorithm,
+                    write_grant_scope::certificate_version,
                     application::code,
-                    account::public_key
+                    account::public_key,
+                    account::key_algorithm,
                     ))
             .get_results(connection)?)
     }
@@ -219,12 +334,52 @@ impl WriteScope {
                     write_grant_scope::private_key_salt,
                     write_grant_scope::expiration_date,
                     write_grant_scope::signature,
+                    write_grant_scope::key_version,
+                    write_grant_scope::key_algorithm,
+                    write_grant_scope::certificate_version,
                     application::code,
-                    account::public_key
+                    account::public_key,
+                    account::key_algorithm,
                     ))
             .get_result(connection)?)
     }
 
+    // Signs a message with the scope key for a client authorized for the
+    // scope. The key is unlocked through the client's authorization, which
+    // must be signed by the account that certified the scope. Returns the
+    // signature with the scope's certificate, which verify_message takes.
+    pub fn sign_for_client(
+        client: &UnlockedClient,
+        code: &str,
+        message: &[u8],
+        connection: &MyConnection,
+    ) -> CommonResult<(Vec<u8>, Certificate)> {
+        let authorization: WriteAuthorization = write_authorization::table
+            .inner_join(write_grant_scope::table)
+            .filter(write_authorization::client_id.eq(&client.client_id))
+            .filter(write_grant_scope::application_id.eq(client.application_id))
+            .filter(write_grant_scope::code.eq(code))
+            .select(write_authorization::all_columns)
+            .first(connection)
+            .optional()?
+            .ok_or_else(|| CommonError::NotFound(Some(format!("Client is not authorized for write:{}.", code))))?;
+
+        let scope = WriteScope::load_id(authorization.write_grant_scope_id, connection)?;
+        let certificate = scope.certificate();
+
+        certificate.verify(&scope.signing_key, Utc::now().naive_utc())?;
+
+        if !verify_signature(scope.signing_algorithm, &scope.signing_key, &authorization.record_hash(), &authorization.signature) {
+            return Err(CommonError::FailedVerification(Some(
+                "Write authorization signature is invalid.".to_owned(),
+            )));
+        }
+
+        let unlocked = scope.unlock_by_client(client, &authorization)?;
+
+        Ok((unlocked.signing_key.sign(message), certificate))
+    }
+
     pub fn load_all_for_application(application: &Application, connection: &MyConnection) -> CommonResult<Vec<LockedWriteScope>> {
 
         Ok(write_grant_scope::table
@@ -241,8 +396,12 @@ impl WriteScope {
                     write_grant_scope::private_key_salt,
                     write_grant_scope::expiration_date,
                     write_grant_scope::signature,
+                    write_grant_scope::key_version,
+                    write_grant_scope::key_algorithm,
+                    write_grant_scope::certificate_version,
                     application::code,
-                    account::public_key
+                    account::public_key,
+                    account::key_algorithm,
                     ))
             .get_results(connection)?)
     }
@@ -255,18 +414,8 @@ impl UnlockedWriteScope {
         client: &Client,
         connection: &MyConnection,
     ) -> CommonResult<()> {
-        let ephemeral = EphemeralKey::new();
-        let public_key = ephemeral.public_key().to_vec();
-        let encryption_key = ephemeral.key_gen(*as_256(&client.client_id));
-        let access_key = account.generate_key(&self.private_key_salt);
-        let encrypted_access_key = encrypt_32(&encryption_key, &access_key).to_vec();
-
-        let new_authorization = UnsignedWriteAuthorization {
-            client_id: client.client_id.clone(),
-            write_grant_scope_id: self.id,
-            public_key,
-            encrypted_access_key,
-        };
+        let access_key = scope_access_key(account, self.key_version, &self.public_key, &self.private_key_salt)?;
+        let new_authorization = UnsignedWriteAuthorization::new(&client.client_id, self.id, &access_key);
 
         account.sign_record(&new_authorization).save(connection)?;
 
@@ -277,21 +426,117 @@ impl UnlockedWriteScope {
         let auth = WriteAuthorization::load_scope_client(self, &client, connection)?;
         auth.delete(connection)
     }
+
+    pub fn export_public_key(&self, format: KeyFormat) -> CommonResult<String> {
+        encode_public_key(format, self.key_algorithm, &self.public_key, &self.key_comment())
+    }
+
+    // Like the account key, the scope key is only released with the
+    // account's export key.
+    pub fn export_private_key(
+        &self,
+        account: &UnlockedAccount,
+        export_key: &str,
+        format: KeyFormat,
+    ) -> CommonResult<SecretString> {
+        account.export_signing_key(export_key, &self.signing_key, format, &self.key_comment())
+    }
+
+    fn key_comment(&self) -> String {
+        format!("{}/{}", self.application_code, self.code)
+    }
+
+    // Move the scope onto a new signing key. The account certifies the new
+    // key, a succession statement signed by both keys and the account links
+    // it to the previous one, and every authorization is reissued with the
+    // new access key. Returns the rotated scope and the number of clients
+    // re-authorized.
+    pub fn rotate_key(
+        &self,
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<(LockedWriteScope, usize)> {
+        connection.transaction::<_, CommonError, _>(|| {
+            let salt = random_int_256();
+            let expiration_date = (Utc::now() + Duration::days(365)).naive_utc();
+            let signing_key = SigningKey::generate(self.key_algorithm)?;
+            let public_key = signing_key.public_key().to_vec();
+            let access_key = scope_access_key(account, KEY_VERSION, &public_key, &salt)?;
+            let encrypted_private_key = signing_key
+                .encrypted_private_key(&access_key, &private_key_context(&public_key));
+
+            let scope = account.certify_record(&UncertifiedWriteScope {
+                application_id: self.application_id,
+                application_code: self.application_code.clone(),
+                code: self.code.clone(),
+                display_name: self.display_name.clone(),
+                description: self.description.clone(),
+                public_key: public_key.clone(),
+                encrypted_private_key,
+                private_key_salt: salt.to_vec(),
+                key_version: KEY_VERSION,
+                key_algorithm: self.key_algorithm,
+                expiration_date,
+                signing_algorithm: account.algorithm(),
+                signing_key: account.public_key.clone(),
+            });
+
+            let mut succession = UnsignedWriteScopeSuccession {
+                write_grant_scope_id: self.id,
+                application_code: self.application_code.clone(),
+                scope_code: self.code.clone(),
+                previous_public_key: self.public_key.clone(),
+                public_key,
+                succession_date: Utc::now().naive_utc(),
+                previous_signature: Vec::new(),
+                successor_signature: Vec::new(),
+            };
+
+            let hash = succession.record_hash();
+            succession.previous_signature = self.signing_key.sign(&hash);
+            succession.successor_signature = signing_key.sign(&hash);
+            account.sign_record(&succession).save(connection)?;
+
+            diesel::update(write_grant_scope::table.filter(write_grant_scope::id.eq(self.id)))
+                .set((
+                        write_grant_scope::public_key.eq(scope.public_key),
+                        write_grant_scope::encrypted_private_key.eq(scope.encrypted_private_key),
+                        write_grant_scope::private_key_salt.eq(scope.private_key_salt),
+                        write_grant_scope::key_version.eq(scope.key_version),
+                        write_grant_scope::expiration_date.eq(scope.expiration_date),
+                        write_grant_scope::signature.eq(scope.signature),
+                        write_grant_scope::certificate_version.eq(CERTIFICATE_VERSION as i16),
+                        ))
+                .execute(connection)?;
+
+            let rotated = WriteScope::load_id(self.id, connection)?;
+            let mut reauthorized = 0;
+
+            for authorization in WriteAuthorization::load_all_for_scope(&rotated, connection)? {
+                reauthorized += authorization.reissue(&access_key, account, connection)?;
+            }
+
+            Ok((rotated, reauthorized))
+        })
+    }
 }
 
 impl Certified for NewWriteScope {
     fn certificate(&self) -> Certificate {
         Certificate {
             data: CertData{
-                signing_key:     self.signing_key,
-                public_key:      *as_256(&self.public_key),
+                version:         CERTIFICATE_VERSION,
+                signing_algorithm: self.signing_algorithm,
+                signing_key:     self.signing_key.clone(),
+                key_algorithm:   self.key_algorithm,
+                public_key:      self.public_key.clone(),
                 scope:           Scope::Write{
                    application: self.application_code.clone(),
                    grant: self.code.clone(),
                 },
                 expiration_date: self.expiration_date,
             },
-            signature: *as_512(&self.signature),
+            signature: self.signature.clone(),
         }
     }
 }
@@ -300,8 +545,11 @@ impl Certifiable<NewWriteScope> for UncertifiedWriteScope {
 
     fn data(&self) -> CertData {
         CertData{
-            signing_key: *as_256(&self.signing_key),
-            public_key: *as_256(&self.public_key.clone()),
+            version: CERTIFICATE_VERSION,
+            signing_algorithm: self.signing_algorithm,
+            signing_key: self.signing_key.clone(),
+            key_algorithm: self.key_algorithm,
+            public_key: self.public_key.clone(),
             scope: Scope::Write{
                 application: self.application_code.clone(),
                 grant: self.code.clone(),
@@ -322,7 +570,10 @@ impl Certifiable<NewWriteScope> for UncertifiedWriteScope {
             private_key_salt: self.private_key_salt.clone(),
             expiration_date: self.expiration_date,
             signature,
-            signing_key: *as_256(&authorizing_key),
+            key_version: self.key_version,
+            key_algorithm: self.key_algorithm,
+            signing_algorithm: self.signing_algorithm,
+            signing_key: authorizing_key,
         }
     }
 }
@@ -332,15 +583,18 @@ impl Certified for LockedWriteScope {
     fn certificate(&self) -> Certificate {
         Certificate {
             data: CertData{
-                signing_key:     *as_256(&self.signing_key),
-                public_key:      *as_256(&self.public_key),
+                version:         self.certificate_version as u8,
+                signing_algorithm: self.signing_algorithm,
+                signing_key:     self.signing_key.clone(),
+                key_algorithm:   self.key_algorithm,
+                public_key:      self.public_key.clone(),
                 scope:           Scope::Write{
                    application: self.application_code.clone(),
                    grant: self.code.clone(),
                 },
                 expiration_date: self.expiration_date,
             },
-            signature: *as_512(&self.signature),
+            signature: self.signature.clone(),
         }
     }
 }
@@ -357,7 +611,21 @@ impl NewWriteScope {
 }
 
 impl LockedWriteScope {
-    pub fn delete(self, connection: &MyConnection) -> CommonResult<()> {
+    // Revokes the scope certificate, and those of keys it was rotated from,
+    // before deleting the scope.
+    pub fn delete(self, account: &UnlockedAccount, connection: &MyConnection) -> CommonResult<()> {
+        let mut public_keys = WriteScopeSuccession::load_previous_keys(self.id, connection)?;
+        public_keys.push(self.public_key.clone());
+
+        for public_key in public_keys {
+            CertificateRevocation::ensure_revoked(
+                account,
+                &public_key,
+                RevocationReason::CessationOfOperation,
+                connection,
+                )?;
+        }
+
         // First delete write authorizations
         let authorizations = WriteAuthorization::load_all_for_scope(&self, connection)?;
 
@@ -365,16 +633,54 @@ impl LockedWriteScope {
             authorization.delete(connection)?;
         }
 
+        WriteScopeSuccession::delete_for_scope(self.id, connection)?;
+
         diesel::delete(write_grant_scope::table.filter(write_grant_scope::id.eq(self.id)))
             .execute(connection)?;
         Ok(())
     }
 
+    pub fn to_portable(
+        &self,
+        account: &UnlockedAccount,
+        bundle_key: &[u8; 32],
+        connection: &MyConnection,
+    ) -> CommonResult<PortableWriteScope> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let scope = self.unlock_by_account(account)?;
+        let portable_scope = Scope::Write { application: self.application_code.clone(), grant: self.code.clone() };
+        let mut authorizations = Vec::new();
+
+        for authorization in WriteAuthorization::load_all_for_scope(self, connection)? {
+            authorizations.push(authorization.to_portable(account, &portable_scope, &self.public_key)?);
+        }
+
+        Ok(PortableWriteScope {
+            code: self.code.clone(),
+            display_name: self.display_name.clone(),
+            description: self.description.clone(),
+            public_key: encode(&self.public_key),
+            key_algorithm: self.key_algorithm.name().to_owned(),
+            encrypted_private_key: encode(scope
+                .signing_key
+                .encrypted_private_key(bundle_key, &portable_key_context(&self.public_key))),
+            expiration_date: self.expiration_date,
+            certificate_version: self.certificate_version as u8,
+            signature: encode(&self.signature),
+            authorizations,
+        })
+    }
+
     fn to_unlocked(&self, encryption_key: &[u8; 32]) -> CommonResult<UnlockedWriteScope> {
         let signing_key = SigningKey::from_encrypted(
             &encryption_key,
-            as_256(&self.public_key),
-            as_512(&self.encrypted_private_key),
+            self.key_algorithm,
+            &self.public_key,
+            &self.encrypted_private_key,
+            &private_key_context(&self.public_key),
         )?;
 
         Ok(UnlockedWriteScope {
@@ -389,12 +695,14 @@ impl LockedWriteScope {
             private_key_salt: self.private_key_salt.clone(),
             expiration_date: self.expiration_date.clone(),
             signature: self.signature.clone(),
+            key_version: self.key_version,
+            key_algorithm: self.key_algorithm,
             signing_key,
         })
     }
 
     pub fn unlock_by_account(&self, account: &UnlockedAccount) -> CommonResult<UnlockedWriteScope> {
-        let encryption_key = account.generate_key(as_256(&self.private_key_salt));
+        let encryption_key = scope_access_key(account, self.key_version, &self.public_key, &self.private_key_salt)?;
         self.to_unlocked(&encryption_key)
     }
 
@@ -405,13 +713,19 @@ impl LockedWriteScope {
     ) -> CommonResult<UnlockedWriteScope> {
         let encryption_key = client.unlock_key(
             as_256(&authorization.public_key),
-            as_512(&authorization.encrypted_access_key),
+            &authorization.encrypted_access_key,
+            &WriteAuthorization::access_key_context(
+                &authorization.client_id,
+                authorization.write_grant_scope_id,
+            ),
         )?;
 
         let signing_key = SigningKey::from_encrypted(
             &encryption_key,
-            as_256(&self.public_key),
-            as_512(&self.encrypted_private_key),
+            self.key_algorithm,
+            &self.public_key,
+            &self.encrypted_private_key,
+            &private_key_context(&self.public_key),
         )?;
 
         Ok(UnlockedWriteScope {
@@ -426,7 +740,87 @@ impl LockedWriteScope {
             private_key_salt: self.private_key_salt.clone(),
             expiration_date: self.expiration_date.clone(),
             signature: self.signature.clone(),
+            key_version: self.key_version,
+            key_algorithm: self.key_algorithm,
             signing_key,
         })
     }
+
+    // Re-encrypts the scope key and its authorizations if they are still in
+    // the legacy format, and moves the scope to the current key version.
+    // Changing the key version changes the access key so every authorization
+    // is reissued. Returns the number of records rewritten.
+    pub fn upgrade_encryption(
+        &self,
+        account: &UnlockedAccount,
+        connection: &MyConnection,
+    ) -> CommonResult<usize> {
+        if !account.verify_record(self) {
+            return Err(CommonError::FailedVerification(None));
+        }
+
+        let mut access_key = scope_access_key(account, self.key_version, &self.public_key, &self.private_key_salt)?;
+        let authorizations = WriteAuthorization::load_all_for_scope(self, connection)?;
+        let mut upgraded = 0;
+
+        if self.key_version != KEY_VERSION {
+            let scope = self.to_unlocked(&access_key)?;
+            access_key = scope_access_key(account, KEY_VERSION, &self.public_key, &self.private_key_salt)?;
+            let encrypted_private_key = scope
+                .signing_key
+                .encrypted_private_key(&access_key, &private_key_context(&self.public_key));
+
+            diesel::update(write_grant_scope::table.filter(write_grant_scope::id.eq(self.id)))
+                .set((
+                        write_grant_scope::encrypted_private_key.eq(encrypted_private_key),
+                        write_grant_scope::key_version.eq(KEY_VERSION),
+                        ))
+                .execute(connection)?;
+
+            upgraded += 1;
+
+            for authorization in authorizations {
+                upgraded += authorization.reissue(&access_key, account, connection)?;
+            }
+
+            return Ok(upgraded);
+        }
+
+        if is_legacy(&self.encrypted_private_key) {
+            let scope = self.to_unlocked(&access_key)?;
+            let encrypted_private_key = scope
+                .signing_key
+                .encrypted_private_key(&access_key, &private_key_context(&self.public_key));
+
+            diesel::update(write_grant_scope::table.filter(write_grant_scope::id.eq(self.id)))
+                .set(write_grant_scope::encrypted_private_key.eq(encrypted_private_key))
+                .execute(connection)?;
+
+            upgraded += 1;
+        }
+
+        for authorization in authorizations {
+            upgraded += authorization.upgrade_encryption(&access_key, account, connection)?;
+        }
+
+        Ok(upgraded)
+    }
+}
+
+// The key wrapping the scope signing key, shared with authorized clients.
+fn scope_access_key(
+    account: &UnlockedAccount,
+    key_version: i16,
+    public_key: &[u8],
+    salt: &[u8],
+) -> CommonResult<SecretKey> {
+    account.derive_key(key_version, KeyPurpose::WriteScope, "write_grant_scope", &[public_key], salt)
+}
+
+fn private_key_context(public_key: &[u8]) -> [u8; 32] {
+    record_context("write_grant_scope", &[public_key])
+}
+
+fn portable_key_context(public_key: &[u8]) -> [u8; 32] {
+    record_context("portable_write_scope", &[public_key])
 }
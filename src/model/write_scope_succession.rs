@@ -0,0 +1,294 @@
+use chrono::NaiveDateTime;
+use crate::database::schema::{account, application, write_grant_scope, write_scope_succession};
+use crate::database::MyConnection;
+use diesel::prelude::*;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::signing_key::verify_signature;
+use crate::encryption::{hash_by_parts, lpad_to_256};
+use crate::error::{CommonError, CommonResult};
+use crate::model::{Scope, Signable, Signed};
+
+// A statement that a write scope has moved from one signing key to the next.
+// The same hash is signed by the previous key, the successor key and the
+// account, so a verifier holding a certificate for the previous key can
+// follow the chain to the current one. A rotation keeps the scope key
+// algorithm, so both scope keys use the algorithm of the scope.
+//
+// hash = hash_by_parts("write_scope_succession", scope hash, previous public key,
+//                      public key, succession date)
+pub struct WriteScopeSuccession {}
+
+pub struct UnsignedWriteScopeSuccession {
+    pub write_grant_scope_id: i32,
+    pub application_code: String,
+    pub scope_code: String,
+    pub previous_public_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub succession_date: NaiveDateTime,
+    pub previous_signature: Vec<u8>,
+    pub successor_signature: Vec<u8>,
+}
+
+pub struct NewWriteScopeSuccession {
+    pub write_grant_scope_id: i32,
+    pub application_code: String,
+    pub scope_code: String,
+    pub previous_public_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub succession_date: NaiveDateTime,
+    pub previous_signature: Vec<u8>,
+    pub successor_signature: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(Insertable)]
+#[table_name = "write_scope_succession"]
+pub struct InsertWriteScopeSuccession {
+    pub write_grant_scope_id: i32,
+    pub previous_public_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub succession_date: NaiveDateTime,
+    pub previous_signature: Vec<u8>,
+    pub successor_signature: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug, Queryable)]
+pub struct LockedWriteScopeSuccession {
+    pub id: i32,
+    pub write_grant_scope_id: i32,
+    pub previous_public_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub succession_date: NaiveDateTime,
+    pub previous_signature: Vec<u8>,
+    pub successor_signature: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub application_code: String,
+    pub scope_code: String,
+    pub key_algorithm: KeyAlgorithm,
+    pub account_public_key: Vec<u8>,
+    pub account_key_algorithm: KeyAlgorithm,
+}
+
+pub fn succession_hash(
+    application_code: &str,
+    scope_code: &str,
+    previous_public_key: &[u8],
+    public_key: &[u8],
+    succession_date: &NaiveDateTime,
+) -> [u8; 32] {
+    let scope = Scope::Write {
+        application: application_code.to_owned(),
+        grant: scope_code.to_owned(),
+    };
+    let date = lpad_to_256(&succession_date.and_utc().timestamp().to_le_bytes());
+
+    hash_by_parts(&[
+        b"write_scope_succession",
+        &scope.hash(),
+        previous_public_key,
+        public_key,
+        &date,
+    ])
+}
+
+impl WriteScopeSuccession {
+    // Every succession starting from the given key, oldest first. Fails if
+    // any statement does not verify.
+    pub fn load_chain(
+        public_key: &[u8],
+        connection: &MyConnection,
+    ) -> CommonResult<Vec<LockedWriteScopeSuccession>> {
+        let mut chain = Vec::new();
+        let mut current = public_key.to_vec();
+
+        while let Some(succession) = write_scope_succession::table
+            .inner_join(write_grant_scope::table.inner_join(application::table.inner_join(account::table)))
+            .filter(write_scope_succession::previous_public_key.eq(&current))
+            .select((
+                    write_scope_succession::id,
+                    write_scope_succession::write_grant_scope_id,
+                    write_scope_succession::previous_public_key,
+                    write_scope_succession::public_key,
+                    write_scope_succession::succession_date,
+                    write_scope_succession::previous_signature,
+                    write_scope_succession::successor_signature,
+                    write_scope_succession::signature,
+                    application::code,
+                    write_grant_scope::code,
+                    write_grant_scope::key_algorithm,
+                    account::public_key,
+                    account::key_algorithm,
+                    ))
+            .first::<LockedWriteScopeSuccession>(connection)
+            .optional()?
+        {
+            if !succession.verify() {
+                return Err(CommonError::FailedVerification(Some(
+                    "Write scope succession failed verification.".to_owned(),
+                )));
+            }
+
+            current = succession.public_key.clone();
+            chain.push(succession);
+        }
+
+        Ok(chain)
+    }
+
+    pub fn load_previous_keys(write_grant_scope_id: i32, connection: &MyConnection) -> CommonResult<Vec<Vec<u8>>> {
+        Ok(write_scope_succession::table
+            .filter(write_scope_succession::write_grant_scope_id.eq(write_grant_scope_id))
+            .select(write_scope_succession::previous_public_key)
+            .get_results(connection)?)
+    }
+
+    pub fn delete_for_scope(write_grant_scope_id: i32, connection: &MyConnection) -> CommonResult<()> {
+        diesel::delete(write_scope_succession::table
+                       .filter(write_scope_succession::write_grant_scope_id.eq(write_grant_scope_id))
+                       )
+            .execute(connection)?;
+        Ok(())
+    }
+}
+
+impl Signable<NewWriteScopeSuccession> for UnsignedWriteScopeSuccession {
+    fn record_hash(&self) -> [u8; 32] {
+        succession_hash(
+            &self.application_code,
+            &self.scope_code,
+            &self.previous_public_key,
+            &self.public_key,
+            &self.succession_date,
+        )
+    }
+
+    fn sign(&self, signature: Vec<u8>) -> NewWriteScopeSuccession {
+        NewWriteScopeSuccession {
+            write_grant_scope_id: self.write_grant_scope_id,
+            application_code: self.application_code.clone(),
+            scope_code: self.scope_code.clone(),
+            previous_public_key: self.previous_public_key.clone(),
+            public_key: self.public_key.clone(),
+            succession_date: self.succession_date,
+            previous_signature: self.previous_signature.clone(),
+            successor_signature: self.successor_signature.clone(),
+            signature,
+        }
+    }
+}
+
+impl From<NewWriteScopeSuccession> for InsertWriteScopeSuccession {
+    fn from(item: NewWriteScopeSuccession) -> InsertWriteScopeSuccession {
+        InsertWriteScopeSuccession {
+            write_grant_scope_id: item.write_grant_scope_id,
+            previous_public_key: item.previous_public_key,
+            public_key: item.public_key,
+            succession_date: item.succession_date,
+            previous_signature: item.previous_signature,
+            successor_signature: item.successor_signature,
+            signature: item.signature,
+        }
+    }
+}
+
+impl Signed for NewWriteScopeSuccession {
+    fn record_hash(&self) -> [u8; 32] {
+        succession_hash(
+            &self.application_code,
+            &self.scope_code,
+            &self.previous_public_key,
+            &self.public_key,
+            &self.succession_date,
+        )
+    }
+
+    fn signature(&self) -> Vec<u8> {
+        self.signature.clone()
+    }
+}
+
+impl NewWriteScopeSuccession {
+    pub fn save(self, connection: &MyConnection) -> CommonResult<()> {
+        diesel::insert_into(write_scope_succession::table)
+            .values(InsertWriteScopeSuccession::from(self))
+            .execute(connection)?;
+        Ok(())
+    }
+}
+
+impl Signed for LockedWriteScopeSuccession {
+    fn record_hash(&self) -> [u8; 32] {
+        succession_hash(
+            &self.application_code,
+            &self.scope_code,
+            &self.previous_public_key,
+            &self.public_key,
+            &self.succession_date,
+        )
+    }
+
+    fn signature(&self) -> Vec<u8> {
+        self.signature.clone()
+    }
+}
+
+impl LockedWriteScopeSuccession {
+    // Checks the signatures of both scope keys and the account.
+    pub fn verify(&self) -> bool {
+        let hash = self.record_hash();
+
+        verify_signature(self.key_algorithm, &self.previous_public_key, &hash, &self.previous_signature)
+            && verify_signature(self.key_algorithm, &self.public_key, &hash, &self.successor_signature)
+            && verify_signature(self.account_key_algorithm, &self.account_public_key, &hash, &self.signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::signing_key::SigningKey;
+    use chrono::Utc;
+
+    #[test]
+    fn verify_succession() {
+        let account = SigningKey::new();
+        let previous = SigningKey::new();
+        let successor = SigningKey::new();
+        let succession_date = Utc::now().naive_utc();
+        let hash = succession_hash("spout", "crap", &previous.public_key(), &successor.public_key(), &succession_date);
+
+        let mut succession = LockedWriteScopeSuccession {
+            id: 1,
+            write_grant_scope_id: 1,
+            previous_public_key: previous.public_key().to_vec(),
+            public_key: successor.public_key().to_vec(),
+            succession_date,
+            previous_signature: previous.sign(&hash),
+            successor_signature: successor.sign(&hash),
+            signature: account.sign(&hash),
+            application_code: "spout".to_owned(),
+            scope_code: "crap".to_owned(),
+            key_algorithm: KeyAlgorithm::Ed25519,
+            account_public_key: account.public_key().to_vec(),
+            account_key_algorithm: KeyAlgorithm::Ed25519,
+        };
+
+        assert!(succession.verify());
+
+        succession.scope_code = "flush".to_owned();
+        assert!(!succession.verify());
+
+        succession.scope_code = "crap".to_owned();
+        succession.account_public_key = previous.public_key().to_vec();
+        assert!(!succession.verify());
+
+        succession.account_public_key = account.public_key().to_vec();
+        succession.account_key_algorithm = KeyAlgorithm::P256;
+        assert!(!succession.verify());
+
+        succession.account_key_algorithm = KeyAlgorithm::Ed25519;
+        succession.successor_signature = previous.sign(&hash);
+        assert!(!succession.verify());
+    }
+}
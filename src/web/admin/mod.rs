@@ -1,15 +1,26 @@
 mod view;
 
 use rocket::response::{Redirect, Flash};
+use rocket::{Either, State};
 use rocket::request::{self, FromRequest, Request};
 use rocket::form::Form;
 use rocket::outcome::Outcome;
-use rocket::http::{Cookie, CookieJar};
+use rocket::http::{Cookie, CookieJar, RawStr, Status};
+use rocket::http::uri::Origin;
 use rocket_dyn_templates::Template;
+use crate::encryption::secret::SecretString;
+use crate::encryption::shamir::Share;
+use crate::error::CommonError;
 use crate::model::account::Account;
 use crate::model::application::Application;
+use crate::model::authorization_code::{AuthorizationCode, CodeRequest};
+use crate::model::openid::IdToken;
+use crate::model::server_identity::ServerIdentity;
 use crate::database::DbConn;
-use self::view::{JoinContext, AdminContext, LoginContext, ApplicationView};
+use std::sync::Arc;
+use self::view::{
+    JoinContext, AdminContext, ConsentContext, ConsentScopeView, LoginContext, RecoverContext, ApplicationView,
+};
 use base64::encode;
 
 #[derive(FromForm, Clone)]
@@ -19,13 +30,43 @@ pub struct LoginParameters {
 }
 
 pub struct LoggedInUser {
-    username: String,
-    password: String,
+    pub username: String,
+    pub password: SecretString,
 }
 
 pub struct LoggedInAdmin {
     username: String,
-    password: String,
+    password: SecretString,
+}
+
+#[derive(FromForm)]
+pub struct RecoverParameters {
+    username: String,
+    shares: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+// An application's request to be authorized for scopes, the client is the
+// application's code. OpenID Connect libraries send it as client_id. Only
+// the S256 code challenge method is supported.
+#[derive(FromForm, Serialize)]
+pub struct AuthorizationRequest {
+    #[field(name = "client")]
+    #[field(name = "client_id")]
+    client: String,
+    scope: String,
+    code_challenge: String,
+    code_challenge_method: Option<String>,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+}
+
+#[derive(FromForm)]
+pub struct ConsentParameters {
+    request: AuthorizationRequest,
+    decision: String,
 }
 
 #[derive(FromForm, Clone)]
@@ -46,8 +87,8 @@ impl<'r> FromRequest<'r> for LoggedInUser {
             None => return Outcome::Forward(()),
         };
          
-        let password: String = match request.cookies().get_private("password") {
-            Some(c) => c.value().to_owned(),
+        let password = match request.cookies().get_private("password") {
+            Some(c) => SecretString::new(c.value().to_owned()),
             None => return Outcome::Forward(()),
         };
 
@@ -69,8 +110,8 @@ impl<'r> FromRequest<'r> for LoggedInAdmin {
             None => return Outcome::Forward(()),
         };
          
-        let password: String = match request.cookies().get_private("password") {
-            Some(c) => c.value().to_owned(),
+        let password = match request.cookies().get_private("password") {
+            Some(c) => SecretString::new(c.value().to_owned()),
             None => return Outcome::Forward(()),
         };
 
@@ -122,19 +163,65 @@ pub async fn post_login(connection: DbConn, cookies: &CookieJar<'_>, login_param
     let LoginParameters {username, password} = login_params.into_inner();
     let cookie_username = username.clone();
     let cookie_password = password.clone();
+    let password = SecretString::new(password);
    
-    let account = connection.run(move |c| Account::load_unlocked(username.clone(), password.clone(), &c)).await;
+    let account = connection.run(move |c| Account::load_unlocked(username, password, &c)).await;
 
     match account {
         Ok(_) => {
             cookies.add_private(Cookie::new("username", cookie_username));
             cookies.add_private(Cookie::new("password", cookie_password));
-            Ok(Redirect::to("/home"))
+
+            // Back to the authorization request that sent the user here.
+            match cookies.get_private("authorize_url") {
+                Some(c) => {
+                    cookies.remove_private(Cookie::named("authorize_url"));
+                    Ok(Redirect::to(c.value().to_owned()))
+                }
+                None => Ok(Redirect::to("/home")),
+            }
         },
         Err(_) => Err(Flash::error(Redirect::to("/login"), "Invalid username/password.")),
     }
 }
 
+#[get("/recover")]
+pub async fn recover() -> Template {
+    let context = RecoverContext {
+        title: "Recover Account".to_string()
+    };
+    Template::render("recover", &context)
+}
+
+// Recovery by shares, one per line. It always sets a new password, the user
+// then logs in with it as usual.
+#[post("/recover", format = "application/x-www-form-urlencoded", data = "<recover_params>")]
+pub async fn post_recover(connection: DbConn, recover_params: Form<RecoverParameters>) -> Result<Flash<Redirect>, Flash<Redirect>> {
+
+    let RecoverParameters {username, shares, new_password, confirm_password} = recover_params.into_inner();
+    let shares = SecretString::new(shares);
+    let new_password = SecretString::new(new_password);
+    let confirm_password = SecretString::new(confirm_password);
+
+    if new_password.is_empty() || *new_password != *confirm_password {
+        return Err(Flash::error(Redirect::to("/recover"), "Passwords do not match."));
+    }
+
+    let shares: Vec<Share> = shares
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| Flash::error(Redirect::to("/recover"), "A share is not valid."))?;
+
+    let recovered = connection.run(move |c| Account::recover(&username, &shares, &new_password, c)).await;
+
+    match recovered {
+        Ok(_) => Ok(Flash::success(Redirect::to("/login"), "Account recovered, log in with the new password and create new recovery shares.")),
+        Err(_) => Err(Flash::error(Redirect::to("/recover"), "Could not recover the account with these shares.")),
+    }
+}
+
 #[post("/logout")]
 pub fn logout(cookies: &CookieJar<'_>) -> Flash<Redirect> {
     cookies.remove_private(Cookie::named("account"));
@@ -207,3 +294,130 @@ pub fn join_server(connection: DbConn, cookies: &CookieJar<'_>, application_serv
 
     Template::render("join", &context)
 }
+
+// Redirects back to the application with the parameters and the request's
+// state added to the query.
+fn redirect_back(request: &AuthorizationRequest, parameters: &[(&str, &str)]) -> Redirect {
+    let mut uri = request.redirect_uri.clone();
+    let state = request.state.as_deref().map(|state| ("state", state));
+
+    for (name, value) in parameters.iter().copied().chain(state) {
+        uri.push(if uri.contains('?') { '&' } else { '?' });
+        uri.push_str(name);
+        uri.push('=');
+        uri.push_str(RawStr::new(value).percent_encode().as_str());
+    }
+
+    Redirect::to(uri)
+}
+
+fn redirect_error(request: &AuthorizationRequest, err: CommonError) -> Redirect {
+    let (error, description) = match err {
+        CommonError::NotFound(description) | CommonError::Misconfiguration(description) => ("invalid_scope", description),
+        _ => ("server_error", None),
+    };
+
+    match description {
+        Some(description) => redirect_back(request, &[("error", error), ("error_description", &description)]),
+        None => redirect_back(request, &[("error", error)]),
+    }
+}
+
+// The consent screen of the authorization code flow. Nothing redirects back
+// to the application until its redirect is known to be on its server.
+#[get("/authorize?<request..>")]
+pub async fn authorize(connection: DbConn, user: LoggedInUser, request: AuthorizationRequest) -> Result<Template, Either<Redirect, Status>> {
+    let LoggedInUser { username, password } = user;
+    let display_user = username.clone();
+
+    // The redirect is boxed inside the closure to keep its error small.
+    connection.run(move |c| {
+        let account = Account::load_unlocked(username, password, c)
+            .map_err(|_| Either::Right(Status::Unauthorized))?;
+
+        let application = Application::load_by_code(&request.client, &account, c)
+            .map_err(|_| Either::Right(Status::NotFound))?;
+
+        AuthorizationCode::check_redirect(&application, &request.redirect_uri)
+            .map_err(|_| Either::Right(Status::BadRequest))?;
+
+        if request.code_challenge_method.as_deref().unwrap_or("S256") != "S256" {
+            return Err(Either::Left(Box::new(redirect_back(&request, &[
+                ("error", "invalid_request"),
+                ("error_description", "Only the S256 code challenge method is supported."),
+            ]))));
+        }
+
+        let details = AuthorizationCode::scope_details(&account, &application, &request.scope, c)
+            .map_err(|err| Either::Left(Box::new(redirect_error(&request, err))))?;
+
+        let context = ConsentContext {
+            title: "Authorize".to_string(),
+            username: display_user,
+            application: application.code.clone(),
+            description: application.description.clone(),
+            server_url: application.server_url.clone(),
+            scopes: ConsentScopeView::from_details(&details),
+            openid: IdToken::split_scope(&request.scope).0,
+            request: &request,
+        };
+
+        Ok(Template::render("consent", &context))
+    }).await.map_err(|err| err.map_left(|redirect| *redirect))
+}
+
+// Remembers the request so logging in comes back to it.
+#[get("/authorize", rank = 2)]
+pub fn authorize_login(user: Option<LoggedInUser>, cookies: &CookieJar<'_>, origin: &Origin<'_>) -> Result<Redirect, Status> {
+    if user.is_some() {
+        return Err(Status::BadRequest);
+    }
+
+    cookies.add_private(Cookie::new("authorize_url", origin.to_string()));
+    Ok(Redirect::to("/login"))
+}
+
+// Approving creates the client and its authorizations and sends the
+// application a code to exchange for the client's secret.
+#[post("/authorize/consent", format = "application/x-www-form-urlencoded", data = "<consent_params>")]
+pub async fn post_consent(
+    connection: DbConn,
+    identity: &State<Arc<ServerIdentity>>,
+    user: LoggedInUser,
+    consent_params: Form<ConsentParameters>,
+) -> Result<Redirect, Status> {
+    let LoggedInUser { username, password } = user;
+    let ConsentParameters { request, decision } = consent_params.into_inner();
+    let issuer = identity.url.trim_end_matches('/').to_owned();
+
+    connection.run(move |c| {
+        let account = Account::load_unlocked(username, password, c)
+            .map_err(|_| Status::Unauthorized)?;
+
+        let application = Application::load_by_code(&request.client, &account, c)
+            .map_err(|_| Status::NotFound)?;
+
+        AuthorizationCode::check_redirect(&application, &request.redirect_uri)
+            .map_err(|_| Status::BadRequest)?;
+
+        if decision != "approve" {
+            return Ok(redirect_back(&request, &[("error", "access_denied")]));
+        }
+
+        if request.code_challenge_method.as_deref().unwrap_or("S256") != "S256" {
+            return Ok(redirect_back(&request, &[("error", "invalid_request")]));
+        }
+
+        let code_request = CodeRequest {
+            scope: &request.scope,
+            code_challenge: &request.code_challenge,
+            redirect_uri: &request.redirect_uri,
+            nonce: request.nonce.as_deref(),
+        };
+
+        match AuthorizationCode::grant(&account, &application, &code_request, &issuer, c) {
+            Ok(code) => Ok(redirect_back(&request, &[("code", &code)])),
+            Err(err) => Ok(redirect_error(&request, err)),
+        }
+    }).await
+}
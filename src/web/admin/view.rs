@@ -1,4 +1,7 @@
 use crate::model::application::Application;
+use crate::model::authorization_code::ScopeDetail;
+use crate::model::oauth_token::OAuthScope;
+use super::AuthorizationRequest;
 
 /// Data to pass join application page
 #[derive(Serialize)]
@@ -14,6 +17,25 @@ pub struct LoginContext {
     pub title: String,
 }
 
+/// Data to pass to the account recovery screen
+#[derive(Serialize)]
+pub struct RecoverContext {
+    pub title: String,
+}
+
+/// Data to pass to the scope consent screen
+#[derive(Serialize)]
+pub struct ConsentContext<'a> {
+    pub title: String,
+    pub username: String,
+    pub application: String,
+    pub description: String,
+    pub server_url: String,
+    pub scopes: Vec<ConsentScopeView>,
+    pub openid: bool,
+    pub request: &'a AuthorizationRequest,
+}
+
 /// data to pass the admin home screen
 #[derive(Serialize)]
 pub struct AdminContext {
@@ -58,3 +80,32 @@ impl ApplicationView {
         application_views
     }
 }
+
+/// A requested scope as the account sees it before approving.
+#[derive(Serialize)]
+pub struct ConsentScopeView {
+    pub scope: String,
+    pub name: String,
+    pub description: String,
+    pub write: bool,
+}
+
+impl ConsentScopeView {
+    pub fn from_details(details: &[ScopeDetail]) -> Vec<ConsentScopeView> {
+        details
+            .iter()
+            .map(|detail| {
+                let code = match &detail.scope {
+                    OAuthScope::Read(code) | OAuthScope::Write(code) => code.clone(),
+                };
+
+                ConsentScopeView {
+                    scope: detail.scope.to_string(),
+                    name: detail.display_name.clone().unwrap_or(code),
+                    description: detail.description.clone().unwrap_or_default(),
+                    write: matches!(detail.scope, OAuthScope::Write(_)),
+                }
+            })
+            .collect()
+    }
+}
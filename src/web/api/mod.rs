@@ -1,56 +1,516 @@
-/*
 use rocket::form::Form;
+use rocket::http::{Header, Status};
 use rocket::response::content::Json;
+use rocket::State;
 use serde_json::json;
-use serde_json::Value;
-use serde::Serialize;
-use rocket::response::status::Unauthorized;
+use base64::{decode, decode_config, encode, URL_SAFE};
+use crate::database::{DbConn, MyConnection};
+use crate::encryption::secret::{SecretBytes, SecretKey, SecretString};
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::Account;
+use crate::model::account_migration::{AccountMove, MigrationBundle, MoveStatement};
+use crate::model::application::Application;
+use crate::model::authorization_code::AuthorizationCode;
+use crate::model::certificate_revocation::CertificateRevocation;
+use crate::model::client::{Client, UnlockedClient};
+use crate::model::oauth_token::{OAuthScope, OAuthToken};
+use crate::model::openid::{IdToken, OPENID_SCOPE};
+use crate::model::server_identity::{ServerIdentity, ServerPublicKey};
+use crate::model::read_scope::ReadScope;
+use crate::model::write_scope::WriteScope;
+use crate::model::write_scope_succession::WriteScopeSuccession;
+use super::admin::LoggedInUser;
+use std::sync::Arc;
+
+pub mod v1;
+
+#[derive(FromForm)]
+pub struct AuthorizeParameters {
+    client_id: String,
+    scope: String,
+}
 
 #[derive(FromForm)]
-pub struct AuthorizationParameters {
+pub struct TokenParameters {
     grant_type: String,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    scope: Option<String>,
+    refresh_token: Option<String>,
+    code: Option<String>,
+    code_verifier: Option<String>,
+    redirect_uri: Option<String>,
+}
+
+#[derive(FromForm)]
+pub struct IntrospectParameters {
+    token: String,
+    client_id: String,
+    client_secret: String,
+}
+
+#[derive(FromForm)]
+pub struct RevokeParameters {
+    token: String,
+    client_id: String,
+    client_secret: String,
+}
+
+// Token endpoint responses must not be cached, RFC 6749 section 5.1.
+#[derive(Responder)]
+#[response(content_type = "json")]
+pub struct NoStore {
+    body: String,
+    cache_control: Header<'static>,
+}
+
+impl NoStore {
+    fn new(body: String) -> NoStore {
+        NoStore { body, cache_control: Header::new("Cache-Control", "no-store") }
+    }
+}
+
+// An error response from the token endpoint, RFC 6749 section 5.2.
+fn oauth_error(status: Status, error: &str, description: Option<String>) -> (Status, NoStore) {
+    (status, NoStore::new(json!({ "error": error, "error_description": description }).to_string()))
+}
+
+fn token_error(err: CommonError) -> (Status, NoStore) {
+    match err {
+        CommonError::CouldNotAuthenticate(description) => oauth_error(Status::Unauthorized, "invalid_client", description),
+        CommonError::FailedVerification(description) => oauth_error(Status::BadRequest, "invalid_grant", description),
+        CommonError::NotFound(description) | CommonError::Misconfiguration(description) => {
+            oauth_error(Status::BadRequest, "invalid_scope", description)
+        }
+        _ => oauth_error(Status::InternalServerError, "server_error", None),
+    }
+}
+
+// Clients authenticate with their base64 id and secret as printed by
+// `client add`.
+fn authenticate_client(client_id: &str, client_secret: &str, connection: &MyConnection) -> CommonResult<Client> {
+    unlock_client(client_id, client_secret, connection).map(|(client, _)| client)
+}
+
+// As authenticate_client, with the client unlocked by its secret.
+fn unlock_client(
+    client_id: &str,
+    client_secret: &str,
+    connection: &MyConnection,
+) -> CommonResult<(Client, UnlockedClient)> {
+    let not_recognized = || CommonError::CouldNotAuthenticate(Some("Client not recognized.".to_owned()));
+    let secret = SecretBytes::new(decode(client_secret).map_err(|_| not_recognized())?);
+
+    if secret.len() != 32 {
+        return Err(not_recognized());
+    }
+
+    let mut secret_key = SecretKey::new([0u8; 32]);
+    secret_key.copy_from_slice(&secret);
+
+    let client = Client::load_id(decode(client_id).map_err(|_| not_recognized())?, connection)
+        .map_err(|_| not_recognized())?;
+    let unlocked = client.authenticate(&secret_key)?;
+
+    Ok((client, unlocked))
+}
+
+// Authorizes one of the logged in account's clients for read and write
+// scopes of its application, such as "write:post read:view".
+#[post("/authorize", format = "application/x-www-form-urlencoded", data = "<params>")]
+pub async fn authorize(
+    connection: DbConn,
+    user: LoggedInUser,
+    params: Form<AuthorizeParameters>,
+) -> Result<Json<String>, Status> {
+    let LoggedInUser { username, password } = user;
+    let AuthorizeParameters { client_id, scope } = params.into_inner();
+    let id = decode(&client_id).map_err(|_| Status::BadRequest)?;
+
+    let scopes = connection.run(move |c| {
+        let account = Account::load_unlocked(username, password, c)
+            .map_err(|_| Status::Unauthorized)?;
+        let client = Client::load_id(id, c).map_err(|_| Status::NotFound)?;
+
+        OAuthToken::authorize(&account, &client, &scope, c).map_err(error_status)
+    }).await?;
+
+    Ok(Json(json!({
+        "client_id": client_id,
+        "scope": OAuthScope::join(&scopes),
+    }).to_string()))
+}
+
+// Issues tokens with the client_credentials grant and rotates them with the
+// refresh_token grant. The authorization_code grant instead returns the
+// credentials of the client created when the account approved the request.
+#[post("/token", format = "application/x-www-form-urlencoded", data = "<params>")]
+pub async fn token(connection: DbConn, params: Form<TokenParameters>) -> Result<NoStore, (Status, NoStore)> {
+    let TokenParameters {
+        grant_type, client_id, client_secret, scope, refresh_token, code, code_verifier, redirect_uri,
+    } = params.into_inner();
+    let missing = |parameter: &str| {
+        oauth_error(Status::BadRequest, "invalid_request", Some(format!("Missing {}.", parameter)))
+    };
+
+    if grant_type == "authorization_code" {
+        let code = code.ok_or_else(|| missing("code"))?;
+        let code_verifier = code_verifier.ok_or_else(|| missing("code_verifier"))?;
+        let redirect_uri = redirect_uri.ok_or_else(|| missing("redirect_uri"))?;
+
+        let credentials = connection
+            .run(move |c| AuthorizationCode::exchange(&code, &code_verifier, &redirect_uri, c))
+            .await
+            .map_err(token_error)?;
+
+        return serde_json::to_string(&credentials)
+            .map(NoStore::new)
+            .map_err(|_| oauth_error(Status::InternalServerError, "server_error", None));
+    }
+
+    match (grant_type.as_str(), &refresh_token) {
+        ("client_credentials", _) | ("refresh_token", Some(_)) => {}
+        ("refresh_token", None) => return Err(missing("refresh_token")),
+        _ => return Err(oauth_error(Status::BadRequest, "unsupported_grant_type", None)),
+    }
+
+    let client_id = client_id.ok_or_else(|| missing("client_id"))?;
+    let client_secret = client_secret.ok_or_else(|| missing("client_secret"))?;
+
+    let response = connection.run(move |c| {
+        let client = authenticate_client(&client_id, &client_secret, c)?;
+
+        match refresh_token {
+            Some(refresh_token) if grant_type == "refresh_token" => OAuthToken::refresh(&client, &refresh_token, c),
+            _ => OAuthToken::issue(&client, scope.as_deref(), c),
+        }
+    }).await.map_err(token_error)?;
+
+    serde_json::to_string(&response)
+        .map(NoStore::new)
+        .map_err(|_| oauth_error(Status::InternalServerError, "server_error", None))
+}
+
+// Revokes a token of the client and the authorizations it was issued for.
+#[post("/revoke", format = "application/x-www-form-urlencoded", data = "<params>")]
+pub async fn revoke(connection: DbConn, params: Form<RevokeParameters>) -> Result<NoStore, (Status, NoStore)> {
+    let RevokeParameters { token, client_id, client_secret } = params.into_inner();
+
+    connection.run(move |c| {
+        let client = authenticate_client(&client_id, &client_secret, c)?;
+        OAuthToken::revoke(&client, &token, c)
+    }).await.map_err(token_error)?;
+
+    Ok(NoStore::new("{}".to_owned()))
+}
+
+// Tells a resource server whether an access token is active and what it
+// covers, RFC 7662. The resource server authenticates as a client of the
+// token's application, tokens of other applications are never active.
+#[post("/introspect", format = "application/x-www-form-urlencoded", data = "<params>")]
+pub async fn introspect(connection: DbConn, params: Form<IntrospectParameters>) -> Result<NoStore, (Status, NoStore)> {
+    let IntrospectParameters { token, client_id, client_secret } = params.into_inner();
+
+    let response = connection.run(move |c| {
+        let caller = authenticate_client(&client_id, &client_secret, c)?;
+
+        match OAuthToken::validate(&token, c) {
+            Ok(active) if active.client.application_id == caller.application_id => Ok(json!({
+                "active": true,
+                "scope": OAuthScope::join(&active.scopes),
+                "client_id": encode(&active.client.client_id),
+                "token_type": "bearer",
+                "exp": active.expiration_date.and_utc().timestamp(),
+            })),
+            Ok(_) | Err(CommonError::CouldNotAuthenticate(_)) => Ok(json!({ "active": false })),
+            Err(err) => Err(err),
+        }
+    }).await.map_err(token_error)?;
+
+    Ok(NoStore::new(response.to_string()))
+}
+
+// The OpenID Connect discovery document, the issuer is SERVER_URL.
+#[get("/.well-known/openid-configuration")]
+pub fn openid_configuration(identity: &State<Arc<ServerIdentity>>) -> Json<String> {
+    let issuer = identity.url.trim_end_matches('/');
+
+    Json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "revocation_endpoint": format!("{}/revoke", issuer),
+        "introspection_endpoint": format!("{}/introspect", issuer),
+        "jwks_uri": format!("{}/.well-known/jwks.json", issuer),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", "client_credentials", "refresh_token"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["EdDSA"],
+        "scopes_supported": [OPENID_SCOPE],
+        "claims_supported": ["iss", "sub", "aud", "exp", "iat", "nonce"],
+        "token_endpoint_auth_methods_supported": ["none", "client_secret_post"],
+        "code_challenge_methods_supported": ["S256"],
+    }).to_string())
+}
+
+// The keys id tokens and write scope certificates are checked against.
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(connection: DbConn) -> Result<Json<String>, Status> {
+    let keys = connection
+        .run(|c| IdToken::key_set(c))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    Ok(Json(keys.to_string()))
+}
+
+#[post("/api/application/<application_code>/read/<scope_code>/rotate")]
+pub async fn rotate_read_key(
+    connection: DbConn,
+    user: LoggedInUser,
+    application_code: String,
+    scope_code: String,
+) -> Result<Json<String>, Status> {
+    let LoggedInUser { username, password } = user;
+
+    connection.run(move |c| {
+        let account = Account::load_unlocked(username, password, c)
+            .map_err(|_| Status::Unauthorized)?;
+
+        let application = Application::load_by_code(&application_code, &account, c)
+            .map_err(|_| Status::NotFound)?;
+
+        let scope = ReadScope::load_codes(vec![scope_code], &account, &application, c)
+            .map_err(|_| Status::InternalServerError)?
+            .pop()
+            .ok_or(Status::NotFound)?;
+
+        let (key, clients) = scope
+            .to_unlocked(&account, c)
+            .and_then(|s| s.rotate_key(&account, c))
+            .map_err(|_| Status::InternalServerError)?;
+
+        Ok(Json(json!({
+            "scope": scope.code,
+            "public_key": encode(&key.public_key),
+            "clients_reauthorized": clients,
+        }).to_string()))
+    }).await
+}
+
+#[post("/api/application/<application_code>/write/<scope_code>/rotate")]
+pub async fn rotate_write_key(
+    connection: DbConn,
+    user: LoggedInUser,
+    application_code: String,
+    scope_code: String,
+) -> Result<Json<String>, Status> {
+    let LoggedInUser { username, password } = user;
+
+    connection.run(move |c| {
+        let account = Account::load_unlocked(username, password, c)
+            .map_err(|_| Status::Unauthorized)?;
+
+        let application = Application::load_by_code(&application_code, &account, c)
+            .map_err(|_| Status::NotFound)?;
+
+        let scope = WriteScope::load_unlocked(&[scope_code], &account, &application, c)
+            .map_err(|_| Status::InternalServerError)?
+            .pop()
+            .ok_or(Status::NotFound)?;
+
+        let (rotated, clients) = scope
+            .rotate_key(&account, c)
+            .map_err(|_| Status::InternalServerError)?;
+
+        Ok(Json(json!({
+            "scope": rotated.code,
+            "public_key": encode(&rotated.public_key),
+            "clients_reauthorized": clients,
+        }).to_string()))
+    }).await
+}
+
+// Successions from a write scope key to the current one, for verifiers
+// holding a certificate for an older key. The key is url safe base64.
+#[get("/api/write_scope/<public_key>/succession")]
+pub async fn write_scope_succession(
+    connection: DbConn,
+    public_key: String,
+) -> Result<Json<String>, Status> {
+    let public_key = decode_config(&public_key, URL_SAFE).map_err(|_| Status::BadRequest)?;
+
+    let chain = connection
+        .run(move |c| WriteScopeSuccession::load_chain(&public_key, c))
+        .await
+        .map_err(|_| Status::InternalServerError)?;
+
+    let successions: Vec<_> = chain.iter().map(|succession| json!({
+        "application": succession.application_code,
+        "scope": succession.scope_code,
+        "previous_public_key": encode(&succession.previous_public_key),
+        "public_key": encode(&succession.public_key),
+        "key_algorithm": succession.key_algorithm.name(),
+        "succession_date": succession.succession_date.and_utc().timestamp(),
+        "previous_signature": encode(&succession.previous_signature),
+        "successor_signature": encode(&succession.successor_signature),
+        "signature": encode(&succession.signature),
+        "account_key_algorithm": succession.account_key_algorithm.name(),
+    })).collect();
+
+    Ok(Json(json!(successions).to_string()))
+}
+
+// The account's signed revocation list, optionally only the entries after a
+// serial the caller has already verified. The key is url safe base64. The
+// list of a deleted account is still served.
+#[get("/api/account/<public_key>/crl?<since>")]
+pub async fn certificate_revocation_list(
+    connection: DbConn,
+    public_key: String,
+    since: Option<i32>,
+) -> Result<Json<String>, Status> {
+    let public_key = decode_config(&public_key, URL_SAFE).map_err(|_| Status::BadRequest)?;
+
+    let list = connection
+        .run(move |c| CertificateRevocation::load_list(&public_key, since.unwrap_or(0), c))
+        .await
+        .map_err(|err| match err {
+            CommonError::NotFound(_) => Status::NotFound,
+            _ => Status::InternalServerError,
+        })?;
+
+    serde_json::to_string(&list)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+#[derive(Deserialize)]
+pub struct MigrationRequest {
+    export_key: String,
+    destination: ServerPublicKey,
+}
+
+#[derive(Deserialize)]
+pub struct MigrationImport {
+    bundle: MigrationBundle,
     username: String,
+    email: String,
     password: String,
-    scope: String
-}
-
-#[derive(Serialize)]
-pub struct ClientAccessToken {
-    access_token: Vec<u8>,
-    token_type: String,
-    expires_in: u64,
-    refresh_token: Vec<u8>,
-}
-
-#[post("/authorize", format = "application/x-www-form-urlencoded", data = "<auth_params>")]
-pub fn authorize(auth_params: Form<AuthorizationParameters>) -> Result<Json<String>, Unauthorized<String>> {
-
-    if auth_params.grant_type == "password" {
-        Ok(
-        Json(
-                ClientAccessToken {
-                    access_token: b"1234567789".to_vec(),
-                    token_type: "bearer".to_string(),
-                    expires_in: 300,
-                    refresh_token: b"987654321".to_vec(),
-                }
-            ).to_string()
-        )
-    } else {
-        Unauthorized(String::from("Unrecognized token_type"))
+    export_key: String,
+}
+
+// The status for an error from the model.
+fn error_status(err: CommonError) -> Status {
+    match err {
+        CommonError::CouldNotAuthenticate(_) => Status::Unauthorized,
+        CommonError::FailedVerification(_) => Status::Forbidden,
+        CommonError::Duplicate(_) => Status::Conflict,
+        CommonError::NotFound(_) => Status::NotFound,
+        CommonError::Misconfiguration(_) => Status::BadRequest,
+        _ => Status::InternalServerError,
     }
 }
 
-#[get("/token")]
-pub fn token() ->Json<Value> {
-    Json(serde_json::from_str( r#"{ "status": "ok"}"#))
+// This server's public keys, which a source server seals migration bundles
+// to.
+#[get("/api/server")]
+pub fn server_public_key(identity: &State<Arc<ServerIdentity>>) -> Result<Json<String>, Status> {
+    serde_json::to_string(&identity.public_key())
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+// Seals the logged in account for the destination server. The bundle is
+// handed to the destination by the user.
+#[post("/api/migration/export", format = "json", data = "<request>")]
+pub async fn export_migration(
+    connection: DbConn,
+    identity: &State<Arc<ServerIdentity>>,
+    user: LoggedInUser,
+    request: String,
+) -> Result<Json<String>, Status> {
+    let LoggedInUser { username, password } = user;
+    let MigrationRequest { export_key, destination } =
+        serde_json::from_str(&request).map_err(|_| Status::BadRequest)?;
+    let export_key = SecretString::new(export_key);
+    let identity = Arc::clone(identity);
+
+    let bundle = connection.run(move |c| {
+        let account = Account::load_unlocked(username, password, c)
+            .map_err(|_| Status::Unauthorized)?;
+
+        MigrationBundle::seal(&account, &export_key, &identity, &destination, c)
+            .map_err(error_status)
+    }).await?;
+
+    serde_json::to_string(&bundle)
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+// Creates the account from a bundle sealed to this server and returns the
+// statement that it moved here, which the source server is given next.
+#[post("/api/migration/import", format = "json", data = "<request>")]
+pub async fn import_migration(
+    connection: DbConn,
+    identity: &State<Arc<ServerIdentity>>,
+    request: String,
+) -> Result<Json<String>, Status> {
+    let MigrationImport { bundle, username, email, password, export_key } =
+        serde_json::from_str(&request).map_err(|_| Status::BadRequest)?;
+    let password = SecretString::new(password);
+    let export_key = SecretString::new(export_key);
+    let identity = Arc::clone(identity);
+
+    let statement = connection
+        .run(move |c| bundle.import(&identity, &username, &email, &password, &export_key, c))
+        .await
+        .map_err(error_status)?;
+
+    serde_json::to_string(&MoveStatement::from(&statement))
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
 }
 
-#[get("/revoke")]
-pub fn revoke() ->Json<Value> {
-    Json(serde_json::from_str(r#"{ "status": "ok"}"#))
+// Records on the source server that the account moved. The statement is
+// signed by the account, so it needs no login.
+#[post("/api/migration/complete", format = "json", data = "<statement>")]
+pub async fn complete_migration(
+    connection: DbConn,
+    identity: &State<Arc<ServerIdentity>>,
+    statement: String,
+) -> Result<Json<String>, Status> {
+    let statement: MoveStatement = serde_json::from_str(&statement).map_err(|_| Status::BadRequest)?;
+    let identity = Arc::clone(identity);
+
+    let recorded = connection
+        .run(move |c| AccountMove::record_departure(&statement, &identity, c))
+        .await
+        .map_err(error_status)?;
+
+    serde_json::to_string(&MoveStatement::from(&recorded))
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
+}
+
+// Where the account moved to, if it moved. The key is url safe base64.
+#[get("/api/account/<public_key>/moved")]
+pub async fn account_moved(
+    connection: DbConn,
+    public_key: String,
+) -> Result<Json<String>, Status> {
+    let public_key = decode_config(&public_key, URL_SAFE).map_err(|_| Status::BadRequest)?;
+
+    let latest = connection
+        .run(move |c| AccountMove::load_latest(&public_key, c))
+        .await
+        .map_err(|_| Status::NotFound)?;
+
+    serde_json::to_string(&MoveStatement::from(&latest))
+        .map(Json)
+        .map_err(|_| Status::InternalServerError)
 }
-*/
 
 //#[post("/account/add")]
 //pub fn add_account() -> Json<JsonValue>
@@ -0,0 +1,638 @@
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::content::Json;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use base64::{decode, decode_config, encode, URL_SAFE};
+use crate::database::{DbConn, MyConnection};
+use diesel::Connection;
+use crate::encryption::algorithm::KeyAlgorithm;
+use crate::encryption::{Digest, Sha512Trunc256};
+use crate::encryption::exchange_key::ExchangeKey;
+use crate::encryption::secret::SecretString;
+use crate::encryption::signing_key::SigningKey;
+use crate::error::{CommonError, CommonResult};
+use crate::model::account::{Account, LockedAccount, NewAccount, UnlockedAccount};
+use crate::model::application::Application;
+use crate::model::client::Client;
+use crate::model::oauth_token::{OAuthScope, OAuthToken};
+use crate::model::read_authorization::ReadGrantKey;
+use crate::model::read_scope::ReadScope;
+use crate::model::write_scope::WriteScope;
+use super::{error_status, unlock_client};
+
+// The administration API, what the account, application and client
+// commands do over HTTP. Every call is made as an account with HTTP Basic
+// authentication, calls for the whole server need an admin account.
+pub struct ApiUser {
+    username: String,
+    password: SecretString,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let credentials = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Basic "))
+            .and_then(|encoded| decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+
+        match credentials.as_deref().and_then(|c| c.split_once(':')) {
+            Some((username, password)) => Outcome::Success(ApiUser {
+                username: username.to_owned(),
+                password: SecretString::new(password.to_owned()),
+            }),
+            None => Outcome::Failure((Status::Unauthorized, ())),
+        }
+    }
+}
+
+type ApiResponse = (Status, Json<String>);
+type ApiResult = Result<ApiResponse, ApiResponse>;
+
+#[derive(Deserialize)]
+pub struct AccountRequest {
+    name: String,
+    email: String,
+    password: String,
+    export_key: String,
+    #[serde(default)]
+    is_admin: bool,
+    algorithm: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PasswordRequest {
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ApplicationRequest {
+    code: String,
+    description: String,
+    server_url: String,
+}
+
+#[derive(Deserialize)]
+pub struct ApplicationUpdate {
+    description: Option<String>,
+    server_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ScopeRequest {
+    #[serde(default)]
+    read: Vec<String>,
+    #[serde(default)]
+    write: Vec<String>,
+    read_algorithm: Option<String>,
+    write_algorithm: Option<String>,
+}
+
+// A message for a client to sign with a write scope key, base64 encoded.
+// Large messages can be sent as their 32 byte hash, which is signed instead.
+#[derive(Deserialize)]
+pub struct SignRequest {
+    client_id: String,
+    client_secret: String,
+    scope: String,
+    payload: Option<String>,
+    payload_hash: Option<String>,
+}
+
+// Scope codes of an application, for client add and revoke.
+#[derive(Deserialize)]
+pub struct ClientScopes {
+    #[serde(default)]
+    read: Vec<String>,
+    #[serde(default)]
+    write: Vec<String>,
+}
+
+fn error_message(err: &CommonError) -> Option<String> {
+    match err {
+        CommonError::NotFound(message)
+        | CommonError::TooFewResults(message)
+        | CommonError::CouldNotAuthenticate(message)
+        | CommonError::Misconfiguration(message)
+        | CommonError::LibraryError(message)
+        | CommonError::Duplicate(message)
+        | CommonError::RecordNotSaved(message)
+        | CommonError::FailedVerification(message) => message.clone(),
+    }
+}
+
+fn error_response(status: Status, message: Option<String>) -> ApiResponse {
+    let message = message.unwrap_or_else(|| status.reason_lossy().to_owned());
+
+    (status, Json(json!({ "error": message }).to_string()))
+}
+
+// Errors from the model, internal ones without their details.
+fn api_error(err: CommonError) -> ApiResponse {
+    let message = error_message(&err);
+
+    let status = error_status(err);
+
+    if status == Status::InternalServerError {
+        return error_response(status, None);
+    }
+
+    error_response(status, message)
+}
+
+// Requests rejected before a handler runs, such as without credentials.
+#[catch(default)]
+pub fn default_catcher(status: Status, _request: &Request) -> Json<String> {
+    error_response(status, None).1
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, ApiResponse> {
+    serde_json::from_str(body).map_err(|err| error_response(Status::BadRequest, Some(err.to_string())))
+}
+
+fn respond(status: Status, value: CommonResult<Value>) -> ApiResult {
+    value.map(|value| (status, Json(value.to_string()))).map_err(api_error)
+}
+
+fn unlock(user: ApiUser, connection: &MyConnection) -> CommonResult<UnlockedAccount> {
+    Account::load_unlocked(user.username, user.password, connection)
+        .map_err(|_| CommonError::CouldNotAuthenticate(Some("Account and password not recognized.".to_owned())))
+}
+
+fn unlock_admin(user: ApiUser, connection: &MyConnection) -> CommonResult<UnlockedAccount> {
+    let account = unlock(user, connection)?;
+
+    if !account.is_admin {
+        return Err(CommonError::FailedVerification(Some("Requires an admin account.".to_owned())));
+    }
+
+    Ok(account)
+}
+
+fn algorithm(name: Option<&str>, default: KeyAlgorithm) -> CommonResult<KeyAlgorithm> {
+    name.map_or(Ok(default), |name| name.parse())
+        .map_err(|_| CommonError::Misconfiguration(Some(format!("Unknown key algorithm {}.", name.unwrap_or_default()))))
+}
+
+fn account_json(account: &LockedAccount) -> Value {
+    json!({
+        "name": account.name,
+        "email": account.email,
+        "public_key": encode(&account.public_key),
+        "key_algorithm": account.key_algorithm.name(),
+        "is_admin": account.is_admin,
+    })
+}
+
+fn application_json(application: &Application) -> Value {
+    json!({
+        "code": application.code,
+        "description": application.description,
+        "server_url": application.server_url,
+    })
+}
+
+fn client_json(client: &Client, connection: &MyConnection) -> CommonResult<Value> {
+    let scopes: Vec<OAuthScope> = OAuthToken::authorized_scopes(client, connection)?
+        .into_iter()
+        .map(|(scope, _)| scope)
+        .collect();
+
+    Ok(json!({
+        "client_id": encode(&client.client_id),
+        "application": client.application_code,
+        "scope": OAuthScope::join(&scopes),
+    }))
+}
+
+fn scope_json(application: &Application, connection: &MyConnection) -> CommonResult<Value> {
+    let read: Vec<Value> = ReadScope::load_all_for_application(application, connection)?
+        .iter()
+        .map(|scope| json!({
+            "code": scope.code,
+            "display_name": scope.display_name,
+            "description": scope.description,
+        }))
+        .collect();
+
+    let write: Vec<Value> = WriteScope::load_all_for_application(application, connection)?
+        .iter()
+        .map(|scope| json!({
+            "code": scope.code,
+            "display_name": scope.display_name,
+            "description": scope.description,
+            "public_key": encode(&scope.public_key),
+            "key_algorithm": scope.key_algorithm.name(),
+            "expiration_date": scope.expiration_date.and_utc().timestamp(),
+        }))
+        .collect();
+
+    Ok(json!({ "read": read, "write": write }))
+}
+
+// The account's client with the given url safe base64 id, and its
+// application.
+fn load_client(
+    client_id: &str,
+    account: &UnlockedAccount,
+    connection: &MyConnection,
+) -> CommonResult<(Client, Application)> {
+    let not_found = || CommonError::NotFound(Some("Client not found.".to_owned()));
+    let id = decode_config(client_id, URL_SAFE).map_err(|_| not_found())?;
+    let client = Client::load_id(id, connection).map_err(|_| not_found())?;
+    let application = Application::load_by_code(&client.application_code, account, connection)
+        .map_err(|_| not_found())?;
+
+    if application.id != client.application_id {
+        return Err(not_found());
+    }
+
+    Ok((client, application))
+}
+
+#[get("/accounts")]
+pub async fn list_accounts(connection: DbConn, user: ApiUser) -> ApiResult {
+    let accounts = connection.run(move |c| {
+        unlock_admin(user, c)?;
+
+        let accounts: Vec<Value> = Account::load_all(c)?.iter().map(account_json).collect();
+        Ok(json!(accounts))
+    }).await;
+
+    respond(Status::Ok, accounts)
+}
+
+#[post("/accounts", format = "json", data = "<request>")]
+pub async fn add_account(connection: DbConn, user: ApiUser, request: String) -> ApiResult {
+    let AccountRequest { name, email, password, export_key, is_admin, algorithm: key_algorithm } = parse(&request)?;
+
+    let account = connection.run(move |c| {
+        unlock_admin(user, c)?;
+
+        let signing_key = SigningKey::generate(algorithm(key_algorithm.as_deref(), KeyAlgorithm::Ed25519)?)?;
+        let account = NewAccount::with_key(&name, &email, &password, &export_key, signing_key, is_admin).save(c)?;
+
+        Ok(account_json(&account))
+    }).await;
+
+    respond(Status::Created, account)
+}
+
+#[get("/account")]
+pub async fn get_account(connection: DbConn, user: ApiUser) -> ApiResult {
+    let account = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let applications: Vec<Value> = Application::load_all_for_account(&account, c)?
+            .iter()
+            .map(application_json)
+            .collect();
+
+        let mut json = account_json(&Account::load_locked(&account.name, c)?);
+        json["applications"] = json!(applications);
+        Ok(json)
+    }).await;
+
+    respond(Status::Ok, account)
+}
+
+#[put("/account/password", format = "json", data = "<request>")]
+pub async fn change_password(connection: DbConn, user: ApiUser, request: String) -> ApiResult {
+    let PasswordRequest { new_password } = parse(&request)?;
+
+    let changed = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let name = account.name.clone();
+
+        account.change_password(&new_password, c)?;
+        Ok(json!({ "name": name }))
+    }).await;
+
+    respond(Status::Ok, changed)
+}
+
+#[delete("/account")]
+pub async fn delete_account(connection: DbConn, user: ApiUser) -> ApiResult {
+    let deleted = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let name = account.name.clone();
+
+        account.delete(c)?;
+        Ok(json!({ "name": name }))
+    }).await;
+
+    respond(Status::Ok, deleted)
+}
+
+#[get("/applications")]
+pub async fn list_applications(connection: DbConn, user: ApiUser) -> ApiResult {
+    let applications = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let applications: Vec<Value> = Application::load_all_for_account(&account, c)?
+            .iter()
+            .map(application_json)
+            .collect();
+
+        Ok(json!(applications))
+    }).await;
+
+    respond(Status::Ok, applications)
+}
+
+#[post("/applications", format = "json", data = "<request>")]
+pub async fn add_application(connection: DbConn, user: ApiUser, request: String) -> ApiResult {
+    let ApplicationRequest { code, description, server_url } = parse(&request)?;
+
+    let application = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let application = Application::new(&code, &description, &server_url, &account).save(c)?;
+
+        Ok(application_json(&application))
+    }).await;
+
+    respond(Status::Created, application)
+}
+
+// The application with its scopes and clients.
+#[get("/applications/<code>")]
+pub async fn get_application(connection: DbConn, user: ApiUser, code: String) -> ApiResult {
+    let application = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let application = Application::load_by_code(&code, &account, c)?;
+        let mut clients = Vec::new();
+
+        for client in Client::load_all_for_application(&application, c)? {
+            clients.push(client_json(&client, c)?);
+        }
+
+        let mut json = application_json(&application);
+        json["scopes"] = scope_json(&application, c)?;
+        json["clients"] = json!(clients);
+        Ok(json)
+    }).await;
+
+    respond(Status::Ok, application)
+}
+
+#[put("/applications/<code>", format = "json", data = "<request>")]
+pub async fn update_application(connection: DbConn, user: ApiUser, code: String, request: String) -> ApiResult {
+    let ApplicationUpdate { description, server_url } = parse(&request)?;
+
+    let application = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let application = Application::load_by_code(&code, &account, c)?;
+        let description = description.unwrap_or_else(|| application.description.clone());
+        let server_url = server_url.unwrap_or_else(|| application.server_url.clone());
+
+        let application = application.update(&description, &server_url, &account, c)?;
+        Ok(application_json(&application))
+    }).await;
+
+    respond(Status::Ok, application)
+}
+
+// Deletes the application with its scopes and clients.
+#[delete("/applications/<code>")]
+pub async fn delete_application(connection: DbConn, user: ApiUser, code: String) -> ApiResult {
+    let deleted = connection.run(move |c| {
+        let account = unlock(user, c)?;
+
+        Application::load_by_code(&code, &account, c)?.delete(&account, c)?;
+        Ok(json!({ "code": code }))
+    }).await;
+
+    respond(Status::Ok, deleted)
+}
+
+// Adds read and write scopes, each with a new key. Defaults to Ed25519 write
+// keys and X25519 read keys as `application scope` does.
+#[post("/applications/<code>/scopes", format = "json", data = "<request>")]
+pub async fn add_scopes(connection: DbConn, user: ApiUser, code: String, request: String) -> ApiResult {
+    let ScopeRequest { read, write, read_algorithm, write_algorithm } = parse(&request)?;
+
+    let scopes = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let application = Application::load_by_code(&code, &account, c)?;
+        let read_algorithm = algorithm(read_algorithm.as_deref(), KeyAlgorithm::X25519)?;
+        let write_algorithm = algorithm(write_algorithm.as_deref(), KeyAlgorithm::Ed25519)?;
+
+        c.transaction::<_, CommonError, _>(|| {
+            for scope_code in &write {
+                let signing_key = SigningKey::generate(write_algorithm)?;
+                WriteScope::with_key(scope_code, &application, &account, signing_key).save(c)?;
+            }
+
+            for scope_code in &read {
+                let scope = ReadScope::new(scope_code, &application, &account)
+                    .save(c)?
+                    .to_unlocked(&account, c)?;
+                let exchange_key = ExchangeKey::generate(read_algorithm)?;
+                ReadGrantKey::with_key(&scope, &account, exchange_key).save(c)?;
+            }
+
+            scope_json(&application, c)
+        })
+    }).await;
+
+    respond(Status::Created, scopes)
+}
+
+// Deletes a scope, kind is read or write.
+#[delete("/applications/<code>/scopes/<kind>/<scope_code>")]
+pub async fn delete_scope(
+    connection: DbConn,
+    user: ApiUser,
+    code: String,
+    kind: String,
+    scope_code: String,
+) -> ApiResult {
+    let scope = match kind.as_str() {
+        "read" => OAuthScope::Read(scope_code),
+        "write" => OAuthScope::Write(scope_code),
+        _ => return Err(error_response(Status::NotFound, None)),
+    };
+
+    let deleted = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let application = Application::load_by_code(&code, &account, c)?;
+        let unknown = || CommonError::NotFound(Some(format!("Unknown scope {}.", scope)));
+
+        match &scope {
+            OAuthScope::Read(scope_code) => ReadScope::load_codes(vec![scope_code.clone()], &account, &application, c)?
+                .pop()
+                .ok_or_else(unknown)?
+                .delete(&account, c)?,
+            OAuthScope::Write(scope_code) => WriteScope::load_codes(vec![scope_code.clone()], &application, c)?
+                .pop()
+                .ok_or_else(unknown)?
+                .delete(&account, c)?,
+        }
+
+        Ok(json!({ "scope": scope.to_string() }))
+    }).await;
+
+    respond(Status::Ok, deleted)
+}
+
+#[get("/applications/<code>/clients")]
+pub async fn list_clients(connection: DbConn, user: ApiUser, code: String) -> ApiResult {
+    let clients = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let application = Application::load_by_code(&code, &account, c)?;
+        let mut clients = Vec::new();
+
+        for client in Client::load_all_for_application(&application, c)? {
+            clients.push(client_json(&client, c)?);
+        }
+
+        Ok(json!(clients))
+    }).await;
+
+    respond(Status::Ok, clients)
+}
+
+// Adds a client authorized for the scopes. The secret is only in this
+// response, as `client add` prints it once.
+#[post("/applications/<code>/clients", format = "json", data = "<request>")]
+pub async fn add_client(connection: DbConn, user: ApiUser, code: String, request: String) -> ApiResult {
+    let ClientScopes { read, write } = parse(&request)?;
+
+    let client = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let application = Application::load_by_code(&code, &account, c)?;
+        let scopes: Vec<OAuthScope> = read
+            .into_iter()
+            .map(OAuthScope::Read)
+            .chain(write.into_iter().map(OAuthScope::Write))
+            .collect();
+
+        c.transaction::<_, CommonError, _>(|| {
+            let (secret, new_client) = Client::new(&account, &application);
+            let client = new_client.save(c)?;
+            let scopes = OAuthToken::authorize(&account, &client, &OAuthScope::join(&scopes), c)?;
+
+            Ok(json!({
+                "client_id": encode(&client.client_id),
+                "client_secret": encode(secret),
+                "application": client.application_code,
+                "scope": OAuthScope::join(&scopes),
+            }))
+        })
+    }).await;
+
+    respond(Status::Created, client)
+}
+
+// Revokes the client's authorizations for the scopes. The client id is url
+// safe base64.
+#[post("/clients/<client_id>/revoke", format = "json", data = "<request>")]
+pub async fn revoke_client(connection: DbConn, user: ApiUser, client_id: String, request: String) -> ApiResult {
+    let ClientScopes { read, write } = parse(&request)?;
+
+    let client = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let (client, application) = load_client(&client_id, &account, c)?;
+        let unknown = || CommonError::NotFound(Some("Unknown scope.".to_owned()));
+
+        c.transaction::<_, CommonError, _>(|| {
+            let write_scopes = WriteScope::load_unlocked(&write, &account, &application, c)?;
+
+            if write_scopes.len() != write.len() {
+                return Err(unknown());
+            }
+
+            for write_scope in write_scopes {
+                write_scope.revoke(&client, c)?;
+            }
+
+            let read_count = read.len();
+            let read_scopes = ReadScope::load_codes(read, &account, &application, c)?;
+
+            if read_scopes.len() != read_count {
+                return Err(unknown());
+            }
+
+            for read_scope in read_scopes {
+                read_scope.to_unlocked(&account, c)?.revoke(&client, c)?;
+            }
+
+            client_json(&client, c)
+        })
+    }).await;
+
+    respond(Status::Ok, client)
+}
+
+// Deletes the client with all of its authorizations and tokens.
+#[delete("/clients/<client_id>")]
+pub async fn delete_client(connection: DbConn, user: ApiUser, client_id: String) -> ApiResult {
+    let deleted = connection.run(move |c| {
+        let account = unlock(user, c)?;
+        let (client, _) = load_client(&client_id, &account, c)?;
+        let client_id = encode(&client.client_id);
+
+        client.delete(c)?;
+        Ok(json!({ "client_id": client_id }))
+    }).await;
+
+    respond(Status::Ok, deleted)
+}
+
+// Tags what a scope key signs for /sign, so a payload can't pass for any
+// other record signed with the key.
+const PAYLOAD_TAG: &[u8] = b"payload";
+
+// The message signed for a payload, given its SHA-512/256 hash. It is
+// hash_by_parts(&[PAYLOAD_TAG, payload]), so payload and payload_hash
+// requests for the same payload are signed alike.
+fn payload_message(payload_hash: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha512Trunc256::new();
+    hasher.update(Sha512Trunc256::digest(PAYLOAD_TAG));
+    hasher.update(payload_hash);
+
+    hasher.finalize().into()
+}
+
+// Signs a payload with a write scope key for a client authorized for the
+// scope. The client authenticates with its id and secret, as at the token
+// endpoint, and needs no account.
+#[post("/sign", format = "json", data = "<request>")]
+pub async fn sign(connection: DbConn, request: String) -> ApiResult {
+    let SignRequest { client_id, client_secret, scope, payload, payload_hash } = parse(&request)?;
+    let invalid = |message: &str| error_response(Status::BadRequest, Some(message.to_owned()));
+
+    let payload_hash = match (payload, payload_hash) {
+        (Some(payload), None) => {
+            let payload = decode(&payload).map_err(|_| invalid("Payload is not base64."))?;
+            Sha512Trunc256::digest(&payload).to_vec()
+        }
+        (None, Some(payload_hash)) => match decode(&payload_hash) {
+            Ok(hash) if hash.len() == 32 => hash,
+            _ => return Err(invalid("Payload hash is not a base64 SHA-512/256 hash.")),
+        },
+        _ => return Err(invalid("Send either payload or payload_hash.")),
+    };
+    let message = payload_message(&payload_hash);
+
+    let signed = connection.run(move |c| {
+        let (_, client) = unlock_client(&client_id, &client_secret, c)?;
+        let (signature, certificate) = WriteScope::sign_for_client(&client, &scope, &message, c)?;
+
+        Ok(json!({
+            "scope": OAuthScope::Write(scope).to_string(),
+            "key_algorithm": certificate.data.key_algorithm.name(),
+            "public_key": encode(&certificate.data.public_key),
+            "hash": "sha512_256",
+            "message": encode(message),
+            "signature": encode(&signature),
+            "certificate": encode(certificate.to_bytes()),
+        }))
+    }).await;
+
+    respond(Status::Ok, signed)
+}
@@ -1,35 +1,908 @@
 mod admin;
 mod api;
+use rocket::figment::Figment;
+use rocket::{Build, Rocket};
 use rocket_dyn_templates::Template;
 use rocket::fs::{FileServer, relative};
 use rocket::tokio::runtime::Runtime;
 use crate::database::{DbConn};
-use anyhow::Result;
+use crate::encryption::password;
+use crate::model::server_identity::ServerIdentity;
+use anyhow::{Context, Result};
+use std::sync::Arc;
 
 pub fn run() -> Result<()> {
+    let identity = ServerIdentity::from_env().context("Server identity is not configured.")?;
+    password::configured_params().context("Password hashing costs are misconfigured.")?;
     let rt = Runtime::new()?;
 
-    rt.block_on(
-    rocket::build()
+    rt.block_on(build(rocket::Config::figment(), identity).launch())?;
+
+    Ok(())
+}
+
+pub fn build(figment: Figment, identity: ServerIdentity) -> Rocket<Build> {
+    rocket::custom(figment)
         .attach(DbConn::fairing())
+        .manage(Arc::new(identity))
         .mount("/", routes![
-               //api::authorize, 
-               //api::token, 
-               //api::revoke, 
+               api::authorize,
+               api::token,
+               api::revoke,
+               api::introspect,
+               api::openid_configuration,
+               api::jwks,
+               api::rotate_read_key,
+               api::rotate_write_key,
+               api::write_scope_succession,
+               api::certificate_revocation_list,
+               api::server_public_key,
+               api::export_migration,
+               api::import_migration,
+               api::complete_migration,
+               api::account_moved,
                admin::login,
                admin::post_login, 
+               admin::recover,
+               admin::post_recover,
                admin::index,
                admin::forbidden_index,
                admin::logout,
                admin::user_logged_in_root,
                admin::not_logged_in_root,
                admin::join_server,
+               admin::authorize,
+               admin::authorize_login,
+               admin::post_consent,
         ])
+        .mount("/api/v1", routes![
+               api::v1::list_accounts,
+               api::v1::add_account,
+               api::v1::get_account,
+               api::v1::change_password,
+               api::v1::delete_account,
+               api::v1::list_applications,
+               api::v1::add_application,
+               api::v1::get_application,
+               api::v1::update_application,
+               api::v1::delete_application,
+               api::v1::add_scopes,
+               api::v1::delete_scope,
+               api::v1::list_clients,
+               api::v1::add_client,
+               api::v1::revoke_client,
+               api::v1::delete_client,
+               api::v1::sign,
+        ])
+        .register("/api/v1", catchers![api::v1::default_catcher])
         .mount("/public", FileServer::from(relative!("/src/web/media")))
         .mount("/css", FileServer::from(relative!("/src/web/css")))
         .attach(Template::fairing())
-        .launch())?;
-
-    Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{decode, encode, encode_config, URL_SAFE, URL_SAFE_NO_PAD};
+    use crate::database::schema::{account_move, certificate_revocation, oauth_token};
+    use crate::database::{establish_connection, test_database, MyConnection};
+    use crate::encryption::{hash_by_parts, random_int_256};
+    use crate::encryption::secret::SecretString;
+    use crate::model::account::Account;
+    use crate::model::account_migration::{MigrationBundle, MoveStatement};
+    use crate::model::application::Application;
+    use crate::model::authorization_code::{AuthorizationCode, ClientCredentials};
+    use crate::model::client::Client as AccountClient;
+    use crate::model::certificate::Certificate;
+    use crate::model::certificate_revocation::{CertificateRevocation, RevocationReason};
+    use crate::model::oauth_token::{OAuthToken, TokenResponse};
+    use crate::model::openid::IdToken;
+    use crate::encryption::fingerprint;
+    use crate::model::read_authorization::ReadAuthorization;
+    use crate::model::read_scope::{ReadScope, UnsignedReadScope};
+    use crate::model::write_authorization::WriteAuthorization;
+    use crate::model::write_scope::WriteScope;
+    use crate::model::server_identity::ServerPublicKey;
+    use diesel::prelude::*;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::asynchronous::Client;
+    use serde_json::json;
+    use sha2::{Digest, Sha512Trunc256};
+
+    async fn server(database_url: &str, url: &str) -> Client {
+        let figment = rocket::Config::figment()
+            .merge(("databases.diesel.url", database_url))
+            .merge(("databases.diesel.pool_size", 2));
+        let identity = ServerIdentity::from_seed(url, &random_int_256()).unwrap();
+
+        Client::tracked(build(figment, identity)).await.unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn migrate_account() {
+        let source_url = dotenv::var("DATABASE_URL").unwrap();
+        let destination_url = test_database("idvault_destination");
+        let source = server(&source_url, "https://source.example.com").await;
+        let destination = server(&destination_url, "https://destination.example.com").await;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("Migrate01", "migrate01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        Application::new("migrate", "Migrate", "https://example.com", &account)
+            .save(&connection)
+            .unwrap();
+
+        let response = destination.get("/api/server").dispatch().await;
+        let destination_key: ServerPublicKey = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(destination_key.url, "https://destination.example.com");
+
+        let response = source
+            .post("/login")
+            .header(ContentType::Form)
+            .body("username=Migrate01&password=password")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let export = |export_key: &str| {
+            source
+                .post("/api/migration/export")
+                .header(ContentType::JSON)
+                .body(json!({ "export_key": export_key, "destination": destination_key }).to_string())
+        };
+
+        assert_eq!(export("wrong_key").dispatch().await.status(), Status::Unauthorized);
+
+        let response = export("export_key").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let bundle: MigrationBundle = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+
+        let import = |bundle: &MigrationBundle, export_key: &str| {
+            destination
+                .post("/api/migration/import")
+                .header(ContentType::JSON)
+                .body(json!({
+                    "bundle": bundle,
+                    "username": "Migrate01",
+                    "email": "migrate01@example.com",
+                    "password": "new_password",
+                    "export_key": export_key,
+                }).to_string())
+        };
+
+        // Bundles are bound to the destination and useless without the export key.
+        let mut redirected = bundle.clone();
+        redirected.destination.url = "https://elsewhere.example.com".to_owned();
+        assert_eq!(import(&redirected, "export_key").dispatch().await.status(), Status::Forbidden);
+        assert_eq!(source.post("/api/migration/import").header(ContentType::JSON)
+            .body(json!({
+                "bundle": bundle,
+                "username": "Migrate02",
+                "email": "migrate02@example.com",
+                "password": "new_password",
+                "export_key": "export_key",
+            }).to_string())
+            .dispatch().await.status(), Status::Forbidden);
+        assert_ne!(import(&bundle, "wrong_key").dispatch().await.status(), Status::Ok);
+
+        let response = import(&bundle, "export_key").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let statement = response.into_string().await.unwrap();
+        assert_eq!(import(&bundle, "export_key").dispatch().await.status(), Status::Conflict);
+
+        let response = source
+            .post("/api/migration/complete")
+            .header(ContentType::JSON)
+            .body(statement.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let public_key = encode_config(&account.public_key, URL_SAFE);
+        let response = source.get(format!("/api/account/{}/moved", public_key)).dispatch().await;
+        let moved: MoveStatement = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(moved, serde_json::from_str(&statement).unwrap());
+        assert_eq!(moved.destination_url, "https://destination.example.com");
+        assert_eq!(decode(&moved.destination_server_key).unwrap(), decode(&destination_key.signing_key).unwrap());
+        assert!(moved.to_record().unwrap().verify());
+
+        let response = destination.get(format!("/api/account/{}/moved", public_key)).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // The account keeps its key and applications on the destination.
+        let destination_connection = MyConnection::establish(&destination_url).unwrap();
+        let moved_account = Account::load_unlocked(
+            "Migrate01".to_owned(),
+            SecretString::new("new_password".to_owned()),
+            &destination_connection,
+        )
+        .unwrap();
+        assert_eq!(moved_account.public_key, account.public_key);
+        assert!(moved_account.check_export_key("export_key"));
+        Application::load_by_code("migrate", &moved_account, &destination_connection).unwrap();
+
+        for connection in &[&connection, &destination_connection] {
+            diesel::delete(account_move::table.filter(account_move::account_public_key.eq(&account.public_key)))
+                .execute(*connection)
+                .unwrap();
+        }
+
+        moved_account.delete(&destination_connection).unwrap();
+        account.delete(&connection).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn certificate_revocation_list() {
+        let server = server(&dotenv::var("DATABASE_URL").unwrap(), "https://crl.example.com").await;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("Crl01", "crl01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        let account_public_key = account.public_key.clone();
+        let crl = format!("/api/account/{}/crl", encode_config(&account_public_key, URL_SAFE));
+
+        let response = server.get(crl.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(json_body(response).await["entries"], json!([]));
+
+        let revoked = random_int_256();
+        CertificateRevocation::revoke(&account, &revoked, RevocationReason::KeyCompromise, &connection).unwrap();
+        account.delete(&connection).unwrap();
+
+        // The list outlives the account.
+        let response = server.get(crl.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let list = json_body(response).await;
+        assert_eq!(list["entries"][0]["public_key"], encode(revoked));
+        assert_eq!(list["entries"][0]["reason"], "key_compromise");
+
+        let response = server.get(format!("/api/account/{}/crl", encode_config(random_int_256(), URL_SAFE))).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        diesel::delete(certificate_revocation::table.filter(certificate_revocation::account_public_key.eq(&account_public_key)))
+            .execute(&connection)
+            .unwrap();
+    }
+
+    // A form body, the base64 values need their +, / and = escaped.
+    fn form(fields: &[(&str, &str)]) -> String {
+        fields
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value.replace('+', "%2B").replace('/', "%2F").replace('=', "%3D")))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    #[rocket::async_test]
+    async fn oauth_tokens() {
+        let server = server(&dotenv::var("DATABASE_URL").unwrap(), "https://oauth.example.com").await;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("OAuth01", "oauth01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        let application = Application::new("oauth", "OAuth", "https://example.com", &account)
+            .save(&connection)
+            .unwrap();
+        ReadScope::new("view", &application, &account)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked(&account, &connection)
+            .unwrap()
+            .add_new_key(&account, &connection)
+            .unwrap();
+        WriteScope::new("post", &application, &account).save(&connection).unwrap();
+        let (secret, client) = AccountClient::new(&account, &application);
+        let client = client.save(&connection).unwrap();
+        let client_id = encode(&client.client_id);
+        let client_secret = encode(secret);
+
+        let authorize = |scope: &str| {
+            server
+                .post("/authorize")
+                .header(ContentType::Form)
+                .body(form(&[("client_id", &client_id), ("scope", scope)]))
+        };
+
+        let token = |grant_type: &str, secret: &str, extra: &[(&str, &str)]| {
+            let mut fields = vec![("grant_type", grant_type), ("client_id", client_id.as_str()), ("client_secret", secret)];
+            fields.extend_from_slice(extra);
+            server.post("/token").header(ContentType::Form).body(form(&fields))
+        };
+
+        let revoke = |token: &str, secret: &str| {
+            server
+                .post("/revoke")
+                .header(ContentType::Form)
+                .body(form(&[("token", token), ("client_id", &client_id), ("client_secret", secret)]))
+        };
+
+        let introspect = |token: &str, client_id: &str, secret: &str| {
+            server
+                .post("/introspect")
+                .header(ContentType::Form)
+                .body(form(&[("token", token), ("client_id", client_id), ("client_secret", secret)]))
+        };
+
+        // Scopes are authorized by the logged in account.
+        assert_ne!(authorize("write:post").dispatch().await.status(), Status::Ok);
+
+        let response = server
+            .post("/login")
+            .header(ContentType::Form)
+            .body("username=OAuth01&password=password")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::SeeOther);
+
+        assert_eq!(authorize("write:missing").dispatch().await.status(), Status::NotFound);
+        assert_eq!(authorize("post").dispatch().await.status(), Status::BadRequest);
+        let response = authorize("write:post read:view").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("write:post read:view"));
+
+        // Clients authenticate with their secret.
+        let response = token("client_credentials", &encode(random_int_256()), &[]).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.into_string().await.unwrap().contains("invalid_client"));
+
+        let response = token("password", &client_secret, &[]).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().await.unwrap().contains("unsupported_grant_type"));
+
+        let response = token("client_credentials", &client_secret, &[("scope", "write:other")]).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().await.unwrap().contains("invalid_scope"));
+
+        let response = token("client_credentials", &client_secret, &[("scope", "write:post")]).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+        let write_only: TokenResponse = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(write_only.token_type, "bearer");
+        assert_eq!(write_only.scope, "write:post");
+
+        let response = token("client_credentials", &client_secret, &[]).dispatch().await;
+        let full: TokenResponse = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(full.scope, "read:view write:post");
+
+        // Resource servers authenticate as a client of the token's application.
+        let other = Application::new("oauth-other", "Other", "https://other.example.com", &account)
+            .save(&connection)
+            .unwrap();
+        let (other_secret, other_client) = AccountClient::new(&account, &other);
+        let other_client_id = encode(&other_client.save(&connection).unwrap().client_id);
+        let other_secret = encode(other_secret);
+
+        let response = introspect(&full.access_token, &client_id, &encode(random_int_256())).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = introspect(&full.access_token, &client_id, &client_secret).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+        let active = json_body(response).await;
+        assert_eq!(active["active"], true);
+        assert_eq!(active["scope"], "read:view write:post");
+        assert_eq!(active["client_id"], client_id.as_str());
+        assert_eq!(active["token_type"], "bearer");
+        assert!(active["exp"].as_i64().unwrap() > chrono::Utc::now().timestamp());
+
+        let response = introspect(&full.access_token, &other_client_id, &other_secret).dispatch().await;
+        assert_eq!(json_body(response).await, json!({ "active": false }));
+        let response = introspect(&full.refresh_token, &client_id, &client_secret).dispatch().await;
+        assert_eq!(json_body(response).await, json!({ "active": false }));
+        let response = introspect("unknown", &client_id, &client_secret).dispatch().await;
+        assert_eq!(json_body(response).await, json!({ "active": false }));
+
+        // Refresh tokens rotate.
+        let response = token("refresh_token", &client_secret, &[("refresh_token", &full.refresh_token)]).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let refreshed: TokenResponse = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_ne!(refreshed.access_token, full.access_token);
+        assert_ne!(refreshed.refresh_token, full.refresh_token);
+        assert_eq!(refreshed.scope, full.scope);
+
+        let response = token("refresh_token", &client_secret, &[("refresh_token", &full.refresh_token)]).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().await.unwrap().contains("invalid_grant"));
+
+        // Revoking a token removes the authorizations it was issued for.
+        assert_eq!(revoke(&write_only.access_token, &encode(random_int_256())).dispatch().await.status(), Status::Unauthorized);
+        assert_eq!(revoke(&write_only.access_token, &client_secret).dispatch().await.status(), Status::Ok);
+        assert!(WriteAuthorization::load_all_for_client(&client, &connection).unwrap().is_empty());
+        assert!(!ReadAuthorization::load_all_for_client(&client, &connection).unwrap().is_empty());
+        assert_eq!(revoke("unknown", &client_secret).dispatch().await.status(), Status::Ok);
+
+        let response = token("refresh_token", &client_secret, &[("refresh_token", &write_only.refresh_token)]).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        // Other tokens keep only the scopes still authorized.
+        let response = token("refresh_token", &client_secret, &[("refresh_token", &refreshed.refresh_token)]).dispatch().await;
+        let refreshed: TokenResponse = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(refreshed.scope, "read:view");
+
+        // Introspection follows revoked scopes and expiry.
+        let response = introspect(&write_only.access_token, &client_id, &client_secret).dispatch().await;
+        assert_eq!(json_body(response).await, json!({ "active": false }));
+        let response = introspect(&refreshed.access_token, &client_id, &client_secret).dispatch().await;
+        assert_eq!(json_body(response).await["scope"], "read:view");
+
+        diesel::update(oauth_token::table.filter(oauth_token::client_id.eq(&client.client_id)))
+            .set(oauth_token::expiration_date.eq(chrono::Utc::now().naive_utc() - chrono::Duration::seconds(1)))
+            .execute(&connection)
+            .unwrap();
+        let response = introspect(&refreshed.access_token, &client_id, &client_secret).dispatch().await;
+        assert_eq!(json_body(response).await, json!({ "active": false }));
+
+        account.delete(&connection).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn authorization_code_flow() {
+        let server = server(&dotenv::var("DATABASE_URL").unwrap(), "https://consent.example.com").await;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("Consent01", "consent01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        let application = Application::new("consent", "Consent", "https://example.com", &account)
+            .save(&connection)
+            .unwrap();
+        account
+            .sign_record(&UnsignedReadScope {
+                application_id: application.id,
+                application_code: application.code.clone(),
+                code: "view".to_owned(),
+                display_name: Some("View posts".to_owned()),
+                description: Some("Read the posts you follow.".to_owned()),
+            })
+            .save(&connection)
+            .unwrap()
+            .to_unlocked(&account, &connection)
+            .unwrap()
+            .add_new_key(&account, &connection)
+            .unwrap();
+        WriteScope::new("post", &application, &account).save(&connection).unwrap();
+
+        let verifier = encode_config(random_int_256(), URL_SAFE_NO_PAD);
+        let challenge = AuthorizationCode::code_challenge(&verifier);
+        let request = |scope: &str, redirect_uri: &str| {
+            format!(
+                "/authorize?client=consent&scope={}&code_challenge={}&redirect_uri={}&state=xyz",
+                scope.replace(' ', "%20"),
+                challenge,
+                redirect_uri.replace(':', "%3A").replace('/', "%2F"),
+            )
+        };
+        let callback = "https://example.com/callback";
+        let authorize_url = request("write:post read:view", callback);
+
+        let consent = |decision: &str| {
+            let fields = [
+                ("request.client", "consent"),
+                ("request.scope", "write:post read:view"),
+                ("request.code_challenge", challenge.as_str()),
+                ("request.redirect_uri", callback),
+                ("request.state", "xyz"),
+                ("decision", decision),
+            ];
+            server.post("/authorize/consent").header(ContentType::Form).body(form(&fields))
+        };
+
+        let exchange = |code: &str, verifier: &str| {
+            let fields = [
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("code_verifier", verifier),
+                ("redirect_uri", callback),
+            ];
+            server.post("/token").header(ContentType::Form).body(form(&fields))
+        };
+
+        let location = |response: &rocket::local::asynchronous::LocalResponse<'_>| {
+            response.headers().get_one("Location").unwrap().to_owned()
+        };
+
+        // Logging in returns to the request.
+        let response = server.get(authorize_url.clone()).dispatch().await;
+        assert_eq!(location(&response), "/login");
+
+        let response = server
+            .post("/login")
+            .header(ContentType::Form)
+            .body("username=Consent01&password=password")
+            .dispatch()
+            .await;
+        assert_eq!(location(&response), authorize_url);
+
+        let response = server.get(authorize_url.clone()).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let page = response.into_string().await.unwrap();
+        assert!(page.contains("View posts"));
+        assert!(page.contains("Read the posts you follow."));
+        assert!(page.contains("post"));
+
+        // Errors only go back to the application's own server.
+        let response = server.get(request("write:post", "https://example.org/callback")).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let response = server.get(request("write:missing", callback)).dispatch().await;
+        assert!(location(&response).starts_with("https://example.com/callback?error=invalid_scope&"));
+        assert!(location(&response).ends_with("&state=xyz"));
+
+        let response = consent("deny").dispatch().await;
+        assert_eq!(location(&response), "https://example.com/callback?error=access_denied&state=xyz");
+
+        // A code works once and only with the verifier.
+        let code_from = |location: String| {
+            let code = location.strip_prefix("https://example.com/callback?code=").unwrap();
+            code.strip_suffix("&state=xyz").unwrap().to_owned()
+        };
+
+        let code = code_from(location(&consent("approve").dispatch().await));
+        let response = exchange(&code, &encode_config(random_int_256(), URL_SAFE_NO_PAD)).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().await.unwrap().contains("invalid_grant"));
+        assert_eq!(exchange(&code, &verifier).dispatch().await.status(), Status::BadRequest);
+
+        let code = code_from(location(&consent("approve").dispatch().await));
+        let response = server
+            .post("/token")
+            .header(ContentType::Form)
+            .body(form(&[("grant_type", "authorization_code"), ("code", &code)]))
+            .dispatch()
+            .await;
+        assert!(response.into_string().await.unwrap().contains("invalid_request"));
+
+        let response = exchange(&code, &verifier).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-store"));
+        let credentials: ClientCredentials = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(credentials.token.scope, "write:post read:view");
+        assert_eq!(credentials.token.token_type, "bearer");
+        assert_eq!(exchange(&code, &verifier).dispatch().await.status(), Status::BadRequest);
+
+        // The credentials belong to a client authorized for the scopes.
+        let response = server
+            .post("/token")
+            .header(ContentType::Form)
+            .body(form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", &credentials.client_id),
+                ("client_secret", &credentials.client_secret),
+            ]))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let tokens: TokenResponse = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(tokens.scope, "read:view write:post");
+
+        account.delete(&connection).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn openid_sign_in() {
+        let server = server(&dotenv::var("DATABASE_URL").unwrap(), "https://vault.example.com/").await;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("OpenId01", "openid01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        Application::new("openid", "OpenId", "https://example.com", &account)
+            .save(&connection)
+            .unwrap();
+
+        let response = server.get("/.well-known/openid-configuration").dispatch().await;
+        let configuration: serde_json::Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(configuration["issuer"], "https://vault.example.com");
+        assert_eq!(configuration["jwks_uri"], "https://vault.example.com/.well-known/jwks.json");
+        assert_eq!(configuration["id_token_signing_alg_values_supported"], json!(["EdDSA"]));
+
+        let response = server.get("/.well-known/jwks.json").dispatch().await;
+        let keys: serde_json::Value = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        let key = keys["keys"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|key| key["kid"] == fingerprint(&account.public_key).as_str())
+            .unwrap();
+        assert_eq!(key["x"], encode_config(&account.public_key, URL_SAFE_NO_PAD).as_str());
+        assert_eq!(key["alg"], "EdDSA");
+
+        server
+            .post("/login")
+            .header(ContentType::Form)
+            .body("username=OpenId01&password=password")
+            .dispatch()
+            .await;
+
+        // Libraries send the application code as client_id.
+        let verifier = encode_config(random_int_256(), URL_SAFE_NO_PAD);
+        let response = server
+            .get(format!(
+                "/authorize?response_type=code&client_id=openid&scope=openid&code_challenge={}&code_challenge_method=S256&redirect_uri=https%3A%2F%2Fexample.com%2Fcallback&nonce=abc",
+                AuthorizationCode::code_challenge(&verifier),
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("Sign you in as OpenId01"));
+
+        let response = server
+            .post("/authorize/consent")
+            .header(ContentType::Form)
+            .body(form(&[
+                ("request.client", "openid"),
+                ("request.scope", "openid"),
+                ("request.code_challenge", &AuthorizationCode::code_challenge(&verifier)),
+                ("request.redirect_uri", "https://example.com/callback"),
+                ("request.nonce", "abc"),
+                ("decision", "approve"),
+            ]))
+            .dispatch()
+            .await;
+        let location = response.headers().get_one("Location").unwrap().to_owned();
+        let code = location.strip_prefix("https://example.com/callback?code=").unwrap();
+
+        let response = server
+            .post("/token")
+            .header(ContentType::Form)
+            .body(form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("code_verifier", &verifier),
+                ("redirect_uri", "https://example.com/callback"),
+            ]))
+            .dispatch()
+            .await;
+        let credentials: ClientCredentials = serde_json::from_str(&response.into_string().await.unwrap()).unwrap();
+        assert_eq!(credentials.token.scope, "openid");
+        assert_eq!(credentials.token.token_type, "bearer");
+        assert!(!credentials.token.access_token.is_empty());
+
+        let claims = IdToken::verify(&credentials.id_token.unwrap(), &account.public_key).unwrap();
+        assert_eq!(claims.iss, "https://vault.example.com");
+        assert_eq!(claims.sub, IdToken::subject(&account.public_key));
+        assert_eq!(claims.aud, "openid");
+        assert_eq!(claims.nonce.as_deref(), Some("abc"));
+
+        account.delete(&connection).unwrap();
+    }
+
+    fn basic(username: &str, password: &str) -> Header<'static> {
+        Header::new("Authorization", format!("Basic {}", encode(format!("{}:{}", username, password))))
+    }
+
+    async fn json_body(response: rocket::local::asynchronous::LocalResponse<'_>) -> serde_json::Value {
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn api_v1_administration() {
+        let server = server(&dotenv::var("DATABASE_URL").unwrap(), "https://api.example.com").await;
+
+        let connection = establish_connection().unwrap();
+        let admin = Account::new("ApiAdmin01", "apiadmin01@example.com", "password", "export_key", true)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        let admin_auth = || basic("ApiAdmin01", "password");
+        let user_auth = |password: &str| basic("ApiUser01", password);
+
+        let response = server.get("/api/v1/accounts").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(json_body(response).await["error"].is_string());
+
+        let response = server.get("/api/v1/accounts").header(basic("ApiAdmin01", "wrong")).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let new_account = json!({
+            "name": "ApiUser01",
+            "email": "apiuser01@example.com",
+            "password": "password",
+            "export_key": "export_key",
+        })
+        .to_string();
+        let add_account = |auth: Header<'static>| {
+            server.post("/api/v1/accounts").header(ContentType::JSON).header(auth).body(new_account.clone())
+        };
+
+        let response = add_account(admin_auth()).dispatch().await;
+        assert_eq!(response.status(), Status::Created);
+        assert_eq!(json_body(response).await["key_algorithm"], "ed25519");
+        assert_eq!(add_account(admin_auth()).dispatch().await.status(), Status::Conflict);
+        assert_eq!(add_account(user_auth("password")).dispatch().await.status(), Status::Forbidden);
+
+        let response = server.get("/api/v1/accounts").header(admin_auth()).dispatch().await;
+        assert!(json_body(response).await.as_array().unwrap().iter().any(|a| a["name"] == "ApiUser01"));
+
+        let response = server
+            .post("/api/v1/applications")
+            .header(ContentType::JSON)
+            .header(user_auth("password"))
+            .body(json!({ "code": "apiapp", "description": "Api", "server_url": "https://example.com" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+
+        let response = server
+            .put("/api/v1/applications/apiapp")
+            .header(ContentType::JSON)
+            .header(user_auth("password"))
+            .body(json!({ "description": "Api Application" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = server
+            .post("/api/v1/applications/apiapp/scopes")
+            .header(ContentType::JSON)
+            .header(user_auth("password"))
+            .body(json!({ "read": ["view"], "write": ["post"] }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Created);
+        let scopes = json_body(response).await;
+        assert_eq!(scopes["read"][0]["code"], "view");
+        assert_eq!(scopes["write"][0]["key_algorithm"], "ed25519");
+
+        let add_client = |scopes: serde_json::Value| {
+            server
+                .post("/api/v1/applications/apiapp/clients")
+                .header(ContentType::JSON)
+                .header(user_auth("password"))
+                .body(scopes.to_string())
+        };
+
+        let response = add_client(json!({ "write": ["unknown"] })).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = add_client(json!({ "read": ["view"], "write": ["post"] })).dispatch().await;
+        assert_eq!(response.status(), Status::Created);
+        let credentials = json_body(response).await;
+        assert_eq!(credentials["scope"], "read:view write:post");
+        let client_id = decode(credentials["client_id"].as_str().unwrap()).unwrap();
+        let url_id = encode_config(&client_id, URL_SAFE);
+
+        // The application is signed again with its new description.
+        let response = server.get("/api/v1/applications/apiapp").header(user_auth("password")).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let application = json_body(response).await;
+        assert_eq!(application["description"], "Api Application");
+        assert_eq!(application["clients"].as_array().unwrap().len(), 1);
+
+        // Another account's clients are not found.
+        let response = server.delete(format!("/api/v1/clients/{}", url_id)).header(admin_auth()).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = server
+            .post(format!("/api/v1/clients/{}/revoke", url_id))
+            .header(ContentType::JSON)
+            .header(user_auth("password"))
+            .body(json!({ "write": ["post"] }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(json_body(response).await["scope"], "read:view");
+
+        let response = server
+            .delete("/api/v1/applications/apiapp/scopes/write/post")
+            .header(user_auth("password"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = server.delete(format!("/api/v1/clients/{}", url_id)).header(user_auth("password")).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = server.get("/api/v1/applications/apiapp/clients").header(user_auth("password")).dispatch().await;
+        assert_eq!(json_body(response).await, json!([]));
+
+        let response = server
+            .put("/api/v1/account/password")
+            .header(ContentType::JSON)
+            .header(user_auth("password"))
+            .body(json!({ "new_password": "new_password" }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = server.get("/api/v1/account").header(user_auth("password")).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = server.get("/api/v1/account").header(user_auth("new_password")).dispatch().await;
+        assert_eq!(json_body(response).await["applications"][0]["code"], "apiapp");
+
+        let response = server.delete("/api/v1/applications/apiapp").header(user_auth("new_password")).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = server.delete("/api/v1/account").header(user_auth("new_password")).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(Account::load_locked("ApiUser01", &connection).is_err());
+
+        admin.delete(&connection).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn api_v1_sign() {
+        let server = server(&dotenv::var("DATABASE_URL").unwrap(), "https://sign.example.com").await;
+
+        let connection = establish_connection().unwrap();
+        let account = Account::new("Sign01", "sign01@example.com", "password", "export_key", false)
+            .save(&connection)
+            .unwrap()
+            .to_unlocked("password")
+            .unwrap();
+        let application = Application::new("sign", "Sign", "https://example.com", &account)
+            .save(&connection)
+            .unwrap();
+        WriteScope::new("post", &application, &account).save(&connection).unwrap();
+        WriteScope::new("delete", &application, &account).save(&connection).unwrap();
+        let (secret, client) = AccountClient::new(&account, &application);
+        let client = client.save(&connection).unwrap();
+        OAuthToken::authorize(&account, &client, "write:post", &connection).unwrap();
+
+        let client_id = encode(&client.client_id);
+        let client_secret = encode(secret);
+        let sign = |secret: &str, scope: &str, payload: serde_json::Value| {
+            let mut request = json!({ "client_id": client_id, "client_secret": secret, "scope": scope });
+            request.as_object_mut().unwrap().extend(payload.as_object().unwrap().clone());
+            server.post("/api/v1/sign").header(ContentType::JSON).body(request.to_string())
+        };
+        let now = chrono::Utc::now().naive_utc();
+
+        let response = sign(&client_secret, "post", json!({ "payload": encode("Hello") })).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let signed = json_body(response).await;
+        assert_eq!(signed["scope"], "write:post");
+        let certificate = Certificate::from_bytes(&decode(signed["certificate"].as_str().unwrap()).unwrap()).unwrap();
+        assert_eq!(signed["hash"], "sha512_256");
+        let message = hash_by_parts(&[b"payload", b"Hello"]);
+        assert_eq!(signed["message"], encode(message));
+        let signature = decode(signed["signature"].as_str().unwrap()).unwrap();
+        assert!(certificate.verify_message(&account.public_key, now, &message, &signature).is_ok());
+        assert!(certificate.verify_message(&account.public_key, now, b"Hello", &signature).is_err());
+        assert!(certificate.verify_message(&account.public_key, now, &hash_by_parts(&[b"payload", b"Goodbye"]), &signature).is_err());
+
+        // A payload hash is signed as its payload would be, never as the raw bytes.
+        let hash = Sha512Trunc256::digest(b"A long document");
+        let response = sign(&client_secret, "post", json!({ "payload_hash": encode(hash) })).dispatch().await;
+        let signed = json_body(response).await;
+        let signature = decode(signed["signature"].as_str().unwrap()).unwrap();
+        let message = hash_by_parts(&[b"payload", b"A long document"]);
+        assert!(certificate.verify_message(&account.public_key, now, &message, &signature).is_ok());
+        assert!(certificate.verify_message(&account.public_key, now, &hash, &signature).is_err());
+
+        let response = sign(&client_secret, "post", json!({ "payload": encode(hash) })).dispatch().await;
+        let signed = json_body(response).await;
+        assert_ne!(signed["message"], encode(message));
+
+        let response = sign(&encode(random_int_256()), "post", json!({ "payload": encode("Hello") })).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = sign(&client_secret, "delete", json!({ "payload": encode("Hello") })).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = sign(&client_secret, "post", json!({ "payload": encode("Hello"), "payload_hash": encode(hash) }))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let response = sign(&client_secret, "post", json!({ "payload_hash": encode("short") })).dispatch().await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        account.delete(&connection).unwrap();
+    }
+}
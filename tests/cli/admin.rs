@@ -0,0 +1,54 @@
+extern crate assert_cmd;
+extern crate predicates;
+use crate::cli::admin::assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::env;
+use std::process::Command;
+use crate::cli::account::{create_account, delete_account};
+use crate::cli::application::create_application;
+
+#[test]
+fn test_backup() {
+    create_account("admin_user1", "admin_email1@example.com", "test_password");
+    create_application("admin_user1", "test_password", "spout1", "Spout", "https://spout.example.com");
+
+    let file = env::temp_dir().join("admin_user1.backup");
+    let file = file.to_str().unwrap();
+
+    admin("backup", "test_passphrase", &["-o", file])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Backed up"));
+
+    admin("restore", "wrong_passphrase", &["-f", file])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Wrong passphrase or damaged backup."));
+
+    // The server still holds the account, restore only fills an empty database.
+    admin("restore", "test_passphrase", &["-f", file])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Restore needs an empty database."));
+
+    std::fs::write(file, b"not a backup").unwrap();
+    admin("restore", "test_passphrase", &["-f", file])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Not an idvault backup."));
+
+    std::fs::remove_file(file).unwrap();
+    delete_account("admin_user1", "test_password");
+}
+
+fn admin(subcommand: &str, passphrase: &str, args: &[&str]) -> Command {
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("admin")
+        .arg(subcommand)
+        .arg("-P")
+        .arg(passphrase)
+        .args(args);
+
+    cmd
+}
@@ -3,6 +3,8 @@ extern crate predicates;
 use crate::cli::application::assert_cmd::prelude::*;
 use std::process::Command;
 use crate::cli::account::{create_account, delete_account};
+use crate::cli::client::create_client;
+use predicates::prelude::*;
 
 #[test]
 fn test_create_application() {
@@ -37,6 +39,181 @@ fn test_add_remove_scopes() {
     delete_account("application_user2", "test_password");
 }
 
+#[test]
+fn test_rotate_read_key() {
+    create_account("application_user3", "application_email3@example.com", "test_password");
+    create_application("application_user3", "test_password", "spout1", "Spout", "https://spout.example.com");
+    add_scopes("application_user3", "test_password", "spout1", &[], &["smell"]);
+    create_client("application_user3", "test_password", "spout1", &[], &["smell"]);
+
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("application")
+        .arg("rotate")
+        .arg("-a")
+        .arg("application_user3")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-c")
+        .arg("spout1")
+        .arg("-r")
+        .arg("smell");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Read Scope smell key rotated, 1 clients re-authorized."));
+
+    delete_scopes("application_user3", "test_password", "spout1", &[], &["smell"]);
+    delete_application("application_user3", "test_password", "spout1");
+    delete_account("application_user3", "test_password");
+}
+
+#[test]
+fn test_rotate_write_key() {
+    create_account("application_user4", "application_email4@example.com", "test_password");
+    create_application("application_user4", "test_password", "spout1", "Spout", "https://spout.example.com");
+    add_scopes("application_user4", "test_password", "spout1", &["crap"], &[]);
+    create_client("application_user4", "test_password", "spout1", &["crap"], &[]);
+
+    for _ in 0..2 {
+        let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+        cmd.arg("application")
+            .arg("rotate")
+            .arg("-a")
+            .arg("application_user4")
+            .arg("-p")
+            .arg("test_password")
+            .arg("-c")
+            .arg("spout1")
+            .arg("-w")
+            .arg("crap");
+
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Write Scope crap key rotated, 1 clients re-authorized."));
+    }
+
+    delete_scopes("application_user4", "test_password", "spout1", &["crap"], &[]);
+    delete_application("application_user4", "test_password", "spout1");
+    delete_account("application_user4", "test_password");
+}
+
+#[test]
+fn test_p256_keys() {
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("account")
+        .arg("add")
+        .arg("-a")
+        .arg("application_user5")
+        .arg("-e")
+        .arg("application_email5@example.com")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-x")
+        .arg("test_export_key")
+        .arg("--algorithm")
+        .arg("p256");
+
+    cmd.assert().success();
+
+    create_application("application_user5", "test_password", "spout1", "Spout", "https://spout.example.com");
+
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("application")
+        .arg("scope")
+        .arg("-a")
+        .arg("application_user5")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-c")
+        .arg("spout1")
+        .arg("-w")
+        .arg("crap")
+        .arg("-r")
+        .arg("smell")
+        .arg("--write_algorithm")
+        .arg("p256")
+        .arg("--read_algorithm")
+        .arg("p256");
+
+    cmd.assert().success();
+
+    create_client("application_user5", "test_password", "spout1", &["crap"], &["smell"]);
+
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("application")
+        .arg("rotate")
+        .arg("-a")
+        .arg("application_user5")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-c")
+        .arg("spout1")
+        .arg("-w")
+        .arg("crap")
+        .arg("-r")
+        .arg("smell");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Read Scope smell key rotated, 1 clients re-authorized."))
+        .stdout(predicate::str::contains("Write Scope crap key rotated, 1 clients re-authorized."));
+
+    delete_scopes("application_user5", "test_password", "spout1", &["crap"], &["smell"]);
+    delete_application("application_user5", "test_password", "spout1");
+    delete_account("application_user5", "test_password");
+}
+
+#[test]
+fn test_hybrid_read_key() {
+    create_account("application_user6", "application_email6@example.com", "test_password");
+    create_application("application_user6", "test_password", "spout1", "Spout", "https://spout.example.com");
+
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("application")
+        .arg("scope")
+        .arg("-a")
+        .arg("application_user6")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-c")
+        .arg("spout1")
+        .arg("-r")
+        .arg("smell")
+        .arg("--read_algorithm")
+        .arg("x25519_mlkem768");
+
+    cmd.assert().success();
+
+    create_client("application_user6", "test_password", "spout1", &[], &["smell"]);
+
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("application")
+        .arg("rotate")
+        .arg("-a")
+        .arg("application_user6")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-c")
+        .arg("spout1")
+        .arg("-r")
+        .arg("smell");
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Read Scope smell key rotated, 1 clients re-authorized."));
+
+    delete_scopes("application_user6", "test_password", "spout1", &[], &["smell"]);
+    delete_application("application_user6", "test_password", "spout1");
+    delete_account("application_user6", "test_password");
+}
+
 pub fn create_application(account: &str, password: &str, code: &str, description: &str, url: &str) {
     let mut cmd = Command::cargo_bin("idvault").unwrap();
 
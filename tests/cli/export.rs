@@ -0,0 +1,103 @@
+extern crate assert_cmd;
+extern crate predicates;
+use crate::cli::export::assert_cmd::prelude::*;
+use predicates::prelude::*;
+use std::env;
+use std::process::Command;
+use crate::cli::account::{create_account, delete_account};
+use crate::cli::application::{create_application, add_scopes};
+use crate::cli::client::create_client;
+
+#[test]
+fn test_export_import() {
+    create_account("export_user1", "export_email1@example.com", "test_password");
+    create_application("export_user1", "test_password", "spout1", "Spout", "https://spout.example.com");
+    add_scopes("export_user1", "test_password", "spout1", &["crap"], &["smell"]);
+    create_client("export_user1", "test_password", "spout1", &["crap"], &["smell"]);
+
+    // The export key is required.
+    export("export_user1", "wrong_export_key", &["-o", "/dev/null"]).assert().failure();
+
+    let file = env::temp_dir().join("export_user1.json");
+    export("export_user1", "test_export_key", &["-o", file.to_str().unwrap()]).assert().success();
+
+    let output = export("export_user1", "test_export_key", &["-t", "cbor"]).output().unwrap();
+    assert!(output.status.success());
+    let cbor = output.stdout;
+
+    delete_account("export_user1", "test_password");
+
+    import("export_user1", "wrong_passphrase", &["-f", file.to_str().unwrap()])
+        .assert()
+        .failure();
+
+    import("export_user1", "test_passphrase", &["-f", file.to_str().unwrap(), "-n"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Dry run, nothing was saved."));
+
+    import("export_user1", "test_passphrase", &["-f", file.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("account export_user1: created"))
+        .stdout(predicate::str::contains("write scope spout1/crap: created"))
+        .stdout(predicate::str::contains("read scope spout1/smell: created"));
+
+    // Importing again from standard input merges into the account.
+    let mut cmd = assert_cmd::Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("import")
+        .arg("-a")
+        .arg("export_user1")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-P")
+        .arg("test_passphrase")
+        .arg("-t")
+        .arg("cbor")
+        .write_stdin(cbor);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("account export_user1: merged"))
+        .stdout(predicate::str::contains("write scope spout1/crap: merged"));
+
+    std::fs::remove_file(&file).unwrap();
+    delete_account("export_user1", "test_password");
+}
+
+fn export(account: &str, export_key: &str, args: &[&str]) -> Command {
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("export")
+        .arg("-a")
+        .arg(account)
+        .arg("-p")
+        .arg("test_password")
+        .arg("-x")
+        .arg(export_key)
+        .arg("-P")
+        .arg("test_passphrase")
+        .args(args);
+
+    cmd
+}
+
+fn import(account: &str, passphrase: &str, args: &[&str]) -> Command {
+    let mut cmd = Command::cargo_bin("idvault").unwrap();
+
+    cmd.arg("import")
+        .arg("-a")
+        .arg(account)
+        .arg("-e")
+        .arg("export_email1@example.com")
+        .arg("-p")
+        .arg("test_password")
+        .arg("-x")
+        .arg("test_export_key")
+        .arg("-P")
+        .arg(passphrase)
+        .args(args);
+
+    cmd
+}
@@ -1,3 +1,5 @@
 mod account;
+mod admin;
 mod application;
 mod client;
+mod export;
@@ -0,0 +1,27 @@
+-- Account test_user3 as stored before the XChaCha20-Poly1305 envelope: the
+-- master key is xor encrypted under a password key with the legacy key
+-- derivation settings, the account private key under the master key, and
+-- write scope spout1/crap has a legacy version 0 access key and a version 1
+-- certificate. The password is test_password, the export key
+-- test_export_key.
+INSERT INTO account (name, email, password_hash, export_key_hash, public_key, encrypted_private_key, master_key_salt, encrypted_master_key, is_admin, master_key_kdf)
+    VALUES ('test_user3', 'test_email3@example.com', '$argon2id$v=19$m=19456,t=2,p=1$a2r43Z2dRuFb4TTx3xomRI/SjVHE+VsYfA7eiF41qnY$I+AobaPbE52GQazFO8UtQOy6N5XIxyNNA1prLMnYZAw', '$argon2id$v=19$m=19456,t=2,p=1$ixZVHB1ST1KG3B2Z3eectVGmUcod6ybF1I+vqLKWPP4$rmUyYb8VlbGc7ThapiimAdo5sdWkiU32sCKirzyf+TU',
+    '\x8bf927b7506614c730627813cd19c62cd494ae08168dd1d7b2e559b2578a9b01',
+    '\xbc2672f0b7b1100b47b663157eeb4a91c2bcda1c275b80cffb61a540943bd726369e3589749233572a18f531e985194b665beeedbd011d0e0e01651df6c378f0',
+    '\xcc42cdb6fe0564c1c8482c3ccfe14a464601f836184feb9ded9828d7c0f46918',
+    '\xadd650bbd142a244d184e8e79a8350e11e6bdc37573a7ba1b80b5d331a14896252b7d1e3b459b0892c68ae64e0485745e4ae39f4943b093123dc7be8109728b3',
+    false, '');
+INSERT INTO application (account_id, code, description, server_url, signature)
+    SELECT id, 'spout1', 'Spout', 'https://spout.example.com',
+    '\x08e306fd64c5366d01005e484cb13f4c4f697bdc1e53496386f8bc9a50638b84ea4e6770a1b2ce699fb8649d7a34d8437bf66f37032a14d8cc7d8e426c99580b'
+    FROM account WHERE name = 'test_user3';
+INSERT INTO write_grant_scope (application_id, code, public_key, encrypted_private_key, private_key_salt, expiration_date, signature, key_version)
+    SELECT application.id, 'crap',
+    '\x5b78688eae73b7288919b17a8c3453595f1fd0f0cde306bbad0acd4441363c19',
+    '\x50a2d0031d6b2474d7855695e4340288db962fb922dd573aa1422b383c7337cf1f9ce75b128133720aa1b26e1207feb23d25dd63ac805d7549e4f7957dc13a1b',
+    '\xe93baba3a83270423c9b18e4ff820b2f5361edd4fb24d61d6637b038173cf84f',
+    '2036-10-14 12:08:39',
+    '\xbd08f9875de6a81a995f9e0abc69ff46018767fda8ddb19ae709b03bc48b781dd344c53ee97091f4e0d1e9d60ad7777c7c2558344b7e993390d850aa80081a02',
+    0
+    FROM application JOIN account ON account.id = application.account_id
+    WHERE account.name = 'test_user3' AND application.code = 'spout1';
@@ -1,4 +1,6 @@
 extern crate assert_cmd;
+#[macro_use]
+extern crate diesel;
 extern crate predicates;
 
 mod cli;